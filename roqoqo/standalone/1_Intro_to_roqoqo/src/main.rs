@@ -10,11 +10,35 @@
 // express or implied. See the License for the specific language governing permissions and
 // limitations under the License.
 
+use examples_support::backend::Backend;
+use examples_support::cache::CachingBackend;
+use examples_support::density_matrix::DensityMatrixBackend;
+use examples_support::diagram::{self, DiagramStyle};
+use examples_support::mps::MpsBackend;
+use examples_support::optimizer::{Adam, Cobyla, Lbfgs, NelderMead, Objective, Optimizer, Spsa};
+use examples_support::remote::{
+    self, HttpJobClient, JobBackend, JobRequest, JobStatus, LocalJobQueue, RunOptions,
+};
+use examples_support::runner::RunResult;
+use examples_support::stabilizer::{self, StabilizerBackend};
+use examples_support::state_vector::StateVectorBackend;
+use examples_support::zero_noise::{self, Extrapolation};
+use examples_support::{
+    batch, decomposition, figure, gradient, noise, qasm, runner, seed, unitary,
+};
+use qoqo_calculator::{Calculator, CalculatorFloat};
 use roqoqo::backends::{EvaluatingBackend, RegisterResult};
-use roqoqo::measurements::{PauliZProduct, PauliZProductInput};
-use roqoqo::{operations::*, registers::*, Circuit, QuantumProgram};
-use roqoqo_quest::Backend;
+use roqoqo::devices::AllToAllDevice;
+use roqoqo::measurements::{Measure, MeasureExpectationValues, PauliZProduct, PauliZProductInput};
+use roqoqo::noise_models::{
+    ContinuousDecoherenceModel, DecoherenceOnGateModel, DecoherenceOnIdleModel, NoiseModel,
+};
+use roqoqo::{operations::*, registers::*, Circuit, QuantumProgram, RoqoqoBackendError};
+use roqoqo_quest::Backend as QuestBackend;
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // Introduction examples for simple circuits and measurements.
 // For extended examples on "Fine control over decoherence", usage of "Symbolic parameters",
@@ -55,7 +79,8 @@ pub fn entangling_circuit_snippet() {
 ///
 /// roqoqo uses classical registers for the readout. We need to add a classical register definition to the circuit and a measurement statement.
 /// The number of projective measurements can be directly set in the circuit.  
-/// The simulation and measurement of the circuit is handled by the roqoqo_quest interface (in this example).
+/// The simulation and measurement of the circuit is handled by the roqoqo_quest interface (in this example). With a global seed, set with
+/// `--seed <seed>` or `ROQOQO_EXAMPLES_SEED`, the state-vector backend of examples_support runs it instead and the shots are reproducible.
 ///
 pub fn measuring_qubits() {
     // Create new modifiable circuit
@@ -133,11 +158,85 @@ pub fn measuring_observables() {
     assert!(result < 4.0 * 10.0);
 }
 
+/// Running a parameter sweep in parallel
+///
+/// The program of the observable measurement above gets a free parameter: the Hadamard gate is replaced by a rotation
+/// RotateY(theta), which gives 3 * < Z0 > + < Z0 Z1 > = 3 cos(theta) + 1. The program is run for many values of theta, once
+/// one after the other and once with the batch runner of examples_support, which spreads the runs over all threads with a
+/// backend for every worker and returns the results in the order of the parameters.
+///
+pub fn parameter_sweep() {
+    let number_measurements = 10000;
+    let mut circuit = Circuit::new();
+    circuit += DefinitionBit::new("ro".to_string(), 2, true);
+    circuit += RotateY::new(0, CalculatorFloat::from("theta"));
+    circuit += CNOT::new(0, 1);
+    circuit += PragmaRepeatedMeasurement::new("ro".to_string(), number_measurements, None);
+    let mut measurement_input = PauliZProductInput::new(2, false);
+    measurement_input
+        .add_pauliz_product("ro".to_string(), vec![0])
+        .unwrap();
+    measurement_input
+        .add_pauliz_product("ro".to_string(), vec![0, 1])
+        .unwrap();
+    measurement_input
+        .add_linear_exp_val("example".to_string(), HashMap::from([(0, 3.0), (1, 1.0)]))
+        .unwrap();
+    let program = QuantumProgram::PauliZProduct {
+        measurement: PauliZProduct {
+            input: measurement_input,
+            circuits: vec![circuit],
+            constant_circuit: None,
+        },
+        input_parameter_names: vec!["theta".to_string()],
+    };
+
+    let number_points = 100;
+    let parameter_sets: Vec<Vec<f64>> = (0..number_points)
+        .map(|point| vec![2.0 * std::f64::consts::PI * point as f64 / number_points as f64])
+        .collect();
+
+    let start = Instant::now();
+    let sequential: Vec<f64> = parameter_sets
+        .iter()
+        .map(|parameters| program.run(Backend::new(2), parameters).unwrap().unwrap()["example"])
+        .collect();
+    let sequential_time = start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    let parallel: Vec<f64> = batch::run_program(&program, &parameter_sets, || Backend::new(2))
+        .into_iter()
+        .map(|result| result.unwrap().unwrap()["example"])
+        .collect();
+    let parallel_time = start.elapsed().as_secs_f64();
+    println!(
+        ">> Sweep of {} parameters with {} shots each: sequential {:.3} s, batch on {} threads {:.3} s, speedup {:.1}",
+        number_points,
+        number_measurements,
+        sequential_time,
+        batch::number_threads(),
+        parallel_time,
+        sequential_time / parallel_time
+    );
+
+    // < Z0 > is estimated from the shots with a standard deviation of at most 1 / sqrt(number_measurements)
+    let tolerance = 5.0 * 3.0 / (number_measurements as f64).sqrt();
+    for ((parameters, sequential), parallel) in parameter_sets.iter().zip(sequential).zip(parallel)
+    {
+        let exact = 3.0 * parameters[0].cos() + 1.0;
+        assert!((sequential - exact).abs() < tolerance);
+        assert!((parallel - exact).abs() < tolerance);
+    }
+}
+
 /// De/Serializing the quantum program
 ///
 /// Same procedure as introduced in the example 1.3 "Measurement observables", but now the measurement, and afterwards the quantum program, are serialized to and de-serialized from json.
 /// The measurement result is compared before and after the de/-serialization.
 ///
+/// The same objects are also serialized to the compact binary format of bincode.
+/// Finally, json and bincode are compared in encoded size and encode/decode time for a large generated 20-qubit VHA program.
+///
 pub fn serialization_quantum_program() {
     let mut circuit = Circuit::new();
     circuit.add_operation(DefinitionBit::new("ro".to_string(), 2, true));
@@ -181,12 +280,1303 @@ pub fn serialization_quantum_program() {
     let program_new: QuantumProgram = serde_json::from_str(&program_json).unwrap();
     assert!(program == program_new);
     println!(">> De/Serialization of QuantumProgram performed successfully.");
+
+    // The same objects can be de-/serialized to the binary bincode format, starting with the Circuit
+    let circuit_bincode: Vec<u8> = bincode::serialize(&circuit).unwrap();
+    let circuit_new: Circuit = bincode::deserialize(&circuit_bincode).unwrap();
+    assert!(circuit_new == circuit);
+    println!(">> Binary De/Serialization of Circuit performed successfully.");
+
+    let measurement_bincode: Vec<u8> = bincode::serialize(&measurement).unwrap();
+    let measurement_new: PauliZProduct = bincode::deserialize(&measurement_bincode).unwrap();
+    assert!(measurement_new == measurement);
+    println!(">> Binary De/Serialization of PauliZProduct performed successfully.");
+
+    let program_bincode: Vec<u8> = bincode::serialize(&program).unwrap();
+    let program_new: QuantumProgram = bincode::deserialize(&program_bincode).unwrap();
+    assert!(program_new == program);
+    println!(">> Binary De/Serialization of QuantumProgram performed successfully.");
+
+    // For large circuits the binary format pays off. We generate a 20-qubit VHA program with many layers
+    // and compare the two formats for the evolution circuit, the measurement and the full program.
+    let number_qubits = 20;
+    let number_layers = 50;
+    let large_program = vha_quantum_program(number_qubits, number_layers);
+    let large_measurement = match &large_program {
+        QuantumProgram::PauliZProduct { measurement, .. } => measurement.clone(),
+        _ => unreachable!(),
+    };
+    println!(
+        ">> Comparing json and bincode for a {}-qubit VHA with {} layers:",
+        number_qubits, number_layers
+    );
+    compare_serialization_formats(
+        "Circuit",
+        &vha_evolution_circuit(number_qubits, number_layers),
+    );
+    compare_serialization_formats("PauliZProduct", &large_measurement);
+    compare_serialization_formats("QuantumProgram", &large_program);
+}
+
+/// Running programs remotely
+///
+/// Remote quantum devices run programs as jobs: the serialized program is submitted, the job is polled until it has completed
+/// and its result is fetched. A mock remote service runs the jobs on QuEST behind a local HTTP server. It rejects a third of
+/// all requests, which the client retries. The VHA program of the serialization example is run remotely and its energy is
+/// compared with a local run. A job on a busy service, which holds every job for two seconds, runs into a timeout.
+///
+/// Setting the environment variable `ROQOQO_REMOTE_ADDRESS`, e.g. to `127.0.0.1:8080`, runs the jobs on a separately
+/// started `qoqo-mock-server` instead.
+///
+pub fn remote_execution() {
+    let number_qubits = 4;
+    let program = vha_quantum_program(number_qubits, 2);
+    let names = [
+        "theta_even_0",
+        "theta_odd_0",
+        "theta_z_0",
+        "theta_even_1",
+        "theta_odd_1",
+        "theta_z_1",
+    ];
+    let values = [1.3856, -0.7088, -1.1684, -0.6501, 1.4443, -0.9187];
+    let parameters: HashMap<String, f64> = names
+        .iter()
+        .map(|name| name.to_string())
+        .zip(values)
+        .collect();
+    // The service receives the same json as written in the serialization example
+    let program_json = serde_json::to_string(&program).unwrap();
+    let request = JobRequest::new(
+        runner::SerializedProgram::from_json(&program_json).unwrap(),
+        parameters,
+    );
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let address = match std::env::var("ROQOQO_REMOTE_ADDRESS") {
+            Ok(address) => address,
+            Err(_) => {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let address = listener.local_addr().unwrap().to_string();
+                tokio::spawn(remote::serve(
+                    listener,
+                    Arc::new(LocalJobQueue::new(Backend::new)),
+                    0.3,
+                ));
+                address
+            }
+        };
+        let client = HttpJobClient::new(&address);
+        let options = RunOptions {
+            retries: 10,
+            ..RunOptions::default()
+        };
+
+        // Submitting, polling and fetching one job step by step, repeating the requests rejected by the service
+        let job_id = remote::with_retries(&options, || client.submit(&request))
+            .await
+            .unwrap();
+        println!(">> Submitted the VHA program to http://{} as {}", address, job_id);
+        let mut status = JobStatus::Queued;
+        while status != JobStatus::Completed {
+            tokio::time::sleep(options.poll_interval).await;
+            let new_status = remote::with_retries(&options, || client.status(&job_id))
+                .await
+                .unwrap();
+            if new_status != status {
+                println!(">> Status of {}: {:?}", job_id, new_status);
+            }
+            status = new_status;
+            assert!(!matches!(status, JobStatus::Failed(_)));
+        }
+        let result = remote::with_retries(&options, || client.fetch(&job_id)).await;
+        let RunResult::ExpectationValues(first) = result.unwrap() else {
+            panic!("The VHA program returns expectation values")
+        };
+
+        // wait_for_job polls and fetches with a timeout and retries, several jobs run concurrently. The jobs are submitted one
+        // after the other, the job numbers and with them the sampled shots of the jobs do not depend on which request
+        // arrives first.
+        let second_id = remote::with_retries(&options, || client.submit(&request))
+            .await
+            .unwrap();
+        let third_id = remote::with_retries(&options, || client.submit(&request))
+            .await
+            .unwrap();
+        let (second, third) = tokio::join!(
+            remote::wait_for_job(&client, &second_id, &options),
+            remote::wait_for_job(&client, &third_id, &options)
+        );
+        let mut remote_energies = vec![first["energy"]];
+        for result in [second, third] {
+            match result.unwrap() {
+                RunResult::ExpectationValues(values) => remote_energies.push(values["energy"]),
+                RunResult::Registers { .. } => panic!("The VHA program returns expectation values"),
+            }
+        }
+        let local_energy = program
+            .run(Backend::new(number_qubits), &values)
+            .unwrap()
+            .unwrap()["energy"];
+        // Every shot gives energies between -16 and 16, which bounds the statistical error of 10000 shots
+        let statistical_error = 2.0f64.sqrt() * 16.0 / 10000f64.sqrt();
+        println!(
+            ">> VHA energies of three remote jobs: {:.4?}, of a local run: {:.4}, {} failed requests were retried",
+            remote_energies,
+            local_energy,
+            client.failed_requests()
+        );
+        for energy in remote_energies {
+            assert!((energy - local_energy).abs() < 5.0 * statistical_error);
+        }
+
+        // A service holding every job for two seconds does not finish the job within a timeout of 200 ms
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let busy_client = HttpJobClient::new(&listener.local_addr().unwrap().to_string());
+        tokio::spawn(remote::serve(
+            listener,
+            Arc::new(LocalJobQueue::new(Backend::new).set_delay(Duration::from_secs(2))),
+            0.0,
+        ));
+        let timeout = RunOptions {
+            timeout: Duration::from_millis(200),
+            ..RunOptions::default()
+        };
+        let error = remote::run_job(&busy_client, &request, &timeout)
+            .await
+            .unwrap_err();
+        println!(">> Job on a busy service: {}", error);
+        assert!(matches!(error, RoqoqoBackendError::Timeout { .. }));
+    });
+}
+
+/// Caching circuit results
+///
+/// Scanning the energy of the 16-qubit VHA program along one parameter twice, as an optimizer returning to points it has already
+/// visited would, runs every circuit of the second scan again. Wrapping the backend in a caching backend reads the repeated
+/// results from a cache directory instead. The cache is kept on disk, so running the example again answers even the first
+/// scan from the cache.
+///
+pub fn caching_results() {
+    let number_qubits = 16;
+    let program = vha_quantum_program(number_qubits, 2);
+    let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("cache");
+    let backend = CachingBackend::new(Backend::new(number_qubits), &directory).unwrap();
+    let scan: Vec<f64> = (0..10).map(|step| -1.5 + 0.1 * step as f64).collect();
+    let mut energies: Vec<Vec<f64>> = Vec::new();
+    for repetition in 0..2 {
+        let start = Instant::now();
+        let previous = backend.statistics();
+        energies.push(
+            scan.iter()
+                .map(|theta_z| {
+                    let parameters = [1.3856, -0.7088, *theta_z, -0.6501, 1.4443, -0.9187];
+                    program.run(backend.clone(), &parameters).unwrap().unwrap()["energy"]
+                })
+                .collect(),
+        );
+        let statistics = backend.statistics();
+        println!(
+            ">> Scan {} of theta_z_0: {} cache hits and {} misses in {:.3} s",
+            repetition + 1,
+            statistics.hits - previous.hits,
+            statistics.misses - previous.misses,
+            start.elapsed().as_secs_f64()
+        );
+    }
+    let statistics = backend.statistics();
+    println!(
+        ">> Cache hit rate {:.0} %, results stored in {}",
+        100.0 * statistics.hit_rate(),
+        backend.directory().display()
+    );
+    // The second scan reads every result of the first one from the cache
+    assert_eq!(energies[0], energies[1]);
+    assert!(statistics.hit_rate() >= 0.5);
+}
+
+/// Exporting circuits to OpenQASM
+///
+/// The Bell circuit from example 1.2 is exported to OpenQASM 2.0 and 3.0 with the roqoqo-qasm interface and parsed back into a roqoqo circuit.
+/// The parsed circuit is compared to the original: the gates have to agree up to a global phase, the measurements one by one.
+///
+pub fn exporting_to_qasm() {
+    let mut circuit = Circuit::new();
+    circuit += DefinitionBit::new("ro".to_string(), 2, true);
+    circuit += Hadamard::new(0);
+    circuit += CNOT::new(0, 1);
+    circuit += MeasureQubit::new(0, "ro".to_string(), 0);
+    circuit += MeasureQubit::new(1, "ro".to_string(), 1);
+
+    for qasm_version in ["2.0", "3.0"] {
+        let qasm_string = qasm::circuit_to_qasm(&circuit, qasm_version).unwrap();
+        println!(
+            ">> Bell circuit in OpenQASM {}:\n{}",
+            qasm_version, qasm_string
+        );
+        let circuit_from_qasm = qasm::qasm_to_circuit(&qasm_string).unwrap();
+        assert!(unitary::circuits_equivalent(&circuit, &circuit_from_qasm).unwrap());
+    }
+    println!(">> OpenQASM export and import of the Bell circuit performed successfully.");
+}
+
+/// Exporting circuit figures
+///
+/// The entangling circuit from example 1.1 and one layer of the VHA circuit from the serialization example are written as SVG images
+/// and as LaTeX documents using the quantikz package. The symbolic angles of the VHA circuit appear with their parameter names.
+///
+pub fn exporting_figures() {
+    let mut circuit = Circuit::new();
+    circuit += Hadamard::new(0);
+    circuit += Hadamard::new(1);
+    circuit += CNOT::new(0, 1);
+
+    let figures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("figures");
+    figure::write_figures(&circuit, &figures, "entangling_circuit").unwrap();
+    figure::write_figures(&vha_evolution_circuit(4, 1), &figures, "vha_circuit").unwrap();
+    println!(">> Circuit figures written to {}", figures.display());
+}
+
+/// Decomposing circuits into the gates of a device
+///
+/// A roqoqo device lists the gates it can execute natively. The entangling circuit from example 1.1 uses a Hadamard gate, which can not
+/// be written with RotateZ rotations alone, so the decomposition reports an error for a device with only RotateZ and CNOT gates. With an
+/// additional RotateX or SqrtPauliX gate every single-qubit gate can be decomposed. The VHA circuit keeps its symbolic angles, it is checked
+/// after substituting numbers for the parameters.
+///
+pub fn decomposing_circuits() {
+    let mut circuit = Circuit::new();
+    circuit += Hadamard::new(0);
+    circuit += Hadamard::new(1);
+    circuit += CNOT::new(0, 1);
+
+    let rotate_z_device =
+        AllToAllDevice::new(2, &["RotateZ".to_string()], &["CNOT".to_string()], 1.0);
+    let error = decomposition::decompose_for_device(&circuit, &rotate_z_device).unwrap_err();
+    println!(
+        ">> Decomposition for a RotateZ and CNOT device fails: {}",
+        error
+    );
+
+    let device = AllToAllDevice::new(
+        4,
+        &["RotateZ".to_string(), "RotateX".to_string()],
+        &["CNOT".to_string()],
+        1.0,
+    );
+    let decomposed = decomposition::decompose_for_device(&circuit, &device).unwrap();
+    assert!(unitary::circuits_equivalent(&circuit, &decomposed).unwrap());
+    println!(
+        ">> Entangling circuit with RotateZ, RotateX and CNOT gates:\n{}",
+        diagram::circuit_diagram(&decomposed, DiagramStyle::Unicode)
+    );
+
+    let mut calculator = Calculator::new();
+    for (index, name) in ["theta_even_0", "theta_odd_0", "theta_z_0"]
+        .iter()
+        .enumerate()
+    {
+        calculator.set_variable(name, 0.1 * (index + 1) as f64);
+    }
+    let vha_circuit = vha_evolution_circuit(4, 1);
+    let sqrt_x_device = AllToAllDevice::new(
+        4,
+        &["RotateZ".to_string(), "SqrtPauliX".to_string()],
+        &["ControlledPauliZ".to_string()],
+        1.0,
+    );
+    println!(
+        ">> Gate counts of the VHA circuit: {:?}",
+        decomposition::gate_counts(&vha_circuit)
+    );
+    for (name, target) in [
+        ("RotateZ, RotateX, CNOT", &device),
+        ("RotateZ, SqrtPauliX, ControlledPauliZ", &sqrt_x_device),
+    ] {
+        let decomposed = decomposition::decompose_for_device(&vha_circuit, target).unwrap();
+        assert!(decomposed.is_parametrized());
+        assert!(unitary::circuits_equivalent(
+            &vha_circuit.substitute_parameters(&calculator).unwrap(),
+            &decomposed.substitute_parameters(&calculator).unwrap(),
+        )
+        .unwrap());
+        println!(
+            ">> Gate counts with {}: {:?}",
+            name,
+            decomposition::gate_counts(&decomposed)
+        );
+    }
+}
+
+/// Simulating a noisy Bell state
+///
+/// The noise models of roqoqo describe how qubits decohere, the device gives the gate times. Adding the noise to the Bell circuit
+/// puts a noise pragma after every gate, and QuEST simulates the noisy circuit with a density matrix. With continuous dephasing at
+/// the rate `gamma` the coherence between |00> and |11> decays with `exp(-gamma t)` for every qubit during the time `t` of a gate.
+/// The fidelity with the ideal Bell state is `(1 + exp(-gamma (t_H + 2 t_CNOT))) / 2`. Extra depolarising noise on the CNOT gate
+/// reduces it further. The density matrices of QuEST are compared with the density-matrix backend of examples_support.
+///
+pub fn noisy_bell_state() {
+    let (hadamard_time, cnot_time) = (0.05, 0.3);
+    let device = AllToAllDevice::new(2, &["Hadamard".to_string()], &["CNOT".to_string()], 1.0)
+        .set_all_single_qubit_gate_times("Hadamard", hadamard_time)
+        .set_all_two_qubit_gate_times("CNOT", cnot_time);
+    let mut circuit = Circuit::new();
+    circuit += Hadamard::new(0);
+    circuit += CNOT::new(0, 1);
+    let mut readout = Circuit::new();
+    readout += DefinitionComplex::new("rho".to_string(), 16, true);
+    readout += PragmaGetDensityMatrix::new("rho".to_string(), None);
+
+    let dephasing_rate = 0.1;
+    let continuous = ContinuousDecoherenceModel::new().add_dephasing_rate(&[0, 1], dephasing_rate);
+    let cnot_noise = ContinuousDecoherenceModel::new()
+        .add_depolarising_rate(&[0, 1], 0.05)
+        .lindblad_noise;
+    let on_gate = DecoherenceOnGateModel::new().set_two_qubit_gate_error("CNOT", 0, 1, cnot_noise);
+    let expected_fidelity =
+        (1.0 + (-dephasing_rate * (hadamard_time + 2.0 * cnot_time)).exp()) / 2.0;
+
+    let backend = QuestBackend::new(2);
+    let mut fidelities = Vec::new();
+    let mut noisy_circuits = Vec::new();
+    for noise_models in [
+        vec![NoiseModel::from(continuous.clone())],
+        vec![continuous.into(), on_gate.into()],
+    ] {
+        let noisy = noise::add_noise(&circuit, &device, &noise_models).unwrap();
+        let (_, _, complex_registers) = backend
+            .run_circuit(&(noisy.clone() + readout.clone()))
+            .unwrap();
+        let rho = &complex_registers["rho"][0];
+        let fidelity = (rho[0] + rho[3] + rho[12] + rho[15]).re / 2.0;
+        println!(
+            ">> Noisy Bell circuit:\n{}\n>> Fidelity with the Bell state: {:.6}",
+            diagram::circuit_diagram(&noisy, DiagramStyle::Unicode),
+            fidelity
+        );
+        fidelities.push(fidelity);
+        noisy_circuits.push(noisy);
+    }
+    assert!((fidelities[0] - expected_fidelity).abs() < 1e-10);
+    assert!(fidelities[1] < fidelities[0]);
+
+    // The density-matrix backend of examples_support applies the noise pragmas exactly with their superoperators. The
+    // noise models above add PragmaGeneralNoise, QuEST has its own functions for damping, dephasing and depolarising.
+    // Both simulators have to give the same density matrices.
+    let mut pragmas = circuit.clone();
+    pragmas += PragmaDamping::new(0, 0.3.into(), 0.2.into());
+    pragmas += PragmaDephasing::new(1, 0.3.into(), 0.1.into());
+    pragmas += PragmaDepolarising::new(0, 0.3.into(), 0.05.into());
+    noisy_circuits.push(pragmas);
+    for noisy in noisy_circuits {
+        let circuit = noisy + readout.clone();
+        let (_, _, quest) = backend.run_circuit(&circuit).unwrap();
+        let (_, _, rust) = DensityMatrixBackend::new(2).run_circuit(&circuit).unwrap();
+        let difference = quest["rho"][0]
+            .iter()
+            .zip(rust["rho"][0].iter())
+            .map(|(quest, rust)| (quest - rust).norm())
+            .fold(0.0, f64::max);
+        println!(
+            ">> Largest difference of the density matrices of QuEST and the density-matrix backend {:.2e}",
+            difference
+        );
+        assert!(difference < 1e-10);
+    }
+}
+
+/// Cross-checking the state-vector backend with QuEST
+///
+/// examples_support contains a state-vector simulator written in Rust that can replace QuEST for circuits without noise. Circuits
+/// with all kinds of gates, the VHA circuit and a circuit with a measurement and a conditional operation are run on both backends,
+/// and the state vectors, occupation probabilities and Pauli products have to agree. The measurement statistics of repeated
+/// measurements are compared as well.
+///
+pub fn cross_checking_backends() {
+    let mut gates = Circuit::new();
+    gates += Hadamard::new(0);
+    gates += PauliY::new(1);
+    gates += SGate::new(2);
+    gates += TGate::new(0);
+    gates += SqrtPauliX::new(1);
+    gates += InvSqrtPauliX::new(2);
+    gates += RotateX::new(0, 0.3.into());
+    gates += RotateY::new(1, 0.7.into());
+    gates += RotateZ::new(2, (-1.1).into());
+    gates += PhaseShiftState0::new(0, 0.4.into());
+    gates += PhaseShiftState1::new(1, 0.9.into());
+    gates += RotateXY::new(2, 0.5.into(), 0.2.into());
+    gates += RotateAroundSphericalAxis::new(0, 0.6.into(), 0.3.into(), 0.8.into());
+    gates += CNOT::new(0, 1);
+    gates += ControlledPauliY::new(1, 2);
+    gates += ControlledPauliZ::new(2, 0);
+    gates += ControlledPhaseShift::new(0, 2, 0.5.into());
+    gates += ControlledRotateX::new(1, 0, 0.8.into());
+    gates += SWAP::new(0, 2);
+    gates += ISwap::new(1, 2);
+    gates += XY::new(0, 1, 0.4.into());
+    gates += MolmerSorensenXX::new(1, 2);
+    // Three-qubit gates like Toffoli are left out: roqoqo-quest 0.10 applies their unitary matrix with the qubit order
+    // reversed, so Toffoli(0, 1, 2) flips qubit 0 instead of qubit 2. The state-vector backend follows the roqoqo
+    // convention |control_0, control_1, target>.
+    gates += MultiQubitZZ::new(vec![0, 1, 2], 0.3.into());
+
+    let mut calculator = Calculator::new();
+    for (name, value) in ["theta_even_0", "theta_odd_0", "theta_z_0"]
+        .iter()
+        .zip([1.3856, -0.7088, -1.1684])
+    {
+        calculator.set_variable(name, value);
+    }
+    let vha = vha_evolution_circuit(4, 1)
+        .substitute_parameters(&calculator)
+        .unwrap();
+
+    let mut feed_forward = Circuit::new();
+    feed_forward += DefinitionBit::new("ro".to_string(), 1, true);
+    feed_forward += PauliX::new(0);
+    feed_forward += MeasureQubit::new(0, "ro".to_string(), 0);
+    let mut correction = Circuit::new();
+    correction += Hadamard::new(1);
+    correction += CNOT::new(1, 2);
+    feed_forward += PragmaConditional::new("ro".to_string(), 0, correction);
+    feed_forward += PragmaActiveReset::new(0);
+
+    for (name, circuit, number_qubits) in [
+        ("gates", gates, 3),
+        ("VHA", vha, 4),
+        ("feed-forward", feed_forward, 3),
+    ] {
+        let mut readout = Circuit::new();
+        readout += DefinitionComplex::new("psi".to_string(), 1 << number_qubits, true);
+        readout += DefinitionFloat::new("occupation".to_string(), 1 << number_qubits, true);
+        readout += DefinitionFloat::new("pauli_product".to_string(), 1, true);
+        readout += PragmaGetStateVector::new("psi".to_string(), None);
+        readout += PragmaGetOccupationProbability::new("occupation".to_string(), None);
+        readout += PragmaGetPauliProduct::new(
+            HashMap::from([(0, 3), (1, 1)]),
+            "pauli_product".to_string(),
+            Circuit::new(),
+        );
+        let circuit = circuit + readout;
+        let (_, quest_floats, quest_complex) = QuestBackend::new(number_qubits)
+            .run_circuit(&circuit)
+            .unwrap();
+        let (_, rust_floats, rust_complex) = StateVectorBackend::new(number_qubits)
+            .run_circuit(&circuit)
+            .unwrap();
+        let difference = quest_complex["psi"][0]
+            .iter()
+            .zip(rust_complex["psi"][0].iter())
+            .map(|(quest, rust)| (quest - rust).norm())
+            .fold(0.0, f64::max);
+        println!(
+            ">> {} circuit: largest difference of the state vectors of QuEST and the state-vector backend {:.2e}",
+            name, difference
+        );
+        assert!(difference < 1e-10);
+        assert!(
+            (quest_floats["pauli_product"][0][0] - rust_floats["pauli_product"][0][0]).abs()
+                < 1e-10
+        );
+        // roqoqo-quest 0.10 writes the real parts of the amplitudes instead of the occupation probabilities,
+        // they are compared with the state vector of QuEST instead
+        for (amplitude, probability) in quest_complex["psi"][0]
+            .iter()
+            .zip(rust_floats["occupation"][0].iter())
+        {
+            assert!((amplitude.norm_sqr() - probability).abs() < 1e-10);
+        }
+    }
+
+    // Shots are random, the fraction of |11> in the Bell state has to agree within the statistical error
+    let number_measurements = 10000;
+    let mut repeated = Circuit::new();
+    repeated += DefinitionBit::new("ro".to_string(), 2, true);
+    repeated += Hadamard::new(0);
+    repeated += CNOT::new(0, 1);
+    let mut measured = repeated.clone();
+    repeated += PragmaRepeatedMeasurement::new("ro".to_string(), number_measurements, None);
+    measured += MeasureQubit::new(0, "ro".to_string(), 0);
+    measured += MeasureQubit::new(1, "ro".to_string(), 1);
+    measured += PragmaSetNumberOfMeasurements::new(number_measurements, "ro".to_string());
+    for circuit in [repeated, measured] {
+        let fraction_of_ones = |bit_registers: HashMap<String, BitOutputRegister>| {
+            assert_eq!(bit_registers["ro"].len(), number_measurements);
+            bit_registers["ro"]
+                .iter()
+                .filter(|shot| shot[0] && shot[1])
+                .count() as f64
+                / number_measurements as f64
+        };
+        let (quest_bits, _, _) = QuestBackend::new(2).run_circuit(&circuit).unwrap();
+        let (rust_bits, _, _) = StateVectorBackend::new(2).run_circuit(&circuit).unwrap();
+        let (quest, rust) = (fraction_of_ones(quest_bits), fraction_of_ones(rust_bits));
+        println!(
+            ">> Fraction of |11> in {} Bell state shots: QuEST {:.4}, state-vector backend {:.4}",
+            number_measurements, quest, rust
+        );
+        assert!((quest - rust).abs() < 0.05);
+    }
+}
+
+/// Simulating Clifford circuits with a stabilizer tableau
+///
+/// Clifford gates like Hadamard, SGate and CNOT map Pauli operators to Pauli operators. A state prepared by them is described by the
+/// Pauli products that stabilize it, which the stabilizer backend of examples_support stores in a tableau with `O(n^2)` bits
+/// instead of `2^n` amplitudes. Every gate is decomposed into Hadamard, SGate, InvSGate, Pauli and CNOT gates, and the decompositions
+/// are compared with the unitaries of the gates. The entangling snippet gives the same Pauli products on the stabilizer and the
+/// state-vector backend, and a GHZ state of 500 qubits is measured.
+///
+pub fn stabilizer_simulation() {
+    let quarter = std::f64::consts::FRAC_PI_2;
+    let mut gates: Vec<Operation> = vec![
+        Identity::new(0).into(),
+        Hadamard::new(0).into(),
+        SGate::new(0).into(),
+        InvSGate::new(0).into(),
+        PauliX::new(0).into(),
+        PauliY::new(0).into(),
+        PauliZ::new(0).into(),
+        SqrtPauliX::new(0).into(),
+        InvSqrtPauliX::new(0).into(),
+        SqrtPauliY::new(0).into(),
+        InvSqrtPauliY::new(0).into(),
+        SXGate::new(0).into(),
+        InvSXGate::new(0).into(),
+        CNOT::new(0, 1).into(),
+        CNOT::new(1, 0).into(),
+        ControlledPauliZ::new(0, 1).into(),
+        ControlledPauliY::new(0, 1).into(),
+        ControlledPauliY::new(1, 0).into(),
+        SWAP::new(0, 1).into(),
+        ISwap::new(0, 1).into(),
+    ];
+    for turns in -1..4 {
+        let theta = CalculatorFloat::from(turns as f64 * quarter);
+        gates.push(RotateX::new(0, theta.clone()).into());
+        gates.push(RotateY::new(0, theta.clone()).into());
+        gates.push(RotateZ::new(0, theta.clone()).into());
+        gates.push(PhaseShiftState0::new(0, theta.clone()).into());
+        gates.push(PhaseShiftState1::new(0, theta).into());
+    }
+    for gate in gates.iter() {
+        let decomposition = stabilizer::clifford_decomposition(gate).unwrap();
+        let mut original = Circuit::new();
+        original.add_operation(gate.clone());
+        let comparison = unitary::compare_unitaries(
+            &unitary::circuit_unitary(&original, 2).unwrap(),
+            &unitary::circuit_unitary(&decomposition, 2).unwrap(),
+            unitary::TOLERANCE,
+        )
+        .unwrap();
+        assert!(comparison.is_equivalent(), "{:?}: {}", gate, comparison);
+    }
+    assert!(stabilizer::clifford_decomposition(&TGate::new(0).into()).is_err());
+    println!(
+        ">> Clifford decompositions of {} gates agree with their unitaries.",
+        gates.len()
+    );
+
+    let mut circuit = Circuit::new();
+    circuit += Hadamard::new(0);
+    circuit += Hadamard::new(1);
+    circuit += CNOT::new(0, 1);
+    circuit += SGate::new(1);
+    circuit += ControlledPauliY::new(1, 2);
+    circuit += SqrtPauliX::new(2);
+    circuit += PauliZ::new(0);
+    let paulis: Vec<Vec<(usize, usize)>> = vec![
+        vec![(0, 1)],
+        vec![(0, 3)],
+        vec![(0, 1), (1, 2)],
+        vec![(1, 2), (2, 1)],
+        vec![(0, 1), (1, 2), (2, 3)],
+        vec![(0, 1), (1, 1), (2, 2)],
+    ];
+    for (index, qubit_paulis) in paulis.iter().enumerate() {
+        circuit += DefinitionFloat::new(format!("pauli_{}", index), 1, true);
+        circuit += PragmaGetPauliProduct::new(
+            qubit_paulis.iter().copied().collect(),
+            format!("pauli_{}", index),
+            Circuit::new(),
+        );
+    }
+    let (_, stabilizer_floats, _) = StabilizerBackend::new(3).run_circuit(&circuit).unwrap();
+    let (_, state_vector_floats, _) = StateVectorBackend::new(3).run_circuit(&circuit).unwrap();
+    for (index, qubit_paulis) in paulis.iter().enumerate() {
+        let name = format!("pauli_{}", index);
+        println!(
+            ">> <{:?}>: stabilizer backend {}, state-vector backend {:.6}",
+            qubit_paulis, stabilizer_floats[&name][0][0], state_vector_floats[&name][0][0]
+        );
+        assert!((stabilizer_floats[&name][0][0] - state_vector_floats[&name][0][0]).abs() < 1e-10);
+    }
+
+    // In the GHZ state (|0...0> + |1...1>)/sqrt(2) all qubits are measured with the same value, and the product of X on
+    // all qubits has the expectation value 1
+    let number_qubits = 500;
+    let number_measurements = 20;
+    let mut ghz = Circuit::new();
+    ghz += DefinitionBit::new("ro".to_string(), number_qubits, true);
+    ghz += DefinitionFloat::new("all_x".to_string(), 1, true);
+    ghz += Hadamard::new(0);
+    for qubit in 1..number_qubits {
+        ghz += CNOT::new(qubit - 1, qubit);
+    }
+    ghz += PragmaGetPauliProduct::new(
+        (0..number_qubits).map(|qubit| (qubit, 1)).collect(),
+        "all_x".to_string(),
+        Circuit::new(),
+    );
+    for qubit in 0..number_qubits {
+        ghz += MeasureQubit::new(qubit, "ro".to_string(), qubit);
+    }
+    ghz += PragmaSetNumberOfMeasurements::new(number_measurements, "ro".to_string());
+    let start = Instant::now();
+    let (bit_registers, float_registers, _) = StabilizerBackend::new(number_qubits)
+        .run_circuit(&ghz)
+        .unwrap();
+    let ones = bit_registers["ro"]
+        .iter()
+        .filter(|shot| shot.iter().all(|bit| *bit))
+        .count();
+    println!(
+        ">> GHZ state of {} qubits: {} of {} shots measured all ones, the others all zeros, <X...X> = {}, simulated in {:.3} s",
+        number_qubits,
+        ones,
+        number_measurements,
+        float_registers["all_x"][0][0],
+        start.elapsed().as_secs_f64()
+    );
+    assert_eq!(bit_registers["ro"].len(), number_measurements);
+    assert!(bit_registers["ro"]
+        .iter()
+        .all(|shot| shot.iter().all(|bit| *bit == shot[0])));
+    assert_eq!(float_registers["all_x"][0][0], 1.0);
+}
+
+/// Simulating wide circuits with matrix product states
+///
+/// The state vector of the VHA spin chain doubles with every qubit. The matrix-product-state backend of examples_support stores one
+/// matrix per qubit and basis state instead, whose size is limited by the bond dimension. Without truncation it reproduces the state
+/// vector of 8 qubits exactly. For a chain of 50 qubits the energy is computed from the Pauli products of the matrix product state
+/// for growing bond dimensions, together with the weight of the discarded singular values. The PauliZProduct measurement of the VHA
+/// QuantumProgram measures the energy from shots, with a statistical error estimated from the variance of the shot energies.
+///
+pub fn matrix_product_states() {
+    let hopping_parameter = 3.0;
+    let magnetic_field = 1.0;
+    // The parameters minimizing the energy of four qubits, in the order theta_even, theta_odd and theta_z of each layer
+    let parameters = [1.3856, -0.7088, -1.1684, -0.6501, 1.4443, -0.9187];
+    let mut calculator = Calculator::new();
+    for (name, value) in [
+        "theta_even_0",
+        "theta_odd_0",
+        "theta_z_0",
+        "theta_even_1",
+        "theta_odd_1",
+        "theta_z_1",
+    ]
+    .iter()
+    .zip(parameters)
+    {
+        calculator.set_variable(name, value);
+    }
+
+    let number_qubits = 8;
+    let mut circuit = vha_evolution_circuit(number_qubits, 2)
+        .substitute_parameters(&calculator)
+        .unwrap();
+    circuit += DefinitionComplex::new("psi".to_string(), 1 << number_qubits, true);
+    circuit += PragmaGetStateVector::new("psi".to_string(), None);
+    let (_, _, mps_registers, report) = MpsBackend::new(number_qubits, 1 << (number_qubits / 2))
+        .run_circuit_with_report(&circuit)
+        .unwrap();
+    let (_, _, state_vector_registers) = StateVectorBackend::new(number_qubits)
+        .run_circuit(&circuit)
+        .unwrap();
+    let difference = mps_registers["psi"][0]
+        .iter()
+        .zip(state_vector_registers["psi"][0].iter())
+        .map(|(mps, state_vector)| (mps - state_vector).norm())
+        .fold(0.0, f64::max);
+    println!(
+        ">> VHA state of {} qubits: largest difference of the matrix product state and the state vector {:.2e}, {}",
+        number_qubits, difference, report
+    );
+    assert!(difference < 1e-10);
+    assert_eq!(report.truncated_gates, 0);
+
+    let number_qubits = 50;
+    let mut circuit = vha_evolution_circuit(number_qubits, 2)
+        .substitute_parameters(&calculator)
+        .unwrap();
+    for i in 0..number_qubits {
+        circuit += DefinitionFloat::new(format!("z_{}", i), 1, true);
+        circuit +=
+            PragmaGetPauliProduct::new(HashMap::from([(i, 3)]), format!("z_{}", i), Circuit::new());
+        circuit += DefinitionFloat::new(format!("xx_{}", i), 1, true);
+        circuit += PragmaGetPauliProduct::new(
+            HashMap::from([(i, 1), ((i + 1) % number_qubits, 1)]),
+            format!("xx_{}", i),
+            Circuit::new(),
+        );
+    }
+    let mut energies = Vec::new();
+    for max_bond_dimension in [4, 8, 16] {
+        let start = Instant::now();
+        let (_, float_registers, _, report) = MpsBackend::new(number_qubits, max_bond_dimension)
+            .run_circuit_with_report(&circuit)
+            .unwrap();
+        let energy: f64 = (0..number_qubits)
+            .map(|i| {
+                magnetic_field * float_registers[&format!("z_{}", i)][0][0]
+                    + hopping_parameter * float_registers[&format!("xx_{}", i)][0][0]
+            })
+            .sum();
+        println!(
+            ">> VHA energy of {} qubits with bond dimension {}: {:.4}, {}, simulated in {:.3} s",
+            number_qubits,
+            max_bond_dimension,
+            energy,
+            report,
+            start.elapsed().as_secs_f64()
+        );
+        assert!(report.largest_bond_dimension <= max_bond_dimension);
+        energies.push(energy);
+    }
+    // Truncating the bond dimension less gives an energy closer to the one of the largest bond dimension
+    assert!((energies[1] - energies[2]).abs() < (energies[0] - energies[2]).abs());
+
+    // The PauliZProduct measurement of the program is run by hand to keep its registers. The variance of the magnetic energy
+    // of the Z basis shots and of the hopping energy of the X basis shots gives the statistical error of the measured energy.
+    let number_measurements = 1000;
+    let mut program = vha_quantum_program(number_qubits, 2);
+    for circuit in runner::program_circuits_mut(&mut program) {
+        *circuit = runner::set_number_measurements(circuit, number_measurements);
+    }
+    let measurement = match program {
+        QuantumProgram::PauliZProduct {
+            measurement,
+            input_parameter_names,
+        } => measurement
+            .substitute_parameters(input_parameter_names.into_iter().zip(parameters).collect())
+            .unwrap(),
+        _ => unreachable!("The VHA program measures PauliZ products"),
+    };
+    let start = Instant::now();
+    let (bit_registers, float_registers, complex_registers) = MpsBackend::new(number_qubits, 16)
+        .run_measurement_registers(&measurement)
+        .unwrap();
+    let measured_energy = measurement
+        .evaluate(bit_registers.clone(), float_registers, complex_registers)
+        .unwrap()
+        .unwrap()["energy"];
+    let spins = |shot: &Vec<bool>| -> Vec<f64> {
+        shot.iter()
+            .map(|bit| if *bit { -1.0 } else { 1.0 })
+            .collect()
+    };
+    let variance_of_mean = |shot_energies: Vec<f64>| {
+        let mean = shot_energies.iter().sum::<f64>() / shot_energies.len() as f64;
+        shot_energies
+            .iter()
+            .map(|energy| (energy - mean).powi(2))
+            .sum::<f64>()
+            / ((shot_energies.len() - 1) * shot_energies.len()) as f64
+    };
+    let magnetic_variance = variance_of_mean(
+        bit_registers["ro_z"]
+            .iter()
+            .map(|shot| magnetic_field * spins(shot).iter().sum::<f64>())
+            .collect(),
+    );
+    let hopping_variance = variance_of_mean(
+        bit_registers["ro_x"]
+            .iter()
+            .map(|shot| {
+                let spins = spins(shot);
+                hopping_parameter
+                    * (0..number_qubits)
+                        .map(|i| spins[i] * spins[(i + 1) % number_qubits])
+                        .sum::<f64>()
+            })
+            .collect(),
+    );
+    let statistical_error = (magnetic_variance + hopping_variance).sqrt();
+    println!(
+        ">> VHA energy of {} qubits measured with {} shots per circuit: {:.4} +- {:.4}, simulated in {:.3} s",
+        number_qubits,
+        number_measurements,
+        measured_energy,
+        statistical_error,
+        start.elapsed().as_secs_f64()
+    );
+    assert!((measured_energy - energies[2]).abs() < 5.0 * statistical_error);
+}
+
+/// Gradients with the parameter-shift rule
+///
+/// The VHA program with four layers has the twelve parameters `theta_even_i`, `theta_odd_i` and `theta_z_i` of the VHA notebook.
+/// Each parameter enters the angles of several gates, the hopping angles scaled by the hopping parameter. The gradient of the
+/// energy is computed with the parameter-shift rule, which shifts every gate angle by ±π/2. Without shot noise, reading the Pauli
+/// products from the simulator, it agrees with central finite differences. With 10000 shots per circuit it agrees with the exact
+/// gradient within the statistical error.
+///
+pub fn parameter_shift_gradients() {
+    let number_qubits = 4;
+    let number_layers = 4;
+    let program = vha_quantum_program(number_qubits, number_layers);
+    let parameters = [
+        0.4, -0.3, 0.8, -0.2, 0.6, -0.7, 0.1, 0.5, -0.4, 0.3, -0.1, 0.9,
+    ];
+    let backend = Backend::new(number_qubits);
+
+    let exact_program = gradient::cheated_program(&program).unwrap();
+    let start = Instant::now();
+    let exact_gradient = gradient::parameter_shift_gradient(&exact_program, &backend, &parameters)
+        .unwrap()["energy"]
+        .clone();
+    let shift_time = start.elapsed().as_secs_f64();
+    let finite_differences =
+        gradient::finite_difference_gradient(&exact_program, &backend, &parameters, 1e-5).unwrap()
+            ["energy"]
+            .clone();
+    let difference = exact_gradient
+        .iter()
+        .zip(finite_differences.iter())
+        .map(|(shift, finite)| (shift - finite).abs())
+        .fold(0.0, f64::max);
+    println!(
+        ">> Exact parameter-shift gradient of the VHA energy in {:.3} s: {:.4?}",
+        shift_time, exact_gradient
+    );
+    println!(
+        ">> Largest difference to central finite differences: {:.2e}",
+        difference
+    );
+    assert!(difference < 1e-6);
+
+    let start = Instant::now();
+    let sampled_gradient = gradient::parameter_shift_gradient(&program, &backend, &parameters)
+        .unwrap()["energy"]
+        .clone();
+    // Every shot gives energies between -16 and 16. A parameter enters at most eight gate angles, scaled by at most 3, and every
+    // shifted energy differs from the unshifted one only by the results of one circuit.
+    let statistical_error = 3.0 * (8.0f64 / 2.0).sqrt() * 16.0 / 10000f64.sqrt();
+    println!(
+        ">> Parameter-shift gradient with 10000 shots per circuit in {:.3} s: {:.4?}, statistical error below {:.4}",
+        start.elapsed().as_secs_f64(),
+        sampled_gradient,
+        statistical_error
+    );
+    for (sampled, exact) in sampled_gradient.iter().zip(exact_gradient.iter()) {
+        assert!((sampled - exact).abs() < 5.0 * statistical_error);
+    }
+}
+
+/// Optimizing the VHA parameters
+///
+/// The VHA notebook minimizes the energy with `scipy.optimize.minimize(method='COBYLA')`. Here the energy returned by
+/// `QuantumProgram::run` is minimized by COBYLA and Nelder-Mead, which only evaluate the energy, by Adam and L-BFGS following
+/// the parameter-shift gradient and by SPSA, which estimates the gradient from two evaluations and copes with the shot noise of
+/// the sampled energy. The deterministic optimizers run on the cheated program, which returns the exact energy from the Pauli
+/// products of the simulator. The energy, parameters and number of evaluations after every iteration are written to json files
+/// in the `figures` directory.
+///
+pub fn variational_optimization() {
+    let number_qubits = 4;
+    let program = vha_quantum_program(number_qubits, 2);
+    let exact_program = gradient::cheated_program(&program).unwrap();
+    let backend = Backend::new(number_qubits);
+    let ground_state_energy = vha_ground_state_energy(number_qubits);
+    let start = [0.1; 6];
+    let energy = |program: &QuantumProgram, parameters: &[f64]| {
+        Ok(program
+            .run(backend.clone(), parameters)?
+            .unwrap_or_default()["energy"])
+    };
+    let figures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("figures");
+    std::fs::create_dir_all(&figures).unwrap();
+
+    let optimizers: Vec<(Box<dyn Optimizer>, &QuantumProgram)> = vec![
+        (Box::new(Cobyla::default()), &exact_program),
+        (Box::new(NelderMead::default()), &exact_program),
+        (Box::new(Adam::default()), &exact_program),
+        (Box::new(Lbfgs::default()), &exact_program),
+        (Box::new(Spsa::default()), &program),
+    ];
+    for (optimizer, program) in optimizers {
+        let mut objective = Objective::new(|parameters: &[f64]| energy(program, parameters))
+            .set_gradient(|parameters: &[f64]| {
+                Ok(
+                    gradient::parameter_shift_gradient(program, &backend, parameters)?["energy"]
+                        .clone(),
+                )
+            });
+        let timer = Instant::now();
+        let result = optimizer.minimize(&mut objective, &start).unwrap();
+        let exact_energy = energy(&exact_program, &result.parameters).unwrap();
+        println!(
+            ">> {:>14}: energy {:.4} (exact {:.4}) after {} iterations, {} energy and {} gradient evaluations in {:.3} s",
+            optimizer.name(),
+            result.energy,
+            exact_energy,
+            result.history.iterations.len() - 1,
+            result.number_evaluations,
+            objective.number_gradient_evaluations(),
+            timer.elapsed().as_secs_f64()
+        );
+        let name = optimizer.name().to_lowercase().replace(['-', ' '], "_");
+        std::fs::write(
+            figures.join(format!("vha_{}_history.json", name)),
+            result.history.to_json(),
+        )
+        .unwrap();
+        assert!(exact_energy < result.history.iterations[0].energy);
+        assert!(exact_energy - ground_state_energy < 1.0);
+    }
+    println!(
+        ">> Exact ground state energy: {:.4}, optimization histories written to {}",
+        ground_state_energy,
+        figures.display()
+    );
+}
+
+/// Zero-noise extrapolation of the VHA energy
+///
+/// The VHA program with two layers is run with parameters that prepare the ground state of the spin chain. On a noisy device the
+/// measured energy lies above the ground state energy. The noise is amplified on purpose, once by folding every gate `G` into
+/// `G G^-1 G ...` and once by letting the qubits decohere for a multiple of the gate time. Fitting the energies as a function of the
+/// noise scale factor and evaluating the fit at zero noise gives an energy much closer to the exact ground state energy.
+///
+pub fn zero_noise_extrapolation() {
+    let number_qubits = 4;
+    // Parameters minimizing the noiseless energy, in the order theta_even, theta_odd and theta_z of each layer
+    let parameters = [1.3856, -0.7088, -1.1684, -0.6501, 1.4443, -0.9187];
+    let mut program = vha_quantum_program(number_qubits, 2);
+    for circuit in runner::program_circuits_mut(&mut program) {
+        *circuit = runner::set_number_measurements(circuit, 100000);
+    }
+    let ground_state_energy = vha_ground_state_energy(number_qubits);
+    let backend = Backend::new(number_qubits);
+    let noiseless_energy = program.run(backend.clone(), &parameters).unwrap().unwrap()["energy"];
+    println!(
+        ">> VHA energy without noise: {:.4}, exact ground state energy: {:.4}",
+        noiseless_energy, ground_state_energy
+    );
+
+    let device = AllToAllDevice::new(
+        number_qubits,
+        &["Hadamard".to_string(), "RotateZ".to_string()],
+        &["CNOT".to_string()],
+        1.0,
+    )
+    .set_all_single_qubit_gate_times("Hadamard", 0.05)
+    .set_all_single_qubit_gate_times("RotateZ", 0.05)
+    .set_all_two_qubit_gate_times("CNOT", 0.3);
+    let qubits: Vec<usize> = (0..number_qubits).collect();
+    let noise_models: Vec<NoiseModel> = vec![
+        ContinuousDecoherenceModel::new()
+            .add_dephasing_rate(&qubits, 0.02)
+            .add_depolarising_rate(&qubits, 0.01)
+            .into(),
+        DecoherenceOnIdleModel::new()
+            .add_dephasing_rate(&qubits, 0.02)
+            .add_depolarising_rate(&qubits, 0.01)
+            .into(),
+    ];
+
+    // The dephasing and depolarising noise drives the qubits into the maximally mixed state, where the energy is zero.
+    let extrapolations = [
+        ("linear", Extrapolation::Linear),
+        ("Richardson", Extrapolation::Richardson),
+        ("exponential", Extrapolation::Exponential { asymptote: 0.0 }),
+    ];
+    let folding = zero_noise::run_scaled(
+        &program,
+        &backend,
+        &parameters,
+        &[1.0, 3.0, 5.0],
+        |circuit, scale| {
+            noise::add_noise(
+                &zero_noise::fold_gates(circuit, scale)?,
+                &device,
+                &noise_models,
+            )
+        },
+    )
+    .unwrap();
+    let scaling = zero_noise::run_scaled(
+        &program,
+        &backend,
+        &parameters,
+        &[1.0, 2.0, 3.0],
+        |circuit, scale| {
+            Ok(zero_noise::scale_noise(
+                &noise::add_noise(circuit, &device, &noise_models)?,
+                scale,
+            ))
+        },
+    )
+    .unwrap();
+
+    for (name, scaled) in [("gate folding", folding), ("scaled decoherence", scaling)] {
+        let energies = scaled.values_of("energy");
+        println!(
+            ">> Zero-noise extrapolation with {}, energies {:.4?} for the scale factors {:?}",
+            name, energies, scaled.scale_factors
+        );
+        let noisy_error = (energies[0] - ground_state_energy).abs();
+        for (fit, extrapolation) in extrapolations {
+            let energy = scaled.extrapolate(extrapolation).unwrap()["energy"];
+            println!(
+                "   {:>11} fit: energy {:.4}, error {:.4} instead of {:.4}",
+                fit,
+                energy,
+                (energy - ground_state_energy).abs(),
+                noisy_error
+            );
+            assert!((energy - ground_state_energy).abs() < noisy_error);
+        }
+    }
     println!(">> Introduction example end.")
 }
 
+/// Circuit for the evolution under the exchange term sigma^x_control sigma^x_target with angle `theta`.
+///
+/// The interaction is decomposed into Hadamard, CNOT and RotateZ gates as in the VHA example notebook.
+///
+fn xx_evolution(control: usize, target: usize, theta: CalculatorFloat) -> Circuit {
+    let mut circuit = Circuit::new();
+    circuit += Hadamard::new(target);
+    circuit += Hadamard::new(control);
+    circuit += CNOT::new(control, target);
+    circuit += RotateZ::new(target, theta);
+    circuit += CNOT::new(control, target);
+    circuit += Hadamard::new(target);
+    circuit += Hadamard::new(control);
+    circuit
+}
+
+/// Time evolution circuit of the Variational Hamiltonian Ansatz (VHA) for a periodic spin chain.
+///
+/// Each layer `i` applies the even hopping, the odd hopping (including the periodic boundary) and the magnetic field
+/// with the symbolic parameters `theta_even_i`, `theta_odd_i` and `theta_z_i`.
+///
+fn vha_evolution_circuit(number_qubits: usize, number_layers: usize) -> Circuit {
+    let hopping_parameter = 3.0;
+    let magnetic_field = 1.0;
+    let mut circuit = Circuit::new();
+    for layer in 0..number_layers {
+        let theta_even = CalculatorFloat::from(format!("theta_even_{}", layer));
+        for k in (0..number_qubits - 1).step_by(2) {
+            circuit += xx_evolution(k + 1, k, theta_even.clone() * hopping_parameter);
+        }
+        let theta_odd = CalculatorFloat::from(format!("theta_odd_{}", layer));
+        for k in (1..number_qubits - 1).step_by(2) {
+            circuit += xx_evolution(k + 1, k, theta_odd.clone() * hopping_parameter);
+        }
+        circuit += xx_evolution(0, number_qubits - 1, theta_odd * hopping_parameter);
+        let theta_z = CalculatorFloat::from(format!("theta_z_{}", layer));
+        for i in 0..number_qubits {
+            circuit += RotateZ::new(i, theta_z.clone() * magnetic_field);
+        }
+    }
+    circuit
+}
+
+/// QuantumProgram measuring the energy of the VHA spin chain.
+///
+/// The magnetic field terms are measured in the Z-basis and the hopping terms in the X-basis, as in the VHA example notebook.
+///
+fn vha_quantum_program(number_qubits: usize, number_layers: usize) -> QuantumProgram {
+    let hopping_parameter = 3.0;
+    let magnetic_field = 1.0;
+    let number_measurements = 10000;
+    let evolution = vha_evolution_circuit(number_qubits, number_layers);
+
+    let mut z_basis_circuit = Circuit::new();
+    z_basis_circuit += DefinitionBit::new("ro_z".to_string(), number_qubits, true);
+    z_basis_circuit +=
+        PragmaRepeatedMeasurement::new("ro_z".to_string(), number_measurements, None);
+    let mut x_basis_circuit = Circuit::new();
+    x_basis_circuit += DefinitionBit::new("ro_x".to_string(), number_qubits, true);
+    for i in 0..number_qubits {
+        x_basis_circuit += Hadamard::new(i);
+    }
+    x_basis_circuit +=
+        PragmaRepeatedMeasurement::new("ro_x".to_string(), number_measurements, None);
+
+    let mut measurement_input = PauliZProductInput::new(number_qubits, false);
+    let mut linear: HashMap<usize, f64> = HashMap::new();
+    for i in 0..number_qubits {
+        let index = measurement_input
+            .add_pauliz_product("ro_z".to_string(), vec![i])
+            .unwrap();
+        linear.insert(index, magnetic_field);
+    }
+    for i in 0..number_qubits {
+        let index = measurement_input
+            .add_pauliz_product("ro_x".to_string(), vec![i, (i + 1) % number_qubits])
+            .unwrap();
+        linear.insert(index, hopping_parameter);
+    }
+    measurement_input
+        .add_linear_exp_val("energy".to_string(), linear)
+        .unwrap();
+
+    let measurement = PauliZProduct {
+        input: measurement_input,
+        circuits: vec![
+            evolution.clone() + z_basis_circuit,
+            evolution + x_basis_circuit,
+        ],
+        constant_circuit: None,
+    };
+    let input_parameter_names = (0..number_layers)
+        .flat_map(|layer| {
+            [
+                format!("theta_even_{}", layer),
+                format!("theta_odd_{}", layer),
+                format!("theta_z_{}", layer),
+            ]
+        })
+        .collect();
+    QuantumProgram::PauliZProduct {
+        measurement,
+        input_parameter_names,
+    }
+}
+
+/// Exact ground state energy of the spin chain measured by [vha_quantum_program].
+fn vha_ground_state_energy(number_qubits: usize) -> f64 {
+    let hopping_parameter = 3.0;
+    let magnetic_field = 1.0;
+    let dimension = 1 << number_qubits;
+    let mut hamiltonian = nalgebra::DMatrix::<f64>::zeros(dimension, dimension);
+    for state in 0..dimension {
+        for i in 0..number_qubits {
+            hamiltonian[(state, state)] += if state >> i & 1 == 0 {
+                magnetic_field
+            } else {
+                -magnetic_field
+            };
+            let flipped = state ^ (1 << i) ^ (1 << ((i + 1) % number_qubits));
+            hamiltonian[(flipped, state)] += hopping_parameter;
+        }
+    }
+    hamiltonian.symmetric_eigenvalues().min()
+}
+
+/// Round-trips `value` through json and bincode and prints encoded size and encode/decode time of both formats.
+///
+/// Asserts that both round-trips reproduce the original object.
+///
+fn compare_serialization_formats<T>(name: &str, value: &T)
+where
+    T: Serialize + DeserializeOwned + PartialEq,
+{
+    let start = Instant::now();
+    let json = serde_json::to_string(value).unwrap();
+    let json_encode_time = start.elapsed();
+    let start = Instant::now();
+    let value_from_json: T = serde_json::from_str(&json).unwrap();
+    let json_decode_time = start.elapsed();
+    assert!(value_from_json == *value);
+
+    let start = Instant::now();
+    let binary = bincode::serialize(value).unwrap();
+    let bincode_encode_time = start.elapsed();
+    let start = Instant::now();
+    let value_from_bincode: T = bincode::deserialize(&binary).unwrap();
+    let bincode_decode_time = start.elapsed();
+    assert!(value_from_bincode == *value);
+
+    println!(
+        "{:>16} | json: {:>9} bytes, encode {:>10.3?}, decode {:>10.3?} | bincode: {:>9} bytes, encode {:>10.3?}, decode {:>10.3?} | size ratio {:.2}",
+        name,
+        json.len(),
+        json_encode_time,
+        json_decode_time,
+        binary.len(),
+        bincode_encode_time,
+        bincode_decode_time,
+        json.len() as f64 / binary.len() as f64,
+    );
+}
+
 fn main() {
-    entangling_circuit_snippet();
-    measuring_qubits();
-    measuring_observables();
-    serialization_quantum_program();
+    // The global seed of the sampled shots, see examples_support::seed
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    match seed::parse_seed(&arguments, std::env::var(seed::SEED_VARIABLE).ok()) {
+        Ok(global_seed) => seed::set_global_seed(global_seed),
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            std::process::exit(2)
+        }
+    }
+
+    // Every section draws its random numbers from its own stream, so with a global seed a section samples the same
+    // shots no matter how many runs the sections before it made
+    let sections: [fn(); 17] = [
+        entangling_circuit_snippet,
+        measuring_qubits,
+        measuring_observables,
+        parameter_sweep,
+        serialization_quantum_program,
+        remote_execution,
+        caching_results,
+        exporting_to_qasm,
+        exporting_figures,
+        decomposing_circuits,
+        noisy_bell_state,
+        cross_checking_backends,
+        stabilizer_simulation,
+        matrix_product_states,
+        parameter_shift_gradients,
+        variational_optimization,
+        zero_noise_extrapolation,
+    ];
+    for (index, section) in sections.into_iter().enumerate() {
+        seed::with_stream(index as u64, section);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serializes a value to bincode and json and checks that both deserialize to the same value.
+    fn assert_round_trip<T>(value: &T)
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        let binary = bincode::serialize(value).unwrap();
+        assert_eq!(&bincode::deserialize::<T>(&binary).unwrap(), value);
+        let json = serde_json::to_string(value).unwrap();
+        assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), value);
+        assert!(binary.len() < json.len());
+    }
+
+    #[test]
+    fn circuit_round_trip() {
+        assert_round_trip(&vha_evolution_circuit(20, 3));
+    }
+
+    #[test]
+    fn pauli_z_product_round_trip() {
+        let QuantumProgram::PauliZProduct { measurement, .. } = vha_quantum_program(20, 3) else {
+            panic!("The VHA program measures a PauliZProduct")
+        };
+        assert_round_trip(&measurement);
+    }
+
+    #[test]
+    fn quantum_program_round_trip() {
+        assert_round_trip(&vha_quantum_program(20, 3));
+    }
 }
@@ -10,12 +10,29 @@
 // express or implied. See the License for the specific language governing permissions and
 // limitations under the License.
 
+use examples_support::backend::Backend;
+use examples_support::diagram::{self, DiagramStyle};
+use examples_support::{decomposition, feed_forward, figure, seed, unitary};
 use roqoqo::backends::EvaluatingBackend;
+use roqoqo::devices::AllToAllDevice;
 use roqoqo::{operations, Circuit};
 
 pub fn main() {
+    // The global seed of the sampled shots, see examples_support::seed
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    match seed::parse_seed(&arguments, std::env::var(seed::SEED_VARIABLE).ok()) {
+        Ok(global_seed) => seed::set_global_seed(global_seed),
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            std::process::exit(2)
+        }
+    }
+
     println!(">> Measurement example start.");
 
+    // Figures of the circuits are written as SVG images and LaTeX documents to the figures directory
+    let figures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("figures");
+
     // #Measuring a single qubit
     let mut state_init = Circuit::new();
     state_init += operations::Hadamard::new(0); //prepare |+> state
@@ -38,9 +55,10 @@ pub fn main() {
 
     // put each step of the circuit together
     let mut circuit = state_init + read_input + meas_circ + read_output;
+    figure::write_figures(&circuit, &figures, "measurement_z_basis").unwrap();
 
     // run the circuit and collect output
-    let mut backend = roqoqo_quest::Backend::new(1);
+    let mut backend = Backend::new(1);
     let mut result_of_run = backend.run_circuit(&circuit);
     let (result_bit_registers, _result_float_registers, result_complex_registers) =
         result_of_run.unwrap();
@@ -90,9 +108,10 @@ pub fn main() {
         operations::PragmaGetStateVector::new("psi_out".to_string(), Some(Circuit::new()));
 
     circuit = state_init + read_input + meas_circ + read_output;
+    figure::write_figures(&circuit, &figures, "measurement_x_basis").unwrap();
 
     // run the circuit and collect output
-    backend = roqoqo_quest::Backend::new(number_of_qubits);
+    backend = Backend::new(number_of_qubits);
     result_of_run = backend.run_circuit(&circuit);
     let (result_bit_registers, _result_float_registers, result_complex_registers) =
         result_of_run.unwrap_or_default();
@@ -141,9 +160,10 @@ pub fn main() {
         operations::PragmaGetStateVector::new("psi_out".to_string(), Some(Circuit::new()));
 
     circuit = state_init + read_input + meas_circ + read_output;
+    figure::write_figures(&circuit, &figures, "measurement_multi_qubit_register").unwrap();
 
     // run the circuit and collect output
-    backend = roqoqo_quest::Backend::new(number_of_qubits);
+    backend = Backend::new(number_of_qubits);
     result_of_run = backend.run_circuit(&circuit);
     let (result_bit_registers, _result_float_registers, result_complex_registers) =
         result_of_run.unwrap_or_default();
@@ -156,5 +176,35 @@ pub fn main() {
         result_complex_registers["psi_out"]
     );
 
+    // Decomposing the multi-qubit circuit into the gates of a device
+    // The Hadamard, PauliX and SGate gates become RotateZ and SqrtPauliX gates, measurements and readouts are kept
+    let device = AllToAllDevice::new(
+        number_of_qubits,
+        &["RotateZ".to_string(), "SqrtPauliX".to_string()],
+        &["CNOT".to_string()],
+        1.0,
+    );
+    let decomposed = decomposition::decompose_for_device(&circuit, &device).unwrap();
+    assert!(unitary::circuits_equivalent(&circuit, &decomposed).unwrap());
+    let (_, _, decomposed_complex_registers) = Backend::new(number_of_qubits)
+        .run_circuit(&decomposed)
+        .unwrap();
+    // The decomposition only agrees up to a global phase
+    assert!(feed_forward::states_equal_up_to_phase(
+        &result_complex_registers["psi_in"][0],
+        &decomposed_complex_registers["psi_in"][0],
+        1e-10
+    ));
+    println!(
+        "> Circuit decomposed into RotateZ, SqrtPauliX and CNOT gates:\n{}",
+        diagram::circuit_diagram(&decomposed, DiagramStyle::Unicode)
+    );
+    println!(
+        "Gate counts before and after decomposition: {:?} -> {:?}\n",
+        decomposition::gate_counts(&circuit),
+        decomposition::gate_counts(&decomposed)
+    );
+
+    println!("Circuit figures written to {}", figures.display());
     println!(">> Measurement example end.");
 }
@@ -11,12 +11,30 @@
 // limitations under the License.
 
 use core::f64::consts::PI as Pi;
+use examples_support::backend::Backend;
+use examples_support::density_matrix::DensityMatrixBackend;
+use examples_support::diagram::{circuit_diagram, DiagramStyle};
+use examples_support::stabilizer::StabilizerBackend;
+use examples_support::{decomposition, feed_forward, figure, noise, qasm, schedule, seed, unitary};
+use num_complex::Complex64;
 use qoqo_calculator::CalculatorFloat;
 use roqoqo::backends::EvaluatingBackend;
+use roqoqo::devices::{AllToAllDevice, Device};
+use roqoqo::noise_models::{ContinuousDecoherenceModel, DecoherenceOnIdleModel};
 use roqoqo::{operations as ops, Circuit};
-use roqoqo_quest::Backend;
+use std::collections::HashMap;
 
 pub fn main() {
+    // The global seed of the sampled shots, see examples_support::seed
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    match seed::parse_seed(&arguments, std::env::var(seed::SEED_VARIABLE).ok()) {
+        Ok(global_seed) => seed::set_global_seed(global_seed),
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            std::process::exit(2)
+        }
+    }
+
     println!(">> Teleportation example start.");
 
     fn prep_psi(angle_thet: CalculatorFloat, angle_phi: CalculatorFloat) -> Circuit {
@@ -74,6 +92,20 @@ pub fn main() {
         + conditional_circ
         + verification;
 
+    // The wire diagram shows the three qubits and the classical register M1M2. The measurements write into M1M2
+    // and the frames around the conditional Pauli operations are linked to the bit they depend on.
+
+    println!(
+        "Teleportation circuit:\n{}",
+        circuit_diagram(&teleportation_circuit, DiagramStyle::Unicode)
+    );
+
+    // The same circuit is written as an SVG image and as a LaTeX document using the quantikz package
+
+    let figures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("figures");
+    figure::write_figures(&teleportation_circuit, &figures, "teleportation").unwrap();
+    println!("Circuit figures written to {}", figures.display());
+
     let backend = Backend::new(3);
     let result_of_run = backend.run_circuit(&teleportation_circuit);
     let (result_bit_registers, _result_float_registers, result_complex_registers) =
@@ -85,5 +117,217 @@ pub fn main() {
         result_complex_registers["psi"]
     );
 
+    // Stabilizer simulation
+
+    // Preparing |1> with RotateY(pi) and all other gates of the protocol are Clifford gates, so the teleportation can
+    // run on the stabilizer backend of examples_support as well. It has no state vector, instead the expectation value
+    // of Z on qubit 2 is read out with PragmaGetPauliProduct. It is -1 for the received state |1> in every run,
+    // whatever the measured bits are.
+
+    let mut pauli_readout = Circuit::new();
+    pauli_readout += ops::DefinitionFloat::new("z2".to_string(), 1, true);
+    pauli_readout +=
+        ops::PragmaGetPauliProduct::new(HashMap::from([(2, 3)]), "z2".to_string(), Circuit::new());
+    let clifford_teleportation = teleportation_circuit
+        .iter()
+        .filter(|operation| {
+            !matches!(
+                operation,
+                ops::Operation::DefinitionComplex(_) | ops::Operation::PragmaGetStateVector(_)
+            )
+        })
+        .cloned()
+        .collect::<Circuit>()
+        + pauli_readout;
+    let stabilizer_backend = StabilizerBackend::new(3);
+    for _ in 0..4 {
+        let (bit_registers, float_registers, _) = stabilizer_backend
+            .run_circuit(&clifford_teleportation)
+            .unwrap();
+        println!(
+            "Stabilizer simulation: measured {:?}, <Z> of the received qubit {}",
+            bit_registers["M1M2"][0], float_registers["z2"][0][0]
+        );
+        assert_eq!(float_registers["z2"][0][0], -1.0);
+    }
+
+    // Decomposing into the gates of a device
+
+    // The device only offers RotateZ, RotateX and ControlledPauliZ gates. The gates inside the conditional operations
+    // are decomposed as well. For every measurement outcome the decomposed circuit teleports the same state, up to a
+    // global phase.
+
+    let device = AllToAllDevice::new(
+        3,
+        &["RotateZ".to_string(), "RotateX".to_string()],
+        &["ControlledPauliZ".to_string()],
+        1.0,
+    );
+    let decomposed = decomposition::decompose_for_device(&teleportation_circuit, &device).unwrap();
+    assert!(unitary::circuits_equivalent(&teleportation_circuit, &decomposed).unwrap());
+    let branches = feed_forward::measurement_branches(&teleportation_circuit, 3).unwrap();
+    let decomposed_branches = feed_forward::measurement_branches(&decomposed, 3).unwrap();
+    assert_eq!(branches.len(), decomposed_branches.len());
+    for (branch, decomposed_branch) in branches.iter().zip(decomposed_branches.iter()) {
+        assert_eq!(branch.bit_registers, decomposed_branch.bit_registers);
+        assert!((branch.probability - decomposed_branch.probability).abs() < 1e-10);
+        assert!(feed_forward::states_equal_up_to_phase(
+            branch.state_vector.as_slice(),
+            decomposed_branch.state_vector.as_slice(),
+            1e-10
+        ));
+    }
+    println!(
+        "Teleportation circuit with RotateZ, RotateX and ControlledPauliZ gates:\n{}",
+        circuit_diagram(&decomposed, DiagramStyle::Unicode)
+    );
+    println!("Gate counts: {:?}", decomposition::gate_counts(&decomposed));
+
+    // Timing on the device
+
+    // The device gets gate times in microseconds, as set in the Devices notebook. RotateZ rotations are fast, the
+    // ControlledPauliZ gate between qubit 1 and qubit 2, which belong to different parties, is slower than the one
+    // between qubits 0 and 1. All qubits are damped and dephased, the receiving qubit 2 twice as strongly. The schedule
+    // starts every operation as soon as its qubits are free, the conditional corrections have to wait for the
+    // measurements. The report lists how long every qubit works, how long it waits, and how much it decoheres.
+
+    let mut device = device
+        .set_all_single_qubit_gate_times("RotateZ", 0.02)
+        .set_all_single_qubit_gate_times("RotateX", 0.05)
+        .set_all_two_qubit_gate_times("ControlledPauliZ", 0.3)
+        .add_damping_all(1.0 / 80.0)
+        .add_dephasing_all(1.0 / 120.0);
+    device
+        .set_two_qubit_gate_time("ControlledPauliZ", 1, 2, 0.5)
+        .unwrap();
+    device
+        .set_two_qubit_gate_time("ControlledPauliZ", 2, 1, 0.5)
+        .unwrap();
+    let receiver_rates = device.qubit_decoherence_rates(&2).unwrap() * 2.0;
+    device
+        .set_qubit_decoherence_rates(2, receiver_rates)
+        .unwrap();
+    let measurement_time = 1.0;
+    let timing = schedule::schedule_circuit(&decomposed, &device, measurement_time).unwrap();
+    println!(
+        "Timing of the decomposed teleportation circuit:\n{}",
+        timing
+    );
+
+    // Along the critical path every operation starts when the previous one ends, the corrections wait for both
+    // measurements
+
+    let mut time = 0.0;
+    for index in timing.critical_path.iter() {
+        assert!((timing.operations[*index].start - time).abs() < 1e-12);
+        time = timing.operations[*index].end();
+    }
+    assert!(timing
+        .qubits
+        .iter()
+        .all(|qubit| qubit.lifetime <= timing.duration()));
+    assert!(timing.qubits[2].coherence_loss() > timing.qubits[0].coherence_loss());
+    assert!(timing.duration() > 2.0 * measurement_time);
+
+    // Noisy teleportation
+
+    // The noise models describe the same damping and dephasing as the device. The qubits of a gate decohere with the
+    // continuous model and all other qubits with the idle model while the gate is executed. After adding the noise the
+    // circuit is simulated with a density matrix, which replaces the state vector readout. The teleported state |1>
+    // is received with the probability of qubit 2 being in |1>.
+    //
+    // The density-matrix backend of examples_support follows both outcomes of every measurement, so the mean fidelity
+    // is the exact average over the four branches instead of an estimate from random runs. A QuEST run follows one
+    // random branch, and its density matrix has to agree with the branch of the same measured bits.
+
+    let rates = |qubit: usize| if qubit == 2 { 2.0 } else { 1.0 };
+    let mut continuous = ContinuousDecoherenceModel::new();
+    let mut idle = DecoherenceOnIdleModel::new();
+    for qubit in 0..3 {
+        continuous = continuous
+            .add_damping_rate(&[qubit], rates(qubit) / 80.0)
+            .add_dephasing_rate(&[qubit], rates(qubit) / 120.0);
+        idle = idle
+            .add_damping_rate(&[qubit], rates(qubit) / 80.0)
+            .add_dephasing_rate(&[qubit], rates(qubit) / 120.0);
+    }
+    let mut noisy = noise::add_noise(&decomposed, &device, &[continuous.into(), idle.into()])
+        .unwrap()
+        .iter()
+        .filter(|operation| {
+            !matches!(
+                operation,
+                ops::Operation::DefinitionComplex(_) | ops::Operation::PragmaGetStateVector(_)
+            )
+        })
+        .cloned()
+        .collect::<Circuit>();
+    noisy += ops::DefinitionComplex::new("rho".to_string(), 64, true);
+    noisy += ops::PragmaGetDensityMatrix::new("rho".to_string(), None);
+    let fidelity = |rho: &[Complex64]| (4..8).map(|index| rho[index * 8 + index].re).sum::<f64>();
+    let branches = DensityMatrixBackend::new(3).branches(&noisy).unwrap();
+    assert_eq!(branches.len(), 4);
+    assert!(
+        (branches
+            .iter()
+            .map(|branch| branch.probability)
+            .sum::<f64>()
+            - 1.0)
+            .abs()
+            < 1e-12
+    );
+    for branch in branches.iter() {
+        println!(
+            "Noisy teleportation: measured {:?} with probability {:.5}, fidelity {:.5}",
+            branch.bit_registers["M1M2"],
+            branch.probability,
+            fidelity(&branch.complex_registers["rho"])
+        );
+    }
+    let mean_fidelity: f64 = branches
+        .iter()
+        .map(|branch| branch.probability * fidelity(&branch.complex_registers["rho"]))
+        .sum();
+    println!(
+        "Noisy teleportation: mean fidelity {:.5} of the received state, estimated coherence {:.5}",
+        mean_fidelity,
+        timing.estimated_coherence()
+    );
+    assert!(branches.iter().all(|branch| {
+        let fidelity = fidelity(&branch.complex_registers["rho"]);
+        fidelity < 1.0 && fidelity > 0.95
+    }));
+
+    for _ in 0..4 {
+        let (bit_registers, _, complex_registers) = backend.run_circuit(&noisy).unwrap();
+        let branch = branches
+            .iter()
+            .find(|branch| branch.bit_registers["M1M2"] == bit_registers["M1M2"][0])
+            .unwrap();
+        let difference = complex_registers["rho"][0]
+            .iter()
+            .zip(branch.complex_registers["rho"].iter())
+            .map(|(quest, rust)| (quest - rust).norm())
+            .fold(0.0, f64::max);
+        assert!(difference < 1e-10);
+    }
+
+    // Exporting to OpenQASM
+
+    // The conditional operations become classically controlled `if` statements. The complex register and
+    // PragmaGetStateVector only exist in simulations, they are omitted and listed as comments.
+    // Parsing the QASM string gives back the representable part of the teleportation circuit.
+
+    for qasm_version in ["2.0", "3.0"] {
+        let qasm_string = qasm::circuit_to_qasm(&teleportation_circuit, qasm_version).unwrap();
+        println!("OpenQASM {}:\n{}", qasm_version, qasm_string);
+        let circuit_from_qasm = qasm::qasm_to_circuit(&qasm_string).unwrap();
+        assert!(unitary::circuits_equivalent(
+            &qasm::qasm_representable(&teleportation_circuit),
+            &circuit_from_qasm
+        )
+        .unwrap());
+    }
+
     println!(">> Teleportation example end.");
 }
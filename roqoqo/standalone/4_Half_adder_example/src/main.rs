@@ -1,8 +1,17 @@
 use core::f64::consts::PI as Pi;
+use examples_support::backend::Backend;
+use examples_support::diagram::{circuit_diagram, DiagramStyle};
+use examples_support::readout::{self, ReadoutCalibration};
+use examples_support::stabilizer::StabilizerBackend;
+use examples_support::{decomposition, figure, noise, qasm, schedule, seed, unitary};
 use qoqo_calculator::CalculatorFloat;
 use roqoqo::backends::EvaluatingBackend;
+use roqoqo::devices::AllToAllDevice;
+use roqoqo::noise_models::{
+    ContinuousDecoherenceModel, DecoherenceOnGateModel, DecoherenceOnIdleModel,
+    ImperfectReadoutModel, NoiseModel,
+};
 use roqoqo::{operations as ops, Circuit};
-use roqoqo_quest::Backend;
 
 // In this example we write a quantum algorithm to perform an add operation between two qubits and store the result in two qubits
 // that act as output registers.
@@ -11,7 +20,20 @@ use roqoqo_quest::Backend;
 // if the result is measured immediately. A half adder implementation without an extra phase would require a decomposition of the Toffoli gate
 // which requires more than three CNOT gates.
 
+/// The seed of the readout errors when no global seed is set, see `examples_support::seed::rng_or`.
+const READOUT_ERROR_SEED: u64 = 39;
+
 fn main() {
+    // The global seed of the sampled shots, see examples_support::seed
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    match seed::parse_seed(&arguments, std::env::var(seed::SEED_VARIABLE).ok()) {
+        Ok(global_seed) => seed::set_global_seed(global_seed),
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            std::process::exit(2)
+        }
+    }
+
     println!(">> Half adder example start.");
 
     // We define a circuit that generates the main block of the algorithm
@@ -59,6 +81,32 @@ fn main() {
 
     println!("Prepared circuit: {}", half_adder);
 
+    // The wire diagram shows which qubits the gates of the main block act on.
+
+    println!(
+        "Half adder circuit:\n{}",
+        circuit_diagram(&half_adder, DiagramStyle::Unicode)
+    );
+
+    // The circuit is also written as an SVG image and as a LaTeX document using the quantikz package
+
+    let figures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("figures");
+    figure::write_figures(&half_adder, &figures, "half_adder").unwrap();
+
+    // The circuit can be exported to OpenQASM to run it on other platforms. `GetStateVector` has no QASM counterpart and
+    // is listed as a comment. Parsing the QASM string gives back a circuit with the same unitary.
+
+    for qasm_version in ["2.0", "3.0"] {
+        let qasm_string = qasm::circuit_to_qasm(&half_adder, qasm_version).unwrap();
+        println!("OpenQASM {}:\n{}", qasm_version, qasm_string);
+        let circuit_from_qasm = qasm::qasm_to_circuit(&qasm_string).unwrap();
+        assert!(unitary::circuits_equivalent(
+            &qasm::qasm_representable(&half_adder),
+            &circuit_from_qasm
+        )
+        .unwrap());
+    }
+
     // The two `X` operations at the beginning are used to generate the input, in this case both input qubits are set to `1`.
 
     // We simulate the half adder using `qoqo_quest`. Running the circuit in the backend returns a tuple with entries
@@ -100,6 +148,13 @@ fn main() {
     half_adder += ops::MeasureQubit::new(3, "ro".to_string(), 1);
     half_adder += ops::PragmaSetNumberOfMeasurements::new(number_of_measurements, "ro".to_string());
 
+    println!(
+        "Half adder circuit with measurements:\n{}",
+        circuit_diagram(&half_adder, DiagramStyle::Unicode)
+    );
+    figure::write_figures(&half_adder, &figures, "half_adder_measurement").unwrap();
+    println!("Circuit figures written to {}", figures.display());
+
     let backend = Backend::new(4);
     let result_of_run = backend.run_circuit(&half_adder);
     let (result_bit_registers, _result_float_registers, _result_complex_registers) =
@@ -107,5 +162,251 @@ fn main() {
 
     println!("Result bit registers :{:?}", result_bit_registers["ro"][0]);
 
+    // STABILIZER SIMULATION
+
+    // The stabilizer backend of examples_support simulates circuits of Clifford gates with hundreds of qubits. The
+    // RotateY(pi/4) rotations of the half adder are not Clifford gates, so the backend refuses the circuit.
+
+    let error = StabilizerBackend::new(4)
+        .run_circuit(&half_adder)
+        .unwrap_err();
+    println!("Stabilizer simulation of the half adder: {}", error);
+    assert!(error.to_string().contains("RotateY"));
+
+    // CHECKING THE EXTRA PHASE
+
+    // Without the extra phase the main block would be a reversible classical function: it adds `q0 XOR q1` to qubit 2
+    // and `q0 AND q1` to qubit 3. We compare the unitary of the main block with the permutation matrix of this function.
+    // The differing matrix elements only differ in their sign, so measured populations are the same. The sign only appears
+    // for input states where the output qubits are not initialized to `0`, the half adder itself is exact.
+
+    let ideal_half_adder = unitary::permutation_unitary(4, |input| {
+        let (a, b) = (input & 1, (input >> 1) & 1);
+        input ^ ((a ^ b) << 2) ^ ((a & b) << 3)
+    });
+    let comparison = unitary::compare_unitaries(
+        &unitary::circuit_unitary(&half_adder_main_block(), 4).unwrap(),
+        &ideal_half_adder,
+        unitary::TOLERANCE,
+    )
+    .unwrap();
+    println!(
+        "Main block compared to the ideal half adder: {}",
+        comparison
+    );
+    assert!(!comparison.is_equivalent());
+    assert!(comparison
+        .differences
+        .iter()
+        .all(|difference| difference.is_phase_only(unitary::TOLERANCE)
+            && difference.column & 0b1100 != 0));
+
+    // DECOMPOSING INTO DEVICE GATES
+
+    // A device only executes its native gates. We decompose the main block for a device with RotateZ, SqrtPauliX and CNOT
+    // gates and check that the unitary does not change. The exact half adder without the extra phase uses a Toffoli
+    // gate for the carry. Its decomposition needs six CNOT gates instead of three, but it is equal to the ideal half
+    // adder up to a global phase. With ControlledPauliZ as the only two-qubit gate every CNOT gets wrapped in Hadamard
+    // gates, which are decomposed further.
+
+    let device = AllToAllDevice::new(
+        4,
+        &["RotateZ".to_string(), "SqrtPauliX".to_string()],
+        &["CNOT".to_string()],
+        1.0,
+    );
+    let controlled_z_device = AllToAllDevice::new(
+        4,
+        &["RotateZ".to_string(), "SqrtPauliX".to_string()],
+        &["ControlledPauliZ".to_string()],
+        1.0,
+    );
+    let mut exact_half_adder = Circuit::new();
+    exact_half_adder += ops::CNOT::new(0, 2);
+    exact_half_adder += ops::CNOT::new(1, 2);
+    exact_half_adder += ops::Toffoli::new(0, 1, 3);
+    for (name, target) in [
+        ("CNOT", &device),
+        ("ControlledPauliZ", &controlled_z_device),
+    ] {
+        let decomposed_main_block =
+            decomposition::decompose_for_device(&half_adder_main_block(), target).unwrap();
+        assert!(unitary::compare_circuit_unitaries(
+            &half_adder_main_block(),
+            &decomposed_main_block
+        )
+        .unwrap()
+        .is_equivalent());
+        let decomposed_exact =
+            decomposition::decompose_for_device(&exact_half_adder, target).unwrap();
+        assert!(unitary::compare_unitaries(
+            &unitary::circuit_unitary(&decomposed_exact, 4).unwrap(),
+            &ideal_half_adder,
+            unitary::TOLERANCE,
+        )
+        .unwrap()
+        .is_equivalent());
+        println!(
+            "Gate counts with RotateZ, SqrtPauliX and {}: main block {:?}, exact half adder {:?}",
+            name,
+            decomposition::gate_counts(&decomposed_main_block),
+            decomposition::gate_counts(&decomposed_exact)
+        );
+    }
+
+    // TIMING ON THE DEVICE
+
+    // With gate times and decoherence rates for the device we can estimate how long the circuits run and how much the
+    // qubits decohere. The CNOT gates dominate the duration, so the exact half adder with its longer Toffoli
+    // decomposition takes more time and loses more coherence than the main block with the extra phase.
+
+    let timed_device = device
+        .set_all_single_qubit_gate_times("RotateZ", 0.02)
+        .set_all_single_qubit_gate_times("SqrtPauliX", 0.04)
+        .set_all_two_qubit_gate_times("CNOT", 0.25)
+        .add_damping_all(1.0 / 80.0)
+        .add_dephasing_all(1.0 / 120.0);
+    let measurement_time = 1.0;
+    let mut durations = Vec::new();
+    let mut decomposed_circuits = Vec::new();
+    for (name, circuit) in [
+        ("main block", half_adder_main_block()),
+        ("exact half adder", exact_half_adder),
+    ] {
+        let decomposed = decomposition::decompose_for_device(&circuit, &timed_device).unwrap();
+        let timing =
+            schedule::schedule_circuit(&decomposed, &timed_device, measurement_time).unwrap();
+        println!("Timing of the decomposed {}:\n{}", name, timing);
+        durations.push((timing.duration(), timing.estimated_coherence()));
+        decomposed_circuits.push((name, decomposed));
+    }
+    assert!(durations[0].0 < durations[1].0);
+    assert!(durations[0].1 > durations[1].1);
+
+    // SIMULATING NOISE
+
+    // The noise models add the decoherence of the device to the decomposed circuits: the qubits of a gate decohere with
+    // the continuous model, all other qubits with the idle model, and every CNOT gate depolarises its two qubits. QuEST
+    // runs the noisy circuits as density matrices. For the input `|11>` the half adder has to produce `|1011>`, the
+    // probability of this result shows how much the noise affects the two versions of the half adder.
+
+    let qubits = [0, 1, 2, 3];
+    let continuous = ContinuousDecoherenceModel::new()
+        .add_damping_rate(&qubits, 1.0 / 80.0)
+        .add_dephasing_rate(&qubits, 1.0 / 120.0);
+    let idle = DecoherenceOnIdleModel::new()
+        .add_damping_rate(&qubits, 1.0 / 80.0)
+        .add_dephasing_rate(&qubits, 1.0 / 120.0);
+    let mut on_gate = DecoherenceOnGateModel::new();
+    for control in qubits {
+        for target in qubits.into_iter().filter(|target| *target != control) {
+            let cnot_noise = ContinuousDecoherenceModel::new()
+                .add_depolarising_rate(&[control, target], 0.02)
+                .lindblad_noise;
+            on_gate = on_gate.set_two_qubit_gate_error("CNOT", control, target, cnot_noise);
+        }
+    }
+    let noise_models: Vec<NoiseModel> = vec![continuous.into(), idle.into(), on_gate.into()];
+    let mut probabilities = Vec::new();
+    for (name, decomposed) in decomposed_circuits {
+        let mut input = Circuit::new();
+        input += ops::PauliX::new(0);
+        input += ops::PauliX::new(1);
+        let circuit =
+            decomposition::decompose_for_device(&input, &timed_device).unwrap() + decomposed;
+        let mut noisy = noise::add_noise(&circuit, &timed_device, &noise_models).unwrap();
+        noisy += ops::DefinitionComplex::new("rho".to_string(), 256, true);
+        noisy += ops::PragmaGetDensityMatrix::new("rho".to_string(), None);
+        let (_, _, complex_registers) = Backend::new(4).run_circuit(&noisy).unwrap();
+        let probability = complex_registers["rho"][0][0b1011 * 16 + 0b1011].re;
+        println!(
+            "Noisy {}: probability {:.5} of the correct result |1011>",
+            name, probability
+        );
+        probabilities.push(probability);
+    }
+    assert!(probabilities
+        .iter()
+        .all(|probability| *probability > 0.8 && *probability < 1.0));
+    assert!(probabilities[0] > probabilities[1]);
+
+    // READOUT ERRORS
+
+    // The simulator measures perfectly. On a device the readout of a qubit sometimes returns the wrong value.
+    // We use an `ImperfectReadoutModel` where every qubit reads 0 as 1 with a probability of 3% and 1 as 0 with a
+    // probability of 8%, and flip the bits of the simulated shots accordingly.
+    // The exact probabilities of (carry, sum) are 1/4 for 00, 1/2 for 01 (sum 1), 1/4 for 10 (carry 1) and 0 for 11,
+    // with the sum measured into bit 0 and the carry into bit 1 of the readout.
+
+    let readout_model = ImperfectReadoutModel::new_with_uniform_error(4, 0.03, 0.08).unwrap();
+    let measured_qubits = [2, 3];
+    let number_of_shots: usize = 20000;
+    let mut rng = seed::rng_or(READOUT_ERROR_SEED);
+
+    let mut readout_circuit: Circuit = half_adder
+        .iter()
+        .filter(|operation| !matches!(operation, ops::Operation::PragmaSetNumberOfMeasurements(_)))
+        .cloned()
+        .collect();
+    readout_circuit += ops::PragmaSetNumberOfMeasurements::new(number_of_shots, "ro".to_string());
+    let (result_bit_registers, _, _) = backend.run_circuit(&readout_circuit).unwrap();
+    let noisy_register = readout::apply_readout_errors(
+        &result_bit_registers["ro"],
+        &measured_qubits,
+        &readout_model,
+        &mut rng,
+    );
+
+    // To mitigate the errors we measure calibration circuits that prepare each basis state of the output qubits.
+    // The calibration matrix holds the probability of every readout for every prepared state. The measured
+    // probabilities are corrected with its inverse or with iterative Bayesian unfolding.
+
+    let calibration_registers: Vec<_> =
+        readout::calibration_circuits(&measured_qubits, "ro", number_of_shots)
+            .iter()
+            .map(|circuit| {
+                let (bit_registers, _, _) = backend.run_circuit(circuit).unwrap();
+                readout::apply_readout_errors(
+                    &bit_registers["ro"],
+                    &measured_qubits,
+                    &readout_model,
+                    &mut rng,
+                )
+            })
+            .collect();
+    let calibration = ReadoutCalibration::from_registers(&calibration_registers).unwrap();
+    let exact_calibration = ReadoutCalibration::from_model(&readout_model, &measured_qubits);
+    println!("Measured calibration matrix:{}", calibration.matrix);
+    println!(
+        "Calibration matrix of the model:{}",
+        exact_calibration.matrix
+    );
+
+    let exact = [0.25, 0.5, 0.25, 0.0];
+    let raw = readout::outcome_probabilities(&noisy_register, 2);
+    let inverse = calibration.mitigate_inverse(&raw).unwrap();
+    let bayesian = calibration.mitigate_bayesian(&raw, 100).unwrap();
+    println!(
+        "Half adder readout (carry, sum):\n{}",
+        readout::format_histograms(
+            &[
+                ("exact", &exact),
+                ("raw", &raw),
+                ("inverse", &inverse),
+                ("bayesian", &bayesian)
+            ],
+            2
+        )
+    );
+    let raw_distance = readout::total_variation_distance(&raw, &exact);
+    let inverse_distance = readout::total_variation_distance(&inverse, &exact);
+    let bayesian_distance = readout::total_variation_distance(&bayesian, &exact);
+    println!(
+        "Total variation distance to the exact probabilities: raw {:.4}, inverse {:.4}, bayesian {:.4}",
+        raw_distance, inverse_distance, bayesian_distance
+    );
+    assert!(inverse_distance < raw_distance);
+    assert!(bayesian_distance < raw_distance);
+
     println!(">> Half adder example end.");
 }
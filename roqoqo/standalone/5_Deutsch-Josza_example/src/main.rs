@@ -10,11 +10,34 @@
 // express or implied. See the License for the specific language governing permissions and
 // limitations under the License.
 
+use examples_support::backend::Backend;
+use examples_support::diagram::{circuit_diagram, DiagramStyle};
+use examples_support::readout::{self, ReadoutCalibration};
+use examples_support::stabilizer::StabilizerBackend;
+use examples_support::{decomposition, figure, qasm, routing, seed, unitary};
+use nalgebra::DMatrix;
+use num_complex::Complex64;
 use roqoqo::backends::EvaluatingBackend;
-use roqoqo::{operations as ops, Circuit};
-use roqoqo_quest::Backend;
+use roqoqo::devices::{Device, SquareLatticeDevice};
+use roqoqo::noise_models::ImperfectReadoutModel;
+use roqoqo::operations::{self as ops, InvolveQubits, InvolvedQubits};
+use roqoqo::Circuit;
+use std::time::Instant;
+
+/// The seed of the readout errors when no global seed is set, see `examples_support::seed::rng_or`.
+const READOUT_ERROR_SEED: u64 = 39;
 
 fn main() {
+    // The global seed of the sampled shots, see examples_support::seed
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    match seed::parse_seed(&arguments, std::env::var(seed::SEED_VARIABLE).ok()) {
+        Ok(global_seed) => seed::set_global_seed(global_seed),
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            std::process::exit(2)
+        }
+    }
+
     println!(">> Deutsch-Josza example start.");
 
     // We have given a function `f: {0, 1}^n->{0, 1}` from input bitstrings with length `n`, *e.g.*, `00110`, to a single bit output.
@@ -77,6 +100,49 @@ fn main() {
         oracle
     }
 
+    //  CHECKING THE ORACLES
+    // An oracle for `f` maps the basis state `|x>|y>` to `|x>|y oplus f(x)>`, where `y` is stored in the output qubit
+    // `number_qubits`. This is a permutation of the basis states, so we can build the expected unitary and compare it with
+    // the unitary of the oracle circuit. The balanced oracle computes the parity of the input bits, the constant oracle `f(x) = 1`.
+
+    fn oracle_unitary(number_qubits: usize, f: impl Fn(usize) -> bool) -> DMatrix<Complex64> {
+        let input_mask = (1 << number_qubits) - 1;
+        unitary::permutation_unitary(number_qubits + 1, |state| {
+            state ^ (usize::from(f(state & input_mask)) << number_qubits)
+        })
+    }
+
+    for number_qubits in 1..=4 {
+        let parity = |x: usize| x.count_ones() % 2 == 1;
+        let comparison = unitary::compare_unitaries(
+            &unitary::circuit_unitary(&balanced_oracle(number_qubits), number_qubits + 1).unwrap(),
+            &oracle_unitary(number_qubits, parity),
+            unitary::TOLERANCE,
+        )
+        .unwrap();
+        assert!(comparison.is_equivalent(), "{}", comparison);
+        let comparison = unitary::compare_unitaries(
+            &unitary::circuit_unitary(&constant_oracle(number_qubits), number_qubits + 1).unwrap(),
+            &oracle_unitary(number_qubits, |_| true),
+            unitary::TOLERANCE,
+        )
+        .unwrap();
+        assert!(comparison.is_equivalent(), "{}", comparison);
+    }
+
+    // Comparing the balanced oracle with the constant function lists the matrix elements in which the two oracles differ.
+
+    let comparison = unitary::compare_unitaries(
+        &unitary::circuit_unitary(&balanced_oracle(2), 3).unwrap(),
+        &oracle_unitary(2, |_| true),
+        unitary::TOLERANCE,
+    )
+    .unwrap();
+    println!(
+        "Balanced oracle compared to the constant function: {}",
+        comparison
+    );
+
     // To finalize the circuit, we define a measurement circuit that:
     //  - applies a Hadamard gate to the first two qubits
     //  - defines a bit register
@@ -104,10 +170,97 @@ fn main() {
     }
     println!("{:?}", constant);
 
+    // Figures of both circuits are written as SVG images and LaTeX documents using the quantikz package
+
+    let figures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("figures");
+    figure::write_figures(&balanced, &figures, "deutsch_josza_balanced").unwrap();
+    figure::write_figures(&constant, &figures, "deutsch_josza_constant").unwrap();
+    println!("Circuit figures written to {}", figures.display());
+
+    //  EXPORT TO OPENQASM
+    // Both circuits can be exported to OpenQASM and parsed back into roqoqo circuits with the same gates and measurements.
+
+    for circuit in [&balanced, &constant] {
+        for qasm_version in ["2.0", "3.0"] {
+            let qasm_string = qasm::circuit_to_qasm(circuit, qasm_version).unwrap();
+            println!("OpenQASM {}:\n{}", qasm_version, qasm_string);
+            let circuit_from_qasm = qasm::qasm_to_circuit(&qasm_string).unwrap();
+            assert!(unitary::circuits_equivalent(circuit, &circuit_from_qasm).unwrap());
+        }
+    }
+
+    //  ROUTING ON A DEVICE
+    // On a real device two-qubit gates can only act on connected qubits. A `SquareLatticeDevice` with `rows` x `columns`
+    // qubits, as built in the Devices notebook, connects qubit `row * columns + column` only to its neighbours on the
+    // lattice. The balanced oracle applies a CNOT from every input qubit to the output qubit, so most of these gates act
+    // on qubits that are not connected. The routing pass inserts SWAP gates that move the qubits next to each other.
+    // Afterwards the Hadamard, PauliX and SWAP gates are decomposed into the RotateZ, RotateX and CNOT gates of the
+    // device.
+
+    for (rows, columns, number_inputs) in [(2, 2, 2), (2, 3, 5), (3, 3, 8)] {
+        let device = SquareLatticeDevice::new(
+            rows,
+            columns,
+            &["RotateZ".to_string(), "RotateX".to_string()],
+            &["CNOT".to_string()],
+            1.0,
+        );
+        let mut circuit = deutsch_josza_circuit(number_inputs, balanced_oracle(number_inputs));
+        circuit += ops::DefinitionBit::new("ro".to_string(), number_inputs, true);
+        for q in 0..number_inputs {
+            circuit += ops::MeasureQubit::new(q, "ro".to_string(), q);
+        }
+        let routed = routing::route_circuit(&circuit, &device).unwrap();
+        println!(
+            "Balanced Deutsch-Josza circuit with {} inputs on a {}x{} lattice: {}",
+            number_inputs, rows, columns, routed
+        );
+
+        // Every two-qubit gate of the routed circuit acts on connected qubits and the routed circuit is equivalent to
+        // the original circuit followed by moving the qubits to their final positions.
+
+        let edges = device.two_qubit_edges();
+        for operation in routed.circuit.iter() {
+            if let InvolvedQubits::Set(qubits) = operation.involved_qubits() {
+                if qubits.len() == 2 {
+                    let qubits: Vec<usize> = qubits.into_iter().collect();
+                    assert!(
+                        edges.contains(&(qubits[0], qubits[1]))
+                            || edges.contains(&(qubits[1], qubits[0]))
+                    );
+                }
+            }
+        }
+        let comparison = routing::compare_routed_circuit(&circuit, &routed).unwrap();
+        assert!(comparison.is_equivalent(), "{}", comparison);
+
+        let decomposed = routing::RoutedCircuit {
+            circuit: decomposition::decompose_for_device(&routed.circuit, &device).unwrap(),
+            ..routed.clone()
+        };
+        let comparison = routing::compare_routed_circuit(&circuit, &decomposed).unwrap();
+        assert!(comparison.is_equivalent(), "{}", comparison);
+        println!(
+            "Gate counts after decomposition: {:?}",
+            decomposition::gate_counts(&decomposed.circuit)
+        );
+
+        if number_inputs == number_qubits {
+            println!(
+                "Routed circuit:\n{}",
+                circuit_diagram(&routed.circuit, DiagramStyle::Unicode)
+            );
+            let backend = Backend::new(device.number_qubits());
+            let (result_bit_registers, _, _) = backend.run_circuit(&decomposed.circuit).unwrap();
+            assert!(!result_bit_registers["ro"][0].iter().all(|&bit| !bit));
+        }
+    }
+
     //  SIMULATION
 
-    // The algorithm is then tested on a (simulated) quantum computer. We use the `qoqo_quest` library to run the simulation and
-    // from this library we need the `Backend`.
+    // The algorithm is then tested on a (simulated) quantum computer. We use the `Backend` of `examples_support`, which is the
+    // `qoqo_quest` simulator or, built without the `quest` feature or run with a global seed, the state-vector simulator
+    // written in Rust.
     // A circuit can be simulated on the backend using `run_circuit`. The method returns a tuple.
     // The first entry of the tuple is a dictionary of BitRegisters. The result of `run_circuit` is saved into `res`,
     // we then access our registry via `res[0]['ro']`.
@@ -115,7 +268,7 @@ fn main() {
     // The following code runs the simulation for the balanced and the constant oracle.
 
     fn checking_constant(res: &[bool]) -> bool {
-        return res.iter().all(|&el| !el);
+        res.iter().all(|&el| !el)
     }
 
     let backend = Backend::new(number_qubits + 1);
@@ -146,4 +299,109 @@ fn main() {
     // This means that you can measure all bitstrings but `[False, False]`.
 
     // In conclusion, measuring '[False, False]' means that the function is constant whereas all other results mean that the function is balanced.
+
+    //  READOUT ERRORS
+    // On a device the readout of a qubit sometimes returns the wrong value. With an `ImperfectReadoutModel` the simulated
+    // shots are flipped like on a device, where every qubit reads 0 as 1 with a probability of 2% and 1 as 0 with a
+    // probability of 6%. Single shots of the constant oracle are then sometimes taken for a balanced function.
+    // The parity oracle is balanced with `f(x) = 1` for the input `11`, the interference leads to the outcome `11` only.
+    // We measure calibration circuits for every basis state of the input qubits and mitigate the measured probabilities
+    // with the inverse of the calibration matrix and with iterative Bayesian unfolding.
+
+    let readout_model =
+        ImperfectReadoutModel::new_with_uniform_error(number_qubits + 1, 0.02, 0.06).unwrap();
+    let measured_qubits: Vec<usize> = (0..number_qubits).collect();
+    let number_of_shots: usize = 10000;
+    let mut rng = seed::rng_or(READOUT_ERROR_SEED);
+    let backend = Backend::new(number_qubits + 1);
+
+    let calibration_registers: Vec<_> =
+        readout::calibration_circuits(&measured_qubits, "ro", number_of_shots)
+            .iter()
+            .map(|circuit| {
+                let (bit_registers, _, _) = backend.run_circuit(circuit).unwrap();
+                readout::apply_readout_errors(
+                    &bit_registers["ro"],
+                    &measured_qubits,
+                    &readout_model,
+                    &mut rng,
+                )
+            })
+            .collect();
+    let calibration = ReadoutCalibration::from_registers(&calibration_registers).unwrap();
+
+    for (name, circuit, exact_outcome) in [
+        ("balanced", &balanced, (1 << number_qubits) - 1),
+        ("constant", &constant, 0),
+    ] {
+        let circuit = circuit.clone()
+            + ops::PragmaSetNumberOfMeasurements::new(number_of_shots, "ro".to_string());
+        let (result_bit_registers, _, _) = backend.run_circuit(&circuit).unwrap();
+        let noisy_register = readout::apply_readout_errors(
+            &result_bit_registers["ro"],
+            &measured_qubits,
+            &readout_model,
+            &mut rng,
+        );
+        let wrong_decisions = noisy_register
+            .iter()
+            .filter(|shot| checking_constant(shot) != (exact_outcome == 0))
+            .count();
+
+        let mut exact = vec![0.0; 1 << number_qubits];
+        exact[exact_outcome] = 1.0;
+        let raw = readout::outcome_probabilities(&noisy_register, number_qubits);
+        let inverse = calibration.mitigate_inverse(&raw).unwrap();
+        let bayesian = calibration.mitigate_bayesian(&raw, 100).unwrap();
+        println!(
+            "Readout of the {} oracle, {} of {} shots give the wrong answer:\n{}",
+            name,
+            wrong_decisions,
+            number_of_shots,
+            readout::format_histograms(
+                &[
+                    ("exact", &exact),
+                    ("raw", &raw),
+                    ("inverse", &inverse),
+                    ("bayesian", &bayesian)
+                ],
+                number_qubits
+            )
+        );
+        let raw_distance = readout::total_variation_distance(&raw, &exact);
+        assert!(readout::total_variation_distance(&inverse, &exact) < raw_distance);
+        assert!(readout::total_variation_distance(&bayesian, &exact) < raw_distance);
+    }
+
+    //  LARGE INSTANCES
+    // The Deutsch-Josza circuits only contain Hadamard, PauliX and CNOT gates. These are Clifford gates, which map
+    // Pauli operators to Pauli operators, so the state can be stored as a stabilizer tableau instead of a state vector
+    // with 2^(n+1) entries. The stabilizer backend of examples_support simulates the algorithm with 500 input qubits in
+    // a fraction of a second. The balanced oracle computes the parity of the inputs, for which every input qubit is
+    // measured as 1, the constant oracle gives 0 on every input qubit.
+
+    let number_inputs = 500;
+    let backend = StabilizerBackend::new(number_inputs + 1);
+    for (name, oracle, expected) in [
+        ("balanced", balanced_oracle(number_inputs), true),
+        ("constant", constant_oracle(number_inputs), false),
+    ] {
+        let mut circuit = deutsch_josza_circuit(number_inputs, oracle);
+        circuit += ops::DefinitionBit::new("ro".to_string(), number_inputs, true);
+        for q in 0..number_inputs {
+            circuit += ops::MeasureQubit::new(q, "ro".to_string(), q);
+        }
+        let start = Instant::now();
+        let (result_bit_registers, _, _) = backend.run_circuit(&circuit).unwrap();
+        println!(
+            "Deutsch-Josza with the {} oracle on {} input qubits: is constant? {}, simulated in {:.3} s",
+            name,
+            number_inputs,
+            checking_constant(&result_bit_registers["ro"][0]),
+            start.elapsed().as_secs_f64()
+        );
+        assert!(result_bit_registers["ro"][0]
+            .iter()
+            .all(|&bit| bit == expected));
+    }
 }
@@ -0,0 +1,370 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A small simulator of Gaussian bosonic states.
+//!
+//! A Gaussian state of `n` modes is described by the vector of the mean quadratures and the `2n x 2n` covariance
+//! matrix, ordered as `(x_0, p_0, x_1, p_1, ...)`. Like Strawberry Fields we use `hbar = 2`, so the vacuum has the
+//! identity as covariance matrix. Squeezing, phase shifts and beam splitters act as symplectic matrices and
+//! displacements shift the mean quadratures, which is all that is needed to run the bosonic gates of roqoqo.
+//!
+//! Photon detection is not sampled. Instead, the simulator returns the mean and the variance of the photon number
+//! of every detected mode, which is what the averaged shots of a real or sampled run converge to.
+
+use nalgebra::{DMatrix, DVector};
+use qoqo_calculator::CalculatorFloat;
+use roqoqo::operations::*;
+use roqoqo::registers::FloatRegister;
+use roqoqo::{Circuit, RoqoqoBackendError};
+use std::collections::HashMap;
+
+/// The value of hbar in the quadrature convention of the simulator.
+pub const HBAR: f64 = 2.0;
+
+/// A Gaussian state of a fixed number of bosonic modes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GaussianState {
+    number_modes: usize,
+    means: DVector<f64>,
+    covariance: DMatrix<f64>,
+}
+
+impl GaussianState {
+    /// Creates the vacuum state of `number_modes` modes.
+    pub fn vacuum(number_modes: usize) -> Self {
+        GaussianState {
+            number_modes,
+            means: DVector::zeros(2 * number_modes),
+            covariance: DMatrix::identity(2 * number_modes, 2 * number_modes) * (HBAR / 2.0),
+        }
+    }
+
+    /// Returns the covariance matrix of the quadratures.
+    pub fn covariance(&self) -> &DMatrix<f64> {
+        &self.covariance
+    }
+
+    /// Applies a bosonic gate to the state.
+    ///
+    /// # Arguments
+    ///
+    /// * `operation` - The gate. Squeezing, PhaseShift, PhaseDisplacement and BeamSplitter are supported.
+    ///
+    /// # Returns
+    ///
+    /// * `Err(RoqoqoBackendError)` - The gate is not supported, has symbolic parameters or acts on a mode outside the state.
+    pub fn apply_operation(&mut self, operation: &Operation) -> Result<(), RoqoqoBackendError> {
+        match operation {
+            Operation::Squeezing(op) => {
+                let squeezing = float_value(op.squeezing())?;
+                let phase = float_value(op.phase())?;
+                let (sin, cos) = phase.sin_cos();
+                let symplectic = DMatrix::from_row_slice(
+                    2,
+                    2,
+                    &[
+                        squeezing.cosh() - cos * squeezing.sinh(),
+                        -sin * squeezing.sinh(),
+                        -sin * squeezing.sinh(),
+                        squeezing.cosh() + cos * squeezing.sinh(),
+                    ],
+                );
+                self.apply_symplectic(&[*op.mode()], &symplectic)
+            }
+            Operation::PhaseShift(op) => {
+                let (sin, cos) = float_value(op.phase())?.sin_cos();
+                let symplectic = DMatrix::from_row_slice(2, 2, &[cos, -sin, sin, cos]);
+                self.apply_symplectic(&[*op.mode()], &symplectic)
+            }
+            Operation::PhaseDisplacement(op) => {
+                let displacement = float_value(op.displacement())?;
+                let (sin, cos) = float_value(op.phase())?.sin_cos();
+                let mode = self.check_mode(*op.mode())?;
+                let scale = (2.0 * HBAR).sqrt() * displacement;
+                self.means[2 * mode] += scale * cos;
+                self.means[2 * mode + 1] += scale * sin;
+                Ok(())
+            }
+            Operation::BeamSplitter(op) => {
+                // The annihilation operators transform as a_0 -> t a_0 - r* a_1 and a_1 -> r a_0 + t a_1
+                // with t = cos(theta) and r = exp(i phi) sin(theta)
+                let (sin_theta, cos_theta) = float_value(op.theta())?.sin_cos();
+                let (sin_phi, cos_phi) = float_value(op.phi())?.sin_cos();
+                let (re_r, im_r) = (cos_phi * sin_theta, sin_phi * sin_theta);
+                let symplectic = DMatrix::from_row_slice(
+                    4,
+                    4,
+                    &[
+                        cos_theta, 0.0, -re_r, -im_r, //
+                        0.0, cos_theta, im_r, -re_r, //
+                        re_r, -im_r, cos_theta, 0.0, //
+                        im_r, re_r, 0.0, cos_theta,
+                    ],
+                );
+                self.apply_symplectic(&[*op.mode_0(), *op.mode_1()], &symplectic)
+            }
+            _ => Err(RoqoqoBackendError::OperationNotInBackend {
+                backend: "GaussianState",
+                hqslang: operation.hqslang(),
+            }),
+        }
+    }
+
+    /// Returns the mean photon number of a mode.
+    pub fn mean_photon_number(&self, mode: usize) -> f64 {
+        let (x, p) = (2 * mode, 2 * mode + 1);
+        (self.covariance[(x, x)]
+            + self.covariance[(p, p)]
+            + self.means[x].powi(2)
+            + self.means[p].powi(2))
+            / (2.0 * HBAR)
+            - 0.5
+    }
+
+    /// Returns the variance of the photon number of a mode.
+    pub fn photon_number_variance(&self, mode: usize) -> f64 {
+        let x = 2 * mode;
+        let covariance = self.covariance.fixed_view::<2, 2>(x, x);
+        let means = self.means.fixed_rows::<2>(x);
+        ((covariance * covariance).trace() / 2.0 + (means.transpose() * covariance * means)[0])
+            / HBAR.powi(2)
+            - 0.25
+    }
+
+    fn check_mode(&self, mode: usize) -> Result<usize, RoqoqoBackendError> {
+        if mode >= self.number_modes {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "Mode {} is outside of the {} simulated modes",
+                    mode, self.number_modes
+                ),
+            });
+        }
+        Ok(mode)
+    }
+
+    /// Applies the symplectic matrix of a gate acting on `modes` as `V -> S V S^T` and `d -> S d`.
+    fn apply_symplectic(
+        &mut self,
+        modes: &[usize],
+        symplectic: &DMatrix<f64>,
+    ) -> Result<(), RoqoqoBackendError> {
+        let mut full = DMatrix::identity(2 * self.number_modes, 2 * self.number_modes);
+        for (row, &row_mode) in modes.iter().enumerate() {
+            self.check_mode(row_mode)?;
+            for (column, &column_mode) in modes.iter().enumerate() {
+                for (i, j) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                    full[(2 * row_mode + i, 2 * column_mode + j)] =
+                        symplectic[(2 * row + i, 2 * column + j)];
+                }
+            }
+        }
+        self.covariance = &full * &self.covariance * full.transpose();
+        self.means = &full * &self.means;
+        Ok(())
+    }
+}
+
+/// The outcome of simulating a bosonic circuit with [simulate_circuit].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GaussianResult {
+    /// The Gaussian state before the photon detection.
+    pub state: GaussianState,
+    /// The mean photon numbers written to every readout register.
+    pub mean_registers: HashMap<String, FloatRegister>,
+    /// The photon number variances written to every readout register.
+    pub variance_registers: HashMap<String, FloatRegister>,
+    /// The number of measurements set for every readout register.
+    pub number_measurements: HashMap<String, usize>,
+}
+
+impl GaussianResult {
+    /// Returns the standard errors of the mean photon numbers averaged over the measurements of a register.
+    pub fn standard_errors(&self, readout: &str) -> Option<Vec<f64>> {
+        let number_measurements = *self.number_measurements.get(readout).unwrap_or(&1) as f64;
+        self.variance_registers.get(readout).map(|variances| {
+            variances
+                .iter()
+                .map(|variance| (variance / number_measurements).sqrt())
+                .collect()
+        })
+    }
+}
+
+/// Simulates a circuit of bosonic gates followed by photon detections.
+///
+/// # Arguments
+///
+/// * `circuit` - The circuit. All parameters need to be substituted and no gate may follow a photon detection.
+/// * `number_modes` - The number of simulated modes.
+///
+/// # Returns
+///
+/// * `Ok(GaussianResult)` - The final state and the photon number statistics of the detected modes.
+/// * `Err(RoqoqoBackendError)` - The circuit contains operations that cannot be simulated.
+pub fn simulate_circuit(
+    circuit: &Circuit,
+    number_modes: usize,
+) -> Result<GaussianResult, RoqoqoBackendError> {
+    let mut state = GaussianState::vacuum(number_modes);
+    let mut detections: Vec<&PhotonDetection> = Vec::new();
+    let mut mean_registers: HashMap<String, FloatRegister> = HashMap::new();
+    let mut number_measurements: HashMap<String, usize> = HashMap::new();
+    for operation in circuit.iter() {
+        match operation {
+            Operation::DefinitionFloat(def) => {
+                if *def.is_output() {
+                    mean_registers.insert(def.name().clone(), vec![0.0; *def.length()]);
+                }
+            }
+            Operation::PhotonDetection(op) => {
+                state.check_mode(*op.mode())?;
+                detections.push(op);
+            }
+            Operation::PragmaSetNumberOfMeasurements(op) => {
+                number_measurements.insert(op.readout().clone(), *op.number_measurements());
+            }
+            _ => {
+                if !detections.is_empty() {
+                    return Err(RoqoqoBackendError::GenericError {
+                        msg: format!(
+                            "Operation {} follows a photon detection, only final detections can be simulated",
+                            operation.hqslang()
+                        ),
+                    });
+                }
+                state.apply_operation(operation)?;
+            }
+        }
+    }
+    let mut variance_registers = mean_registers.clone();
+    for detection in detections {
+        let mode = *detection.mode();
+        for (registers, value) in [
+            (&mut mean_registers, state.mean_photon_number(mode)),
+            (&mut variance_registers, state.photon_number_variance(mode)),
+        ] {
+            let register = registers.get_mut(detection.readout()).ok_or_else(|| {
+                RoqoqoBackendError::GenericError {
+                    msg: format!("Readout register {} is not defined", detection.readout()),
+                }
+            })?;
+            let entry = register
+                .get_mut(*detection.readout_index())
+                .ok_or_else(|| RoqoqoBackendError::GenericError {
+                    msg: format!(
+                        "Index {} is outside of readout register {}",
+                        detection.readout_index(),
+                        detection.readout()
+                    ),
+                })?;
+            *entry = value;
+        }
+    }
+    Ok(GaussianResult {
+        state,
+        mean_registers,
+        variance_registers,
+        number_measurements,
+    })
+}
+
+fn float_value(value: &CalculatorFloat) -> Result<f64, RoqoqoBackendError> {
+    value
+        .float()
+        .copied()
+        .map_err(|_| RoqoqoBackendError::GenericError {
+            msg: format!("Symbolic parameter {} has not been substituted", value),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// Applies a gate that has to be supported.
+    fn apply(state: &mut GaussianState, operation: Operation) {
+        state.apply_operation(&operation).unwrap();
+    }
+
+    #[test]
+    fn squeezing_along_x() {
+        let r = 0.7;
+        let mut state = GaussianState::vacuum(1);
+        apply(&mut state, Squeezing::new(0, r.into(), 0.0.into()).into());
+        let expected =
+            DMatrix::from_row_slice(2, 2, &[(-2.0 * r).exp(), 0.0, 0.0, (2.0 * r).exp()]);
+        assert!((state.covariance() - expected).norm() < 1e-12);
+        assert!((state.mean_photon_number(0) - r.sinh().powi(2)).abs() < 1e-12);
+        let variance = 2.0 * r.sinh().powi(2) * r.cosh().powi(2);
+        assert!((state.photon_number_variance(0) - variance).abs() < 1e-12);
+    }
+
+    #[test]
+    fn squeezing_along_the_diagonal() {
+        let r = 0.7;
+        let mut state = GaussianState::vacuum(1);
+        apply(
+            &mut state,
+            Squeezing::new(0, r.into(), (PI / 2.0).into()).into(),
+        );
+        // The phase pi/2 squeezes the quadrature (x + p)/sqrt(2)
+        let (cosh, sinh) = ((2.0 * r).cosh(), (2.0 * r).sinh());
+        let expected = DMatrix::from_row_slice(2, 2, &[cosh, -sinh, -sinh, cosh]);
+        assert!((state.covariance() - expected).norm() < 1e-12);
+        let variance = 2.0 * r.sinh().powi(2) * r.cosh().powi(2);
+        assert!((state.photon_number_variance(0) - variance).abs() < 1e-12);
+    }
+
+    #[test]
+    fn beam_splitter_moves_a_displaced_mode() {
+        let (displacement, theta, phi) = (1.5, PI / 6.0, PI / 3.0);
+        let mut state = GaussianState::vacuum(2);
+        apply(
+            &mut state,
+            PhaseDisplacement::new(0, displacement.into(), 0.0.into()).into(),
+        );
+        apply(
+            &mut state,
+            BeamSplitter::new(0, 1, theta.into(), phi.into()).into(),
+        );
+        // a_1 -> r a_0 + t a_1 moves the amplitude exp(i phi) sin(theta) alpha to mode 1
+        let scale = (2.0 * HBAR).sqrt() * displacement;
+        let expected = DVector::from_vec(vec![
+            scale * theta.cos(),
+            0.0,
+            scale * theta.sin() * phi.cos(),
+            scale * theta.sin() * phi.sin(),
+        ]);
+        assert!((&state.means - expected).norm() < 1e-12);
+        assert!((state.covariance() - DMatrix::identity(4, 4)).norm() < 1e-12);
+        // A coherent state has a Poissonian photon number
+        for (mode, amplitude) in [(0, theta.cos()), (1, theta.sin())] {
+            let photons = (displacement * amplitude).powi(2);
+            assert!((state.mean_photon_number(mode) - photons).abs() < 1e-12);
+            assert!((state.photon_number_variance(mode) - photons).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn unsupported_operations() {
+        let mut state = GaussianState::vacuum(1);
+        assert!(state.apply_operation(&Hadamard::new(0).into()).is_err());
+        assert!(state
+            .apply_operation(&PhaseShift::new(1, 1.0.into()).into())
+            .is_err());
+        assert!(state
+            .apply_operation(&PhaseShift::new(0, "phi".into()).into())
+            .is_err());
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rust version of `qoqo/bosonic_simple_example.py`.
+//!
+//! The Python example runs the circuit on Strawberry Fields. Here the circuit is simulated locally with a small
+//! Gaussian-state simulator, so the example runs without any external backend. Optionally the averaged
+//! `converted_results` printed by the Python script can be passed as arguments to compare them with the simulation:
+//!
+//! ```text
+//! cargo run -- 0.02 0.0 0.04 0.0
+//! ```
+
+mod gaussian;
+
+use roqoqo::{operations as ops, Circuit};
+use std::f64::consts::PI;
+
+fn main() {
+    println!(">> Bosonic example start.");
+
+    // Defining the inputs for the bosonic gates
+    let squeezing = 0.1;
+    let squeezing_angle = PI / 4.0;
+    let beamsplitter_50_50 = PI / 4.0;
+    let beamsplitter_50_50_angle = PI / 2.0;
+    let number_modes = 4;
+
+    // Creating the circuit defined in Figure 20 of https://arxiv.org/abs/1805.02645 as an example.
+    let mut circuit = Circuit::new();
+    circuit += ops::DefinitionFloat::new("ro".to_string(), number_modes, true);
+    for mode in 0..number_modes {
+        circuit += ops::Squeezing::new(mode, squeezing.into(), squeezing_angle.into());
+    }
+    for (mode_0, mode_1) in [(0, 1), (2, 3), (1, 2), (0, 1), (2, 3), (1, 2)] {
+        circuit += ops::BeamSplitter::new(
+            mode_0,
+            mode_1,
+            beamsplitter_50_50.into(),
+            beamsplitter_50_50_angle.into(),
+        );
+    }
+    for mode in 0..number_modes {
+        circuit += ops::PhotonDetection::new(mode, "ro".to_string(), mode);
+    }
+    circuit += ops::PragmaSetNumberOfMeasurements::new(100, "ro".to_string());
+    println!("{}", circuit);
+
+    //  SIMULATION
+    // Squeezing, phase shifts, displacements and beam splitters map Gaussian states onto Gaussian states, so the state of
+    // the modes is fully described by the mean quadratures and their covariance matrix. Instead of sampling photon numbers
+    // the simulator returns the mean photon number of every detected mode, which the averaged shots of the Python example
+    // converge to, and its variance, which tells us how far an average over 100 shots is expected to scatter.
+
+    let result = gaussian::simulate_circuit(&circuit, number_modes).unwrap();
+    let number_shots = result.number_measurements["ro"];
+    let means = &result.mean_registers["ro"];
+    let standard_errors = result.standard_errors("ro").unwrap();
+    println!("Covariance matrix:\n{:.4}", result.state.covariance());
+    println!("Mean photon numbers: {:.5?}", means);
+    println!(
+        "Standard error of the average over {} shots: {:.5?}",
+        number_shots, standard_errors
+    );
+
+    //  CHECKING THE RESULT
+    // A squeezed vacuum has a mean photon number of sinh(r)^2. The beam splitters are passive, they conserve the total
+    // photon number, and since all inputs are identically squeezed states without correlations between the modes,
+    // every output mode carries the same mean photon number sinh(r)^2.
+
+    let expected = squeezing.sinh().powi(2);
+    for mean in means {
+        assert!((mean - expected).abs() < 1e-12);
+    }
+    assert!((means.iter().sum::<f64>() - number_modes as f64 * expected).abs() < 1e-12);
+    println!("Expected mean photon number per mode: {:.5}", expected);
+
+    // All gates are unitary, so the modes stay in a pure state, whose covariance matrix has determinant (hbar / 2)^(2n) = 1.
+
+    assert!((result.state.covariance().determinant() - 1.0).abs() < 1e-10);
+
+    //  COMPARISON WITH THE PYTHON EXAMPLE
+    // The Python example averages 100 sampled shots, so its `converted_results` only agree with the means within a few
+    // standard errors. Most shots detect no photon at all, a squeezed vacuum only creates photons in pairs.
+
+    let python_results: Vec<f64> = std::env::args()
+        .skip(1)
+        .map(|value| value.parse().expect("Averaged results need to be numbers"))
+        .collect();
+    if python_results.len() == number_modes {
+        for (mode, python_result) in python_results.iter().enumerate() {
+            println!(
+                "Mode {}: Python average {:.5}, simulation {:.5}, deviation {:.2} standard errors",
+                mode,
+                python_result,
+                means[mode],
+                (python_result - means[mode]) / standard_errors[mode]
+            );
+        }
+    } else if !python_results.is_empty() {
+        println!(
+            "Expected {} averaged results of the Python example, found {}",
+            number_modes,
+            python_results.len()
+        );
+    }
+
+    println!(">> Bosonic example end.");
+}
@@ -0,0 +1,387 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::f64::consts::PI;
+use examples_support::backend::Backend;
+use examples_support::diagram::{circuit_diagram, DiagramStyle};
+use examples_support::feed_forward::{
+    conditional_on_any, conditional_on_bits, format_state_vector, measurement_branches,
+    states_equal_up_to_phase, MeasurementBranch,
+};
+use examples_support::{seed, unitary};
+use nalgebra::DVector;
+use num_complex::Complex64;
+use roqoqo::backends::EvaluatingBackend;
+use roqoqo::{operations as ops, Circuit};
+
+/// Number of simulator runs compared with the measurement branches of every circuit.
+const NUMBER_RUNS: usize = 50;
+
+fn main() {
+    // The global seed of the sampled shots, see examples_support::seed
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    match seed::parse_seed(&arguments, std::env::var(seed::SEED_VARIABLE).ok()) {
+        Ok(global_seed) => seed::set_global_seed(global_seed),
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            std::process::exit(2)
+        }
+    }
+
+    println!(">> Feed-forward example start.");
+
+    // The teleportation example corrects the teleported state with conditional operations that each depend on a
+    // single measured bit. This example shows more involved feed-forward: corrections that depend on several bits,
+    // measurements that decide whether a sub-circuit is repeated and qubits that are reset in the middle of a circuit.
+
+    // A simulator run follows one random measurement outcome. To check the circuits for every outcome we use
+    // `measurement_branches`, which follows all outcomes and returns the probability, the bit registers and the final
+    // state vector of every branch. The simulator runs are then compared with these branches.
+
+    entanglement_swapping();
+    repeat_until_success();
+    active_reset();
+
+    println!(">> Feed-forward example end.");
+}
+
+//  ENTANGLEMENT SWAPPING
+// Qubits 0 and 1 as well as qubits 2 and 3 start in the Bell state (|00> + |11>)/sqrt(2). A Bell measurement of qubits 1
+// and 2 entangles qubits 0 and 3, which have never interacted. Depending on the two measured bits the Bell state of
+// qubits 0 and 3 is rotated by one of the Pauli operators, so the correction depends on the value of both bits.
+fn entanglement_swapping() {
+    let mut circuit = Circuit::new();
+    circuit += ops::DefinitionBit::new("bell".to_string(), 2, true);
+    circuit += ops::DefinitionBit::new("flag".to_string(), 1, true);
+    for (first, second) in [(0, 1), (2, 3)] {
+        circuit += ops::Hadamard::new(first);
+        circuit += ops::CNOT::new(first, second);
+    }
+
+    // Bell measurement of qubits 1 and 2
+
+    circuit += ops::CNOT::new(1, 2);
+    circuit += ops::Hadamard::new(1);
+    circuit += ops::MeasureQubit::new(1, "bell".to_string(), 0);
+    circuit += ops::MeasureQubit::new(2, "bell".to_string(), 1);
+
+    // One correction for every value of the two bits. `conditional_on_bits` builds the condition `bell == pattern`
+    // from nested conditionals for the bits that need to be true and a flag bit, set with InputBit, that is cleared
+    // by every bit that needs to be false.
+
+    let corrections: [(bool, bool, Circuit); 3] = [
+        (false, true, Circuit::new() + ops::PauliX::new(3)),
+        (true, false, Circuit::new() + ops::PauliZ::new(3)),
+        (true, true, Circuit::new() + ops::PauliY::new(3)),
+    ];
+    for (phase_bit, parity_bit, correction) in corrections {
+        circuit += conditional_on_bits(
+            "bell",
+            &[(0, phase_bit), (1, parity_bit)],
+            "flag",
+            correction,
+        );
+    }
+    circuit += ops::DefinitionComplex::new("psi".to_string(), 16, true);
+    circuit += ops::PragmaGetStateVector::new("psi".to_string(), None);
+
+    println!(
+        "Entanglement swapping:\n{}",
+        circuit_diagram(&circuit, DiagramStyle::Unicode)
+    );
+
+    // In every branch qubits 1 and 2 are left in the measured basis state and qubits 0 and 3 share the Bell state.
+
+    let branches = measurement_branches(&circuit, 4).unwrap();
+    assert_eq!(branches.len(), 4);
+    for branch in branches.iter() {
+        let bits = &branch.bit_registers["bell"];
+        let measured = usize::from(bits[0]) << 1 | usize::from(bits[1]) << 2;
+        let mut expected = DVector::zeros(16);
+        expected[measured] = Complex64::new(1.0 / 2f64.sqrt(), 0.0);
+        expected[measured | 0b1001] = Complex64::new(1.0 / 2f64.sqrt(), 0.0);
+        assert!((branch.probability - 0.25).abs() < 1e-10);
+        assert!(states_equal_up_to_phase(
+            branch.state_vector.as_slice(),
+            expected.as_slice(),
+            1e-10
+        ));
+    }
+    print_branches(&branches, &["bell"], 4);
+    compare_with_backend(&circuit, &branches, 4, "psi");
+}
+
+//  REPEAT-UNTIL-SUCCESS
+// Clifford gates and the T gate can only approximate most rotations. Repeat-until-success circuits apply some of them
+// exactly: two ancillas in |+> control a Clifford+T sub-circuit on the target qubit. If both ancillas are measured as 0,
+// with probability 5/8, the target has been rotated by the phase shift with angle arccos(3/5), which is not a rational
+// multiple of pi. Otherwise the target has only picked up a PauliZ. It is undone and the attempt is repeated.
+fn repeat_until_success() {
+    let number_attempts = 3;
+    let (theta, phi) = (PI / 3.0, PI / 5.0);
+
+    // The Toffoli gate written with Clifford and T gates, which the sub-circuit uses twice
+
+    fn toffoli(control_0: usize, control_1: usize, target: usize) -> Circuit {
+        let t_dagger = |qubit: usize| ops::PhaseShiftState1::new(qubit, (-PI / 4.0).into());
+        let mut circuit = Circuit::new();
+        circuit += ops::Hadamard::new(target);
+        circuit += ops::CNOT::new(control_1, target);
+        circuit += t_dagger(target);
+        circuit += ops::CNOT::new(control_0, target);
+        circuit += ops::TGate::new(target);
+        circuit += ops::CNOT::new(control_1, target);
+        circuit += t_dagger(target);
+        circuit += ops::CNOT::new(control_0, target);
+        circuit += ops::TGate::new(control_1);
+        circuit += ops::TGate::new(target);
+        circuit += ops::CNOT::new(control_0, control_1);
+        circuit += ops::TGate::new(control_0);
+        circuit += t_dagger(control_1);
+        circuit += ops::CNOT::new(control_0, control_1);
+        circuit += ops::Hadamard::new(target);
+        circuit
+    }
+
+    let comparison = unitary::compare_circuit_unitaries(
+        &toffoli(1, 2, 0),
+        &(Circuit::new() + ops::Toffoli::new(1, 2, 0)),
+    )
+    .unwrap();
+    assert!(comparison.is_equivalent(), "{}", comparison);
+
+    // Every attempt measures the ancillas into its own register. If any of the two bits is true the attempt failed:
+    // the PauliZ is undone, the ancillas are reset and the next attempt is nested inside the conditional.
+
+    fn attempt(number: usize, number_attempts: usize) -> Circuit {
+        let register = format!("attempt_{}", number);
+        let mut circuit = Circuit::new();
+        circuit += ops::Hadamard::new(1);
+        circuit += ops::Hadamard::new(2);
+        circuit += toffoli(1, 2, 0);
+        circuit += ops::SGate::new(0);
+        circuit += toffoli(1, 2, 0);
+        circuit += ops::Hadamard::new(1);
+        circuit += ops::Hadamard::new(2);
+        circuit += ops::MeasureQubit::new(1, register.clone(), 0);
+        circuit += ops::MeasureQubit::new(2, register.clone(), 1);
+        let mut failure = Circuit::new();
+        failure += ops::PauliZ::new(0);
+        failure += ops::PragmaActiveReset::new(1);
+        failure += ops::PragmaActiveReset::new(2);
+        if number + 1 < number_attempts {
+            failure += attempt(number + 1, number_attempts);
+        }
+        circuit + conditional_on_any(&register, &[0, 1], "failed", failure)
+    }
+
+    let mut prepare = Circuit::new();
+    prepare += ops::RotateY::new(0, theta.into());
+    prepare += ops::RotateZ::new(0, phi.into());
+
+    let mut circuit = Circuit::new();
+    for number in 0..number_attempts {
+        circuit += ops::DefinitionBit::new(format!("attempt_{}", number), 2, true);
+    }
+    circuit += ops::DefinitionBit::new("failed".to_string(), 1, true);
+    circuit += prepare.clone();
+    circuit += attempt(0, number_attempts);
+    circuit += ops::DefinitionComplex::new("psi".to_string(), 8, true);
+    circuit += ops::PragmaGetStateVector::new("psi".to_string(), None);
+
+    // The successful branches end with the rotated target state, the branch in which all attempts failed ends with
+    // the initial target state. The ancillas are always left in |00>.
+
+    let initial = initial_state(&prepare, 3);
+    let mut rotated = prepare.clone();
+    rotated += ops::PhaseShiftState1::new(0, (3f64 / 5.0).acos().into());
+    let rotated = initial_state(&rotated, 3);
+
+    let branches = measurement_branches(&circuit, 3).unwrap();
+    let mut probabilities = vec![0.0; number_attempts + 1];
+    for branch in branches.iter() {
+        let failed_attempts = (0..number_attempts)
+            .filter(|number| branch.bit_registers[&format!("attempt_{}", number)].contains(&true))
+            .count();
+        let expected = if failed_attempts < number_attempts {
+            // Each of the three failing outcomes of an attempt has probability 1/8
+            let probability = 5.0 / 8.0 * (1f64 / 8.0).powi(failed_attempts as i32);
+            assert!((branch.probability - probability).abs() < 1e-10);
+            &rotated
+        } else {
+            &initial
+        };
+        probabilities[failed_attempts] += branch.probability;
+        assert!(states_equal_up_to_phase(
+            branch.state_vector.as_slice(),
+            expected.as_slice(),
+            1e-10
+        ));
+    }
+    for (failed_attempts, probability) in probabilities.iter().enumerate() {
+        let expected = if failed_attempts < number_attempts {
+            5.0 / 8.0 * (3f64 / 8.0).powi(failed_attempts as i32)
+        } else {
+            (3f64 / 8.0).powi(number_attempts as i32)
+        };
+        assert!((probability - expected).abs() < 1e-10);
+        println!(
+            "  {} failed attempts: probability {:.4}",
+            failed_attempts, probability
+        );
+    }
+    println!(
+        "Repeat-until-success with {} attempts succeeds with probability {:.4}",
+        number_attempts,
+        1.0 - probabilities[number_attempts]
+    );
+    compare_with_backend(&circuit, &branches, 3, "psi");
+}
+
+//  ACTIVE RESET
+// PragmaActiveReset returns a qubit to |0> in the middle of a circuit, so it can be reused. It acts like a measurement
+// followed by a PauliX when the outcome is 1, but the outcome is not stored. We reset one qubit of a Bell pair, which
+// leaves the other qubit in |0> or |1>, and compare it with the same reset written with MeasureQubit and PragmaConditional.
+// Finally a reused ancilla measures the parity of two qubits three times in a PragmaLoop.
+fn active_reset() {
+    let mut bell = Circuit::new();
+    bell += ops::Hadamard::new(0);
+    bell += ops::CNOT::new(0, 1);
+
+    let mut active = Circuit::new();
+    active += bell.clone();
+    active += ops::PragmaActiveReset::new(1);
+    active += ops::DefinitionComplex::new("psi".to_string(), 4, true);
+    active += ops::PragmaGetStateVector::new("psi".to_string(), None);
+
+    let mut measured = Circuit::new();
+    measured += ops::DefinitionBit::new("reset".to_string(), 1, true);
+    measured += bell;
+    measured += ops::MeasureQubit::new(1, "reset".to_string(), 0);
+    measured +=
+        ops::PragmaConditional::new("reset".to_string(), 0, Circuit::new() + ops::PauliX::new(1));
+    measured += ops::DefinitionComplex::new("psi".to_string(), 4, true);
+    measured += ops::PragmaGetStateVector::new("psi".to_string(), None);
+
+    let active_branches = measurement_branches(&active, 2).unwrap();
+    let measured_branches = measurement_branches(&measured, 2).unwrap();
+    assert_eq!(active_branches.len(), 2);
+    for (active_branch, measured_branch) in active_branches.iter().zip(measured_branches.iter()) {
+        let outcome = active_branch.outcomes[0];
+        let mut expected = DVector::zeros(4);
+        expected[usize::from(outcome)] = Complex64::new(1.0, 0.0);
+        assert!((active_branch.probability - 0.5).abs() < 1e-10);
+        assert!(states_equal_up_to_phase(
+            active_branch.state_vector.as_slice(),
+            expected.as_slice(),
+            1e-10
+        ));
+        assert_eq!(measured_branch.bit_registers["reset"], vec![outcome]);
+        assert_eq!(measured_branch.state_vector, active_branch.state_vector);
+    }
+    print_branches(&active_branches, &[], 2);
+    compare_with_backend(&active, &active_branches, 2, "psi");
+    compare_with_backend(&measured, &measured_branches, 2, "psi");
+
+    // The data qubits 0 and 1 start in a superposition of even and odd parity. The first parity measurement decides
+    // the parity, the following rounds have to repeat its result, so there are only two branches after three rounds.
+
+    let angle = PI / 3.0;
+    let mut round = Circuit::new();
+    round += ops::CNOT::new(0, 2);
+    round += ops::CNOT::new(1, 2);
+    round += ops::MeasureQubit::new(2, "parity".to_string(), 0);
+    round += ops::PragmaActiveReset::new(2);
+
+    let mut parity = Circuit::new();
+    parity += ops::DefinitionBit::new("parity".to_string(), 1, true);
+    parity += ops::RotateY::new(0, angle.into());
+    parity += ops::Hadamard::new(1);
+    parity += ops::PragmaLoop::new(3.0.into(), round);
+    parity += ops::DefinitionComplex::new("psi".to_string(), 8, true);
+    parity += ops::PragmaGetStateVector::new("psi".to_string(), None);
+
+    println!(
+        "Repeated parity measurement:\n{}",
+        circuit_diagram(&parity, DiagramStyle::Unicode)
+    );
+
+    let branches = measurement_branches(&parity, 3).unwrap();
+    assert_eq!(branches.len(), 2);
+    for branch in branches.iter() {
+        let odd = usize::from(branch.bit_registers["parity"][0]);
+        assert!(branch
+            .outcomes
+            .chunks(2)
+            .all(|round| round[0] == (odd == 1)));
+        let mut expected = DVector::zeros(8);
+        expected[odd << 1] = Complex64::new((angle / 2.0).cos(), 0.0);
+        expected[1 | (1 - odd) << 1] = Complex64::new((angle / 2.0).sin(), 0.0);
+        assert!((branch.probability - 0.5).abs() < 1e-10);
+        assert!(states_equal_up_to_phase(
+            branch.state_vector.as_slice(),
+            expected.as_slice(),
+            1e-10
+        ));
+    }
+    print_branches(&branches, &["parity"], 3);
+    compare_with_backend(&parity, &branches, 3, "psi");
+}
+
+/// Returns the state vector a gate-only circuit prepares from |0...0>.
+fn initial_state(circuit: &Circuit, number_qubits: usize) -> DVector<Complex64> {
+    unitary::circuit_unitary(circuit, number_qubits)
+        .unwrap()
+        .column(0)
+        .into_owned()
+}
+
+fn print_branches(branches: &[MeasurementBranch], registers: &[&str], number_qubits: usize) {
+    for branch in branches {
+        let bits: Vec<String> = registers
+            .iter()
+            .map(|name| {
+                let bits: String = branch.bit_registers[*name]
+                    .iter()
+                    .map(|&bit| if bit { '1' } else { '0' })
+                    .collect();
+                format!("{}={}", name, bits)
+            })
+            .collect();
+        println!(
+            "  p={:.4} outcomes={:?} {} state={}",
+            branch.probability,
+            branch.outcomes,
+            bits.join(" "),
+            format_state_vector(branch.state_vector.as_slice(), number_qubits)
+        );
+    }
+}
+
+/// Runs the circuit several times on the simulator backend and checks that every run ended in one of the branches.
+fn compare_with_backend(
+    circuit: &Circuit,
+    branches: &[MeasurementBranch],
+    number_qubits: usize,
+    state_register: &str,
+) {
+    let backend = Backend::new(number_qubits);
+    for _ in 0..NUMBER_RUNS {
+        let (bit_registers, _float_registers, complex_registers) =
+            backend.run_circuit(circuit).unwrap();
+        let state_vector = complex_registers[state_register].last().unwrap();
+        assert!(branches
+            .iter()
+            .any(|branch| branch.matches(&bit_registers, state_vector, 1e-8)));
+    }
+    println!("  {} simulator runs agree with the branches", NUMBER_RUNS);
+}
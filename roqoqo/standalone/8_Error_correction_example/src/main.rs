@@ -0,0 +1,411 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::f64::consts::PI;
+use examples_support::backend::Backend;
+use examples_support::diagram::{circuit_diagram, DiagramStyle};
+use examples_support::feed_forward::{conditional_on_bits, measurement_branches};
+use examples_support::{seed, unitary};
+use nalgebra::DVector;
+use num_complex::Complex64;
+use qoqo_calculator::CalculatorFloat;
+use rand::rngs::StdRng;
+use rand::Rng;
+use roqoqo::backends::EvaluatingBackend;
+use roqoqo::operations::{self as ops, OperateSingleQubit, Operation};
+use roqoqo::Circuit;
+
+/// Qubits of the three-qubit codes, 0 to 2 hold the data and 3 and 4 are the syndrome ancillas.
+const NUMBER_QUBITS: usize = 5;
+/// Physical error probabilities of the fidelity table.
+const ERROR_PROBABILITIES: [f64; 6] = [0.01, 0.05, 0.1, 0.2, 0.3, 0.4];
+/// Number of noisy QuEST runs for every error probability.
+const NUMBER_SAMPLES: usize = 200;
+
+fn main() {
+    // The global seed of the sampled shots, see examples_support::seed
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    match seed::parse_seed(&arguments, std::env::var(seed::SEED_VARIABLE).ok()) {
+        Ok(global_seed) => seed::set_global_seed(global_seed),
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            std::process::exit(2)
+        }
+    }
+
+    println!(">> Error correction example start.");
+
+    // A qubit can be protected against errors by encoding it into several physical qubits. The three-qubit bit-flip
+    // code stores a|0> + b|1> as a|000> + b|111>. A PauliX error on one of the qubits can be found without disturbing
+    // the logical state by measuring the parities of qubits 0 and 1 and of qubits 1 and 2 on two ancillas. These two
+    // bits, the syndrome, tell which qubit has been flipped, and a conditional PauliX undoes the error. The phase-flip
+    // code does the same in the basis |+>, |->, where PauliZ errors look like bit flips.
+
+    // The state we protect is prepared with the same rotations as in the teleportation example
+
+    fn prep_psi(angle_thet: CalculatorFloat, angle_phi: CalculatorFloat) -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += ops::RotateY::new(0, angle_thet);
+        circuit += ops::RotateZ::new(0, angle_phi);
+        circuit
+    }
+
+    let init_circuit = prep_psi(
+        CalculatorFloat::Float(PI / 3.0),
+        CalculatorFloat::Float(PI / 4.0),
+    );
+    let psi: DVector<Complex64> = unitary::circuit_unitary(&init_circuit, 1)
+        .unwrap()
+        .column(0)
+        .into_owned();
+
+    for code in [Code::BitFlip, Code::PhaseFlip] {
+        let mut errors = Circuit::new();
+        errors += code.error(1);
+        let circuit = code.circuit(&init_circuit, errors);
+        println!(
+            "{} code with an error on qubit 1:\n{}",
+            code.name(),
+            circuit_diagram(&circuit, DiagramStyle::Unicode)
+        );
+    }
+
+    //  CORRECTING ALL ERROR PATTERNS
+    // Errors on the three data qubits are independent with probability p. For every one of the eight error patterns
+    // the syndrome measurement has a single outcome, so `measurement_branches` gives us the final state of each
+    // pattern. Up to one error is corrected, two or three errors turn into a logical error. For both codes the logical
+    // error becomes a PauliX on qubit 0 after decoding, while an unencoded qubit suffers the physical error itself.
+
+    println!("Logical fidelity against the physical error probability p:");
+    println!(
+        "{:>6} | {:>10} {:>10} {:>16} | {:>10} {:>10} {:>16}",
+        "p", "X error", "bit-flip", "QuEST", "Z error", "phase-flip", "QuEST"
+    );
+    let pattern_fidelities: Vec<Vec<f64>> = [Code::BitFlip, Code::PhaseFlip]
+        .iter()
+        .map(|code| {
+            let logical_error_fidelity = logical_error_fidelity(&psi);
+            (0..8)
+                .map(|pattern: usize| {
+                    let mut errors = Circuit::new();
+                    for qubit in (0..3).filter(|qubit| pattern >> qubit & 1 == 1) {
+                        errors += code.error(qubit);
+                    }
+                    let circuit = code.circuit(&init_circuit, errors);
+                    let fidelity: f64 = measurement_branches(&circuit, NUMBER_QUBITS)
+                        .unwrap()
+                        .iter()
+                        .map(|branch| branch.probability * fidelity(&branch.state_vector, &psi))
+                        .sum();
+                    let expected = if pattern.count_ones() <= 1 {
+                        1.0
+                    } else {
+                        logical_error_fidelity
+                    };
+                    assert!((fidelity - expected).abs() < 1e-10);
+                    fidelity
+                })
+                .collect()
+        })
+        .collect();
+
+    // The same codes with random noise: every data qubit gets a PragmaRandomNoise whose dephasing rate causes a PauliZ
+    // error with probability p. For the bit-flip code the noise is surrounded by Hadamard gates, which turns it into
+    // PauliX errors. The noise is sampled into explicit Pauli operations before each QuEST run.
+
+    let mut rng = seed::rng_or(42);
+    for probability in ERROR_PROBABILITIES {
+        let mut columns = Vec::new();
+        for (code, fidelities) in [Code::BitFlip, Code::PhaseFlip]
+            .iter()
+            .zip(pattern_fidelities.iter())
+        {
+            let weights: Vec<f64> = (0..8)
+                .map(|pattern: usize| {
+                    probability.powi(pattern.count_ones() as i32)
+                        * (1.0 - probability).powi(3 - pattern.count_ones() as i32)
+                })
+                .collect();
+            let exact: f64 = weights.iter().zip(fidelities).map(|(w, f)| w * f).sum();
+            let second_moment: f64 = weights.iter().zip(fidelities).map(|(w, f)| w * f * f).sum();
+            let failure = 3.0 * probability.powi(2) - 2.0 * probability.powi(3);
+            let analytic = 1.0 - failure * (1.0 - logical_error_fidelity(&psi));
+            assert!((exact - analytic).abs() < 1e-10);
+            // A logical error is less likely than a physical error as long as p < 1/2
+            assert!(failure < probability);
+            let unencoded = 1.0 - probability * (1.0 - code.physical_error_fidelity(&psi));
+
+            let noisy = code.circuit(&init_circuit, code.random_noise(probability));
+            let backend = Backend::new(NUMBER_QUBITS);
+            let samples: Vec<f64> = (0..NUMBER_SAMPLES)
+                .map(|_| {
+                    let circuit = sample_random_noise(&noisy, &mut rng);
+                    let (_bits, _floats, complex_registers) =
+                        backend.run_circuit(&circuit).unwrap();
+                    let state_vector = DVector::from_vec(complex_registers["psi"][0].clone());
+                    fidelity(&state_vector, &psi)
+                })
+                .collect();
+            let mean = samples.iter().sum::<f64>() / NUMBER_SAMPLES as f64;
+            let standard_error = ((second_moment - exact.powi(2)) / NUMBER_SAMPLES as f64).sqrt();
+            assert!((mean - exact).abs() <= 5.0 * standard_error + 1e-10);
+            columns.push(format!(
+                "{:>10.4} {:>10.4} {:>7.4} ± {:.4}",
+                unencoded, exact, mean, standard_error
+            ));
+        }
+        println!("{:>6} | {}", probability, columns.join(" | "));
+    }
+
+    shor_code(&init_circuit, &psi);
+
+    println!(">> Error correction example end.");
+}
+
+/// The three-qubit codes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Code {
+    BitFlip,
+    PhaseFlip,
+}
+
+impl Code {
+    fn name(&self) -> &'static str {
+        match self {
+            Code::BitFlip => "Bit-flip",
+            Code::PhaseFlip => "Phase-flip",
+        }
+    }
+
+    /// The error the code corrects, which is also the correction.
+    fn error(&self, qubit: usize) -> Operation {
+        match self {
+            Code::BitFlip => ops::PauliX::new(qubit).into(),
+            Code::PhaseFlip => ops::PauliZ::new(qubit).into(),
+        }
+    }
+
+    /// Changes to the basis in which the errors of the code are bit flips and back.
+    fn basis_change(&self) -> Circuit {
+        let mut circuit = Circuit::new();
+        if *self == Code::PhaseFlip {
+            for qubit in 0..3 {
+                circuit += ops::Hadamard::new(qubit);
+            }
+        }
+        circuit
+    }
+
+    fn encoding(&self) -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += ops::CNOT::new(0, 1);
+        circuit += ops::CNOT::new(0, 2);
+        circuit + self.basis_change()
+    }
+
+    fn decoding(&self) -> Circuit {
+        let mut circuit = self.basis_change();
+        circuit += ops::CNOT::new(0, 2);
+        circuit += ops::CNOT::new(0, 1);
+        circuit
+    }
+
+    /// Measures the parities of qubits 0, 1 and of qubits 1, 2 into the syndrome register.
+    fn syndrome_measurement(&self) -> Circuit {
+        let mut circuit = self.basis_change();
+        circuit += ops::CNOT::new(0, 3);
+        circuit += ops::CNOT::new(1, 3);
+        circuit += ops::CNOT::new(1, 4);
+        circuit += ops::CNOT::new(2, 4);
+        circuit += self.basis_change();
+        circuit += ops::MeasureQubit::new(3, "syndrome".to_string(), 0);
+        circuit += ops::MeasureQubit::new(4, "syndrome".to_string(), 1);
+        circuit
+    }
+
+    /// Undoes the error on the qubit the syndrome points to.
+    fn correction(&self) -> Circuit {
+        let mut circuit = Circuit::new();
+        for (syndrome, qubit) in [((true, false), 0), ((true, true), 1), ((false, true), 2)] {
+            circuit += conditional_on_bits(
+                "syndrome",
+                &[(0, syndrome.0), (1, syndrome.1)],
+                "flag",
+                Circuit::new() + self.error(qubit),
+            );
+        }
+        circuit
+    }
+
+    /// Encodes the state, applies the errors, corrects them and decodes the state back into qubit 0.
+    fn circuit(&self, init_circuit: &Circuit, errors: Circuit) -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += ops::DefinitionBit::new("syndrome".to_string(), 2, true);
+        circuit += ops::DefinitionBit::new("flag".to_string(), 1, true);
+        circuit += init_circuit.clone();
+        circuit += self.encoding();
+        circuit += errors;
+        circuit += self.syndrome_measurement();
+        circuit += self.correction();
+        circuit += self.decoding();
+        circuit += ops::DefinitionComplex::new("psi".to_string(), 1 << NUMBER_QUBITS, true);
+        circuit += ops::PragmaGetStateVector::new("psi".to_string(), None);
+        circuit
+    }
+
+    /// Noise on every data qubit that causes the error of the code with the given probability.
+    fn random_noise(&self, probability: f64) -> Circuit {
+        // A dephasing rate r applies PauliZ with probability (1 - exp(-2 r t)) / 2
+        let rate = -(1.0 - 2.0 * probability).ln() / 2.0;
+        let mut circuit = Circuit::new();
+        for qubit in 0..3 {
+            let noise = ops::PragmaRandomNoise::new(qubit, 1.0.into(), 0.0.into(), rate.into());
+            match self {
+                Code::BitFlip => {
+                    circuit += ops::Hadamard::new(qubit);
+                    circuit += noise;
+                    circuit += ops::Hadamard::new(qubit);
+                }
+                Code::PhaseFlip => circuit += noise,
+            }
+        }
+        circuit
+    }
+
+    /// The fidelity of an unencoded state after the error of the code, |<psi|X|psi>|^2 or |<psi|Z|psi>|^2.
+    fn physical_error_fidelity(&self, psi: &DVector<Complex64>) -> f64 {
+        match self {
+            Code::BitFlip => logical_error_fidelity(psi),
+            Code::PhaseFlip => (psi[0].norm_sqr() - psi[1].norm_sqr()).powi(2),
+        }
+    }
+}
+
+/// The fidelity of the decoded state after a logical error, which is a PauliX for both codes, |<psi|X|psi>|^2.
+fn logical_error_fidelity(psi: &DVector<Complex64>) -> f64 {
+    (psi[0].conj() * psi[1] + psi[1].conj() * psi[0]).norm_sqr()
+}
+
+/// Returns the fidelity of qubit 0 of a state vector with the single-qubit state `psi`.
+fn fidelity(state_vector: &DVector<Complex64>, psi: &DVector<Complex64>) -> f64 {
+    (0..state_vector.len())
+        .step_by(2)
+        .map(|rest| {
+            (psi[0].conj() * state_vector[rest] + psi[1].conj() * state_vector[rest + 1]).norm_sqr()
+        })
+        .sum()
+}
+
+/// Replaces every PragmaRandomNoise of the circuit with a randomly drawn Pauli operation.
+///
+/// The depolarising rate applies PauliX, PauliY or PauliZ with a total probability of 3/4 (1 - exp(-r t)), the
+/// dephasing rate applies PauliZ with probability (1 - exp(-2 r t)) / 2, like the corresponding noise pragmas.
+fn sample_random_noise(circuit: &Circuit, rng: &mut StdRng) -> Circuit {
+    let mut sampled = Circuit::new();
+    for operation in circuit.iter() {
+        match operation {
+            Operation::PragmaRandomNoise(noise) => {
+                let qubit = *noise.qubit();
+                let gate_time = *noise.gate_time().float().unwrap();
+                let depolarising = *noise.depolarising_rate().float().unwrap();
+                let dephasing = *noise.dephasing_rate().float().unwrap();
+                if rng.gen::<f64>() < 0.75 * (1.0 - (-depolarising * gate_time).exp()) {
+                    sampled += match rng.gen_range(0..3) {
+                        0 => Operation::from(ops::PauliX::new(qubit)),
+                        1 => Operation::from(ops::PauliY::new(qubit)),
+                        _ => Operation::from(ops::PauliZ::new(qubit)),
+                    };
+                }
+                if rng.gen::<f64>() < 0.5 * (1.0 - (-2.0 * dephasing * gate_time).exp()) {
+                    sampled += ops::PauliZ::new(qubit);
+                }
+            }
+            _ => sampled += operation.clone(),
+        }
+    }
+    sampled
+}
+
+//  SHOR CODE
+// The nine-qubit Shor code combines both codes: the phase-flip code on three blocks, each of which is protected by the
+// bit-flip code. It corrects any single-qubit error, PauliX, PauliY or PauliZ. Instead of ancillas the syndrome is
+// extracted by decoding: after decoding a block, its second and third qubit hold the parities of the block and are
+// measured, a bit flip of the first qubit shows up as both bits being true. Decoding the three first qubits in the same
+// way in the Hadamard basis finds a phase flip of one of the blocks.
+fn shor_code(init_circuit: &Circuit, psi: &DVector<Complex64>) {
+    let blocks = [0, 3, 6];
+
+    let mut encoding = Circuit::new();
+    encoding += ops::CNOT::new(0, 3);
+    encoding += ops::CNOT::new(0, 6);
+    for block in blocks {
+        encoding += ops::Hadamard::new(block);
+        encoding += ops::CNOT::new(block, block + 1);
+        encoding += ops::CNOT::new(block, block + 2);
+    }
+
+    let mut correction = Circuit::new();
+    for (number, block) in blocks.iter().enumerate() {
+        correction += ops::CNOT::new(*block, block + 1);
+        correction += ops::CNOT::new(*block, block + 2);
+        correction += ops::MeasureQubit::new(block + 1, "shor".to_string(), 2 * number);
+        correction += ops::MeasureQubit::new(block + 2, "shor".to_string(), 2 * number + 1);
+        correction += conditional_on_bits(
+            "shor",
+            &[(2 * number, true), (2 * number + 1, true)],
+            "flag",
+            Circuit::new() + ops::PauliX::new(*block),
+        );
+        correction += ops::Hadamard::new(*block);
+    }
+    correction += ops::CNOT::new(0, 3);
+    correction += ops::CNOT::new(0, 6);
+    correction += ops::MeasureQubit::new(3, "shor".to_string(), 6);
+    correction += ops::MeasureQubit::new(6, "shor".to_string(), 7);
+    correction += conditional_on_bits(
+        "shor",
+        &[(6, true), (7, true)],
+        "flag",
+        Circuit::new() + ops::PauliX::new(0),
+    );
+
+    let mut errors: Vec<(String, Option<Operation>)> = vec![("none".to_string(), None)];
+    for qubit in 0..9 {
+        errors.push((format!("X{}", qubit), Some(ops::PauliX::new(qubit).into())));
+        errors.push((format!("Y{}", qubit), Some(ops::PauliY::new(qubit).into())));
+        errors.push((format!("Z{}", qubit), Some(ops::PauliZ::new(qubit).into())));
+    }
+    for (name, error) in errors.iter() {
+        let mut circuit = Circuit::new();
+        circuit += ops::DefinitionBit::new("shor".to_string(), 8, true);
+        circuit += ops::DefinitionBit::new("flag".to_string(), 1, true);
+        circuit += init_circuit.clone();
+        circuit += encoding.clone();
+        if let Some(error) = error {
+            circuit += error.clone();
+        }
+        circuit += correction.clone();
+        for branch in measurement_branches(&circuit, 9).unwrap() {
+            let fidelity = fidelity(&branch.state_vector, psi);
+            assert!(
+                (fidelity - 1.0).abs() < 1e-10,
+                "Error {} is not corrected, fidelity {}",
+                name,
+                fidelity
+            );
+        }
+    }
+    println!(
+        "Shor code corrects all {} single-qubit Pauli errors",
+        errors.len() - 1
+    );
+}
@@ -0,0 +1,97 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The simulator backend the examples run their circuits on.
+//!
+//! With the default `quest` feature [Backend] runs circuits on the QuEST simulator from roqoqo-quest. Without it, for
+//! example with `cargo run --no-default-features`, and while a [global seed](crate::seed) is set, [Backend] runs them
+//! on the simulators written in Rust, which do not need the QuEST C library and draw their random numbers from the
+//! global seed. Like QuEST, circuits with noise pragmas or `PragmaSetDensityMatrix` are run on the
+//! [DensityMatrixBackend], all other circuits on the [StateVectorBackend].
+
+use crate::density_matrix::DensityMatrixBackend;
+use crate::state_vector::StateVectorBackend;
+use roqoqo::backends::{EvaluatingBackend, RegisterResult};
+use roqoqo::operations::*;
+
+/// Runs circuits on QuEST or, without the `quest` feature or with a global seed, on the simulators written in Rust.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backend {
+    /// Number of qubits supported by the backend.
+    pub number_qubits: usize,
+}
+
+impl Backend {
+    /// Creates a new backend.
+    ///
+    /// # Arguments
+    ///
+    /// `number_qubits` - The number of qubits supported by the backend.
+    pub fn new(number_qubits: usize) -> Self {
+        Self { number_qubits }
+    }
+
+    /// Returns the simulator the circuits are run on, `"QuEST"` or `"examples_support"` for the simulators written in
+    /// Rust.
+    pub fn simulator(&self) -> &'static str {
+        #[cfg(feature = "quest")]
+        if crate::seed::global_seed().is_none() {
+            return "QuEST";
+        }
+        "examples_support"
+    }
+}
+
+impl EvaluatingBackend for Backend {
+    fn run_circuit_iterator<'a>(
+        &self,
+        circuit: impl Iterator<Item = &'a Operation>,
+    ) -> RegisterResult {
+        #[cfg(feature = "quest")]
+        if crate::seed::global_seed().is_none() {
+            return roqoqo_quest::Backend::new(self.number_qubits).run_circuit_iterator(circuit);
+        }
+        let operations: Vec<&Operation> = circuit.collect();
+        if operations
+            .iter()
+            .any(|operation| needs_density_matrix(operation))
+        {
+            DensityMatrixBackend::new(self.number_qubits)
+                .run_circuit_iterator(operations.into_iter())
+        } else {
+            StateVectorBackend::new(self.number_qubits).run_circuit_iterator(operations.into_iter())
+        }
+    }
+}
+
+/// Returns true if QuEST would simulate an operation with a density matrix.
+fn needs_density_matrix(operation: &Operation) -> bool {
+    match operation {
+        Operation::PragmaConditional(op) => op.circuit().iter().any(needs_density_matrix),
+        Operation::PragmaLoop(op) => op.circuit().iter().any(needs_density_matrix),
+        Operation::PragmaGetPauliProduct(op) => op.circuit().iter().any(needs_density_matrix),
+        Operation::PragmaGetOccupationProbability(op) => op
+            .circuit()
+            .as_ref()
+            .is_some_and(|circuit| circuit.iter().any(needs_density_matrix)),
+        Operation::PragmaGetDensityMatrix(op) => op
+            .circuit()
+            .as_ref()
+            .is_some_and(|circuit| circuit.iter().any(needs_density_matrix)),
+        Operation::PragmaDamping(_)
+        | Operation::PragmaDephasing(_)
+        | Operation::PragmaDepolarising(_)
+        | Operation::PragmaGeneralNoise(_)
+        | Operation::PragmaSetDensityMatrix(_) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Running many circuits or parameter sets of a quantum program concurrently.
+//!
+//! Optimising the parameters of a variational algorithm like the VHA runs the same `QuantumProgram` for many parameter
+//! sets, one after the other. [run_circuits] and [run_program] distribute the runs over the threads of the rayon thread
+//! pool instead. Every worker creates its own backend with the `new_backend` closure and reuses it for all runs it
+//! takes, and the results are returned in the order of the inputs.
+//!
+//! With a [global seed](crate::seed) every run draws its random numbers from its own stream, derived from the position
+//! of the run in the batch, so a batch gives the same results no matter how the runs are spread over the threads.
+
+use crate::seed;
+use rand::RngCore;
+use rayon::prelude::*;
+use roqoqo::backends::{EvaluatingBackend, RegisterResult};
+use roqoqo::{Circuit, QuantumProgram, RoqoqoBackendError};
+use std::collections::HashMap;
+
+/// Returns the number of threads the runs are spread over.
+///
+/// The number of threads of the rayon thread pool, which is set with the environment variable `RAYON_NUM_THREADS` and
+/// defaults to the number of logical cores.
+pub fn number_threads() -> usize {
+    rayon::current_num_threads()
+}
+
+/// Runs circuits concurrently, each worker on its own backend.
+///
+/// # Arguments
+///
+/// * `circuits` - The circuits to run.
+/// * `new_backend` - Creates the backend of a worker.
+///
+/// # Returns
+///
+/// The output registers of every circuit, in the order of `circuits`.
+pub fn run_circuits<B, F>(circuits: &[Circuit], new_backend: F) -> Vec<RegisterResult>
+where
+    B: EvaluatingBackend,
+    F: Fn() -> B + Sync + Send,
+{
+    let batch = seed::rng().next_u64();
+    circuits
+        .par_iter()
+        .enumerate()
+        .map_init(&new_backend, |backend, (index, circuit)| {
+            seed::with_stream(batch ^ index as u64, || backend.run_circuit(circuit))
+        })
+        .collect()
+}
+
+/// Runs a quantum program concurrently for many parameter sets, each worker on its own backend.
+///
+/// `QuantumProgram::run` takes the backend by value, every run gets a clone of the backend of its worker.
+///
+/// # Arguments
+///
+/// * `program` - The quantum program to run.
+/// * `parameter_sets` - The values of the input parameters of every run.
+/// * `new_backend` - Creates the backend of a worker.
+///
+/// # Returns
+///
+/// The expectation values of every run, in the order of `parameter_sets`.
+pub fn run_program<B, F>(
+    program: &QuantumProgram,
+    parameter_sets: &[Vec<f64>],
+    new_backend: F,
+) -> Vec<Result<Option<HashMap<String, f64>>, RoqoqoBackendError>>
+where
+    B: EvaluatingBackend + Clone,
+    F: Fn() -> B + Sync + Send,
+{
+    let batch = seed::rng().next_u64();
+    parameter_sets
+        .par_iter()
+        .enumerate()
+        .map_init(&new_backend, |backend, (index, parameters)| {
+            seed::with_stream(batch ^ index as u64, || {
+                program.run(backend.clone(), parameters)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed::GlobalSeedGuard;
+    use crate::state_vector::StateVectorBackend;
+    use roqoqo::measurements::{PauliZProduct, PauliZProductInput};
+    use roqoqo::operations::*;
+
+    /// Returns a circuit writing the bits of `index` to the register `ro` followed by random bits.
+    fn circuit(index: usize) -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 8, true);
+        for bit in 0..4 {
+            if index >> bit & 1 == 1 {
+                circuit += PauliX::new(bit);
+            }
+            circuit += Hadamard::new(bit + 4);
+        }
+        circuit += PragmaRepeatedMeasurement::new("ro".to_string(), 20, None);
+        circuit
+    }
+
+    /// Runs a function on a rayon thread pool with the given number of threads.
+    fn on_threads<T: Send>(number_threads: usize, function: impl FnOnce() -> T + Send) -> T {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(number_threads)
+            .build()
+            .unwrap()
+            .install(function)
+    }
+
+    #[test]
+    fn circuits_in_input_order_on_any_number_of_threads() {
+        let _seed = GlobalSeedGuard::new(Some(17));
+        let circuits: Vec<Circuit> = (0..16).map(circuit).collect();
+        let run = |number_threads: usize| {
+            on_threads(number_threads, || {
+                seed::with_stream(1, || {
+                    run_circuits(&circuits, || StateVectorBackend::new(8))
+                        .into_iter()
+                        .map(|result| result.unwrap().0["ro"].clone())
+                        .collect::<Vec<_>>()
+                })
+            })
+        };
+        let sequential = run(1);
+        for (index, shots) in sequential.iter().enumerate() {
+            assert_eq!(shots.len(), 20);
+            for shot in shots {
+                let written: usize = (0..4).map(|bit| usize::from(shot[bit]) << bit).sum();
+                assert_eq!(written, index);
+            }
+        }
+        assert_eq!(sequential, run(4));
+    }
+
+    #[test]
+    fn program_results_in_input_order() {
+        let _seed = GlobalSeedGuard::new(Some(17));
+        let mut rotations = Circuit::new();
+        rotations += DefinitionBit::new("ro".to_string(), 2, true);
+        rotations += RotateX::new(0, "theta_0".into());
+        rotations += RotateX::new(1, "theta_1".into());
+        rotations += PragmaRepeatedMeasurement::new("ro".to_string(), 10, None);
+        let mut input = PauliZProductInput::new(2, false);
+        let z_0 = input.add_pauliz_product("ro".to_string(), vec![0]).unwrap();
+        let z_1 = input.add_pauliz_product("ro".to_string(), vec![1]).unwrap();
+        input
+            .add_linear_exp_val(
+                "energy".to_string(),
+                HashMap::from([(z_0, 1.0), (z_1, 2.0)]),
+            )
+            .unwrap();
+        let program = QuantumProgram::PauliZProduct {
+            measurement: PauliZProduct {
+                constant_circuit: None,
+                circuits: vec![rotations],
+                input,
+            },
+            input_parameter_names: vec!["theta_0".to_string(), "theta_1".to_string()],
+        };
+        // Rotations by 0 or pi prepare basis states, so the energy Z_0 + 2 Z_1 identifies the index
+        let parameter_sets: Vec<Vec<f64>> = (0..12)
+            .map(|index| {
+                (0..2)
+                    .map(|bit| std::f64::consts::PI * (index >> bit & 1) as f64)
+                    .collect()
+            })
+            .collect();
+        let run = |number_threads: usize| {
+            on_threads(number_threads, || {
+                seed::with_stream(1, || {
+                    run_program(&program, &parameter_sets, || StateVectorBackend::new(2))
+                        .into_iter()
+                        .map(|result| result.unwrap().unwrap()["energy"])
+                        .collect::<Vec<f64>>()
+                })
+            })
+        };
+        let energies = run(1);
+        for (index, energy) in energies.iter().enumerate() {
+            let expected = [3.0, 1.0, -1.0, -3.0][index % 4];
+            assert!((energy - expected).abs() < 1e-10);
+        }
+        assert_eq!(energies, run(3));
+    }
+}
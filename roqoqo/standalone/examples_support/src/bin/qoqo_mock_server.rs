@@ -0,0 +1,137 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serves a mock remote quantum device over HTTP, running the submitted jobs on the QuEST simulator, or with a seed on
+//! the simulators written in Rust.
+//!
+//! ```text
+//! qoqo-mock-server [--port N] [--delay-ms N] [--failure-rate P] [--seed N]
+//! ```
+
+use examples_support::backend::Backend;
+use examples_support::remote::{serve, LocalJobQueue};
+use examples_support::seed::{parse_seed, set_global_seed, SEED_VARIABLE};
+use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: qoqo-mock-server [--port N] [--delay-ms N] [--failure-rate P] [--seed N]
+
+Serves a mock remote quantum device on localhost. Jobs with a Circuit or QuantumProgram serialized to json are
+submitted with POST /jobs, polled with GET /jobs/<id> and fetched with GET /jobs/<id>/result, see
+examples_support::remote. The jobs run on the QuEST simulator. With a seed, or when built without the quest feature,
+they run on the simulators of examples_support written in Rust instead.
+
+Options:
+  --port          Port to listen on, 0 picks a free port (default: 8080)
+  --delay-ms      Time every job stays queued before it runs, in milliseconds (default: 0)
+  --failure-rate  Fraction of requests rejected with 503 to test the retries of clients (default: 0)
+  --seed          Seed of the simulators written in Rust, which replace QuEST to give reproducible shots
+                  (default: the environment variable ROQOQO_EXAMPLES_SEED, otherwise unseeded)";
+
+/// Command line options of qoqo-mock-server.
+struct Options {
+    port: u16,
+    delay: Duration,
+    failure_rate: f64,
+    seed: Option<u64>,
+}
+
+fn parse_options(arguments: &[String]) -> Result<Options, String> {
+    let mut port = 8080;
+    let mut delay = Duration::ZERO;
+    let mut failure_rate = 0.0;
+    let mut seed: Option<u64> = None;
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = |name: &str| {
+            arguments
+                .next()
+                .cloned()
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+        match argument.as_str() {
+            "--port" => {
+                port = value("--port")?
+                    .parse()
+                    .map_err(|_| "Invalid port".to_string())?
+            }
+            "--delay-ms" => {
+                delay = Duration::from_millis(
+                    value("--delay-ms")?
+                        .parse()
+                        .map_err(|_| "Invalid delay".to_string())?,
+                )
+            }
+            "--failure-rate" => {
+                failure_rate = value("--failure-rate")?
+                    .parse()
+                    .ok()
+                    .filter(|rate| (0.0..=1.0).contains(rate))
+                    .ok_or_else(|| "The failure rate must be between 0 and 1".to_string())?
+            }
+            "--seed" => {
+                seed = Some(
+                    value("--seed")?
+                        .parse()
+                        .map_err(|_| "Invalid seed".to_string())?,
+                )
+            }
+            _ if argument.starts_with("--seed=") => {
+                seed = Some(
+                    argument["--seed=".len()..]
+                        .parse()
+                        .map_err(|_| "Invalid seed".to_string())?,
+                )
+            }
+            "-h" | "--help" => return Err(String::new()),
+            _ => return Err(format!("Unexpected argument {}", argument)),
+        }
+    }
+    Ok(Options {
+        port,
+        delay,
+        failure_rate,
+        seed: match seed {
+            Some(seed) => Some(seed),
+            None => parse_seed(&[], std::env::var(SEED_VARIABLE).ok())?,
+        },
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_options(&arguments) {
+        Ok(options) => options,
+        Err(msg) => {
+            if !msg.is_empty() {
+                eprintln!("Error: {}\n", msg);
+            }
+            eprintln!("{}", USAGE);
+            exit(if msg.is_empty() { 0 } else { 2 })
+        }
+    };
+    set_global_seed(options.seed);
+    let listener = match tokio::net::TcpListener::bind(("127.0.0.1", options.port)).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Error: Could not listen on port {}: {}", options.port, err);
+            exit(1)
+        }
+    };
+    if let Ok(address) = listener.local_addr() {
+        println!("Listening on http://{}", address);
+    }
+    let queue = LocalJobQueue::new(Backend::new).set_delay(options.delay);
+    serve(listener, Arc::new(queue), options.failure_rate).await
+}
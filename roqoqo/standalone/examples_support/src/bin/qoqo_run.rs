@@ -0,0 +1,171 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runs a serialized Circuit or QuantumProgram on the QuEST simulator, or with a seed on the simulators written in Rust.
+//!
+//! ```text
+//! qoqo-run <FILE> [--parameters name=value,...] [--qubits N] [--shots N] [--seed N] [--format json|csv]
+//! ```
+
+use examples_support::backend::Backend;
+use examples_support::runner::SerializedProgram;
+use examples_support::seed::{parse_seed, set_global_seed, SEED_VARIABLE};
+use std::collections::HashMap;
+use std::process::exit;
+
+const USAGE: &str = "Usage: qoqo-run <FILE> [--parameters name=value,...] [--qubits N] [--shots N] [--seed N] [--format json|csv]
+
+Runs a Circuit or QuantumProgram serialized to json on the QuEST simulator. With a seed, or when built without the
+quest feature, it runs on the simulators of examples_support written in Rust instead.
+
+Options:
+  --parameters  Values of the symbolic parameters, e.g. theta=0.5,phi=1.2, names the input does not use are rejected
+  --qubits      Number of simulated qubits (default: highest qubit used + 1)
+  --shots       Number of measurements of every readout (default: as set in the circuits)
+  --seed        Seed of the simulators written in Rust, which replace QuEST to give reproducible shots
+                (default: the environment variable ROQOQO_EXAMPLES_SEED, otherwise unseeded)
+  --format      Output format, json (default) or csv";
+
+/// Command line options of qoqo-run.
+struct Options {
+    file: String,
+    parameters: HashMap<String, f64>,
+    number_qubits: Option<usize>,
+    number_shots: Option<usize>,
+    seed: Option<u64>,
+    csv: bool,
+}
+
+fn parse_options(arguments: &[String]) -> Result<Options, String> {
+    let mut file: Option<String> = None;
+    let mut parameters: HashMap<String, f64> = HashMap::new();
+    let mut number_qubits: Option<usize> = None;
+    let mut number_shots: Option<usize> = None;
+    let mut seed: Option<u64> = None;
+    let mut csv = false;
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = |name: &str| {
+            arguments
+                .next()
+                .cloned()
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+        match argument.as_str() {
+            "--parameters" => {
+                for assignment in value("--parameters")?.split(',') {
+                    let (name, number) = assignment
+                        .split_once('=')
+                        .ok_or_else(|| format!("Expected name=value, found {}", assignment))?;
+                    let number: f64 = number
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("Invalid value for parameter {}", name))?;
+                    parameters.insert(name.trim().to_string(), number);
+                }
+            }
+            "--qubits" => {
+                number_qubits = Some(
+                    value("--qubits")?
+                        .parse()
+                        .map_err(|_| "Invalid number of qubits".to_string())?,
+                )
+            }
+            "--shots" => {
+                number_shots = Some(
+                    value("--shots")?
+                        .parse()
+                        .map_err(|_| "Invalid number of shots".to_string())?,
+                )
+            }
+            "--seed" => {
+                seed = Some(
+                    value("--seed")?
+                        .parse()
+                        .map_err(|_| "Invalid seed".to_string())?,
+                )
+            }
+            _ if argument.starts_with("--seed=") => {
+                seed = Some(
+                    argument["--seed=".len()..]
+                        .parse()
+                        .map_err(|_| "Invalid seed".to_string())?,
+                )
+            }
+            "--format" => {
+                csv = match value("--format")?.as_str() {
+                    "json" => false,
+                    "csv" => true,
+                    format => return Err(format!("Unknown output format {}", format)),
+                }
+            }
+            "-h" | "--help" => return Err(String::new()),
+            _ if argument.starts_with("--") => return Err(format!("Unknown option {}", argument)),
+            _ if file.is_none() => file = Some(argument.clone()),
+            _ => return Err(format!("Unexpected argument {}", argument)),
+        }
+    }
+    Ok(Options {
+        file: file.ok_or_else(|| "Missing input file".to_string())?,
+        parameters,
+        number_qubits,
+        number_shots,
+        seed: match seed {
+            Some(seed) => Some(seed),
+            None => parse_seed(&[], std::env::var(SEED_VARIABLE).ok())?,
+        },
+        csv,
+    })
+}
+
+fn run(options: Options) -> Result<String, String> {
+    set_global_seed(options.seed);
+    let json = std::fs::read_to_string(&options.file)
+        .map_err(|err| format!("Could not read {}: {}", options.file, err))?;
+    let mut program = SerializedProgram::from_json(&json).map_err(|err| err.to_string())?;
+    if let Some(number_shots) = options.number_shots {
+        program = program.with_number_measurements(number_shots);
+    }
+    let number_qubits = options
+        .number_qubits
+        .unwrap_or_else(|| program.number_qubits());
+    let backend = Backend::new(number_qubits);
+    let result = program
+        .run(backend, &options.parameters)
+        .map_err(|err| err.to_string())?;
+    Ok(if options.csv {
+        result.to_csv()
+    } else {
+        result.to_json()
+    })
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_options(&arguments) {
+        Ok(options) => options,
+        Err(msg) => {
+            if !msg.is_empty() {
+                eprintln!("Error: {}\n", msg);
+            }
+            eprintln!("{}", USAGE);
+            exit(if msg.is_empty() { 0 } else { 2 })
+        }
+    };
+    match run(options) {
+        Ok(output) => println!("{}", output.trim_end()),
+        Err(msg) => {
+            eprintln!("Error: {}", msg);
+            exit(1)
+        }
+    }
+}
@@ -0,0 +1,344 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Caching the results of circuits on disk.
+//!
+//! [CachingBackend] wraps any `EvaluatingBackend` and stores the `RegisterResult` of every circuit it runs in a cache
+//! directory. The key of a result is a hash of the serialized circuit, the [CacheKey] of the backend, which names the
+//! simulator, and the [global seed](crate::seed).
+//! `QuantumProgram`s substitute their parameters before running the circuits, so the same program run with the same
+//! parameters hits the cache, while new parameter values miss it. Running a circuit again, in the same run of an
+//! example or in a later one, reads the result from the cache instead of simulating it.
+//!
+//! The results are stored in a subdirectory named after the roqoqo version. Opening the cache removes the
+//! subdirectories of all other roqoqo versions, so results of an older roqoqo are never reused.
+//!
+//! Without a global seed a cached result is the one sample drawn when the circuit was first run, every later run of
+//! the circuit returns the same measured bits. With a global seed the circuits that are not in the cache run inside
+//! their own [stream](crate::seed::with_stream) given by their key, so a cached result is the one the run would give
+//! and hits and misses leave the random numbers of the following runs unchanged.
+
+use crate::backend::Backend;
+use crate::density_matrix::DensityMatrixBackend;
+use crate::mps::MpsBackend;
+use crate::runner::RunResult;
+use crate::seed;
+use crate::stabilizer::StabilizerBackend;
+use crate::state_vector::StateVectorBackend;
+use roqoqo::backends::{EvaluatingBackend, RegisterResult};
+use roqoqo::operations::Operation;
+use roqoqo::{RoqoqoBackendError, ROQOQO_VERSION};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The number of results written so far, which names the temporary files.
+static NUMBER_WRITES: AtomicU64 = AtomicU64::new(0);
+
+/// The number of runs answered from the cache and of runs that had to be simulated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStatistics {
+    /// Runs whose result was read from the cache.
+    pub hits: usize,
+    /// Runs that were simulated and stored in the cache.
+    pub misses: usize,
+}
+
+impl CacheStatistics {
+    /// Returns the fraction of runs read from the cache, zero before the first run.
+    pub fn hit_rate(&self) -> f64 {
+        let runs = self.hits + self.misses;
+        if runs == 0 {
+            0.0
+        } else {
+            self.hits as f64 / runs as f64
+        }
+    }
+}
+
+/// Describes a backend in the key of the cached results.
+///
+/// Backends giving different results for the same circuit need different descriptions, e.g. [Backend] running QuEST
+/// and [Backend] running the simulators written in Rust.
+pub trait CacheKey {
+    /// Returns the description of the backend.
+    fn cache_key(&self) -> String;
+}
+
+impl CacheKey for Backend {
+    fn cache_key(&self) -> String {
+        format!("{:?} on {}", self, self.simulator())
+    }
+}
+
+impl CacheKey for StateVectorBackend {
+    fn cache_key(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl CacheKey for DensityMatrixBackend {
+    fn cache_key(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl CacheKey for StabilizerBackend {
+    fn cache_key(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl CacheKey for MpsBackend {
+    fn cache_key(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+#[cfg(feature = "quest")]
+impl CacheKey for roqoqo_quest::Backend {
+    fn cache_key(&self) -> String {
+        format!("roqoqo_quest::{:?}", self)
+    }
+}
+
+/// Runs circuits on a backend and caches the results on disk.
+///
+/// Clones of the backend, e.g. the ones taken by `QuantumProgram::run`, share the cache statistics.
+#[derive(Debug, Clone)]
+pub struct CachingBackend<B> {
+    /// The backend running the circuits that are not in the cache.
+    pub backend: B,
+    directory: PathBuf,
+    hits: Arc<AtomicUsize>,
+    misses: Arc<AtomicUsize>,
+}
+
+impl<B> CachingBackend<B>
+where
+    B: EvaluatingBackend + CacheKey,
+{
+    /// Opens the cache in a directory, creating it if necessary, and removes the results of other roqoqo versions.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend running the circuits that are not in the cache.
+    /// * `directory` - The cache directory.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(CachingBackend)` - The backend with the cache.
+    /// * `Err(RoqoqoBackendError)` - The cache directory could not be created or cleaned.
+    pub fn new(backend: B, directory: &Path) -> Result<Self, RoqoqoBackendError> {
+        let error = |err: std::io::Error| RoqoqoBackendError::GenericError {
+            msg: format!("Could not open the cache {}: {}", directory.display(), err),
+        };
+        let version_directory = directory.join(format!("roqoqo-{}", ROQOQO_VERSION));
+        std::fs::create_dir_all(&version_directory).map_err(error)?;
+        for entry in std::fs::read_dir(directory).map_err(error)? {
+            let path = entry.map_err(error)?.path();
+            let stale = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("roqoqo-"))
+                && path != version_directory;
+            if stale && path.is_dir() {
+                std::fs::remove_dir_all(&path).map_err(error)?;
+            }
+        }
+        Ok(Self {
+            backend,
+            directory: version_directory,
+            hits: Arc::new(AtomicUsize::new(0)),
+            misses: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Returns the directory the results of the current roqoqo version are stored in.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Returns the number of cache hits and misses of this backend and its clones so far.
+    pub fn statistics(&self) -> CacheStatistics {
+        CacheStatistics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the file the result of a circuit is stored in and the stream of random numbers of its run.
+    fn entry(&self, operations: &[&Operation]) -> Result<(PathBuf, u64), RoqoqoBackendError> {
+        let circuit =
+            serde_json::to_string(operations).map_err(|err| RoqoqoBackendError::GenericError {
+                msg: format!("Could not serialize the circuit: {}", err),
+            })?;
+        let key = format!(
+            "{}\n{:?}\n{}",
+            self.backend.cache_key(),
+            seed::global_seed(),
+            circuit
+        );
+        let hash = fnv1a_128(key.as_bytes());
+        Ok((
+            self.directory.join(format!("{:032x}.json", hash)),
+            hash as u64,
+        ))
+    }
+}
+
+impl<B> EvaluatingBackend for CachingBackend<B>
+where
+    B: EvaluatingBackend + CacheKey,
+{
+    fn run_circuit_iterator<'a>(
+        &self,
+        circuit: impl Iterator<Item = &'a Operation>,
+    ) -> RegisterResult {
+        let operations: Vec<&Operation> = circuit.collect();
+        let (entry, stream) = self.entry(&operations)?;
+        if let Some(RunResult::Registers {
+            bit_registers,
+            float_registers,
+            complex_registers,
+        }) = std::fs::read_to_string(&entry)
+            .ok()
+            .and_then(|json| RunResult::from_json(&json).ok())
+        {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((bit_registers, float_registers, complex_registers));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let (bit_registers, float_registers, complex_registers) =
+            seed::with_stream(stream, || {
+                self.backend.run_circuit_iterator(operations.into_iter())
+            })?;
+        let json = RunResult::Registers {
+            bit_registers: bit_registers.clone(),
+            float_registers: float_registers.clone(),
+            complex_registers: complex_registers.clone(),
+        }
+        .to_json();
+        // Writing to a temporary file first keeps parallel runs from reading half-written results
+        let error = |err: std::io::Error| RoqoqoBackendError::GenericError {
+            msg: format!("Could not write {}: {}", entry.display(), err),
+        };
+        let temporary = entry.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            NUMBER_WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&temporary, json).map_err(error)?;
+        std::fs::rename(&temporary, &entry).map_err(error)?;
+        Ok((bit_registers, float_registers, complex_registers))
+    }
+}
+
+/// Returns the 128-bit FNV-1a hash of some bytes, which unlike the hasher of the standard library stays the same
+/// across Rust versions.
+fn fnv1a_128(bytes: &[u8]) -> u128 {
+    const OFFSET_BASIS: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u128::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed::GlobalSeedGuard;
+    use roqoqo::operations::*;
+    use roqoqo::Circuit;
+
+    /// A cache directory in the temporary directory, removed when dropped.
+    struct TemporaryDirectory(PathBuf);
+
+    impl TemporaryDirectory {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "examples_support-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TemporaryDirectory {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn random_bits() -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 3, true);
+        for qubit in 0..3 {
+            circuit += Hadamard::new(qubit);
+        }
+        circuit += PragmaRepeatedMeasurement::new("ro".to_string(), 20, None);
+        circuit
+    }
+
+    #[test]
+    fn removes_only_stale_versions() {
+        let directory = TemporaryDirectory::new("stale");
+        let stale = directory.0.join("roqoqo-0.0.0");
+        std::fs::create_dir_all(&stale).unwrap();
+        std::fs::write(stale.join("result.json"), "{}").unwrap();
+        let unrelated = directory.0.join("plots");
+        std::fs::create_dir_all(&unrelated).unwrap();
+        let backend = CachingBackend::new(StateVectorBackend::new(3), &directory.0).unwrap();
+        assert!(!stale.exists());
+        assert!(unrelated.exists());
+        assert_eq!(
+            backend.directory(),
+            directory.0.join(format!("roqoqo-{}", ROQOQO_VERSION))
+        );
+        assert!(backend.directory().is_dir());
+    }
+
+    #[test]
+    fn clones_share_statistics() {
+        let _seed = GlobalSeedGuard::new(None);
+        let directory = TemporaryDirectory::new("statistics");
+        let backend = CachingBackend::new(StateVectorBackend::new(3), &directory.0).unwrap();
+        let clone = backend.clone();
+        backend.run_circuit(&random_bits()).unwrap();
+        clone.run_circuit(&random_bits()).unwrap();
+        clone
+            .run_circuit(&(random_bits() + PauliX::new(0)))
+            .unwrap();
+        let statistics = CacheStatistics { hits: 1, misses: 2 };
+        assert_eq!(backend.statistics(), statistics);
+        assert_eq!(clone.statistics(), statistics);
+        assert!((statistics.hit_rate() - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn second_run_reads_stored_registers() {
+        let _seed = GlobalSeedGuard::new(None);
+        let directory = TemporaryDirectory::new("registers");
+        let first = CachingBackend::new(StateVectorBackend::new(3), &directory.0)
+            .unwrap()
+            .run_circuit(&random_bits())
+            .unwrap();
+        // A new backend on the same directory, like a later run of an example
+        let backend = CachingBackend::new(StateVectorBackend::new(3), &directory.0).unwrap();
+        let second = backend.run_circuit(&random_bits()).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(backend.statistics(), CacheStatistics { hits: 1, misses: 0 });
+    }
+}
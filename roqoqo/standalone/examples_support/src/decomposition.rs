@@ -0,0 +1,550 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decomposition of circuits into the native gates of a device.
+//!
+//! roqoqo itself does not translate circuits to the gates a device supports. [decompose_circuit] rewrites every gate
+//! that is not in the target [GateSet] step by step:
+//!
+//! * Three-qubit gates like `Toffoli` become CNOT, Hadamard and T gates.
+//! * Two-qubit gates like `ControlledPauliZ`, `ControlledPhaseShift` or `SWAP` become CNOT gates and single-qubit gates,
+//!   and CNOT becomes `ControlledPauliZ` if only that is native.
+//! * Single-qubit gates are written as RotateZ rotations around a RotateX, RotateY or SqrtPauliX gate using their Euler
+//!   angles. Rotations with symbolic angles are rewritten with fixed basis changes, so their parameters stay symbolic.
+//!
+//! Every rewrite of a gate without symbolic parameters is checked by comparing its unitary with the unitary of the
+//! replacement, up to a global phase.
+
+use crate::unitary;
+use num_complex::Complex64;
+use roqoqo::devices::Device;
+use roqoqo::operations::*;
+use roqoqo::{Circuit, RoqoqoError};
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+
+/// Angles below this value are treated as zero and their rotations are dropped.
+const ANGLE_TOLERANCE: f64 = 1e-12;
+
+/// The gates a circuit is decomposed into, by their hqslang names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GateSet {
+    /// Native single-qubit gates.
+    pub single_qubit_gates: Vec<String>,
+    /// Native two-qubit gates.
+    pub two_qubit_gates: Vec<String>,
+}
+
+impl GateSet {
+    /// Creates a gate set from the names of the native gates.
+    pub fn new(single_qubit_gates: &[&str], two_qubit_gates: &[&str]) -> Self {
+        GateSet {
+            single_qubit_gates: single_qubit_gates
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            two_qubit_gates: two_qubit_gates
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+
+    /// Queries the native gates of a device.
+    pub fn from_device<T: Device>(device: &T) -> Self {
+        GateSet {
+            single_qubit_gates: device.single_qubit_gate_names(),
+            two_qubit_gates: device.two_qubit_gate_names(),
+        }
+    }
+
+    fn contains(&self, operation: &Operation) -> bool {
+        let name = operation.hqslang().to_string();
+        match operation.involved_qubits() {
+            InvolvedQubits::Set(qubits) if qubits.len() == 1 => {
+                self.single_qubit_gates.contains(&name)
+            }
+            InvolvedQubits::Set(qubits) if qubits.len() == 2 => {
+                self.two_qubit_gates.contains(&name)
+            }
+            _ => false,
+        }
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.single_qubit_gates.iter().any(|gate| gate == name)
+            || self.two_qubit_gates.iter().any(|gate| gate == name)
+    }
+}
+
+/// Decomposes a circuit into the native gates of a device.
+///
+/// # Arguments
+///
+/// * `circuit` - The circuit.
+/// * `device` - The device whose single- and two-qubit gate names form the target gate set.
+///
+/// # Returns
+///
+/// * `Ok(Circuit)` - The decomposed circuit.
+/// * `Err(RoqoqoError)` - A gate can not be written with the gates of the device.
+///
+pub fn decompose_for_device<T: Device>(
+    circuit: &Circuit,
+    device: &T,
+) -> Result<Circuit, RoqoqoError> {
+    decompose_circuit(circuit, &GateSet::from_device(device))
+}
+
+/// Decomposes all gates of a circuit that are not in the gate set.
+///
+/// Operations that are not gates are kept, the circuits of `PragmaConditional` and `PragmaLoop` are decomposed as well.
+///
+/// # Returns
+///
+/// * `Ok(Circuit)` - The decomposed circuit.
+/// * `Err(RoqoqoError)` - A gate has no decomposition into the gate set.
+///
+pub fn decompose_circuit(circuit: &Circuit, gate_set: &GateSet) -> Result<Circuit, RoqoqoError> {
+    let mut decomposed = Circuit::new();
+    for operation in circuit.iter() {
+        decomposed += match operation {
+            Operation::PragmaConditional(op) => {
+                Circuit::new()
+                    + PragmaConditional::new(
+                        op.condition_register().clone(),
+                        *op.condition_index(),
+                        decompose_circuit(op.circuit(), gate_set)?,
+                    )
+            }
+            Operation::PragmaLoop(op) => {
+                Circuit::new()
+                    + PragmaLoop::new(
+                        op.repetitions().clone(),
+                        decompose_circuit(op.circuit(), gate_set)?,
+                    )
+            }
+            _ if unitary::is_gate(operation) => decompose_operation(operation, gate_set)?,
+            _ => Circuit::new() + operation.clone(),
+        };
+    }
+    Ok(decomposed)
+}
+
+/// Decomposes a single gate into the gate set.
+///
+/// Gates without symbolic parameters are checked by comparing the unitaries of the gate and of the decomposition.
+///
+/// # Returns
+///
+/// * `Ok(Circuit)` - The gate itself if it is native, otherwise its decomposition.
+/// * `Err(RoqoqoError)` - The gate has no decomposition into the gate set or the check of the decomposition failed.
+///
+pub fn decompose_operation(
+    operation: &Operation,
+    gate_set: &GateSet,
+) -> Result<Circuit, RoqoqoError> {
+    if gate_set.contains(operation) {
+        return Ok(Circuit::new() + operation.clone());
+    }
+    let rewrite = rewrite(operation, gate_set)?;
+    let mut decomposed = Circuit::new();
+    for gate in rewrite.iter() {
+        decomposed += decompose_operation(gate, gate_set)?;
+    }
+    if !operation.is_parametrized() {
+        check_decomposition(operation, &decomposed)?;
+    }
+    Ok(decomposed)
+}
+
+/// Counts the gates of a circuit by name, including the gates in nested circuits.
+pub fn gate_counts(circuit: &Circuit) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for operation in circuit.iter() {
+        match operation {
+            Operation::PragmaConditional(op) => {
+                for (name, count) in gate_counts(op.circuit()) {
+                    *counts.entry(name).or_insert(0) += count;
+                }
+            }
+            _ if unitary::is_gate(operation) => {
+                *counts.entry(operation.hqslang().to_string()).or_insert(0) += 1;
+            }
+            _ => (),
+        }
+    }
+    counts
+}
+
+/// Compares the gate with its decomposition on the qubits the gate acts on.
+fn check_decomposition(operation: &Operation, decomposed: &Circuit) -> Result<(), RoqoqoError> {
+    let qubits: Vec<usize> = match operation.involved_qubits() {
+        InvolvedQubits::Set(qubits) => qubits.into_iter().collect(),
+        _ => Vec::new(),
+    };
+    // Remapping needs a permutation, the qubits of the gate go first and all others follow in order
+    let largest_qubit = qubits.iter().copied().max().unwrap_or(0);
+    let mapping: HashMap<usize, usize> = qubits
+        .iter()
+        .copied()
+        .chain((0..=largest_qubit).filter(|qubit| !qubits.contains(qubit)))
+        .enumerate()
+        .map(|(local, qubit)| (qubit, local))
+        .collect();
+    let local_gate = Circuit::new() + operation.remap_qubits(&mapping)?;
+    let local_decomposition = decomposed.remap_qubits(&mapping)?;
+    let number_qubits = qubits.len();
+    let comparison = unitary::compare_unitaries(
+        &unitary::circuit_unitary(&local_gate, number_qubits)?,
+        &unitary::circuit_unitary(&local_decomposition, number_qubits)?,
+        unitary::TOLERANCE,
+    )?;
+    if comparison.is_equivalent() {
+        Ok(())
+    } else {
+        Err(RoqoqoError::GenericError {
+            msg: format!(
+                "Decomposition of {} is not equivalent: {}",
+                operation.hqslang(),
+                comparison
+            ),
+        })
+    }
+}
+
+/// One rewriting step of a gate that is not native, into gates that are closer to the gate set.
+fn rewrite(operation: &Operation, gate_set: &GateSet) -> Result<Circuit, RoqoqoError> {
+    let t_dagger = |qubit: usize| PhaseShiftState1::new(qubit, (-PI / 4.0).into());
+    let s_dagger = |qubit: usize| PhaseShiftState1::new(qubit, (-PI / 2.0).into());
+    let mut circuit = Circuit::new();
+    match operation {
+        Operation::Toffoli(op) => {
+            let (control_0, control_1, target) = (*op.control_0(), *op.control_1(), *op.target());
+            circuit += Hadamard::new(target);
+            circuit += ControlledControlledPauliZ::new(control_0, control_1, target);
+            circuit += Hadamard::new(target);
+        }
+        Operation::ControlledControlledPauliZ(op) => {
+            let (control_0, control_1, target) = (*op.control_0(), *op.control_1(), *op.target());
+            circuit += CNOT::new(control_1, target);
+            circuit += t_dagger(target);
+            circuit += CNOT::new(control_0, target);
+            circuit += TGate::new(target);
+            circuit += CNOT::new(control_1, target);
+            circuit += t_dagger(target);
+            circuit += CNOT::new(control_0, target);
+            circuit += TGate::new(control_1);
+            circuit += TGate::new(target);
+            circuit += CNOT::new(control_0, control_1);
+            circuit += TGate::new(control_0);
+            circuit += t_dagger(control_1);
+            circuit += CNOT::new(control_0, control_1);
+        }
+        Operation::ControlledControlledPhaseShift(op) => {
+            let (control_0, control_1, target) = (*op.control_0(), *op.control_1(), *op.target());
+            let half = op.theta().clone() / 2.0;
+            circuit += ControlledPhaseShift::new(control_1, target, half.clone());
+            circuit += CNOT::new(control_0, control_1);
+            circuit += ControlledPhaseShift::new(control_1, target, -half.clone());
+            circuit += CNOT::new(control_0, control_1);
+            circuit += ControlledPhaseShift::new(control_0, target, half);
+        }
+        Operation::CNOT(op) => {
+            if !gate_set.has("ControlledPauliZ") {
+                return Err(missing_entangling_gate(operation));
+            }
+            circuit += Hadamard::new(*op.target());
+            circuit += ControlledPauliZ::new(*op.control(), *op.target());
+            circuit += Hadamard::new(*op.target());
+        }
+        Operation::ControlledPauliZ(op) => {
+            if !gate_set.has("CNOT") {
+                return Err(missing_entangling_gate(operation));
+            }
+            circuit += Hadamard::new(*op.target());
+            circuit += CNOT::new(*op.control(), *op.target());
+            circuit += Hadamard::new(*op.target());
+        }
+        Operation::ControlledPauliY(op) => {
+            circuit += s_dagger(*op.target());
+            circuit += CNOT::new(*op.control(), *op.target());
+            circuit += SGate::new(*op.target());
+        }
+        Operation::ControlledPhaseShift(op) => {
+            let (control, target) = (*op.control(), *op.target());
+            let half = op.theta().clone() / 2.0;
+            circuit += PhaseShiftState1::new(control, half.clone());
+            circuit += CNOT::new(control, target);
+            circuit += PhaseShiftState1::new(target, -half.clone());
+            circuit += CNOT::new(control, target);
+            circuit += PhaseShiftState1::new(target, half);
+        }
+        Operation::ControlledRotateX(op) => {
+            let (control, target) = (*op.control(), *op.target());
+            let half = op.theta().clone() / 2.0;
+            circuit += Hadamard::new(target);
+            circuit += RotateZ::new(target, half.clone());
+            circuit += CNOT::new(control, target);
+            circuit += RotateZ::new(target, -half);
+            circuit += CNOT::new(control, target);
+            circuit += Hadamard::new(target);
+        }
+        Operation::SWAP(op) => {
+            let (control, target) = (*op.control(), *op.target());
+            circuit += CNOT::new(control, target);
+            circuit += CNOT::new(target, control);
+            circuit += CNOT::new(control, target);
+        }
+        Operation::RotateX(op) if op.theta().is_float() => {
+            circuit += single_qubit_decomposition(operation, gate_set)?;
+        }
+        Operation::RotateX(op) => {
+            circuit += Hadamard::new(*op.qubit());
+            circuit += RotateZ::new(*op.qubit(), op.theta().clone());
+            circuit += Hadamard::new(*op.qubit());
+        }
+        Operation::RotateY(op) if op.theta().is_float() => {
+            circuit += single_qubit_decomposition(operation, gate_set)?;
+        }
+        Operation::RotateY(op) => {
+            // S H Z H S^dagger = Y
+            circuit += s_dagger(*op.qubit());
+            circuit += Hadamard::new(*op.qubit());
+            circuit += RotateZ::new(*op.qubit(), op.theta().clone());
+            circuit += Hadamard::new(*op.qubit());
+            circuit += SGate::new(*op.qubit());
+        }
+        Operation::PhaseShiftState1(op) if !op.theta().is_float() => {
+            circuit += RotateZ::new(*op.qubit(), op.theta().clone());
+        }
+        Operation::RotateZ(op) if !op.theta().is_float() => {
+            if !gate_set.has("PhaseShiftState1") {
+                return Err(RoqoqoError::GenericError {
+                    msg: "RotateZ with a symbolic angle needs RotateZ or PhaseShiftState1 in the gate set"
+                        .to_string(),
+                });
+            }
+            circuit += PhaseShiftState1::new(*op.qubit(), op.theta().clone());
+        }
+        _ if SingleQubitGateOperation::try_from(operation).is_ok() => {
+            if operation.is_parametrized() {
+                return Err(RoqoqoError::GenericError {
+                    msg: format!(
+                        "{} with symbolic parameters can not be decomposed, substitute the parameters first",
+                        operation.hqslang()
+                    ),
+                });
+            }
+            circuit += single_qubit_decomposition(operation, gate_set)?;
+        }
+        _ => {
+            return Err(RoqoqoError::GenericError {
+                msg: format!(
+                    "No decomposition of {} into the gate set is known",
+                    operation.hqslang()
+                ),
+            })
+        }
+    }
+    Ok(circuit)
+}
+
+fn missing_entangling_gate(operation: &Operation) -> RoqoqoError {
+    RoqoqoError::GenericError {
+        msg: format!(
+            "{} can only be decomposed if CNOT or ControlledPauliZ is in the gate set",
+            operation.hqslang()
+        ),
+    }
+}
+
+/// Writes a single-qubit gate without symbolic parameters with its Euler angles.
+///
+/// Every unitary can be written as RotateZ(beta) RotateY(gamma) RotateZ(delta) up to a global phase. The RotateY
+/// rotation is replaced by RotateX or two SqrtPauliX gates if RotateY is not in the gate set.
+fn single_qubit_decomposition(
+    operation: &Operation,
+    gate_set: &GateSet,
+) -> Result<Circuit, RoqoqoError> {
+    let gate = SingleQubitGateOperation::try_from(operation)?;
+    let qubit = *gate.qubit();
+    let matrix = gate.unitary_matrix()?;
+    // Removes the global phase so the determinant is one
+    let determinant = matrix[[0, 0]] * matrix[[1, 1]] - matrix[[0, 1]] * matrix[[1, 0]];
+    let phase = determinant.sqrt();
+    let (u00, u10, u11): (Complex64, Complex64, Complex64) = (
+        matrix[[0, 0]] / phase,
+        matrix[[1, 0]] / phase,
+        matrix[[1, 1]] / phase,
+    );
+    let gamma = 2.0 * u10.norm().atan2(u00.norm());
+    // u11 = cos(gamma / 2) exp(i (beta + delta) / 2) and u10 = sin(gamma / 2) exp(i (beta - delta) / 2)
+    let sum = if u11.norm() > ANGLE_TOLERANCE {
+        2.0 * u11.arg()
+    } else {
+        0.0
+    };
+    let difference = if u10.norm() > ANGLE_TOLERANCE {
+        2.0 * u10.arg()
+    } else {
+        0.0
+    };
+    let (beta, delta) = ((sum + difference) / 2.0, (sum - difference) / 2.0);
+
+    if !gate_set.has("RotateZ") {
+        return Err(RoqoqoError::GenericError {
+            msg: format!(
+                "Single-qubit gate {} can only be decomposed if RotateZ is in the gate set",
+                operation.hqslang()
+            ),
+        });
+    }
+    let mut circuit = Circuit::new();
+    let rotate_z = |circuit: &mut Circuit, angle: f64| {
+        let angle = (angle + PI).rem_euclid(2.0 * PI) - PI;
+        if angle.abs() > ANGLE_TOLERANCE {
+            *circuit += RotateZ::new(qubit, angle.into());
+        }
+    };
+    if gamma.abs() < ANGLE_TOLERANCE {
+        rotate_z(&mut circuit, beta + delta);
+    } else if gate_set.has("RotateY") {
+        rotate_z(&mut circuit, delta);
+        circuit += RotateY::new(qubit, gamma.into());
+        rotate_z(&mut circuit, beta);
+    } else if gate_set.has("RotateX") {
+        // RotateY(gamma) = RotateZ(pi / 2) RotateX(gamma) RotateZ(-pi / 2)
+        rotate_z(&mut circuit, delta - PI / 2.0);
+        circuit += RotateX::new(qubit, gamma.into());
+        rotate_z(&mut circuit, beta + PI / 2.0);
+    } else if gate_set.has("SqrtPauliX") {
+        // RotateY(gamma) = SqrtPauliX RotateZ(pi - gamma) SqrtPauliX RotateZ(-pi) up to a global phase
+        rotate_z(&mut circuit, delta - PI);
+        circuit += SqrtPauliX::new(qubit);
+        rotate_z(&mut circuit, PI - gamma);
+        circuit += SqrtPauliX::new(qubit);
+        rotate_z(&mut circuit, beta);
+    } else {
+        return Err(RoqoqoError::GenericError {
+            msg: format!(
+                "{} is not diagonal and needs RotateY, RotateX or SqrtPauliX in the gate set",
+                operation.hqslang()
+            ),
+        });
+    }
+    Ok(circuit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use roqoqo::devices::AllToAllDevice;
+
+    /// The three single-qubit gate sets, each with CNOT and with ControlledPauliZ as the entangling gate.
+    fn gate_sets() -> Vec<GateSet> {
+        ["RotateY", "RotateX", "SqrtPauliX"]
+            .into_iter()
+            .flat_map(|gate| {
+                ["CNOT", "ControlledPauliZ"]
+                    .into_iter()
+                    .map(move |entangling| GateSet::new(&["RotateZ", gate], &[entangling]))
+            })
+            .collect()
+    }
+
+    /// Decomposes a gate and checks the gates and the unitary of the result independently of the internal check.
+    fn assert_decomposes(operation: Operation, gate_set: &GateSet) {
+        let gate = Circuit::new() + operation.clone();
+        let decomposed = decompose_circuit(&gate, gate_set).unwrap();
+        for name in gate_counts(&decomposed).keys() {
+            assert!(gate_set.has(name), "{name} is not in {gate_set:?}");
+        }
+        assert!(
+            unitary::circuits_equivalent(&gate, &decomposed).unwrap(),
+            "{operation:?} in {gate_set:?}"
+        );
+    }
+
+    #[test]
+    fn random_single_qubit_gates() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut angle = || rng.gen_range(-2.0 * PI..2.0 * PI);
+        let mut gates: Vec<Operation> = Vec::new();
+        for _ in 0..10 {
+            gates.push(RotateX::new(0, angle().into()).into());
+            gates.push(RotateY::new(0, angle().into()).into());
+            gates.push(RotateZ::new(0, angle().into()).into());
+            gates.push(PhaseShiftState1::new(0, angle().into()).into());
+            gates.push(RotateXY::new(0, angle().into(), angle().into()).into());
+            gates.push(
+                RotateAroundSphericalAxis::new(0, angle().into(), angle().into(), angle().into())
+                    .into(),
+            );
+        }
+        gates.extend([
+            Hadamard::new(0).into(),
+            PauliX::new(0).into(),
+            PauliY::new(0).into(),
+            PauliZ::new(0).into(),
+            SGate::new(0).into(),
+            TGate::new(0).into(),
+            SqrtPauliX::new(0).into(),
+            InvSqrtPauliX::new(0).into(),
+        ]);
+        for gate_set in gate_sets() {
+            for gate in gates.iter() {
+                assert_decomposes(gate.clone(), &gate_set);
+            }
+        }
+    }
+
+    #[test]
+    fn multi_qubit_rewrites() {
+        let gates: Vec<Operation> = vec![
+            CNOT::new(2, 0).into(),
+            ControlledPauliZ::new(0, 2).into(),
+            ControlledPauliY::new(1, 0).into(),
+            ControlledPhaseShift::new(2, 1, 0.7.into()).into(),
+            ControlledRotateX::new(0, 1, (-1.3).into()).into(),
+            SWAP::new(1, 2).into(),
+            Toffoli::new(2, 0, 1).into(),
+            ControlledControlledPauliZ::new(0, 2, 1).into(),
+            ControlledControlledPhaseShift::new(1, 2, 0, 2.1.into()).into(),
+        ];
+        for gate_set in gate_sets() {
+            for gate in gates.iter() {
+                assert_decomposes(gate.clone(), &gate_set);
+            }
+        }
+    }
+
+    #[test]
+    fn device_without_basis_change() {
+        let device = AllToAllDevice::new(2, &["RotateZ".to_string()], &["CNOT".to_string()], 1.0);
+        let mut circuit = Circuit::new();
+        circuit += RotateZ::new(0, 0.3.into());
+        circuit += CNOT::new(0, 1);
+        assert_eq!(decompose_for_device(&circuit, &device).unwrap(), circuit);
+        circuit += Hadamard::new(1);
+        let error = decompose_for_device(&circuit, &device).unwrap_err();
+        assert_eq!(
+            error,
+            RoqoqoError::GenericError {
+                msg: "Hadamard is not diagonal and needs RotateY, RotateX or SqrtPauliX in the gate set"
+                    .to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,632 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A density-matrix simulator written in Rust, implementing the roqoqo `EvaluatingBackend`.
+//!
+//! [DensityMatrixBackend] supports the operations of the [StateVectorBackend](crate::state_vector::StateVectorBackend)
+//! and the noise pragmas `PragmaDamping`, `PragmaDephasing`, `PragmaDepolarising` and `PragmaGeneralNoise`. The noise
+//! is applied exactly with the superoperator of the pragma, which acts on the vectorised single-qubit blocks
+//! `[rho_00, rho_01, rho_10, rho_11]` of the density matrix. `PragmaGetDensityMatrix` reads out the density matrix
+//! row by row like QuEST, `PragmaSetDensityMatrix` replaces it.
+//!
+//! Instead of following one random outcome, every `MeasureQubit` splits the simulation into one
+//! [DensityMatrixBranch] per outcome. [DensityMatrixBackend::branches] returns all branches with their probabilities,
+//! so results of noisy circuits with mid-circuit measurements can be averaged without sampling. A run of the
+//! `EvaluatingBackend` picks one branch at random for every repetition, which gives the same statistics as QuEST.
+//! `PragmaActiveReset` does not store its outcome and is applied as a channel without splitting.
+
+use crate::seed;
+use crate::state_vector::{plan_measurements, sample_shots, FinalSampling};
+use crate::unitary;
+use nalgebra::{DMatrix, DVector};
+use num_complex::Complex64;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use roqoqo::backends::{EvaluatingBackend, RegisterResult};
+use roqoqo::operations::*;
+use roqoqo::registers::{
+    BitOutputRegister, BitRegister, ComplexOutputRegister, ComplexRegister, FloatOutputRegister,
+    FloatRegister,
+};
+use roqoqo::{Circuit, RoqoqoBackendError};
+use std::collections::{HashMap, HashSet};
+
+/// The name of the backend in `OperationNotInBackend` errors.
+const BACKEND_NAME: &str = "DensityMatrixBackend";
+
+/// Branches with a probability below this value are dropped.
+const PROBABILITY_CUTOFF: f64 = 1e-12;
+
+/// Simulates circuits with a density matrix of `number_qubits` qubits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DensityMatrixBackend {
+    /// Number of qubits supported by the backend.
+    pub number_qubits: usize,
+    /// The seed of the random number generator of every run, `None` uses [crate::seed::rng].
+    pub seed: Option<u64>,
+}
+
+/// A branch of a density-matrix simulation for one sequence of measurement outcomes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DensityMatrixBranch {
+    /// The probability of the branch.
+    pub probability: f64,
+    /// The normalised density matrix at the end of the branch.
+    pub density_matrix: DMatrix<Complex64>,
+    /// The classical bit registers at the end of the branch.
+    pub bit_registers: HashMap<String, BitRegister>,
+    /// The classical float registers at the end of the branch.
+    pub float_registers: HashMap<String, FloatRegister>,
+    /// The classical complex registers at the end of the branch.
+    pub complex_registers: HashMap<String, ComplexRegister>,
+    /// Repeated measurements, with the probabilities of the basis states and the readout register when they happened.
+    repeated_measurements: Vec<(FinalSampling, Vec<f64>, BitRegister)>,
+}
+
+impl DensityMatrixBackend {
+    /// Creates a new density-matrix backend.
+    ///
+    /// # Arguments
+    ///
+    /// `number_qubits` - The number of qubits supported by the backend.
+    pub fn new(number_qubits: usize) -> Self {
+        Self {
+            number_qubits,
+            seed: None,
+        }
+    }
+
+    /// Sets the seed of the random number generator.
+    ///
+    /// Every run of the backend with a seed draws the same random numbers, so running a circuit twice gives the same
+    /// measurement results. Without a seed the runs draw from [crate::seed::rng], which follows the global seed.
+    ///
+    /// # Arguments
+    ///
+    /// `seed` - The seed of the random number generator.
+    pub fn set_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Follows all measurement outcomes of a circuit starting from the state |0...0>.
+    ///
+    /// # Arguments
+    ///
+    /// * `circuit` - The circuit, all parameters need to be substituted.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<DensityMatrixBranch>)` - All branches with a non-vanishing probability.
+    /// * `Err(RoqoqoBackendError)` - The circuit contains an operation the backend does not support.
+    ///
+    pub fn branches(
+        &self,
+        circuit: &Circuit,
+    ) -> Result<Vec<DensityMatrixBranch>, RoqoqoBackendError> {
+        evolve(circuit.iter(), vec![self.initial_branch()])
+    }
+
+    fn initial_branch(&self) -> DensityMatrixBranch {
+        let dimension = 1 << self.number_qubits;
+        let mut density_matrix = DMatrix::zeros(dimension, dimension);
+        density_matrix[(0, 0)] = Complex64::new(1.0, 0.0);
+        DensityMatrixBranch {
+            probability: 1.0,
+            density_matrix,
+            bit_registers: HashMap::new(),
+            float_registers: HashMap::new(),
+            complex_registers: HashMap::new(),
+            repeated_measurements: Vec::new(),
+        }
+    }
+}
+
+impl EvaluatingBackend for DensityMatrixBackend {
+    fn run_circuit_iterator<'a>(
+        &self,
+        circuit: impl Iterator<Item = &'a Operation>,
+    ) -> RegisterResult {
+        let (operations, final_sampling, repetitions) =
+            plan_measurements(circuit.collect::<Vec<&Operation>>())?;
+        let branches = evolve(operations.iter().copied(), vec![self.initial_branch()])?;
+        let distribution = WeightedIndex::new(branches.iter().map(|branch| branch.probability))
+            .map_err(|err| RoqoqoBackendError::GenericError {
+                msg: format!("Measurement branches can not be sampled: {}", err),
+            })?;
+        let mut rng = seed::LazyRng::new(self.seed);
+
+        let mut bit_outputs: HashMap<String, BitOutputRegister> = HashMap::new();
+        let mut float_outputs: HashMap<String, FloatOutputRegister> = HashMap::new();
+        let mut complex_outputs: HashMap<String, ComplexOutputRegister> = HashMap::new();
+        for operation in operations.iter() {
+            match operation {
+                Operation::DefinitionBit(def) if *def.is_output() => {
+                    bit_outputs.insert(def.name().clone(), Vec::new());
+                }
+                Operation::DefinitionFloat(def) if *def.is_output() => {
+                    float_outputs.insert(def.name().clone(), Vec::new());
+                }
+                Operation::DefinitionComplex(def) if *def.is_output() => {
+                    complex_outputs.insert(def.name().clone(), Vec::new());
+                }
+                _ => (),
+            }
+        }
+
+        for _ in 0..repetitions {
+            // A single branch is taken without drawing, deterministic runs do not take a generator
+            let branch = match branches.len() {
+                1 => &branches[0],
+                _ => &branches[distribution.sample(&mut rng)],
+            };
+            let mut sampled_readouts: HashSet<&String> = HashSet::new();
+            let final_measurement = final_sampling.as_ref().map(|sampling| {
+                let register = branch.bit_registers.get(&sampling.readout);
+                let probabilities = diagonal(&branch.density_matrix);
+                (sampling, probabilities, register)
+            });
+            for (sampling, probabilities, register) in branch
+                .repeated_measurements
+                .iter()
+                .map(|(sampling, probabilities, register)| {
+                    (sampling, probabilities.clone(), Some(register))
+                })
+                .chain(final_measurement)
+            {
+                let register = register.ok_or_else(|| RoqoqoBackendError::GenericError {
+                    msg: format!("Bit register {} is not defined", sampling.readout),
+                })?;
+                let shots = sample_shots(
+                    probabilities,
+                    sampling,
+                    register,
+                    self.number_qubits,
+                    &mut rng,
+                )?;
+                if let Some(output) = bit_outputs.get_mut(&sampling.readout) {
+                    output.extend(shots);
+                    sampled_readouts.insert(&sampling.readout);
+                }
+            }
+            for (name, register) in bit_outputs.iter_mut() {
+                if !sampled_readouts.contains(name) {
+                    if let Some(bits) = branch.bit_registers.get(name) {
+                        register.push(bits.clone());
+                    }
+                }
+            }
+            for (name, register) in float_outputs.iter_mut() {
+                if let Some(values) = branch.float_registers.get(name) {
+                    register.push(values.clone());
+                }
+            }
+            for (name, register) in complex_outputs.iter_mut() {
+                if let Some(values) = branch.complex_registers.get(name) {
+                    register.push(values.clone());
+                }
+            }
+        }
+        Ok((bit_outputs, float_outputs, complex_outputs))
+    }
+}
+
+fn evolve<'a>(
+    operations: impl Iterator<Item = &'a Operation>,
+    mut branches: Vec<DensityMatrixBranch>,
+) -> Result<Vec<DensityMatrixBranch>, RoqoqoBackendError> {
+    for operation in operations {
+        let mut next_branches = Vec::with_capacity(branches.len());
+        for branch in branches {
+            next_branches.extend(step(operation, branch)?);
+        }
+        branches = next_branches;
+    }
+    Ok(branches)
+}
+
+fn step(
+    operation: &Operation,
+    mut branch: DensityMatrixBranch,
+) -> Result<Vec<DensityMatrixBranch>, RoqoqoBackendError> {
+    let number_qubits = branch.density_matrix.nrows().trailing_zeros() as usize;
+    match operation {
+        Operation::DefinitionBit(def) => {
+            branch
+                .bit_registers
+                .insert(def.name().clone(), vec![false; *def.length()]);
+        }
+        Operation::DefinitionFloat(def) => {
+            branch
+                .float_registers
+                .insert(def.name().clone(), vec![0.0; *def.length()]);
+        }
+        Operation::DefinitionComplex(def) => {
+            branch.complex_registers.insert(
+                def.name().clone(),
+                vec![Complex64::new(0.0, 0.0); *def.length()],
+            );
+        }
+        Operation::InputBit(op) => *branch.bit(op.name(), *op.index())? = *op.value(),
+        Operation::MeasureQubit(op) => {
+            check_qubit(*op.qubit(), number_qubits)?;
+            branch.bit(op.readout(), *op.readout_index())?;
+            let mut outcomes = Vec::with_capacity(2);
+            for outcome in [false, true] {
+                let mut projected = branch.clone();
+                let probability = project(&mut projected.density_matrix, *op.qubit(), outcome);
+                if probability * branch.probability > PROBABILITY_CUTOFF {
+                    projected.density_matrix.unscale_mut(probability);
+                    projected.probability *= probability;
+                    *projected.bit(op.readout(), *op.readout_index())? = outcome;
+                    outcomes.push(projected);
+                }
+            }
+            return Ok(outcomes);
+        }
+        Operation::PragmaActiveReset(op) => {
+            check_qubit(*op.qubit(), number_qubits)?;
+            let mut excited = branch.density_matrix.clone();
+            project(&mut branch.density_matrix, *op.qubit(), false);
+            project(&mut excited, *op.qubit(), true);
+            apply_gate(&mut excited, &PauliX::new(*op.qubit()).into())?;
+            branch.density_matrix += excited;
+        }
+        Operation::PragmaConditional(op) => {
+            if *branch.bit(op.condition_register(), *op.condition_index())? {
+                return evolve(op.circuit().iter(), vec![branch]);
+            }
+        }
+        Operation::PragmaLoop(op) => {
+            let mut branches = vec![branch];
+            for _ in 0..*op.repetitions().float()? as usize {
+                branches = evolve(op.circuit().iter(), branches)?;
+            }
+            return Ok(branches);
+        }
+        Operation::PragmaRepeatedMeasurement(op) => {
+            let register = branch
+                .bit_registers
+                .get(op.readout())
+                .cloned()
+                .ok_or_else(|| RoqoqoBackendError::GenericError {
+                    msg: format!("Bit register {} is not defined", op.readout()),
+                })?;
+            let measured_qubits: Vec<(usize, usize)> = (0..number_qubits)
+                .filter_map(|qubit| match op.qubit_mapping() {
+                    Some(mapping) => mapping.get(&qubit).map(|index| (qubit, *index)),
+                    None => Some((qubit, qubit)),
+                })
+                .filter(|(_, index)| *index < register.len())
+                .collect();
+            let sampling = FinalSampling {
+                readout: op.readout().clone(),
+                number_measurements: *op.number_measurements(),
+                measured_qubits,
+            };
+            let probabilities = diagonal(&branch.density_matrix);
+            branch
+                .repeated_measurements
+                .push((sampling, probabilities, register));
+        }
+        Operation::PragmaSetStateVector(op) => {
+            check_dimension(op.statevector().len(), number_qubits)?;
+            let state_vector =
+                DVector::from_iterator(op.statevector().len(), op.statevector().iter().copied());
+            branch.density_matrix = &state_vector * state_vector.adjoint();
+        }
+        Operation::PragmaSetDensityMatrix(op) => {
+            check_dimension(op.density_matrix().nrows(), number_qubits)?;
+            let dimension = op.density_matrix().nrows();
+            branch.density_matrix = DMatrix::from_fn(dimension, dimension, |row, column| {
+                op.density_matrix()[[row, column]]
+            });
+        }
+        Operation::PragmaGetDensityMatrix(op) => {
+            let density_matrix = rotated(&branch.density_matrix, op.circuit().as_ref())?;
+            // nalgebra stores the matrix column by column, the transpose gives the row-major order of QuEST
+            branch.complex_registers.insert(
+                op.readout().clone(),
+                density_matrix.transpose().iter().copied().collect(),
+            );
+        }
+        Operation::PragmaGetOccupationProbability(op) => {
+            let density_matrix = rotated(&branch.density_matrix, op.circuit().as_ref())?;
+            branch
+                .float_registers
+                .insert(op.readout().clone(), diagonal(&density_matrix));
+        }
+        Operation::PragmaGetPauliProduct(op) => {
+            let density_matrix = rotated(&branch.density_matrix, Some(op.circuit()))?;
+            let mut product = density_matrix;
+            for (qubit, pauli) in op.qubit_paulis() {
+                let pauli: Operation = match pauli {
+                    0 => continue,
+                    1 => PauliX::new(*qubit).into(),
+                    2 => PauliY::new(*qubit).into(),
+                    3 => PauliZ::new(*qubit).into(),
+                    _ => {
+                        return Err(RoqoqoBackendError::GenericError {
+                            msg: format!("Unknown Pauli operator {} in PauliProduct", pauli),
+                        })
+                    }
+                };
+                apply_to_columns(&mut product, &pauli)?;
+            }
+            branch
+                .float_registers
+                .insert(op.readout().clone(), vec![product.trace().re]);
+        }
+        Operation::PragmaGetStateVector(_) => {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: "The state vector of a density matrix can not be read out, use PragmaGetDensityMatrix"
+                    .to_string(),
+            })
+        }
+        Operation::PragmaDamping(op) => {
+            apply_superoperator(&mut branch.density_matrix, *op.qubit(), op.superoperator()?)?
+        }
+        Operation::PragmaDephasing(op) => {
+            apply_superoperator(&mut branch.density_matrix, *op.qubit(), op.superoperator()?)?
+        }
+        Operation::PragmaDepolarising(op) => {
+            apply_superoperator(&mut branch.density_matrix, *op.qubit(), op.superoperator()?)?
+        }
+        Operation::PragmaGeneralNoise(op) => {
+            apply_superoperator(&mut branch.density_matrix, *op.qubit(), op.superoperator()?)?
+        }
+        // Operations without an effect on the state, the global phase is dropped like in QuEST
+        Operation::DefinitionUsize(_)
+        | Operation::InputSymbolic(_)
+        | Operation::PragmaSetNumberOfMeasurements(_)
+        | Operation::PragmaGlobalPhase(_)
+        | Operation::PragmaStopParallelBlock(_)
+        | Operation::PragmaStartDecompositionBlock(_)
+        | Operation::PragmaStopDecompositionBlock(_)
+        | Operation::PragmaSleep(_) => (),
+        _ if unitary::is_gate(operation) => apply_gate(&mut branch.density_matrix, operation)?,
+        _ => {
+            return Err(RoqoqoBackendError::OperationNotInBackend {
+                backend: BACKEND_NAME,
+                hqslang: operation.hqslang(),
+            })
+        }
+    }
+    Ok(vec![branch])
+}
+
+impl DensityMatrixBranch {
+    fn bit(&mut self, name: &str, index: usize) -> Result<&mut bool, RoqoqoBackendError> {
+        self.bit_registers
+            .get_mut(name)
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Bit register {} is not defined", name),
+            })?
+            .get_mut(index)
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Index {} is outside of bit register {}", index, name),
+            })
+    }
+}
+
+fn check_qubit(qubit: usize, number_qubits: usize) -> Result<(), RoqoqoBackendError> {
+    if qubit >= number_qubits {
+        return Err(RoqoqoBackendError::GenericError {
+            msg: format!(
+                "Qubit {} is measured but the backend only has {} qubits",
+                qubit, number_qubits
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn check_dimension(dimension: usize, number_qubits: usize) -> Result<(), RoqoqoBackendError> {
+    if dimension != 1 << number_qubits {
+        return Err(RoqoqoBackendError::GenericError {
+            msg: format!(
+                "State with dimension {} does not fit {} qubits",
+                dimension, number_qubits
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the probabilities of the basis states, the real part of the diagonal of the density matrix.
+fn diagonal(density_matrix: &DMatrix<Complex64>) -> Vec<f64> {
+    density_matrix
+        .diagonal()
+        .iter()
+        .map(|entry| entry.re)
+        .collect()
+}
+
+/// Projects the density matrix onto an outcome of a qubit without normalising it.
+///
+/// Returns the probability of the outcome.
+fn project(density_matrix: &mut DMatrix<Complex64>, qubit: usize, outcome: bool) -> f64 {
+    let keep = |index: usize| (index >> qubit & 1 == 1) == outcome;
+    let dimension = density_matrix.nrows();
+    for row in 0..dimension {
+        for column in 0..dimension {
+            if !keep(row) || !keep(column) {
+                density_matrix[(row, column)] = Complex64::new(0.0, 0.0);
+            }
+        }
+    }
+    density_matrix.trace().re
+}
+
+/// Applies a gate `U` to every column of the density matrix, giving `U rho`.
+fn apply_to_columns(
+    density_matrix: &mut DMatrix<Complex64>,
+    operation: &Operation,
+) -> Result<(), RoqoqoBackendError> {
+    for mut column in density_matrix.column_iter_mut() {
+        let mut state = column.clone_owned();
+        unitary::apply_gate(&mut state, operation)?;
+        column.copy_from(&state);
+    }
+    Ok(())
+}
+
+/// Applies a gate `U` to the density matrix, giving `U rho U^dagger`.
+fn apply_gate(
+    density_matrix: &mut DMatrix<Complex64>,
+    operation: &Operation,
+) -> Result<(), RoqoqoBackendError> {
+    apply_to_columns(density_matrix, operation)?;
+    density_matrix.adjoint_mut();
+    apply_to_columns(density_matrix, operation)?;
+    density_matrix.adjoint_mut();
+    Ok(())
+}
+
+/// Returns a copy of the density matrix after applying the gates of an optional basis rotation circuit.
+fn rotated(
+    density_matrix: &DMatrix<Complex64>,
+    circuit: Option<&Circuit>,
+) -> Result<DMatrix<Complex64>, RoqoqoBackendError> {
+    let mut density_matrix = density_matrix.clone();
+    for operation in circuit.into_iter().flat_map(|circuit| circuit.iter()) {
+        apply_gate(&mut density_matrix, operation)?;
+    }
+    Ok(density_matrix)
+}
+
+/// Applies a single-qubit superoperator to every 2x2 block of the density matrix belonging to `qubit`.
+fn apply_superoperator(
+    density_matrix: &mut DMatrix<Complex64>,
+    qubit: usize,
+    superoperator: ndarray::Array2<f64>,
+) -> Result<(), RoqoqoBackendError> {
+    let dimension = density_matrix.nrows();
+    if 1 << qubit >= dimension {
+        return Err(RoqoqoBackendError::GenericError {
+            msg: format!(
+                "Noise acts on qubit {} outside of a density matrix with dimension {}",
+                qubit, dimension
+            ),
+        });
+    }
+    let mask = 1 << qubit;
+    for row in (0..dimension).filter(|row| row & mask == 0) {
+        for column in (0..dimension).filter(|column| column & mask == 0) {
+            let indices = [
+                (row, column),
+                (row, column | mask),
+                (row | mask, column),
+                (row | mask, column | mask),
+            ];
+            let block: Vec<Complex64> =
+                indices.iter().map(|index| density_matrix[*index]).collect();
+            for (block_row, index) in indices.iter().enumerate() {
+                density_matrix[*index] = block
+                    .iter()
+                    .enumerate()
+                    .map(|(block_column, entry)| entry * superoperator[[block_row, block_column]])
+                    .sum();
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array2;
+
+    const THETA: f64 = 1.0;
+    const TIME: f64 = 0.7;
+    const RATE: f64 = 0.3;
+
+    /// Rotates qubit 1 of two qubits by RotateY(THETA), applies the noise and returns rho_11 and rho_01 of the qubit.
+    fn noisy_qubit(noise: Operation) -> (f64, Complex64) {
+        let mut circuit = Circuit::new();
+        circuit += RotateY::new(1, THETA.into());
+        circuit += noise;
+        let branches = DensityMatrixBackend::new(2).branches(&circuit).unwrap();
+        assert_eq!(branches.len(), 1);
+        let density_matrix = &branches[0].density_matrix;
+        assert!((density_matrix.trace().re - 1.0).abs() < 1e-12);
+        (density_matrix[(2, 2)].re, density_matrix[(0, 2)])
+    }
+
+    /// Checks the excited population and the coherence of the qubit against their closed forms.
+    fn assert_qubit(noise: Operation, excited: f64, coherence: f64) {
+        let (rho_11, rho_01) = noisy_qubit(noise);
+        assert!((rho_11 - excited).abs() < 1e-12, "{rho_11} != {excited}");
+        assert!(
+            (rho_01 - Complex64::new(coherence, 0.0)).norm() < 1e-12,
+            "{rho_01} != {coherence}"
+        );
+    }
+
+    /// The population of |1> and the coherence after RotateY(THETA).
+    fn initial() -> (f64, f64) {
+        ((THETA / 2.0).sin().powi(2), THETA.sin() / 2.0)
+    }
+
+    #[test]
+    fn damping() {
+        let (excited, coherence) = initial();
+        assert_qubit(
+            PragmaDamping::new(1, TIME.into(), RATE.into()).into(),
+            excited * (-RATE * TIME).exp(),
+            coherence * (-RATE * TIME / 2.0).exp(),
+        );
+    }
+
+    #[test]
+    fn dephasing() {
+        let (excited, coherence) = initial();
+        assert_qubit(
+            PragmaDephasing::new(1, TIME.into(), RATE.into()).into(),
+            excited,
+            coherence * (-2.0 * RATE * TIME).exp(),
+        );
+    }
+
+    #[test]
+    fn depolarising() {
+        let (excited, coherence) = initial();
+        let decay = (-RATE * TIME).exp();
+        assert_qubit(
+            PragmaDepolarising::new(1, TIME.into(), RATE.into()).into(),
+            0.5 + (excited - 0.5) * decay,
+            coherence * decay,
+        );
+    }
+
+    #[test]
+    fn general_noise() {
+        let (excited, coherence) = initial();
+        // sigma^+ with rate gamma damps like PragmaDamping, sigma^z with rate d dephases the coherence by exp(-2 d t)
+        let dephasing = 0.2;
+        let mut rates = Array2::zeros((3, 3));
+        rates[[0, 0]] = RATE;
+        rates[[2, 2]] = dephasing;
+        assert_qubit(
+            PragmaGeneralNoise::new(1, TIME.into(), rates).into(),
+            excited * (-RATE * TIME).exp(),
+            coherence * (-(RATE / 2.0 + 2.0 * dephasing) * TIME).exp(),
+        );
+    }
+
+    #[test]
+    fn noise_outside_of_the_state() {
+        let circuit = Circuit::new() + PragmaDamping::new(2, TIME.into(), RATE.into());
+        assert!(DensityMatrixBackend::new(2).branches(&circuit).is_err());
+    }
+}
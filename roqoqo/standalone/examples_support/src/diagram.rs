@@ -0,0 +1,1005 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Text wire diagrams of roqoqo circuits.
+//!
+//! Every qubit is drawn as a horizontal wire, followed by one classical wire for every bit register.
+//! Single-qubit gates are boxes on their wire, controlled gates connect control dots and targets,
+//! measurements draw a classical line into the register and the circuits of `PragmaConditional`
+//! operations are drawn inside a dashed frame that is linked to the condition register.
+//! Operations are moved to the left as far as the wires they use allow.
+
+use qoqo_calculator::CalculatorFloat;
+use roqoqo::operations::*;
+use roqoqo::Circuit;
+use std::f64::consts::PI;
+
+/// Character set used to draw a circuit diagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagramStyle {
+    /// Plain ASCII characters.
+    Ascii,
+    /// Unicode box-drawing characters.
+    Unicode,
+}
+
+/// Returns the wire diagram of a circuit.
+///
+/// # Arguments
+///
+/// * `circuit` - The circuit that is drawn.
+/// * `style` - The character set used for the diagram.
+///
+/// # Returns
+///
+/// * `String` - The diagram, one line per wire and gap between wires.
+///
+pub fn circuit_diagram(circuit: &Circuit, style: DiagramStyle) -> String {
+    let mut registers: Vec<String> = Vec::new();
+    collect_registers(circuit, &mut registers);
+    let number_qubits = number_qubits(circuit);
+    let mut layout = Layout {
+        glyphs: match style {
+            DiagramStyle::Ascii => &ASCII,
+            DiagramStyle::Unicode => &UNICODE,
+        },
+        number_qubits,
+        registers,
+        gap: 1,
+    };
+    let sketches = layout.sketches(circuit);
+    layout.gap = sketches.iter().map(Sketch::depth).max().unwrap_or(0).max(1);
+    let drawings: Vec<Drawing> = sketches
+        .iter()
+        .map(|sketch| layout.resolve(sketch))
+        .collect();
+    let (placed, width) = layout.place(drawings);
+
+    let number_lines = layout.number_lines();
+    let glyphs = layout.glyphs;
+    let mut canvas: Vec<Vec<char>> = vec![vec![' '; width + 2]; number_lines];
+    for wire in 0..layout.number_wires() {
+        let wire_character = if wire < number_qubits {
+            glyphs.wire
+        } else {
+            glyphs.classical_wire
+        };
+        canvas[layout.line(Point::on(wire))] = vec![wire_character; width + 2];
+    }
+    for (x, drawing) in placed {
+        for (line, dx, cell) in drawing.cells {
+            let character = &mut canvas[line][1 + x + dx];
+            *character = match cell {
+                Cell::Char(new_character) => new_character,
+                Cell::Vertical { classical } => glyphs.crossing(*character, classical),
+            };
+        }
+    }
+
+    let labels: Vec<String> = (0..number_qubits)
+        .map(|qubit| format!("q{}", qubit))
+        .chain(layout.registers.iter().cloned())
+        .collect();
+    let label_width = labels
+        .iter()
+        .map(|label| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    let lines: Vec<String> = canvas
+        .iter()
+        .enumerate()
+        .map(|(line, characters)| {
+            let label = labels
+                .iter()
+                .enumerate()
+                .find(|(wire, _)| layout.line(Point::on(*wire)) == line)
+                .map_or("", |(_, label)| label.as_str());
+            let text: String = characters.iter().collect();
+            format!("{:>width$} {}", label, text, width = label_width)
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+    // The margins above the first and below the last wire are only kept where something is drawn
+    let first = lines.iter().position(|line| !line.is_empty()).unwrap_or(0);
+    let last = lines.iter().rposition(|line| !line.is_empty()).unwrap_or(0);
+    lines[first..=last.max(first)].join("\n")
+}
+
+/// Formats a rotation angle, multiples of π/n with small n are written as fractions of π.
+///
+/// # Arguments
+///
+/// * `angle` - The angle, symbolic angles are returned with simplified numbers and without enclosing parentheses.
+/// * `pi` - The symbol used for π.
+///
+pub fn format_angle(angle: &CalculatorFloat, pi: &str) -> String {
+    let value = match angle {
+        CalculatorFloat::Float(value) => *value,
+        CalculatorFloat::Str(expression) => return simplify_expression(expression),
+    };
+    if value.abs() < 1e-12 {
+        return "0".to_string();
+    }
+    for denominator in 1..=8_i64 {
+        let numerator = value * denominator as f64 / PI;
+        let rounded = numerator.round() as i64;
+        if (numerator - rounded as f64).abs() < 1e-9 && rounded.abs() <= 16 {
+            let multiple = match rounded {
+                1 => pi.to_string(),
+                -1 => format!("-{}", pi),
+                _ => format!("{}{}", rounded, pi),
+            };
+            return match denominator {
+                1 => multiple,
+                _ => format!("{}/{}", multiple, denominator),
+            };
+        }
+    }
+    let formatted = format!("{:.4}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Writes the numbers of a symbolic expression in their shortest form (`3e0` as `3`) and removes enclosing parentheses.
+fn simplify_expression(expression: &str) -> String {
+    let mut simplified = String::new();
+    let mut characters = expression.chars().peekable();
+    while let Some(character) = characters.next() {
+        if character.is_alphabetic() || character == '_' {
+            simplified.push(character);
+            while let Some(&next) = characters.peek() {
+                if !(next.is_alphanumeric() || next == '_') {
+                    break;
+                }
+                simplified.push(next);
+                characters.next();
+            }
+        } else if character.is_ascii_digit() || character == '.' {
+            let mut number = character.to_string();
+            while let Some(&next) = characters.peek() {
+                let exponent_sign = (next == '-' || next == '+') && number.ends_with(['e', 'E']);
+                if !(next.is_ascii_digit()
+                    || next == '.'
+                    || next == 'e'
+                    || next == 'E'
+                    || exponent_sign)
+                {
+                    break;
+                }
+                number.push(next);
+                characters.next();
+            }
+            match number.parse::<f64>() {
+                Ok(value) => simplified.push_str(&value.to_string()),
+                Err(_) => simplified.push_str(&number),
+            }
+        } else {
+            simplified.push(character);
+        }
+    }
+    while simplified.starts_with('(') && simplified.ends_with(')') {
+        // Only strip the parentheses if the first one closes at the very end
+        let mut depth = 0;
+        let encloses = simplified.char_indices().all(|(position, character)| {
+            match character {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => (),
+            }
+            depth > 0 || position == simplified.len() - 1
+        });
+        if !encloses {
+            break;
+        }
+        simplified = simplified[1..simplified.len() - 1].trim().to_string();
+    }
+    simplified
+}
+
+/// Characters of one diagram style.
+struct Glyphs {
+    wire: char,
+    classical_wire: char,
+    vertical: char,
+    classical_vertical: char,
+    crossing: char,
+    crossing_classical: char,
+    classical_crossing: char,
+    classical_crossing_classical: char,
+    control: char,
+    target: char,
+    swap: char,
+    box_left: char,
+    box_right: char,
+    classical_box_left: char,
+    classical_box_right: char,
+    corners: [char; 4],
+    horizontal: char,
+    frame_horizontal: char,
+    frame_vertical: char,
+    measurement: char,
+    classical_start: char,
+    condition: char,
+    pi: &'static str,
+    dagger: &'static str,
+    sqrt: &'static str,
+    times: &'static str,
+    arrow: &'static str,
+    reset: &'static str,
+}
+
+const UNICODE: Glyphs = Glyphs {
+    wire: '─',
+    classical_wire: '═',
+    vertical: '│',
+    classical_vertical: '║',
+    crossing: '┼',
+    crossing_classical: '╪',
+    classical_crossing: '╫',
+    classical_crossing_classical: '╬',
+    control: '●',
+    target: '⊕',
+    swap: '╳',
+    box_left: '┤',
+    box_right: '├',
+    classical_box_left: '╡',
+    classical_box_right: '╞',
+    corners: ['┌', '┐', '└', '┘'],
+    horizontal: '─',
+    frame_horizontal: '╌',
+    frame_vertical: '╎',
+    measurement: '╩',
+    classical_start: '╥',
+    condition: '●',
+    pi: "π",
+    dagger: "†",
+    sqrt: "√",
+    times: "×",
+    arrow: "←",
+    reset: "|0⟩",
+};
+
+const ASCII: Glyphs = Glyphs {
+    wire: '-',
+    classical_wire: '=',
+    vertical: '|',
+    classical_vertical: '#',
+    crossing: '+',
+    crossing_classical: '+',
+    classical_crossing: '#',
+    classical_crossing_classical: '#',
+    control: '@',
+    target: 'X',
+    swap: 'x',
+    box_left: '[',
+    box_right: ']',
+    classical_box_left: '[',
+    classical_box_right: ']',
+    corners: ['+', '+', '+', '+'],
+    horizontal: '-',
+    frame_horizontal: '-',
+    frame_vertical: ':',
+    measurement: 'v',
+    classical_start: '#',
+    condition: '@',
+    pi: "pi",
+    dagger: "dg",
+    sqrt: "sqrt",
+    times: "x",
+    arrow: "<-",
+    reset: "|0>",
+};
+
+impl Glyphs {
+    /// Returns the character of a vertical line drawn over `under`.
+    fn crossing(&self, under: char, classical: bool) -> char {
+        match (under == self.wire, under == self.classical_wire, classical) {
+            (true, _, false) => self.crossing,
+            (true, _, true) => self.classical_crossing,
+            (_, true, false) => self.crossing_classical,
+            (_, true, true) => self.classical_crossing_classical,
+            (_, _, false) => self.vertical,
+            (_, _, true) => self.classical_vertical,
+        }
+    }
+}
+
+/// Position relative to a wire, `offset` lines below (positive) or above (negative) the wire.
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    wire: usize,
+    offset: isize,
+}
+
+impl Point {
+    fn on(wire: usize) -> Self {
+        Point { wire, offset: 0 }
+    }
+
+    fn above(wire: usize) -> Self {
+        Point { wire, offset: -1 }
+    }
+
+    fn below(wire: usize) -> Self {
+        Point { wire, offset: 1 }
+    }
+}
+
+/// Part of a sketch, `dx` is the column relative to the left edge of the sketch.
+enum Mark {
+    Char {
+        at: Point,
+        dx: usize,
+        character: char,
+    },
+    /// Vertical line from `from` to `to`, crossings with wires are resolved when drawing.
+    Line {
+        from: Point,
+        to: Point,
+        dx: usize,
+        classical: bool,
+    },
+}
+
+/// The drawing of one operation before the spacing between wires is known.
+enum Sketch {
+    Marks {
+        width: usize,
+        marks: Vec<Mark>,
+    },
+    Conditional {
+        title: String,
+        register: Option<usize>,
+        inner: Vec<Sketch>,
+    },
+}
+
+impl Sketch {
+    /// Number of lines the sketch reaches above or below its wires.
+    fn depth(&self) -> usize {
+        match self {
+            Sketch::Marks { marks, .. } => marks
+                .iter()
+                .flat_map(|mark| match mark {
+                    Mark::Char { at, .. } => vec![at.offset],
+                    Mark::Line { from, to, .. } => vec![from.offset, to.offset],
+                })
+                .map(isize::unsigned_abs)
+                .max()
+                .unwrap_or(0),
+            Sketch::Conditional { inner, .. } => {
+                1 + inner.iter().map(Sketch::depth).max().unwrap_or(0)
+            }
+        }
+    }
+}
+
+/// A cell of a drawing.
+#[derive(Debug, Clone, Copy)]
+enum Cell {
+    Char(char),
+    Vertical { classical: bool },
+}
+
+/// A sketch resolved to absolute lines.
+struct Drawing {
+    width: usize,
+    top: usize,
+    bottom: usize,
+    cells: Vec<(usize, usize, Cell)>,
+}
+
+impl Drawing {
+    fn new(width: usize, cells: Vec<(usize, usize, Cell)>) -> Self {
+        Drawing {
+            width,
+            top: cells.iter().map(|(line, _, _)| *line).min().unwrap_or(0),
+            bottom: cells.iter().map(|(line, _, _)| *line).max().unwrap_or(0),
+            cells,
+        }
+    }
+}
+
+/// Symbol on one wire of a controlled gate.
+enum Part {
+    Symbol(char),
+    Label(String),
+}
+
+/// Wires of the diagram and the number of lines between them.
+struct Layout {
+    glyphs: &'static Glyphs,
+    number_qubits: usize,
+    registers: Vec<String>,
+    gap: usize,
+}
+
+impl Layout {
+    fn number_wires(&self) -> usize {
+        self.number_qubits + self.registers.len()
+    }
+
+    fn number_lines(&self) -> usize {
+        self.gap + self.number_wires() * (self.gap + 1)
+    }
+
+    fn line(&self, point: Point) -> usize {
+        ((self.gap + point.wire * (self.gap + 1)) as isize + point.offset) as usize
+    }
+
+    fn register_wire(&self, register: &str) -> Option<usize> {
+        self.registers
+            .iter()
+            .position(|name| name == register)
+            .map(|position| self.number_qubits + position)
+    }
+
+    fn sketches(&self, circuit: &Circuit) -> Vec<Sketch> {
+        circuit
+            .iter()
+            .filter_map(|operation| self.sketch(operation))
+            .collect()
+    }
+
+    fn sketch(&self, operation: &Operation) -> Option<Sketch> {
+        let glyphs = self.glyphs;
+        let sketch = match operation {
+            Operation::DefinitionBit(_)
+            | Operation::DefinitionFloat(_)
+            | Operation::DefinitionComplex(_)
+            | Operation::DefinitionUsize(_) => return None,
+            Operation::CNOT(op) => self.connected(vec![
+                (*op.control(), Part::Symbol(glyphs.control)),
+                (*op.target(), Part::Symbol(glyphs.target)),
+            ]),
+            Operation::ControlledPauliZ(op) => self.connected(vec![
+                (*op.control(), Part::Symbol(glyphs.control)),
+                (*op.target(), Part::Symbol(glyphs.control)),
+            ]),
+            Operation::ControlledPauliY(op) => self.connected(vec![
+                (*op.control(), Part::Symbol(glyphs.control)),
+                (*op.target(), Part::Label("Y".to_string())),
+            ]),
+            Operation::ControlledPhaseShift(op) => self.connected(vec![
+                (*op.control(), Part::Symbol(glyphs.control)),
+                (
+                    *op.target(),
+                    Part::Label(format!("P({})", format_angle(op.theta(), glyphs.pi))),
+                ),
+            ]),
+            Operation::ControlledRotateX(op) => self.connected(vec![
+                (*op.control(), Part::Symbol(glyphs.control)),
+                (
+                    *op.target(),
+                    Part::Label(format!("Rx({})", format_angle(op.theta(), glyphs.pi))),
+                ),
+            ]),
+            Operation::SWAP(op) => self.connected(vec![
+                (*op.control(), Part::Symbol(glyphs.swap)),
+                (*op.target(), Part::Symbol(glyphs.swap)),
+            ]),
+            Operation::Toffoli(op) => self.connected(vec![
+                (*op.control_0(), Part::Symbol(glyphs.control)),
+                (*op.control_1(), Part::Symbol(glyphs.control)),
+                (*op.target(), Part::Symbol(glyphs.target)),
+            ]),
+            Operation::ControlledControlledPauliZ(op) => self.connected(vec![
+                (*op.control_0(), Part::Symbol(glyphs.control)),
+                (*op.control_1(), Part::Symbol(glyphs.control)),
+                (*op.target(), Part::Symbol(glyphs.control)),
+            ]),
+            Operation::ControlledControlledPhaseShift(op) => self.connected(vec![
+                (*op.control_0(), Part::Symbol(glyphs.control)),
+                (*op.control_1(), Part::Symbol(glyphs.control)),
+                (
+                    *op.target(),
+                    Part::Label(format!("P({})", format_angle(op.theta(), glyphs.pi))),
+                ),
+            ]),
+            Operation::MeasureQubit(op) => {
+                self.measurement(*op.qubit(), op.readout(), *op.readout_index())
+            }
+            Operation::PragmaRepeatedMeasurement(op) => {
+                if self.number_qubits == 0 {
+                    return None;
+                }
+                self.repeated_measurement(op)
+            }
+            Operation::PragmaSetNumberOfMeasurements(op) => self.wire_box(
+                self.register_wire(op.readout())?,
+                format!("{}{}", glyphs.times, op.number_measurements()),
+                true,
+            ),
+            Operation::InputBit(op) => self.wire_box(
+                self.register_wire(op.name())?,
+                format!("{}{}{}", op.index(), glyphs.arrow, u8::from(*op.value())),
+                true,
+            ),
+            Operation::PragmaConditional(op) => {
+                let inner = self.sketches(op.circuit());
+                if inner.is_empty() {
+                    return None;
+                }
+                Sketch::Conditional {
+                    title: format!("if {}[{}]", op.condition_register(), op.condition_index()),
+                    register: self.register_wire(op.condition_register()),
+                    inner,
+                }
+            }
+            _ => {
+                let label = self.label(operation);
+                match operation.involved_qubits() {
+                    InvolvedQubits::Set(qubits) => {
+                        let lowest = *qubits.iter().min()?;
+                        let highest = *qubits.iter().max()?;
+                        self.tall_box(lowest, highest, label)
+                    }
+                    InvolvedQubits::All if self.number_qubits > 0 => {
+                        self.tall_box(0, self.number_qubits - 1, label)
+                    }
+                    _ => return None,
+                }
+            }
+        };
+        Some(sketch)
+    }
+
+    /// Returns the box label of an operation that is not drawn with control dots.
+    fn label(&self, operation: &Operation) -> String {
+        let glyphs = self.glyphs;
+        let angle = |angle: &CalculatorFloat| format_angle(angle, glyphs.pi);
+        match operation {
+            Operation::Hadamard(_) => "H".to_string(),
+            Operation::PauliX(_) => "X".to_string(),
+            Operation::PauliY(_) => "Y".to_string(),
+            Operation::PauliZ(_) => "Z".to_string(),
+            Operation::SGate(_) => "S".to_string(),
+            Operation::TGate(_) => "T".to_string(),
+            Operation::SqrtPauliX(_) => format!("{}X", glyphs.sqrt),
+            Operation::InvSqrtPauliX(_) => format!("{}X{}", glyphs.sqrt, glyphs.dagger),
+            Operation::Identity(_) => "I".to_string(),
+            Operation::RotateX(op) => format!("Rx({})", angle(op.theta())),
+            Operation::RotateY(op) => format!("Ry({})", angle(op.theta())),
+            Operation::RotateZ(op) => format!("Rz({})", angle(op.theta())),
+            Operation::RotateXY(op) => {
+                format!("Rxy({},{})", angle(op.theta()), angle(op.phi()))
+            }
+            Operation::PhaseShiftState0(op) => format!("P0({})", angle(op.theta())),
+            Operation::PhaseShiftState1(op) => format!("P({})", angle(op.theta())),
+            Operation::PragmaActiveReset(_) => glyphs.reset.to_string(),
+            Operation::PragmaGetStateVector(op) => {
+                format!("StateVector{}{}", glyphs.arrow, op.readout())
+            }
+            Operation::PragmaGetDensityMatrix(op) => {
+                format!("DensityMatrix{}{}", glyphs.arrow, op.readout())
+            }
+            _ => operation.hqslang().trim_start_matches("Pragma").to_string(),
+        }
+    }
+
+    /// Box on a single quantum or classical wire.
+    fn wire_box(&self, wire: usize, label: String, classical: bool) -> Sketch {
+        let (left, right) = if classical {
+            (
+                self.glyphs.classical_box_left,
+                self.glyphs.classical_box_right,
+            )
+        } else {
+            (self.glyphs.box_left, self.glyphs.box_right)
+        };
+        let mut marks = Vec::new();
+        let text = format!("{}{}{}", left, label, right);
+        push_text(&mut marks, Point::on(wire), 0, &text);
+        Sketch::Marks {
+            width: text.chars().count(),
+            marks,
+        }
+    }
+
+    /// Box covering the qubit wires from `lowest` to `highest`, labelled on the first wire.
+    fn tall_box(&self, lowest: usize, highest: usize, label: String) -> Sketch {
+        if lowest == highest {
+            return self.wire_box(lowest, label, false);
+        }
+        let glyphs = self.glyphs;
+        let width = label.chars().count() + 2;
+        let mut marks = vec![
+            Mark::Line {
+                from: Point::below(lowest),
+                to: Point::above(highest),
+                dx: 0,
+                classical: false,
+            },
+            Mark::Line {
+                from: Point::below(lowest),
+                to: Point::above(highest),
+                dx: width - 1,
+                classical: false,
+            },
+        ];
+        let horizontal = glyphs.horizontal.to_string().repeat(width - 2);
+        let top = format!("{}{}{}", glyphs.corners[0], horizontal, glyphs.corners[1]);
+        let bottom = format!("{}{}{}", glyphs.corners[2], horizontal, glyphs.corners[3]);
+        push_text(&mut marks, Point::above(lowest), 0, &top);
+        push_text(&mut marks, Point::below(highest), 0, &bottom);
+        for wire in lowest..=highest {
+            let inside = if wire == lowest {
+                label.clone()
+            } else {
+                " ".repeat(width - 2)
+            };
+            let text = format!("{}{}{}", glyphs.box_left, inside, glyphs.box_right);
+            push_text(&mut marks, Point::on(wire), 0, &text);
+        }
+        Sketch::Marks { width, marks }
+    }
+
+    /// Symbols on several qubit wires joined by a vertical line.
+    fn connected(&self, parts: Vec<(usize, Part)>) -> Sketch {
+        let part_width = |part: &Part| match part {
+            Part::Symbol(_) => 1,
+            Part::Label(label) => label.chars().count() + 2,
+        };
+        let width = parts
+            .iter()
+            .map(|(_, part)| part_width(part))
+            .max()
+            .unwrap_or(1);
+        let center = width / 2;
+        let lowest = parts.iter().map(|(wire, _)| *wire).min().unwrap_or(0);
+        let highest = parts.iter().map(|(wire, _)| *wire).max().unwrap_or(0);
+        let mut marks = vec![Mark::Line {
+            from: Point::below(lowest),
+            to: Point::above(highest),
+            dx: center,
+            classical: false,
+        }];
+        for (wire, part) in parts {
+            match part {
+                Part::Symbol(character) => marks.push(Mark::Char {
+                    at: Point::on(wire),
+                    dx: center,
+                    character,
+                }),
+                Part::Label(label) => {
+                    let text =
+                        format!("{}{}{}", self.glyphs.box_left, label, self.glyphs.box_right);
+                    let dx = center - text.chars().count() / 2;
+                    push_text(&mut marks, Point::on(wire), dx, &text);
+                }
+            }
+        }
+        Sketch::Marks { width, marks }
+    }
+
+    /// Measurement box with a classical line into bit `index` of the readout register.
+    fn measurement(&self, qubit: usize, readout: &str, index: usize) -> Sketch {
+        let mut sketch = self.wire_box(qubit, "M".to_string(), false);
+        if let (Some(register), Sketch::Marks { width, marks }) =
+            (self.register_wire(readout), &mut sketch)
+        {
+            let index = index.to_string();
+            *width = (*width).max(2 + index.len());
+            marks.push(Mark::Line {
+                from: Point::below(qubit),
+                to: Point::above(register),
+                dx: 1,
+                classical: true,
+            });
+            push_text(marks, Point::above(register), 2, &index);
+            marks.push(Mark::Char {
+                at: Point::on(register),
+                dx: 1,
+                character: self.glyphs.measurement,
+            });
+        }
+        sketch
+    }
+
+    /// Box over all qubits with a classical line into the readout register.
+    fn repeated_measurement(&self, operation: &PragmaRepeatedMeasurement) -> Sketch {
+        let highest = self.number_qubits - 1;
+        let mut sketch = self.tall_box(
+            0,
+            highest,
+            format!("M{}{}", self.glyphs.times, operation.number_measurements()),
+        );
+        if let (Some(register), Sketch::Marks { width, marks }) =
+            (self.register_wire(operation.readout()), &mut sketch)
+        {
+            let center = *width / 2;
+            marks.push(Mark::Line {
+                from: Point::below(highest),
+                to: Point::above(register),
+                dx: center,
+                classical: true,
+            });
+            if highest > 0 {
+                marks.push(Mark::Char {
+                    at: Point::below(highest),
+                    dx: center,
+                    character: self.glyphs.classical_start,
+                });
+            }
+            marks.push(Mark::Char {
+                at: Point::on(register),
+                dx: center,
+                character: self.glyphs.measurement,
+            });
+        }
+        sketch
+    }
+
+    /// Resolves a sketch to absolute lines.
+    fn resolve(&self, sketch: &Sketch) -> Drawing {
+        match sketch {
+            Sketch::Marks { width, marks } => {
+                let mut cells = Vec::new();
+                for mark in marks {
+                    match mark {
+                        Mark::Char { at, dx, character } => {
+                            cells.push((self.line(*at), *dx, Cell::Char(*character)))
+                        }
+                        Mark::Line {
+                            from,
+                            to,
+                            dx,
+                            classical,
+                        } => {
+                            for line in self.line(*from)..=self.line(*to) {
+                                cells.push((
+                                    line,
+                                    *dx,
+                                    Cell::Vertical {
+                                        classical: *classical,
+                                    },
+                                ));
+                            }
+                        }
+                    }
+                }
+                Drawing::new(*width, cells)
+            }
+            Sketch::Conditional {
+                title,
+                register,
+                inner,
+            } => self.resolve_conditional(title, *register, inner),
+        }
+    }
+
+    /// Draws the inner circuit of a conditional operation inside a dashed frame.
+    fn resolve_conditional(
+        &self,
+        title: &str,
+        register: Option<usize>,
+        inner: &[Sketch],
+    ) -> Drawing {
+        let glyphs = self.glyphs;
+        let drawings: Vec<Drawing> = inner.iter().map(|sketch| self.resolve(sketch)).collect();
+        let top = drawings
+            .iter()
+            .map(|drawing| drawing.top)
+            .min()
+            .unwrap_or(0)
+            - 1;
+        let bottom = drawings
+            .iter()
+            .map(|drawing| drawing.bottom)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let (placed, inner_width) = self.place(drawings);
+        let title_width = title.chars().count();
+        let width = inner_width.max(title_width + 2) + 4;
+        let inner_offset = 2 + (width - 4 - inner_width) / 2;
+
+        let mut cells = Vec::new();
+        for dx in 1..width - 1 {
+            cells.push((top, dx, Cell::Char(glyphs.frame_horizontal)));
+            cells.push((bottom, dx, Cell::Char(glyphs.frame_horizontal)));
+        }
+        for (position, character) in title.chars().enumerate() {
+            cells.push((top, 2 + position, Cell::Char(character)));
+        }
+        cells.push((top, 0, Cell::Char(glyphs.corners[0])));
+        cells.push((top, width - 1, Cell::Char(glyphs.corners[1])));
+        cells.push((bottom, 0, Cell::Char(glyphs.corners[2])));
+        cells.push((bottom, width - 1, Cell::Char(glyphs.corners[3])));
+        let wire_lines: Vec<usize> = (0..self.number_wires())
+            .map(|wire| self.line(Point::on(wire)))
+            .collect();
+        for line in top + 1..bottom {
+            if !wire_lines.contains(&line) {
+                cells.push((line, 0, Cell::Char(glyphs.frame_vertical)));
+                cells.push((line, width - 1, Cell::Char(glyphs.frame_vertical)));
+            }
+        }
+        for (x, drawing) in placed {
+            for (line, dx, cell) in drawing.cells {
+                cells.push((line, inner_offset + x + dx, cell));
+            }
+        }
+        if let Some(register) = register {
+            let register_line = self.line(Point::on(register));
+            if register_line > bottom {
+                let center = width / 2;
+                cells.push((bottom, center, Cell::Char(glyphs.classical_start)));
+                for line in bottom + 1..register_line {
+                    cells.push((line, center, Cell::Vertical { classical: true }));
+                }
+                cells.push((register_line, center, Cell::Char(glyphs.condition)));
+            }
+        }
+        Drawing::new(width, cells)
+    }
+
+    /// Places drawings from left to right, every drawing as far left as the lines it uses allow.
+    ///
+    /// Returns the horizontal position of every drawing and the total width.
+    ///
+    fn place(&self, drawings: Vec<Drawing>) -> (Vec<(usize, Drawing)>, usize) {
+        let mut next_free = vec![0; self.number_lines()];
+        let mut width = 0;
+        let mut placed = Vec::new();
+        for drawing in drawings {
+            let lines = drawing.top..=drawing.bottom;
+            let x = next_free[lines.clone()].iter().copied().max().unwrap_or(0);
+            for free in &mut next_free[lines] {
+                *free = x + drawing.width + 1;
+            }
+            width = width.max(x + drawing.width);
+            placed.push((x, drawing));
+        }
+        (placed, width)
+    }
+}
+
+/// Adds a horizontal text starting at column `dx`.
+fn push_text(marks: &mut Vec<Mark>, at: Point, dx: usize, text: &str) {
+    for (position, character) in text.chars().enumerate() {
+        marks.push(Mark::Char {
+            at,
+            dx: dx + position,
+            character,
+        });
+    }
+}
+
+/// Returns the number of qubit wires, operations acting on all qubits do not add wires.
+pub(crate) fn number_qubits(circuit: &Circuit) -> usize {
+    circuit
+        .iter()
+        .filter_map(|operation| match operation.involved_qubits() {
+            InvolvedQubits::Set(qubits) => qubits.into_iter().max(),
+            _ => None,
+        })
+        .max()
+        .map_or(0, |qubit| qubit + 1)
+}
+
+/// Collects the bit registers used in a circuit in the order of first use.
+pub(crate) fn collect_registers(circuit: &Circuit, registers: &mut Vec<String>) {
+    for operation in circuit.iter() {
+        let register = match operation {
+            Operation::DefinitionBit(op) => op.name(),
+            Operation::MeasureQubit(op) => op.readout(),
+            Operation::PragmaRepeatedMeasurement(op) => op.readout(),
+            Operation::PragmaSetNumberOfMeasurements(op) => op.readout(),
+            Operation::InputBit(op) => op.name(),
+            Operation::PragmaConditional(op) => {
+                if !registers.contains(op.condition_register()) {
+                    registers.push(op.condition_register().clone());
+                }
+                collect_registers(op.circuit(), registers);
+                continue;
+            }
+            _ => continue,
+        };
+        if !registers.contains(register) {
+            registers.push(register.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bell() -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 2, true);
+        circuit += Hadamard::new(0);
+        circuit += CNOT::new(0, 1);
+        circuit += MeasureQubit::new(0, "ro".to_string(), 0);
+        circuit += MeasureQubit::new(1, "ro".to_string(), 1);
+        circuit
+    }
+
+    fn teleportation() -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 2, true);
+        circuit += RotateY::new(0, (std::f64::consts::PI / 3.0).into());
+        circuit += Hadamard::new(1);
+        circuit += CNOT::new(1, 2);
+        circuit += CNOT::new(0, 1);
+        circuit += Hadamard::new(0);
+        circuit += MeasureQubit::new(0, "ro".to_string(), 0);
+        circuit += MeasureQubit::new(1, "ro".to_string(), 1);
+        circuit += PragmaConditional::new("ro".to_string(), 1, Circuit::new() + PauliX::new(2));
+        circuit += PragmaConditional::new("ro".to_string(), 0, Circuit::new() + PauliZ::new(2));
+        circuit
+    }
+
+    #[test]
+    fn bell_ascii() {
+        let expected = r#"
+q0 -[H]-@-[M]-----
+        |  #
+q1 -----X--#--[M]-
+           #0  #1
+ro ========v===v=="#;
+        assert_eq!(
+            circuit_diagram(&bell(), DiagramStyle::Ascii),
+            expected.trim_start_matches('\n')
+        );
+    }
+
+    #[test]
+    fn bell_unicode() {
+        let expected = r#"
+q0 ─┤H├─●─┤M├─────
+        │  ║
+q1 ─────⊕──╫──┤M├─
+           ║0  ║1
+ro ════════╩═══╩══"#;
+        assert_eq!(
+            circuit_diagram(&bell(), DiagramStyle::Unicode),
+            expected.trim_start_matches('\n')
+        );
+    }
+
+    #[test]
+    fn teleportation_ascii() {
+        let expected = r#"
+q0 -[Ry(pi/3)]-@-[H]-[M]-----------------------------------
+               |      #
+q1 -[H]-@------X------#--[M]-------------------------------
+        |             #   #  +-if ro[1]---+ +-if ro[0]---+
+q2 -----X-------------#---#-------[X]------------[Z]-------
+                      #0  #1 +------#-----+ +------#-----+
+ro ===================v===v=========@==============@======="#;
+        assert_eq!(
+            circuit_diagram(&teleportation(), DiagramStyle::Ascii),
+            expected.trim_start_matches('\n')
+        );
+    }
+
+    #[test]
+    fn teleportation_unicode() {
+        let expected = r#"
+q0 ─┤Ry(π/3)├─●─┤H├─┤M├───────────────────────────────────
+              │      ║
+q1 ─┤H├─●─────⊕──────╫──┤M├───────────────────────────────
+        │            ║   ║  ┌╌if ro[1]╌╌╌┐ ┌╌if ro[0]╌╌╌┐
+q2 ─────⊕────────────╫───╫───────┤X├────────────┤Z├───────
+                     ║0  ║1 └╌╌╌╌╌╌╥╌╌╌╌╌┘ └╌╌╌╌╌╌╥╌╌╌╌╌┘
+ro ══════════════════╩═══╩═════════●══════════════●═══════"#;
+        assert_eq!(
+            circuit_diagram(&teleportation(), DiagramStyle::Unicode),
+            expected.trim_start_matches('\n')
+        );
+    }
+}
@@ -0,0 +1,553 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mid-circuit measurements and classical feed-forward.
+//!
+//! A simulator like QuEST follows a single random measurement outcome per run. [measurement_branches] instead follows
+//! every possible outcome of the `MeasureQubit` and `PragmaActiveReset` operations of a circuit and returns the
+//! probability, the classical bit registers and the final state vector of each branch. This makes it possible to check
+//! the result of a feed-forward circuit for every branch, and to recognize the branch a simulator run ended up in.
+//!
+//! `PragmaConditional` only depends on a single bit. [conditional_on_bits] and [conditional_on_any] build conditions on
+//! several bits from nested conditionals and a flag bit that is set with `InputBit`.
+
+use crate::unitary;
+use nalgebra::DVector;
+use num_complex::Complex64;
+use roqoqo::operations::*;
+use roqoqo::registers::{BitOutputRegister, BitRegister};
+use roqoqo::{Circuit, RoqoqoError};
+use std::collections::HashMap;
+
+/// Branches with a probability below this value are dropped.
+const PROBABILITY_CUTOFF: f64 = 1e-12;
+
+/// A branch of a circuit for one sequence of measurement outcomes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeasurementBranch {
+    /// The probability of the branch.
+    pub probability: f64,
+    /// The outcomes of all measurements and active resets in the order they happened.
+    pub outcomes: Vec<bool>,
+    /// The classical bit registers at the end of the branch.
+    pub bit_registers: HashMap<String, BitRegister>,
+    /// The normalized state vector at the end of the branch.
+    pub state_vector: DVector<Complex64>,
+}
+
+impl MeasurementBranch {
+    /// Returns true if a simulator run ended in this branch.
+    ///
+    /// # Arguments
+    ///
+    /// * `bit_registers` - The bit registers returned by the run, the last entry of every register is compared.
+    /// * `state_vector` - The state vector returned by the run.
+    /// * `tolerance` - Tolerance of the comparison of the state vectors, which may differ in a global phase.
+    ///
+    pub fn matches(
+        &self,
+        bit_registers: &HashMap<String, BitOutputRegister>,
+        state_vector: &[Complex64],
+        tolerance: f64,
+    ) -> bool {
+        bit_registers.iter().all(|(name, register)| {
+            register.last().is_none() || self.bit_registers.get(name) == register.last()
+        }) && states_equal_up_to_phase(self.state_vector.as_slice(), state_vector, tolerance)
+    }
+}
+
+/// Follows all measurement outcomes of a circuit starting from the state |0...0>.
+///
+/// Gates are applied to the state vector, `MeasureQubit` and `PragmaActiveReset` split every branch into one branch
+/// per outcome, and `PragmaConditional`, `InputBit` and `PragmaLoop` are evaluated with the bit registers of each
+/// branch. Readout pragmas like `PragmaGetStateVector` or `PragmaRepeatedMeasurement` do not change the state and are
+/// skipped.
+///
+/// # Arguments
+///
+/// * `circuit` - The circuit, all parameters need to be substituted.
+/// * `number_qubits` - The number of qubits of the state vector.
+///
+/// # Returns
+///
+/// * `Ok(Vec<MeasurementBranch>)` - All branches with a non-vanishing probability.
+/// * `Err(RoqoqoError)` - The circuit contains an operation without a known action on the state vector.
+///
+pub fn measurement_branches(
+    circuit: &Circuit,
+    number_qubits: usize,
+) -> Result<Vec<MeasurementBranch>, RoqoqoError> {
+    let mut state_vector = DVector::zeros(1 << number_qubits);
+    state_vector[0] = Complex64::new(1.0, 0.0);
+    let branch = MeasurementBranch {
+        probability: 1.0,
+        outcomes: Vec::new(),
+        bit_registers: HashMap::new(),
+        state_vector,
+    };
+    evolve(circuit, vec![branch], number_qubits)
+}
+
+fn evolve(
+    circuit: &Circuit,
+    mut branches: Vec<MeasurementBranch>,
+    number_qubits: usize,
+) -> Result<Vec<MeasurementBranch>, RoqoqoError> {
+    for operation in circuit.iter() {
+        let mut next_branches = Vec::with_capacity(branches.len());
+        for branch in branches {
+            next_branches.extend(step(operation, branch, number_qubits)?);
+        }
+        branches = next_branches;
+    }
+    Ok(branches)
+}
+
+fn step(
+    operation: &Operation,
+    mut branch: MeasurementBranch,
+    number_qubits: usize,
+) -> Result<Vec<MeasurementBranch>, RoqoqoError> {
+    match operation {
+        // Like the simulators, only output registers are created and can be written to
+        Operation::DefinitionBit(def) => {
+            if *def.is_output() {
+                branch
+                    .bit_registers
+                    .insert(def.name().clone(), vec![false; *def.length()]);
+            }
+            Ok(vec![branch])
+        }
+        Operation::InputBit(op) => {
+            *register_bit(&mut branch, op.name(), *op.index())? = *op.value();
+            Ok(vec![branch])
+        }
+        Operation::MeasureQubit(op) => project(branch, *op.qubit(), number_qubits)?
+            .into_iter()
+            .map(|(outcome, mut branch)| {
+                *register_bit(&mut branch, op.readout(), *op.readout_index())? = outcome;
+                Ok(branch)
+            })
+            .collect(),
+        Operation::PragmaActiveReset(op) => Ok(project(branch, *op.qubit(), number_qubits)?
+            .into_iter()
+            .map(|(outcome, mut branch)| {
+                if outcome {
+                    flip(&mut branch.state_vector, *op.qubit());
+                }
+                branch
+            })
+            .collect()),
+        Operation::PragmaConditional(op) => {
+            if *register_bit(&mut branch, op.condition_register(), *op.condition_index())? {
+                evolve(op.circuit(), vec![branch], number_qubits)
+            } else {
+                Ok(vec![branch])
+            }
+        }
+        Operation::PragmaLoop(op) => {
+            let repetitions = *op.repetitions().float()?;
+            let mut branches = vec![branch];
+            for _ in 0..repetitions as usize {
+                branches = evolve(op.circuit(), branches, number_qubits)?;
+            }
+            Ok(branches)
+        }
+        Operation::PragmaGlobalPhase(op) => {
+            branch.state_vector *= Complex64::from_polar(1.0, *op.phase().float()?);
+            Ok(vec![branch])
+        }
+        Operation::DefinitionFloat(_)
+        | Operation::DefinitionComplex(_)
+        | Operation::DefinitionUsize(_)
+        | Operation::PragmaGetStateVector(_)
+        | Operation::PragmaGetDensityMatrix(_)
+        | Operation::PragmaGetOccupationProbability(_)
+        | Operation::PragmaGetPauliProduct(_)
+        | Operation::PragmaRepeatedMeasurement(_)
+        | Operation::PragmaSetNumberOfMeasurements(_) => Ok(vec![branch]),
+        _ if unitary::is_gate(operation) => {
+            unitary::apply_gate(&mut branch.state_vector, operation)?;
+            Ok(vec![branch])
+        }
+        _ => Err(RoqoqoError::GenericError {
+            msg: format!(
+                "Operation {} is not supported when following measurement branches",
+                operation.hqslang()
+            ),
+        }),
+    }
+}
+
+/// Splits a branch into the branches for measuring `qubit` as false and true.
+fn project(
+    branch: MeasurementBranch,
+    qubit: usize,
+    number_qubits: usize,
+) -> Result<Vec<(bool, MeasurementBranch)>, RoqoqoError> {
+    if qubit >= number_qubits {
+        return Err(RoqoqoError::GenericError {
+            msg: format!(
+                "Qubit {} is measured but the state vector only has {} qubits",
+                qubit, number_qubits
+            ),
+        });
+    }
+    let mut branches = Vec::with_capacity(2);
+    for outcome in [false, true] {
+        let mut state_vector = branch.state_vector.clone();
+        for (index, amplitude) in state_vector.iter_mut().enumerate() {
+            if (index >> qubit & 1 == 1) != outcome {
+                *amplitude = Complex64::new(0.0, 0.0);
+            }
+        }
+        let probability = state_vector.norm_squared();
+        if probability * branch.probability < PROBABILITY_CUTOFF {
+            continue;
+        }
+        let mut outcomes = branch.outcomes.clone();
+        outcomes.push(outcome);
+        branches.push((
+            outcome,
+            MeasurementBranch {
+                probability: branch.probability * probability,
+                outcomes,
+                bit_registers: branch.bit_registers.clone(),
+                state_vector: state_vector.unscale(probability.sqrt()),
+            },
+        ));
+    }
+    Ok(branches)
+}
+
+/// Applies PauliX to `qubit`.
+fn flip(state_vector: &mut DVector<Complex64>, qubit: usize) {
+    for index in 0..state_vector.len() {
+        if index >> qubit & 1 == 0 {
+            state_vector.swap_rows(index, index | 1 << qubit);
+        }
+    }
+}
+
+fn register_bit<'a>(
+    branch: &'a mut MeasurementBranch,
+    name: &str,
+    index: usize,
+) -> Result<&'a mut bool, RoqoqoError> {
+    branch
+        .bit_registers
+        .get_mut(name)
+        .ok_or_else(|| RoqoqoError::GenericError {
+            msg: format!("Bit register {} is not defined", name),
+        })?
+        .get_mut(index)
+        .ok_or_else(|| RoqoqoError::GenericError {
+            msg: format!("Index {} is outside of bit register {}", index, name),
+        })
+}
+
+/// Returns a circuit that applies `circuit` only if all listed bits of `register` have the given values.
+///
+/// Bits that need to be true become nested `PragmaConditional` operations. For bits that need to be false the
+/// bit register `flag` is used: its first bit is set with `InputBit` and cleared by a conditional on every such bit.
+/// The circuit using the returned operations has to define `flag` as an output register with at least one bit.
+///
+/// # Arguments
+///
+/// * `register` - The bit register the condition depends on.
+/// * `condition` - Pairs of a bit index and the value the bit needs to have.
+/// * `flag` - A bit register used as scratch space.
+/// * `circuit` - The circuit applied when the condition is fulfilled.
+///
+pub fn conditional_on_bits(
+    register: &str,
+    condition: &[(usize, bool)],
+    flag: &str,
+    circuit: Circuit,
+) -> Circuit {
+    let mut conditional = Circuit::new();
+    let mut inner = circuit;
+    if condition.iter().any(|(_, value)| !value) {
+        conditional += InputBit::new(flag.to_string(), 0, true);
+        for (index, _) in condition.iter().filter(|(_, value)| !value) {
+            let mut clear = Circuit::new();
+            clear += InputBit::new(flag.to_string(), 0, false);
+            conditional += PragmaConditional::new(register.to_string(), *index, clear);
+        }
+        let mut nested = Circuit::new();
+        nested += PragmaConditional::new(flag.to_string(), 0, inner);
+        inner = nested;
+    }
+    for (index, _) in condition.iter().rev().filter(|(_, value)| *value) {
+        let mut nested = Circuit::new();
+        nested += PragmaConditional::new(register.to_string(), *index, inner);
+        inner = nested;
+    }
+    conditional + inner
+}
+
+/// Returns a circuit that applies `circuit` if at least one of the listed bits of `register` is true.
+///
+/// The first bit of the bit register `flag` is cleared with `InputBit` and set by a conditional on every listed bit.
+/// The circuit using the returned operations has to define `flag` as an output register with at least one bit.
+///
+pub fn conditional_on_any(
+    register: &str,
+    indices: &[usize],
+    flag: &str,
+    circuit: Circuit,
+) -> Circuit {
+    let mut conditional = Circuit::new();
+    conditional += InputBit::new(flag.to_string(), 0, false);
+    for index in indices {
+        let mut set = Circuit::new();
+        set += InputBit::new(flag.to_string(), 0, true);
+        conditional += PragmaConditional::new(register.to_string(), *index, set);
+    }
+    conditional += PragmaConditional::new(flag.to_string(), 0, circuit);
+    conditional
+}
+
+/// Returns true if two state vectors are equal up to a global phase.
+pub fn states_equal_up_to_phase(first: &[Complex64], second: &[Complex64], tolerance: f64) -> bool {
+    if first.len() != second.len() {
+        return false;
+    }
+    let overlap: Complex64 = first
+        .iter()
+        .zip(second.iter())
+        .map(|(a, b)| a.conj() * b)
+        .sum();
+    let norm_first: f64 = first.iter().map(|a| a.norm_sqr()).sum();
+    let norm_second: f64 = second.iter().map(|b| b.norm_sqr()).sum();
+    (overlap.norm() - (norm_first * norm_second).sqrt()).abs() < tolerance
+}
+
+/// Formats a state vector as a sum of its non-vanishing basis states, written with the highest qubit first.
+pub fn format_state_vector(state_vector: &[Complex64], number_qubits: usize) -> String {
+    let terms: Vec<String> = state_vector
+        .iter()
+        .enumerate()
+        .filter(|(_, amplitude)| amplitude.norm() > 1e-10)
+        .map(|(index, amplitude)| {
+            format!(
+                "({:.4}{:+.4}i)|{:0width$b}>",
+                amplitude.re,
+                amplitude.im,
+                index,
+                width = number_qubits.max(1)
+            )
+        })
+        .collect();
+    if terms.is_empty() {
+        "0".to_string()
+    } else {
+        terms.join(" + ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// Returns the basis state with the given index.
+    fn basis_state(index: usize, number_qubits: usize) -> DVector<Complex64> {
+        let mut state = DVector::zeros(1 << number_qubits);
+        state[index] = Complex64::new(1.0, 0.0);
+        state
+    }
+
+    /// Returns the state vector a circuit without measurements prepares from |0...0>.
+    fn prepared_state(circuit: &Circuit, number_qubits: usize) -> DVector<Complex64> {
+        let mut branches = measurement_branches(circuit, number_qubits).unwrap();
+        assert_eq!(branches.len(), 1);
+        branches.remove(0).state_vector
+    }
+
+    #[test]
+    fn entanglement_swapping() {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("bell".to_string(), 2, true);
+        circuit += DefinitionBit::new("flag".to_string(), 1, true);
+        for (first, second) in [(0, 1), (2, 3)] {
+            circuit += Hadamard::new(first);
+            circuit += CNOT::new(first, second);
+        }
+        circuit += CNOT::new(1, 2);
+        circuit += Hadamard::new(1);
+        circuit += MeasureQubit::new(1, "bell".to_string(), 0);
+        circuit += MeasureQubit::new(2, "bell".to_string(), 1);
+        for (phase_bit, parity_bit, correction) in [
+            (false, true, Circuit::new() + PauliX::new(3)),
+            (true, false, Circuit::new() + PauliZ::new(3)),
+            (true, true, Circuit::new() + PauliY::new(3)),
+        ] {
+            circuit += conditional_on_bits(
+                "bell",
+                &[(0, phase_bit), (1, parity_bit)],
+                "flag",
+                correction,
+            );
+        }
+
+        let branches = measurement_branches(&circuit, 4).unwrap();
+        assert_eq!(branches.len(), 4);
+        for branch in branches.iter() {
+            let bits = &branch.bit_registers["bell"];
+            assert_eq!(bits, &branch.outcomes);
+            // Qubits 1 and 2 are left in the measured state, qubits 0 and 3 in the Bell state (|00> + |11>)/sqrt(2)
+            let measured = usize::from(bits[0]) << 1 | usize::from(bits[1]) << 2;
+            let expected =
+                (basis_state(measured, 4) + basis_state(measured | 0b1001, 4)).unscale(2f64.sqrt());
+            assert!((branch.probability - 0.25).abs() < 1e-10);
+            assert!(states_equal_up_to_phase(
+                branch.state_vector.as_slice(),
+                expected.as_slice(),
+                1e-10
+            ));
+        }
+    }
+
+    #[test]
+    fn repeat_until_success() {
+        // Two attempts of the rotation by arccos(3/5), the second one nested in the failure of the first
+        fn attempt(number: usize) -> Circuit {
+            let register = format!("attempt_{}", number);
+            let mut circuit = Circuit::new();
+            circuit += Hadamard::new(1);
+            circuit += Hadamard::new(2);
+            circuit += Toffoli::new(1, 2, 0);
+            circuit += SGate::new(0);
+            circuit += Toffoli::new(1, 2, 0);
+            circuit += Hadamard::new(1);
+            circuit += Hadamard::new(2);
+            circuit += MeasureQubit::new(1, register.clone(), 0);
+            circuit += MeasureQubit::new(2, register.clone(), 1);
+            let mut failure = Circuit::new();
+            failure += PauliZ::new(0);
+            failure += PragmaActiveReset::new(1);
+            failure += PragmaActiveReset::new(2);
+            if number == 0 {
+                failure += attempt(1);
+            }
+            circuit + conditional_on_any(&register, &[0, 1], "failed", failure)
+        }
+        let mut prepare = Circuit::new();
+        prepare += RotateY::new(0, (PI / 3.0).into());
+        prepare += RotateZ::new(0, (PI / 5.0).into());
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("attempt_0".to_string(), 2, true);
+        circuit += DefinitionBit::new("attempt_1".to_string(), 2, true);
+        circuit += DefinitionBit::new("failed".to_string(), 1, true);
+        circuit += prepare.clone();
+        circuit += attempt(0);
+
+        let initial = prepared_state(&prepare, 3);
+        let rotated = prepared_state(
+            &(prepare + PhaseShiftState1::new(0, (3f64 / 5.0).acos().into())),
+            3,
+        );
+        let branches = measurement_branches(&circuit, 3).unwrap();
+        // One success after no failure, three failing outcomes followed by one success each and nine double failures
+        assert_eq!(branches.len(), 1 + 3 + 9);
+        let mut success_probability = 0.0;
+        for branch in branches.iter() {
+            let failures = ["attempt_0", "attempt_1"]
+                .iter()
+                .filter(|name| branch.bit_registers[**name].contains(&true))
+                .count();
+            let expected = if failures < 2 {
+                let probability = 5.0 / 8.0 * (1f64 / 8.0).powi(failures as i32);
+                assert!((branch.probability - probability).abs() < 1e-10);
+                success_probability += branch.probability;
+                &rotated
+            } else {
+                &initial
+            };
+            assert!(states_equal_up_to_phase(
+                branch.state_vector.as_slice(),
+                expected.as_slice(),
+                1e-10
+            ));
+        }
+        assert!((success_probability - (1.0 - (3f64 / 8.0).powi(2))).abs() < 1e-10);
+    }
+
+    #[test]
+    fn active_reset() {
+        let mut circuit = Circuit::new();
+        circuit += Hadamard::new(0);
+        circuit += CNOT::new(0, 1);
+        circuit += PragmaActiveReset::new(1);
+        let branches = measurement_branches(&circuit, 2).unwrap();
+        assert_eq!(branches.len(), 2);
+        for branch in branches.iter() {
+            // The reset leaves qubit 1 in |0> and qubit 0 in the state of the outcome, which is not stored
+            let outcome = branch.outcomes[0];
+            assert!(branch.bit_registers.is_empty());
+            assert!((branch.probability - 0.5).abs() < 1e-10);
+            assert_eq!(branch.state_vector, basis_state(usize::from(outcome), 2));
+        }
+        assert_ne!(branches[0].outcomes, branches[1].outcomes);
+    }
+
+    #[test]
+    fn conditions_on_several_bits() {
+        for value in 0..4_usize {
+            let bits = [value & 1 == 1, value & 2 == 2];
+            let mut input = Circuit::new();
+            input += DefinitionBit::new("bits".to_string(), 2, true);
+            input += DefinitionBit::new("flag".to_string(), 1, true);
+            for (index, bit) in bits.iter().enumerate() {
+                input += InputBit::new("bits".to_string(), index, *bit);
+            }
+            let on_bits = input.clone()
+                + conditional_on_bits(
+                    "bits",
+                    &[(0, true), (1, false)],
+                    "flag",
+                    Circuit::new() + PauliX::new(0),
+                );
+            let on_any = input
+                + conditional_on_any("bits", &[0, 1], "flag", Circuit::new() + PauliX::new(0));
+            assert_eq!(
+                prepared_state(&on_bits, 1),
+                basis_state(usize::from(bits[0] && !bits[1]), 1)
+            );
+            assert_eq!(
+                prepared_state(&on_any, 1),
+                basis_state(usize::from(bits[0] || bits[1]), 1)
+            );
+        }
+    }
+
+    #[test]
+    fn conditions_on_true_bits_need_no_flag() {
+        let conditional = conditional_on_bits(
+            "bits",
+            &[(0, true), (1, true)],
+            "flag",
+            Circuit::new() + PauliX::new(0),
+        );
+        assert!(!conditional
+            .iter()
+            .any(|operation| matches!(operation, Operation::InputBit(_))));
+    }
+
+    #[test]
+    fn undefined_register() {
+        let mut circuit = Circuit::new();
+        circuit += MeasureQubit::new(0, "ro".to_string(), 0);
+        assert!(measurement_branches(&circuit, 1).is_err());
+    }
+}
@@ -0,0 +1,987 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Circuit figures as standalone SVG images and as LaTeX `quantikz` source.
+//!
+//! The figures use the same wires as the text diagrams in [crate::diagram]: one wire per qubit followed by
+//! one classical wire per bit register. Operations are assigned to columns, every operation as far left
+//! as the wires it uses allow. The circuits of `PragmaConditional` operations occupy their own columns
+//! inside a dashed frame.
+
+use crate::diagram::{collect_registers, format_angle, number_qubits};
+use qoqo_calculator::CalculatorFloat;
+use roqoqo::operations::*;
+use roqoqo::Circuit;
+use std::path::Path;
+
+/// Distance between two wires in the SVG image.
+const WIRE_SPACING: f64 = 48.0;
+/// Height of a gate box in the SVG image.
+const GATE_SIZE: f64 = 32.0;
+/// Approximate width of one label character in the SVG image.
+const CHARACTER_WIDTH: f64 = 8.0;
+/// Margin around the SVG image.
+const MARGIN: f64 = 24.0;
+
+/// Returns a standalone SVG image of a circuit.
+pub fn circuit_to_svg(circuit: &Circuit) -> String {
+    let figure = Figure::new(circuit, Notation::Svg);
+    let mut columns = vec![0.0; figure.columns];
+    let elements = figure.flattened();
+    for (column, element) in elements.iter() {
+        columns[*column] = f64::max(columns[*column], element_width(element));
+    }
+    // The title of a conditional frame has to fit above the columns of the frame
+    for (column, element) in elements.iter() {
+        if let Element::Conditional {
+            title,
+            columns: span,
+            ..
+        } = element
+        {
+            let title_width = title.chars().count() as f64 * 0.8 * CHARACTER_WIDTH + 8.0;
+            let frame_width: f64 = columns[*column..column + span].iter().sum();
+            if frame_width < title_width {
+                columns[column + span - 1] += title_width - frame_width;
+            }
+        }
+    }
+    let labels: Vec<String> = figure.wire_labels();
+    let label_width = labels
+        .iter()
+        .map(|label| label.chars().count() as f64 * CHARACTER_WIDTH)
+        .fold(0.0, f64::max)
+        + 12.0;
+    let wires_start = MARGIN + label_width;
+    let column_start: Vec<f64> = columns
+        .iter()
+        .scan(wires_start + 8.0, |position, width| {
+            let start = *position;
+            *position += width;
+            Some(start)
+        })
+        .collect();
+    let wires_end = wires_start + 16.0 + columns.iter().sum::<f64>();
+    let svg = Svg {
+        column_start,
+        columns,
+        top: MARGIN + 16.0,
+    };
+    let width = wires_end + MARGIN;
+    let height = svg.wire_y(figure.number_wires().max(1) - 1) + GATE_SIZE / 2.0 + MARGIN;
+
+    let mut image = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width:.0}\" height=\"{height:.0}\" viewBox=\"0 0 {width:.0} {height:.0}\" font-family=\"serif\" font-size=\"14\">\n",
+    );
+    image.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+    for (wire, label) in labels.iter().enumerate() {
+        let y = svg.wire_y(wire);
+        image.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\" dominant-baseline=\"central\">{}</text>\n",
+            wires_start - 6.0,
+            y,
+            escape_xml(label)
+        ));
+        if wire < figure.number_qubits {
+            image.push_str(&svg_line(wires_start, y, wires_end, y));
+        } else {
+            image.push_str(&svg_line(wires_start, y - 1.5, wires_end, y - 1.5));
+            image.push_str(&svg_line(wires_start, y + 1.5, wires_end, y + 1.5));
+        }
+    }
+    for placed in &figure.elements {
+        svg.draw(&mut image, placed, 0);
+    }
+    image.push_str("</svg>\n");
+    image
+}
+
+/// Returns the `quantikz` environment of a circuit.
+///
+/// The environment can be included in any document that loads the `quantikz` TikZ library.
+///
+pub fn circuit_to_quantikz(circuit: &Circuit) -> String {
+    let figure = Figure::new(circuit, Notation::Latex);
+    let mut cells: Vec<Vec<String>> = (0..figure.number_wires())
+        .map(|wire| {
+            let wire_command = if wire < figure.number_qubits {
+                "\\qw"
+            } else {
+                "\\cw"
+            };
+            vec![wire_command.to_string(); figure.columns + 1]
+        })
+        .collect();
+    for placed in &figure.elements {
+        fill_cells(&mut cells, placed, 0);
+    }
+    let rows: Vec<String> = figure
+        .wire_labels()
+        .iter()
+        .enumerate()
+        .map(|(wire, label)| {
+            let label = if wire < figure.number_qubits {
+                format!("$q_{{{}}}$", wire)
+            } else {
+                escape_latex(label)
+            };
+            format!("\\lstick{{{}}} & {}", label, cells[wire].join(" & "))
+        })
+        .collect();
+    format!(
+        "\\begin{{quantikz}}\n{}\n\\end{{quantikz}}\n",
+        rows.join(" \\\\\n")
+    )
+}
+
+/// Returns a standalone LaTeX document with the `quantikz` figure of a circuit.
+pub fn circuit_to_latex(circuit: &Circuit) -> String {
+    format!(
+        "\\documentclass[border=4pt]{{standalone}}\n\\usepackage{{tikz}}\n\\usetikzlibrary{{quantikz}}\n\\begin{{document}}\n{}\\end{{document}}\n",
+        circuit_to_quantikz(circuit)
+    )
+}
+
+/// Writes `<name>.svg` and `<name>.tex` figures of a circuit to a directory, creating the directory if needed.
+pub fn write_figures(circuit: &Circuit, directory: &Path, name: &str) -> std::io::Result<()> {
+    std::fs::create_dir_all(directory)?;
+    std::fs::write(
+        directory.join(format!("{}.svg", name)),
+        circuit_to_svg(circuit),
+    )?;
+    std::fs::write(
+        directory.join(format!("{}.tex", name)),
+        circuit_to_latex(circuit),
+    )
+}
+
+/// Label format of gates, plain text for SVG images and math mode for LaTeX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Notation {
+    Svg,
+    Latex,
+}
+
+/// Target of a controlled gate.
+enum Target {
+    Cross,
+    Dot,
+    Label(String),
+}
+
+/// Drawable form of an operation.
+enum Element {
+    /// Box covering the qubits from `lowest` to `highest`.
+    Gate {
+        lowest: usize,
+        highest: usize,
+        label: String,
+    },
+    Controlled {
+        controls: Vec<usize>,
+        target: usize,
+        shape: Target,
+    },
+    Swap {
+        first: usize,
+        second: usize,
+    },
+    /// Measurement of qubits into a register, `index` is set for single-qubit measurements.
+    Measurement {
+        lowest: usize,
+        highest: usize,
+        register: Option<usize>,
+        index: Option<usize>,
+        label: String,
+    },
+    /// Box on a classical wire.
+    Classical {
+        register: usize,
+        label: String,
+    },
+    Conditional {
+        title: String,
+        register: Option<usize>,
+        lowest: usize,
+        highest: usize,
+        columns: usize,
+        inner: Vec<Placed>,
+    },
+}
+
+impl Element {
+    /// The first and last wire the element uses.
+    fn span(&self) -> (usize, usize) {
+        match self {
+            Element::Gate {
+                lowest, highest, ..
+            } => (*lowest, *highest),
+            Element::Controlled {
+                controls, target, ..
+            } => controls
+                .iter()
+                .fold((*target, *target), |(low, high), control| {
+                    (low.min(*control), high.max(*control))
+                }),
+            Element::Swap { first, second } => (*first.min(second), *first.max(second)),
+            Element::Measurement {
+                lowest,
+                highest,
+                register,
+                ..
+            } => (*lowest, register.unwrap_or(*highest)),
+            Element::Classical { register, .. } => (*register, *register),
+            Element::Conditional {
+                lowest,
+                highest,
+                register,
+                ..
+            } => (*lowest, register.unwrap_or(*highest).max(*highest)),
+        }
+    }
+}
+
+/// An element and its column.
+struct Placed {
+    column: usize,
+    element: Element,
+}
+
+/// Wires and placed elements of a circuit figure.
+struct Figure {
+    number_qubits: usize,
+    registers: Vec<String>,
+    notation: Notation,
+    elements: Vec<Placed>,
+    columns: usize,
+}
+
+impl Figure {
+    fn new(circuit: &Circuit, notation: Notation) -> Self {
+        let mut registers: Vec<String> = Vec::new();
+        collect_registers(circuit, &mut registers);
+        let mut figure = Figure {
+            number_qubits: number_qubits(circuit),
+            registers,
+            notation,
+            elements: Vec::new(),
+            columns: 0,
+        };
+        let (elements, columns) = figure.layout(circuit);
+        figure.elements = elements;
+        figure.columns = columns;
+        figure
+    }
+
+    fn number_wires(&self) -> usize {
+        self.number_qubits + self.registers.len()
+    }
+
+    fn wire_labels(&self) -> Vec<String> {
+        (0..self.number_qubits)
+            .map(|qubit| format!("q{}", qubit))
+            .chain(self.registers.iter().cloned())
+            .collect()
+    }
+
+    fn register_wire(&self, register: &str) -> Option<usize> {
+        self.registers
+            .iter()
+            .position(|name| name == register)
+            .map(|position| self.number_qubits + position)
+    }
+
+    /// Assigns columns to the operations of a circuit, returns the elements and the number of columns.
+    fn layout(&self, circuit: &Circuit) -> (Vec<Placed>, usize) {
+        let mut next_free = vec![0; self.number_wires()];
+        let mut columns = 0;
+        let mut elements = Vec::new();
+        for operation in circuit.iter() {
+            let element = match self.element(operation) {
+                Some(element) => element,
+                None => continue,
+            };
+            let width = match &element {
+                Element::Conditional { columns, .. } => *columns,
+                _ => 1,
+            };
+            let (lowest, highest) = element.span();
+            let column = next_free[lowest..=highest]
+                .iter()
+                .copied()
+                .max()
+                .unwrap_or(0);
+            for free in &mut next_free[lowest..=highest] {
+                *free = column + width;
+            }
+            columns = columns.max(column + width);
+            elements.push(Placed { column, element });
+        }
+        (elements, columns)
+    }
+
+    /// Returns all elements with their absolute column, conditional frames are followed by the elements inside them.
+    fn flattened(&self) -> Vec<(usize, &Element)> {
+        fn flatten<'a>(
+            placed: &'a [Placed],
+            offset: usize,
+            result: &mut Vec<(usize, &'a Element)>,
+        ) {
+            for Placed { column, element } in placed {
+                result.push((offset + column, element));
+                if let Element::Conditional { inner, .. } = element {
+                    flatten(inner, offset + column, result);
+                }
+            }
+        }
+        let mut result = Vec::new();
+        flatten(&self.elements, 0, &mut result);
+        result
+    }
+
+    fn element(&self, operation: &Operation) -> Option<Element> {
+        let controlled = |controls: Vec<usize>, target: usize, shape: Target| Element::Controlled {
+            controls,
+            target,
+            shape,
+        };
+        let element = match operation {
+            Operation::DefinitionBit(_)
+            | Operation::DefinitionFloat(_)
+            | Operation::DefinitionComplex(_)
+            | Operation::DefinitionUsize(_) => return None,
+            Operation::CNOT(op) => controlled(vec![*op.control()], *op.target(), Target::Cross),
+            Operation::ControlledPauliZ(op) => {
+                controlled(vec![*op.control()], *op.target(), Target::Dot)
+            }
+            Operation::ControlledPauliY(op) => controlled(
+                vec![*op.control()],
+                *op.target(),
+                Target::Label("Y".to_string()),
+            ),
+            Operation::ControlledPhaseShift(op) => controlled(
+                vec![*op.control()],
+                *op.target(),
+                Target::Label(self.rotation("P", op.theta())),
+            ),
+            Operation::ControlledRotateX(op) => controlled(
+                vec![*op.control()],
+                *op.target(),
+                Target::Label(self.rotation("R_x", op.theta())),
+            ),
+            Operation::SWAP(op) => Element::Swap {
+                first: *op.control(),
+                second: *op.target(),
+            },
+            Operation::Toffoli(op) => controlled(
+                vec![*op.control_0(), *op.control_1()],
+                *op.target(),
+                Target::Cross,
+            ),
+            Operation::ControlledControlledPauliZ(op) => controlled(
+                vec![*op.control_0(), *op.control_1()],
+                *op.target(),
+                Target::Dot,
+            ),
+            Operation::ControlledControlledPhaseShift(op) => controlled(
+                vec![*op.control_0(), *op.control_1()],
+                *op.target(),
+                Target::Label(self.rotation("P", op.theta())),
+            ),
+            Operation::MeasureQubit(op) => Element::Measurement {
+                lowest: *op.qubit(),
+                highest: *op.qubit(),
+                register: self.register_wire(op.readout()),
+                index: Some(*op.readout_index()),
+                label: "M".to_string(),
+            },
+            Operation::PragmaRepeatedMeasurement(op) => {
+                if self.number_qubits == 0 {
+                    return None;
+                }
+                Element::Measurement {
+                    lowest: 0,
+                    highest: self.number_qubits - 1,
+                    register: self.register_wire(op.readout()),
+                    index: None,
+                    label: match self.notation {
+                        Notation::Svg => format!("M×{}", op.number_measurements()),
+                        Notation::Latex => format!("M^{{\\times {}}}", op.number_measurements()),
+                    },
+                }
+            }
+            Operation::PragmaSetNumberOfMeasurements(op) => Element::Classical {
+                register: self.register_wire(op.readout())?,
+                label: match self.notation {
+                    Notation::Svg => format!("×{}", op.number_measurements()),
+                    Notation::Latex => format!("\\times {}", op.number_measurements()),
+                },
+            },
+            Operation::InputBit(op) => Element::Classical {
+                register: self.register_wire(op.name())?,
+                label: match self.notation {
+                    Notation::Svg => format!("{}←{}", op.index(), u8::from(*op.value())),
+                    Notation::Latex => {
+                        format!("{}\\leftarrow {}", op.index(), u8::from(*op.value()))
+                    }
+                },
+            },
+            Operation::PragmaConditional(op) => {
+                let (inner, columns) = self.layout(op.circuit());
+                let spans: Vec<(usize, usize)> =
+                    inner.iter().map(|placed| placed.element.span()).collect();
+                Element::Conditional {
+                    title: format!("if {}[{}]", op.condition_register(), op.condition_index()),
+                    register: self.register_wire(op.condition_register()),
+                    lowest: spans.iter().map(|(low, _)| *low).min()?,
+                    highest: spans.iter().map(|(_, high)| *high).max()?,
+                    columns,
+                    inner,
+                }
+            }
+            _ => {
+                let (lowest, highest) = match operation.involved_qubits() {
+                    InvolvedQubits::Set(qubits) => (*qubits.iter().min()?, *qubits.iter().max()?),
+                    InvolvedQubits::All if self.number_qubits > 0 => (0, self.number_qubits - 1),
+                    _ => return None,
+                };
+                Element::Gate {
+                    lowest,
+                    highest,
+                    label: self.label(operation),
+                }
+            }
+        };
+        Some(element)
+    }
+
+    /// Returns a rotation label like `R_x(θ)`, subscripts are only kept in LaTeX.
+    fn rotation(&self, name: &str, angle: &CalculatorFloat) -> String {
+        match self.notation {
+            Notation::Svg => format!("{}({})", name.replace('_', ""), format_angle(angle, "π")),
+            Notation::Latex => format!("{}({})", name, latex_angle(angle)),
+        }
+    }
+
+    /// Returns the box label of an operation that is not drawn with control dots.
+    fn label(&self, operation: &Operation) -> String {
+        let latex = self.notation == Notation::Latex;
+        let name = |text: &str| {
+            if latex {
+                format!("\\mathrm{{{}}}", escape_latex(text))
+            } else {
+                text.to_string()
+            }
+        };
+        let readout = |text: &str, readout: &str| {
+            if latex {
+                format!("{}\\rightarrow {}", name(text), name(readout))
+            } else {
+                format!("{}→{}", text, readout)
+            }
+        };
+        match operation {
+            Operation::Hadamard(_) => "H".to_string(),
+            Operation::PauliX(_) => "X".to_string(),
+            Operation::PauliY(_) => "Y".to_string(),
+            Operation::PauliZ(_) => "Z".to_string(),
+            Operation::SGate(_) => "S".to_string(),
+            Operation::TGate(_) => "T".to_string(),
+            Operation::Identity(_) => "I".to_string(),
+            Operation::SqrtPauliX(_) if latex => "\\sqrt{X}".to_string(),
+            Operation::SqrtPauliX(_) => "√X".to_string(),
+            Operation::InvSqrtPauliX(_) if latex => "\\sqrt{X}^\\dagger".to_string(),
+            Operation::InvSqrtPauliX(_) => "√X†".to_string(),
+            Operation::RotateX(op) => self.rotation("R_x", op.theta()),
+            Operation::RotateY(op) => self.rotation("R_y", op.theta()),
+            Operation::RotateZ(op) => self.rotation("R_z", op.theta()),
+            Operation::PhaseShiftState0(op) => self.rotation("P_0", op.theta()),
+            Operation::PhaseShiftState1(op) => self.rotation("P", op.theta()),
+            Operation::RotateXY(op) if latex => format!(
+                "R_{{xy}}({}, {})",
+                latex_angle(op.theta()),
+                latex_angle(op.phi())
+            ),
+            Operation::RotateXY(op) => format!(
+                "Rxy({}, {})",
+                format_angle(op.theta(), "π"),
+                format_angle(op.phi(), "π")
+            ),
+            Operation::PragmaActiveReset(_) if latex => "|0\\rangle".to_string(),
+            Operation::PragmaActiveReset(_) => "|0⟩".to_string(),
+            Operation::PragmaGetStateVector(op) => readout("StateVector", op.readout()),
+            Operation::PragmaGetDensityMatrix(op) => readout("DensityMatrix", op.readout()),
+            _ => name(operation.hqslang().trim_start_matches("Pragma")),
+        }
+    }
+}
+
+/// Returns the width of the column needed for an element in the SVG image.
+fn element_width(element: &Element) -> f64 {
+    let label_width = |label: &str| {
+        f64::max(
+            GATE_SIZE,
+            label.chars().count() as f64 * CHARACTER_WIDTH + 12.0,
+        )
+    };
+    16.0 + match element {
+        Element::Gate { label, .. }
+        | Element::Measurement { label, .. }
+        | Element::Classical { label, .. } => label_width(label),
+        Element::Controlled {
+            shape: Target::Label(label),
+            ..
+        } => label_width(label),
+        Element::Conditional { .. } => 0.0,
+        _ => GATE_SIZE,
+    }
+}
+
+/// Positions of the columns in an SVG image.
+struct Svg {
+    column_start: Vec<f64>,
+    columns: Vec<f64>,
+    top: f64,
+}
+
+impl Svg {
+    fn wire_y(&self, wire: usize) -> f64 {
+        self.top + GATE_SIZE / 2.0 + wire as f64 * WIRE_SPACING
+    }
+
+    fn center_x(&self, column: usize) -> f64 {
+        self.column_start[column] + self.columns[column] / 2.0
+    }
+
+    fn draw(&self, image: &mut String, placed: &Placed, offset: usize) {
+        let column = offset + placed.column;
+        let x = self.center_x(column);
+        match &placed.element {
+            Element::Gate {
+                lowest,
+                highest,
+                label,
+            } => image.push_str(&self.gate_box(x, *lowest, *highest, label)),
+            Element::Controlled {
+                controls,
+                target,
+                shape,
+            } => {
+                let (lowest, highest) = placed.element.span();
+                image.push_str(&svg_line(x, self.wire_y(lowest), x, self.wire_y(highest)));
+                for control in controls {
+                    image.push_str(&svg_dot(x, self.wire_y(*control)));
+                }
+                let y = self.wire_y(*target);
+                match shape {
+                    Target::Cross => {
+                        image.push_str(&format!(
+                            "<circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"10\" fill=\"white\" stroke=\"black\"/>\n"
+                        ));
+                        image.push_str(&svg_line(x - 10.0, y, x + 10.0, y));
+                        image.push_str(&svg_line(x, y - 10.0, x, y + 10.0));
+                    }
+                    Target::Dot => image.push_str(&svg_dot(x, y)),
+                    Target::Label(label) => {
+                        image.push_str(&self.gate_box(x, *target, *target, label))
+                    }
+                }
+            }
+            Element::Swap { first, second } => {
+                image.push_str(&svg_line(x, self.wire_y(*first), x, self.wire_y(*second)));
+                for wire in [first, second] {
+                    let y = self.wire_y(*wire);
+                    image.push_str(&svg_line(x - 6.0, y - 6.0, x + 6.0, y + 6.0));
+                    image.push_str(&svg_line(x - 6.0, y + 6.0, x + 6.0, y - 6.0));
+                }
+            }
+            Element::Measurement {
+                lowest,
+                highest,
+                register,
+                index,
+                label,
+            } => {
+                if let Some(register) = register {
+                    let y = self.wire_y(*register);
+                    image.push_str(&svg_double_line(x, self.wire_y(*highest), y));
+                    image.push_str(&format!(
+                        "<path d=\"M {:.1} {:.1} l -5 -8 h 10 z\" fill=\"black\"/>\n",
+                        x, y
+                    ));
+                    if let Some(index) = index {
+                        image.push_str(&format!(
+                            "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"11\">{}</text>\n",
+                            x + 5.0,
+                            y - 8.0,
+                            index
+                        ));
+                    }
+                }
+                if index.is_some() {
+                    // Single measurements are drawn with a meter symbol
+                    image.push_str(&self.gate_box(x, *lowest, *highest, ""));
+                    let y = self.wire_y(*lowest);
+                    image.push_str(&format!(
+                        "<path d=\"M {:.1} {:.1} a 9 9 0 0 1 18 0\" fill=\"none\" stroke=\"black\"/>\n",
+                        x - 9.0,
+                        y + 6.0
+                    ));
+                    image.push_str(&svg_line(x, y + 6.0, x + 7.0, y - 8.0));
+                } else {
+                    image.push_str(&self.gate_box(x, *lowest, *highest, label));
+                }
+            }
+            Element::Classical { register, label } => {
+                image.push_str(&self.gate_box(x, *register, *register, label))
+            }
+            Element::Conditional {
+                title,
+                register,
+                lowest,
+                highest,
+                columns,
+                inner,
+            } => {
+                let left = self.column_start[column] + 2.0;
+                let right = self.column_start[column + columns - 1]
+                    + self.columns[column + columns - 1]
+                    - 2.0;
+                let top = self.wire_y(*lowest) - GATE_SIZE / 2.0 - 6.0;
+                let bottom = self.wire_y(*highest) + GATE_SIZE / 2.0 + 6.0;
+                image.push_str(&format!(
+                    "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" rx=\"6\" fill=\"none\" stroke=\"gray\" stroke-dasharray=\"4 3\"/>\n",
+                    left,
+                    top,
+                    right - left,
+                    bottom - top
+                ));
+                image.push_str(&format!(
+                    "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"11\" fill=\"gray\">{}</text>\n",
+                    left + 2.0,
+                    top - 3.0,
+                    escape_xml(title)
+                ));
+                if let Some(register) = register.filter(|register| register > highest) {
+                    let center = (left + right) / 2.0;
+                    let y = self.wire_y(register);
+                    image.push_str(&svg_double_line(center, bottom, y));
+                    image.push_str(&svg_dot(center, y));
+                }
+                for placed in inner {
+                    self.draw(image, placed, column);
+                }
+            }
+        }
+    }
+
+    /// Box with a centered label covering the wires from `lowest` to `highest` around `x`.
+    fn gate_box(&self, x: f64, lowest: usize, highest: usize, label: &str) -> String {
+        let width = f64::max(
+            GATE_SIZE,
+            label.chars().count() as f64 * CHARACTER_WIDTH + 12.0,
+        );
+        let top = self.wire_y(lowest) - GATE_SIZE / 2.0;
+        let height = self.wire_y(highest) - self.wire_y(lowest) + GATE_SIZE;
+        format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"white\" stroke=\"black\"/>\n<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\" dominant-baseline=\"central\">{}</text>\n",
+            x - width / 2.0,
+            top,
+            width,
+            height,
+            x,
+            top + height / 2.0,
+            escape_xml(label)
+        )
+    }
+}
+
+/// Writes the cells of a placed element into the quantikz grid.
+fn fill_cells(cells: &mut [Vec<String>], placed: &Placed, offset: usize) {
+    let column = offset + placed.column;
+    let relative = |from: usize, to: usize| to as isize - from as isize;
+    match &placed.element {
+        Element::Gate {
+            lowest,
+            highest,
+            label,
+        } => set_gate(cells, column, *lowest, *highest, label),
+        Element::Controlled {
+            controls,
+            target,
+            shape,
+        } => {
+            for control in controls {
+                cells[*control][column] = format!("\\ctrl{{{}}}", relative(*control, *target));
+            }
+            cells[*target][column] = match shape {
+                Target::Cross => "\\targ{}".to_string(),
+                Target::Dot => "\\control{}".to_string(),
+                Target::Label(label) => format!("\\gate{{{}}}", label),
+            };
+        }
+        Element::Swap { first, second } => {
+            cells[*first][column] = format!("\\swap{{{}}}", relative(*first, *second));
+            cells[*second][column] = "\\targX{}".to_string();
+        }
+        Element::Measurement {
+            lowest,
+            highest,
+            register,
+            index,
+            label,
+        } => {
+            if index.is_some() {
+                cells[*lowest][column] = "\\meter{}".to_string();
+            } else {
+                set_gate(cells, column, *lowest, *highest, label);
+            }
+            if let Some(register) = register {
+                cells[*highest][column]
+                    .push_str(&format!(" \\vcw{{{}}}", relative(*highest, *register)));
+            }
+        }
+        Element::Classical { register, label } => {
+            cells[*register][column] = format!("\\gate{{{}}}", label)
+        }
+        Element::Conditional {
+            title,
+            register,
+            lowest,
+            highest,
+            columns,
+            inner,
+        } => {
+            for placed in inner {
+                fill_cells(cells, placed, column);
+            }
+            cells[*lowest][column].push_str(&format!(
+                " \\gategroup[wires={},steps={},style={{dashed,rounded corners,inner xsep=2pt}},background]{{{}}}",
+                highest - lowest + 1,
+                columns,
+                escape_latex(title)
+            ));
+            if let Some(register) = register.filter(|register| register > highest) {
+                cells[*highest][column]
+                    .push_str(&format!(" \\vcw{{{}}}", relative(*highest, register)));
+            }
+        }
+    }
+}
+
+/// Writes a gate covering the wires from `lowest` to `highest`, the covered cells stay empty.
+fn set_gate(cells: &mut [Vec<String>], column: usize, lowest: usize, highest: usize, label: &str) {
+    cells[lowest][column] = if lowest == highest {
+        format!("\\gate{{{}}}", label)
+    } else {
+        format!("\\gate[wires={}]{{{}}}", highest - lowest + 1, label)
+    };
+    for row in cells.iter_mut().take(highest + 1).skip(lowest + 1) {
+        row[column] = String::new();
+    }
+}
+
+/// Formats an angle in LaTeX math mode, variables of symbolic angles are set upright.
+fn latex_angle(angle: &CalculatorFloat) -> String {
+    let expression = format_angle(angle, "\\pi ");
+    if let CalculatorFloat::Float(_) = angle {
+        return expression;
+    }
+    let mut latex = String::new();
+    let mut identifier = String::new();
+    let flush = |identifier: &mut String, latex: &mut String| {
+        if identifier.is_empty() {
+            return;
+        }
+        let starts_with_letter = identifier
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_');
+        latex.push_str(&match identifier.as_str() {
+            "pi" => "\\pi ".to_string(),
+            "sin" | "cos" | "tan" | "exp" | "log" | "sinh" | "cosh" | "tanh" => {
+                format!("\\{}", identifier)
+            }
+            _ if starts_with_letter => format!("\\mathrm{{{}}}", escape_latex(identifier)),
+            _ => identifier.clone(),
+        });
+        identifier.clear();
+    };
+    for character in expression.chars() {
+        if character.is_alphanumeric() || character == '_' || character == '.' {
+            identifier.push(character);
+            continue;
+        }
+        flush(&mut identifier, &mut latex);
+        match character {
+            '*' => latex.push_str("\\cdot "),
+            ' ' => (),
+            _ => latex.push(character),
+        }
+    }
+    flush(&mut identifier, &mut latex);
+    latex
+}
+
+fn svg_line(x1: f64, y1: f64, x2: f64, y2: f64) -> String {
+    format!(
+        "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"black\"/>\n",
+        x1, y1, x2, y2
+    )
+}
+
+/// Classical vertical connection drawn as two parallel lines.
+fn svg_double_line(x: f64, y1: f64, y2: f64) -> String {
+    format!(
+        "{}{}",
+        svg_line(x - 1.5, y1, x - 1.5, y2),
+        svg_line(x + 1.5, y1, x + 1.5, y2)
+    )
+}
+
+fn svg_dot(x: f64, y: f64) -> String {
+    format!(
+        "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"5\" fill=\"black\"/>\n",
+        x, y
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_latex(text: &str) -> String {
+    text.chars()
+        .map(|character| match character {
+            '_' | '&' | '%' | '#' | '$' | '{' | '}' => format!("\\{}", character),
+            _ => character.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditional_rotation() -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 1, true);
+        circuit += RotateX::new(0, "theta_1".into());
+        circuit += Hadamard::new(1);
+        circuit += CNOT::new(1, 0);
+        circuit += MeasureQubit::new(1, "ro".to_string(), 0);
+        circuit += PragmaConditional::new(
+            "ro".to_string(),
+            0,
+            Circuit::new() + RotateZ::new(0, "2*theta_1".into()),
+        );
+        circuit
+    }
+
+    /// Checks that a document is well-formed XML: one root element, properly nested and closed tags, quoted
+    /// attributes and escaped text.
+    fn assert_well_formed(document: &str) {
+        let check_escaped = |text: &str| {
+            assert!(!text.contains('<'), "Unescaped < in {text:?}");
+            for (index, _) in text.match_indices('&') {
+                assert!(
+                    ["&amp;", "&lt;", "&gt;", "&quot;", "&apos;"]
+                        .iter()
+                        .any(|entity| text[index..].starts_with(entity)),
+                    "Unescaped & in {text:?}"
+                );
+            }
+        };
+        let mut open: Vec<&str> = Vec::new();
+        let mut roots = 0;
+        let mut rest = document;
+        while let Some(start) = rest.find('<') {
+            let text = &rest[..start];
+            check_escaped(text);
+            assert!(
+                !open.is_empty() || text.trim().is_empty(),
+                "Text outside of the root: {text:?}"
+            );
+            let end = start + rest[start..].find('>').expect("Unclosed tag");
+            let tag = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(
+                    open.pop(),
+                    Some(name.trim()),
+                    "Mismatched closing tag {name}"
+                );
+                continue;
+            }
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+            let name = &tag[..name_end];
+            assert!(
+                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            );
+            // Attributes are name="value" pairs separated by whitespace
+            let mut attributes = tag[name_end..].trim();
+            while !attributes.is_empty() {
+                let (attribute, value) = attributes
+                    .split_once("=\"")
+                    .unwrap_or_else(|| panic!("Attribute without quoted value in <{tag}>"));
+                assert!(!attribute.trim().is_empty() && !attribute.contains(char::is_whitespace));
+                let value_end = value.find('"').expect("Unclosed attribute value");
+                check_escaped(&value[..value_end]);
+                attributes = value[value_end + 1..].trim_start();
+            }
+            if open.is_empty() {
+                roots += 1;
+            }
+            if !self_closing {
+                open.push(name);
+            }
+        }
+        assert!(rest.trim().is_empty(), "Text after the root: {rest:?}");
+        assert!(open.is_empty(), "Unclosed elements {open:?}");
+        assert_eq!(roots, 1);
+    }
+
+    #[test]
+    fn quantikz_snapshot() {
+        let expected = r#"
+\begin{quantikz}
+\lstick{$q_{0}$} & \gate{R_x(\mathrm{theta\_1})} & \targ{} & \qw & \gate{R_z(2\cdot \mathrm{theta\_1})} \gategroup[wires=1,steps=1,style={dashed,rounded corners,inner xsep=2pt},background]{if ro[0]} \vcw{2} & \qw \\
+\lstick{$q_{1}$} & \gate{H} & \ctrl{-1} & \meter{} \vcw{1} & \qw & \qw \\
+\lstick{ro} & \cw & \cw & \cw & \cw & \cw
+\end{quantikz}
+"#;
+        assert_eq!(
+            circuit_to_quantikz(&conditional_rotation()),
+            expected.trim_start_matches('\n')
+        );
+    }
+
+    #[test]
+    fn svg_is_well_formed() {
+        assert_well_formed(&circuit_to_svg(&conditional_rotation()));
+        // Register names are written into the image and need escaping
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("a<b&c".to_string(), 1, true);
+        circuit += MeasureQubit::new(0, "a<b&c".to_string(), 0);
+        circuit += PragmaConditional::new("a<b&c".to_string(), 0, Circuit::new() + PauliX::new(0));
+        let svg = circuit_to_svg(&circuit);
+        assert!(svg.contains("a&lt;b&amp;c"));
+        assert_well_formed(&svg);
+        assert_well_formed(&circuit_to_svg(&Circuit::new()));
+    }
+}
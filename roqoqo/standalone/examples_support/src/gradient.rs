@@ -0,0 +1,527 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Gradients of the expectation values of a QuantumProgram with the parameter-shift rule.
+//!
+//! For a gate `exp(-i a G)` whose generator `G` has the two eigenvalues `±1/2` (up to a constant), like `RotateZ`, the
+//! expectation value `E` depends on the angle `a` as `c0 + c1 cos(a) + c2 sin(a)`. Its derivative is exactly
+//! `dE/da = (E(a + π/2) - E(a - π/2)) / 2`, measured on the same device as `E` itself. A parameter of the program can
+//! enter the angles of several gates, with scaled arguments like `theta * hopping_parameter`. By the chain rule the
+//! derivative by the parameter is the sum over all gates of the derivative by the angle of the gate times the
+//! derivative of the angle by the parameter, which is evaluated from the symbolic angle.
+//!
+//! Every gate angle depending on a parameter is shifted on its own, so the gradient costs two runs of the circuit
+//! containing the gate per gate, or of all circuits for gates in the constant circuit. The results of the other
+//! circuits are the same for both shifts.
+//!
+//! [finite_difference_gradient] computes the gradient from central differences instead, which is only accurate
+//! without shot noise. [cheated_program] turns a `PauliZProduct` program into a `CheatedPauliZProduct` program reading
+//! the exact Pauli products from the simulator, so both gradients can be compared.
+
+use qoqo_calculator::{Calculator, CalculatorFloat};
+use roqoqo::backends::EvaluatingBackend;
+use roqoqo::measurements::{
+    CheatedPauliZProduct, CheatedPauliZProductInput, Measure, MeasureExpectationValues,
+};
+use roqoqo::operations::*;
+use roqoqo::registers::{BitOutputRegister, ComplexOutputRegister, FloatOutputRegister};
+use roqoqo::{Circuit, QuantumProgram, RoqoqoBackendError};
+use std::collections::HashMap;
+use std::f64::consts::FRAC_PI_2;
+
+/// The step of the central differences giving the derivatives of the gate angles by the parameters.
+const ANGLE_STEP: f64 = 1e-5;
+
+/// The output registers of one circuit.
+type Registers = (
+    HashMap<String, BitOutputRegister>,
+    HashMap<String, FloatOutputRegister>,
+    HashMap<String, ComplexOutputRegister>,
+);
+
+/// Computes the gradient of the expectation values of a program with the parameter-shift rule.
+///
+/// # Arguments
+///
+/// * `program` - A `PauliZProduct` or `CheatedPauliZProduct` program.
+/// * `backend` - The backend the shifted circuits are run on.
+/// * `parameters` - The values of the input parameters of the program, in the order of `input_parameter_names`.
+///
+/// # Returns
+///
+/// * `Ok(HashMap<String, Vec<f64>>)` - The derivatives of every expectation value by the input parameters.
+/// * `Err(RoqoqoBackendError)` - The program is of another kind, a parameter enters a gate the parameter-shift rule
+///   does not apply to or running failed.
+pub fn parameter_shift_gradient<B>(
+    program: &QuantumProgram,
+    backend: &B,
+    parameters: &[f64],
+) -> Result<HashMap<String, Vec<f64>>, RoqoqoBackendError>
+where
+    B: EvaluatingBackend,
+{
+    match program {
+        QuantumProgram::PauliZProduct {
+            measurement,
+            input_parameter_names,
+        } => measurement_gradient(measurement, input_parameter_names, parameters, backend),
+        QuantumProgram::CheatedPauliZProduct {
+            measurement,
+            input_parameter_names,
+        } => measurement_gradient(measurement, input_parameter_names, parameters, backend),
+        _ => Err(RoqoqoBackendError::GenericError {
+            msg:
+                "The parameter-shift gradient needs a PauliZProduct or CheatedPauliZProduct program"
+                    .to_string(),
+        }),
+    }
+}
+
+/// Computes the gradient of the expectation values of a program with central finite differences.
+///
+/// # Arguments
+///
+/// * `program` - A program returning expectation values.
+/// * `backend` - The backend the program is run on.
+/// * `parameters` - The values of the input parameters of the program.
+/// * `step` - The step of the central differences.
+///
+/// # Returns
+///
+/// * `Ok(HashMap<String, Vec<f64>>)` - The derivatives of every expectation value by the input parameters.
+/// * `Err(RoqoqoBackendError)` - The program returns no expectation values or running failed.
+pub fn finite_difference_gradient<B>(
+    program: &QuantumProgram,
+    backend: &B,
+    parameters: &[f64],
+    step: f64,
+) -> Result<HashMap<String, Vec<f64>>, RoqoqoBackendError>
+where
+    B: EvaluatingBackend + Clone,
+{
+    let run = |parameters: &[f64]| {
+        program
+            .run(backend.clone(), parameters)?
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: "The program returns no expectation values".to_string(),
+            })
+    };
+    let mut gradient: HashMap<String, Vec<f64>> = HashMap::new();
+    for index in 0..parameters.len() {
+        let mut shifted = parameters.to_vec();
+        shifted[index] = parameters[index] + step;
+        let plus = run(&shifted)?;
+        shifted[index] = parameters[index] - step;
+        let minus = run(&shifted)?;
+        for (name, value) in plus {
+            gradient
+                .entry(name.clone())
+                .or_insert_with(|| vec![0.0; parameters.len()])[index] =
+                (value - minus[&name]) / (2.0 * step);
+        }
+    }
+    Ok(gradient)
+}
+
+/// Turns a `PauliZProduct` program into a `CheatedPauliZProduct` program measuring the same expectation values exactly.
+///
+/// The measurements of every readout register are replaced by `PragmaGetPauliProduct` operations reading the Pauli
+/// products of the register from the simulator, after the basis rotations of the circuit.
+///
+/// # Arguments
+///
+/// * `program` - A `PauliZProduct` program without flipped measurements.
+///
+/// # Returns
+///
+/// * `Ok(QuantumProgram)` - The `CheatedPauliZProduct` program.
+/// * `Err(RoqoqoBackendError)` - The program is of another kind or uses flipped measurements.
+pub fn cheated_program(program: &QuantumProgram) -> Result<QuantumProgram, RoqoqoBackendError> {
+    let QuantumProgram::PauliZProduct {
+        measurement,
+        input_parameter_names,
+    } = program
+    else {
+        return Err(RoqoqoBackendError::GenericError {
+            msg: "Only PauliZProduct programs can be turned into CheatedPauliZProduct programs"
+                .to_string(),
+        });
+    };
+    if measurement.input.use_flipped_measurement {
+        return Err(RoqoqoBackendError::GenericError {
+            msg: "Flipped measurements have no CheatedPauliZProduct counterpart".to_string(),
+        });
+    }
+    // The Pauli products keep their indices, so the expectation values can be taken over unchanged
+    let mut products: Vec<(usize, &String, &Vec<usize>)> = measurement
+        .input
+        .pauli_product_qubit_masks
+        .iter()
+        .flat_map(|(readout, masks)| {
+            masks
+                .iter()
+                .map(move |(index, qubits)| (*index, readout, qubits))
+        })
+        .collect();
+    products.sort();
+    let mut input = CheatedPauliZProductInput::new();
+    for (index, readout, _) in products.iter() {
+        input.add_pauliz_product(pauli_product_readout(readout, *index));
+    }
+    input.measured_exp_vals = measurement.input.measured_exp_vals.clone();
+
+    let measured = |readout: &String| {
+        measurement
+            .input
+            .pauli_product_qubit_masks
+            .contains_key(readout)
+    };
+    let circuits = measurement
+        .circuits
+        .iter()
+        .map(|circuit| {
+            let mut cheated = Circuit::new();
+            let mut readouts: Vec<&String> = Vec::new();
+            for operation in circuit.iter() {
+                match operation {
+                    Operation::DefinitionBit(op) if measured(op.name()) => readouts.push(op.name()),
+                    Operation::MeasureQubit(op) if measured(op.readout()) => {}
+                    Operation::PragmaRepeatedMeasurement(op) if measured(op.readout()) => {}
+                    Operation::PragmaSetNumberOfMeasurements(op) if measured(op.readout()) => {}
+                    _ => cheated.add_operation(operation.clone()),
+                }
+            }
+            for (index, readout, qubits) in products.iter() {
+                if readouts.contains(readout) {
+                    let name = pauli_product_readout(readout, *index);
+                    cheated += DefinitionFloat::new(name.clone(), 1, true);
+                    cheated += PragmaGetPauliProduct::new(
+                        qubits.iter().map(|qubit| (*qubit, 3)).collect(),
+                        name,
+                        Circuit::new(),
+                    );
+                }
+            }
+            cheated
+        })
+        .collect();
+    Ok(QuantumProgram::CheatedPauliZProduct {
+        measurement: CheatedPauliZProduct {
+            constant_circuit: measurement.constant_circuit.clone(),
+            circuits,
+            input,
+        },
+        input_parameter_names: input_parameter_names.clone(),
+    })
+}
+
+/// Returns the name of the float register holding a Pauli product of a readout register.
+fn pauli_product_readout(readout: &str, index: usize) -> String {
+    format!("{}_pauli_product_{}", readout, index)
+}
+
+/// Computes the parameter-shift gradient of the expectation values of a measurement.
+fn measurement_gradient<T, B>(
+    measurement: &T,
+    input_parameter_names: &[String],
+    parameters: &[f64],
+    backend: &B,
+) -> Result<HashMap<String, Vec<f64>>, RoqoqoBackendError>
+where
+    T: Measure + MeasureExpectationValues,
+    B: EvaluatingBackend,
+{
+    if parameters.len() != input_parameter_names.len() {
+        return Err(RoqoqoBackendError::GenericError {
+            msg: format!(
+                "Wrong number of parameters {} parameters expected {} parameters given",
+                input_parameter_names.len(),
+                parameters.len()
+            ),
+        });
+    }
+    let values: HashMap<String, f64> = input_parameter_names
+        .iter()
+        .cloned()
+        .zip(parameters.iter().copied())
+        .collect();
+    let mut calculator = Calculator::new();
+    for (name, value) in values.iter() {
+        calculator.set_variable(name, *value);
+    }
+    let substituted = measurement.substitute_parameters(values)?;
+    let constant_circuit = substituted.constant_circuit().clone().unwrap_or_default();
+    let circuits: Vec<Circuit> = substituted.circuits().cloned().collect();
+    let run = |constant_circuit: &Circuit, circuit: &Circuit| {
+        backend.run_circuit_iterator(constant_circuit.iter().chain(circuit.iter()))
+    };
+    let evaluate = |registers: Vec<Registers>| {
+        let (bit_registers, float_registers, complex_registers) = merge_registers(registers);
+        substituted
+            .evaluate(bit_registers, float_registers, complex_registers)?
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: "The measurement returns no expectation values".to_string(),
+            })
+    };
+    let registers = circuits
+        .iter()
+        .map(|circuit| run(&constant_circuit, circuit))
+        .collect::<Result<Vec<Registers>, RoqoqoBackendError>>()?;
+    let mut gradient: HashMap<String, Vec<f64>> = evaluate(registers.clone())?
+        .into_keys()
+        .map(|name| (name, vec![0.0; parameters.len()]))
+        .collect();
+    let mut add =
+        |derivatives: Vec<f64>, plus: HashMap<String, f64>, minus: HashMap<String, f64>| {
+            for (name, values) in gradient.iter_mut() {
+                let difference = (plus[name] - minus[name]) / 2.0;
+                for (value, derivative) in values.iter_mut().zip(derivatives.iter()) {
+                    *value += derivative * difference;
+                }
+            }
+        };
+
+    // Gates of the constant circuit change the results of all circuits
+    let symbolic_constant_circuit = measurement.constant_circuit().clone().unwrap_or_default();
+    for (index, operation) in symbolic_constant_circuit.iter().enumerate() {
+        if let Some(derivatives) = angle_derivatives(operation, &calculator, input_parameter_names)?
+        {
+            let mut shifted_values = Vec::new();
+            for shift in [FRAC_PI_2, -FRAC_PI_2] {
+                let shifted = shift_angle(&constant_circuit, index, shift);
+                let shifted_registers = circuits
+                    .iter()
+                    .map(|circuit| run(&shifted, circuit))
+                    .collect::<Result<Vec<Registers>, RoqoqoBackendError>>()?;
+                shifted_values.push(evaluate(shifted_registers)?);
+            }
+            let minus = shifted_values.pop().unwrap_or_default();
+            let plus = shifted_values.pop().unwrap_or_default();
+            add(derivatives, plus, minus);
+        }
+    }
+    for (circuit_index, symbolic_circuit) in measurement.circuits().enumerate() {
+        for (index, operation) in symbolic_circuit.iter().enumerate() {
+            if let Some(derivatives) =
+                angle_derivatives(operation, &calculator, input_parameter_names)?
+            {
+                let mut shifted_values = Vec::new();
+                for shift in [FRAC_PI_2, -FRAC_PI_2] {
+                    let shifted = shift_angle(&circuits[circuit_index], index, shift);
+                    let mut shifted_registers = registers.clone();
+                    shifted_registers[circuit_index] = run(&constant_circuit, &shifted)?;
+                    shifted_values.push(evaluate(shifted_registers)?);
+                }
+                let minus = shifted_values.pop().unwrap_or_default();
+                let plus = shifted_values.pop().unwrap_or_default();
+                add(derivatives, plus, minus);
+            }
+        }
+    }
+    Ok(gradient)
+}
+
+/// Returns the derivatives of the rotation angle of an operation by the input parameters.
+///
+/// Returns `None` for operations without symbolic parameters and an error for operations with symbolic parameters the
+/// parameter-shift rule does not apply to.
+fn angle_derivatives(
+    operation: &Operation,
+    calculator: &Calculator,
+    input_parameter_names: &[String],
+) -> Result<Option<Vec<f64>>, RoqoqoBackendError> {
+    if !operation.is_parametrized() {
+        return Ok(None);
+    }
+    let angle = rotation_angle(operation).ok_or_else(|| RoqoqoBackendError::GenericError {
+        msg: format!(
+            "The parameter-shift rule does not apply to the symbolic parameters of {}",
+            operation.hqslang()
+        ),
+    })?;
+    let mut derivatives = Vec::with_capacity(input_parameter_names.len());
+    for name in input_parameter_names {
+        let value = calculator.get_variable(name)?;
+        let mut shifted = calculator.clone();
+        shifted.set_variable(name, value + ANGLE_STEP);
+        let plus = shifted.parse_get(angle.clone())?;
+        shifted.set_variable(name, value - ANGLE_STEP);
+        let minus = shifted.parse_get(angle.clone())?;
+        derivatives.push((plus - minus) / (2.0 * ANGLE_STEP));
+    }
+    Ok(Some(derivatives))
+}
+
+/// Returns the rotation angle of an operation the parameter-shift rule applies to.
+///
+/// The other arguments of the operation have to be numbers.
+fn rotation_angle(operation: &Operation) -> Option<&CalculatorFloat> {
+    match operation {
+        Operation::RotateX(op) => Some(op.theta()),
+        Operation::RotateY(op) => Some(op.theta()),
+        Operation::RotateZ(op) => Some(op.theta()),
+        Operation::PhaseShiftState0(op) => Some(op.theta()),
+        Operation::PhaseShiftState1(op) => Some(op.theta()),
+        Operation::ControlledPhaseShift(op) => Some(op.theta()),
+        Operation::VariableMSXX(op) => Some(op.theta()),
+        Operation::RotateXY(op) if op.phi().is_float() => Some(op.theta()),
+        Operation::RotateAroundSphericalAxis(op)
+            if op.spherical_theta().is_float() && op.spherical_phi().is_float() =>
+        {
+            Some(op.theta())
+        }
+        _ => None,
+    }
+}
+
+/// Returns a copy of a circuit with the rotation angle of one operation shifted.
+///
+/// # Arguments
+///
+/// * `circuit` - The circuit with all parameters substituted.
+/// * `index` - The index of the operation in the iterator of the circuit.
+/// * `shift` - The shift of the rotation angle.
+fn shift_angle(circuit: &Circuit, index: usize, shift: f64) -> Circuit {
+    let mut shifted = Circuit::new();
+    for (operation_index, operation) in circuit.iter().enumerate() {
+        if operation_index != index {
+            shifted.add_operation(operation.clone());
+            continue;
+        }
+        shifted.add_operation(match operation {
+            Operation::RotateX(op) => {
+                Operation::from(RotateX::new(*op.qubit(), op.theta() + shift))
+            }
+            Operation::RotateY(op) => RotateY::new(*op.qubit(), op.theta() + shift).into(),
+            Operation::RotateZ(op) => RotateZ::new(*op.qubit(), op.theta() + shift).into(),
+            Operation::PhaseShiftState0(op) => {
+                PhaseShiftState0::new(*op.qubit(), op.theta() + shift).into()
+            }
+            Operation::PhaseShiftState1(op) => {
+                PhaseShiftState1::new(*op.qubit(), op.theta() + shift).into()
+            }
+            Operation::ControlledPhaseShift(op) => {
+                ControlledPhaseShift::new(*op.control(), *op.target(), op.theta() + shift).into()
+            }
+            Operation::VariableMSXX(op) => {
+                VariableMSXX::new(*op.control(), *op.target(), op.theta() + shift).into()
+            }
+            Operation::RotateXY(op) => {
+                RotateXY::new(*op.qubit(), op.theta() + shift, op.phi().clone()).into()
+            }
+            Operation::RotateAroundSphericalAxis(op) => RotateAroundSphericalAxis::new(
+                *op.qubit(),
+                op.theta() + shift,
+                op.spherical_theta().clone(),
+                op.spherical_phi().clone(),
+            )
+            .into(),
+            _ => operation.clone(),
+        });
+    }
+    shifted
+}
+
+/// Merges the output registers of several circuits like `EvaluatingBackend::run_measurement_registers`.
+fn merge_registers(registers: Vec<Registers>) -> Registers {
+    let mut merged: Registers = (HashMap::new(), HashMap::new(), HashMap::new());
+    for (bit_registers, float_registers, complex_registers) in registers {
+        for (name, mut register) in bit_registers {
+            merged.0.entry(name).or_default().append(&mut register);
+        }
+        for (name, mut register) in float_registers {
+            merged.1.entry(name).or_default().append(&mut register);
+        }
+        for (name, mut register) in complex_registers {
+            merged.2.entry(name).or_default().append(&mut register);
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_vector::StateVectorBackend;
+    use roqoqo::measurements::{ClassicalRegister, PauliZProduct, PauliZProductInput};
+
+    /// A program measuring `<Z0> + 0.5 <Z0 Z1> = cos(theta) + 0.5 cos(2 phi)` with `number_measurements` shots.
+    fn program(number_measurements: usize) -> QuantumProgram {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 2, true);
+        circuit += RotateY::new(0, "theta".into());
+        circuit += CNOT::new(0, 1);
+        circuit += RotateX::new(1, "2 * phi".into());
+        circuit += PragmaRepeatedMeasurement::new("ro".to_string(), number_measurements, None);
+        let mut input = PauliZProductInput::new(2, false);
+        let z0 = input.add_pauliz_product("ro".to_string(), vec![0]).unwrap();
+        let z0z1 = input
+            .add_pauliz_product("ro".to_string(), vec![0, 1])
+            .unwrap();
+        input
+            .add_linear_exp_val(
+                "energy".to_string(),
+                HashMap::from([(z0, 1.0), (z0z1, 0.5)]),
+            )
+            .unwrap();
+        QuantumProgram::PauliZProduct {
+            measurement: PauliZProduct {
+                constant_circuit: None,
+                circuits: vec![circuit],
+                input,
+            },
+            input_parameter_names: vec!["theta".to_string(), "phi".to_string()],
+        }
+    }
+
+    #[test]
+    fn exact_gradient() {
+        let parameters: [f64; 2] = [0.4, -0.3];
+        let exact = [-parameters[0].sin(), -(2.0 * parameters[1]).sin()];
+        let cheated = cheated_program(&program(1)).unwrap();
+        let backend = StateVectorBackend::new(2);
+        let shift = parameter_shift_gradient(&cheated, &backend, &parameters).unwrap();
+        let difference =
+            finite_difference_gradient(&cheated, &backend, &parameters, ANGLE_STEP).unwrap();
+        for index in 0..2 {
+            assert!((shift["energy"][index] - exact[index]).abs() < 1e-10);
+            assert!((difference["energy"][index] - exact[index]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn sampled_gradient() {
+        let parameters: [f64; 2] = [0.4, -0.3];
+        let exact = [-parameters[0].sin(), -(2.0 * parameters[1]).sin()];
+        let backend = StateVectorBackend::new(2).set_seed(11);
+        let shift = parameter_shift_gradient(&program(10000), &backend, &parameters).unwrap();
+        // Both shifted energies lie between -1.5 and 1.5 in every shot, which bounds the statistical error
+        let statistical_error = 2.0f64.sqrt() * 1.5 / 10000f64.sqrt();
+        for index in 0..2 {
+            assert!((shift["energy"][index] - exact[index]).abs() < 5.0 * statistical_error);
+        }
+    }
+
+    #[test]
+    fn programs_without_expectation_values() {
+        let program = QuantumProgram::ClassicalRegister {
+            measurement: ClassicalRegister {
+                constant_circuit: None,
+                circuits: vec![Circuit::new()],
+            },
+            input_parameter_names: vec![],
+        };
+        let backend = StateVectorBackend::new(1);
+        assert!(parameter_shift_gradient(&program, &backend, &[]).is_err());
+        assert!(cheated_program(&program).is_err());
+    }
+}
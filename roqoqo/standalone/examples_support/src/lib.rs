@@ -0,0 +1,40 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tools shared by the roqoqo standalone examples.
+//!
+//! The examples themselves only construct and run circuits. Functionality that is used by several
+//! examples, like exporting circuits to other formats or checking circuits for equivalence, lives here.
+
+pub mod backend;
+pub mod batch;
+pub mod cache;
+pub mod decomposition;
+pub mod density_matrix;
+pub mod diagram;
+pub mod feed_forward;
+pub mod figure;
+pub mod gradient;
+pub mod mps;
+pub mod noise;
+pub mod optimizer;
+pub mod qasm;
+pub mod readout;
+pub mod remote;
+pub mod routing;
+pub mod runner;
+pub mod schedule;
+pub mod seed;
+pub mod stabilizer;
+pub mod state_vector;
+pub mod unitary;
+pub mod zero_noise;
@@ -0,0 +1,855 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A matrix-product-state simulator for wide and shallow circuits, implementing the roqoqo `EvaluatingBackend`.
+//!
+//! [MpsBackend] stores the state of a chain of qubits as a matrix product state. Qubit `k` is site `k` of the chain,
+//! every site holds one matrix per basis state of its qubit, and the bond dimension between neighbouring sites grows
+//! with the entanglement across the bond. Single-qubit gates act on one site. A two-qubit gate on neighbouring sites is
+//! applied to the contracted pair of sites, which is split again with a singular value decomposition. Only the
+//! largest `max_bond_dimension` singular values are kept, the weight of the discarded singular values is the
+//! truncation error. Gates on qubits that are not neighbours are applied by moving one qubit next to the other with
+//! SWAP gates and moving it back afterwards.
+//!
+//! The backend supports the measurements, the classical control flow and the readouts `PragmaGetPauliProduct` and
+//! `PragmaGetStateVector` (for up to 20 qubits) of the [StateVectorBackend](crate::state_vector::StateVectorBackend).
+//! Repeated measurements sample every shot site by site from the matrix product state. Gates acting on more than two
+//! qubits are not supported. [MpsBackend::run_circuit_with_report] additionally returns a [TruncationReport].
+
+use crate::seed;
+use crate::state_vector::{plan_measurements, FinalSampling};
+use crate::unitary;
+use nalgebra::{DMatrix, DVector};
+use num_complex::Complex64;
+use rand::prelude::*;
+use roqoqo::backends::EvaluatingBackend;
+use roqoqo::backends::RegisterResult;
+use roqoqo::operations::*;
+use roqoqo::registers::{
+    BitOutputRegister, BitRegister, ComplexOutputRegister, ComplexRegister, FloatOutputRegister,
+    FloatRegister,
+};
+use roqoqo::{Circuit, RoqoqoBackendError};
+use std::collections::{HashMap, HashSet};
+
+/// The name of the backend in `OperationNotInBackend` errors.
+const BACKEND_NAME: &str = "MpsBackend";
+
+/// Singular values below this value are always discarded.
+const SINGULAR_VALUE_CUTOFF: f64 = 1e-12;
+
+/// The largest number of qubits for which the state vector can be read out.
+const MAX_STATE_VECTOR_QUBITS: usize = 20;
+
+/// Simulates circuits on a chain of `number_qubits` qubits with a matrix product state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MpsBackend {
+    /// Number of qubits supported by the backend.
+    pub number_qubits: usize,
+    /// The largest bond dimension kept after a two-qubit gate.
+    pub max_bond_dimension: usize,
+    /// The seed of the random number generator of every run, `None` uses [crate::seed::rng].
+    pub seed: Option<u64>,
+}
+
+/// The truncation of the matrix product state during a simulation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TruncationReport {
+    /// The summed weight of all discarded singular values, which bounds the loss of fidelity.
+    pub discarded_weight: f64,
+    /// The largest bond dimension reached.
+    pub largest_bond_dimension: usize,
+    /// The number of two-qubit gates that discarded singular values.
+    pub truncated_gates: usize,
+}
+
+impl std::fmt::Display for TruncationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "discarded weight {:.3e} in {} gates, largest bond dimension {}",
+            self.discarded_weight, self.truncated_gates, self.largest_bond_dimension
+        )
+    }
+}
+
+impl MpsBackend {
+    /// Creates a new matrix-product-state backend.
+    ///
+    /// # Arguments
+    ///
+    /// `number_qubits` - The number of qubits supported by the backend.
+    /// `max_bond_dimension` - The largest bond dimension kept after a two-qubit gate.
+    pub fn new(number_qubits: usize, max_bond_dimension: usize) -> Self {
+        Self {
+            number_qubits,
+            max_bond_dimension,
+            seed: None,
+        }
+    }
+
+    /// Sets the seed of the random number generator.
+    ///
+    /// Every run of the backend with a seed draws the same random numbers, so running a circuit twice gives the same
+    /// measurement results. Without a seed the runs draw from [crate::seed::rng], which follows the global seed.
+    ///
+    /// # Arguments
+    ///
+    /// `seed` - The seed of the random number generator.
+    pub fn set_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Runs a circuit and reports the truncation of the matrix product state.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok((bit_registers, float_registers, complex_registers, report))` - The output registers like
+    ///   `run_circuit` and the truncation of the run with the largest discarded weight.
+    /// * `Err(RoqoqoBackendError)` - The circuit contains an operation the backend does not support.
+    ///
+    #[allow(clippy::type_complexity)]
+    pub fn run_circuit_with_report(
+        &self,
+        circuit: &Circuit,
+    ) -> Result<
+        (
+            HashMap<String, BitOutputRegister>,
+            HashMap<String, FloatOutputRegister>,
+            HashMap<String, ComplexOutputRegister>,
+            TruncationReport,
+        ),
+        RoqoqoBackendError,
+    > {
+        self.run(circuit.iter())
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn run<'a>(
+        &self,
+        circuit: impl Iterator<Item = &'a Operation>,
+    ) -> Result<
+        (
+            HashMap<String, BitOutputRegister>,
+            HashMap<String, FloatOutputRegister>,
+            HashMap<String, ComplexOutputRegister>,
+            TruncationReport,
+        ),
+        RoqoqoBackendError,
+    > {
+        if self.max_bond_dimension == 0 {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: "The maximal bond dimension of the MpsBackend has to be at least 1"
+                    .to_string(),
+            });
+        }
+        let (operations, final_sampling, repetitions) =
+            plan_measurements(circuit.collect::<Vec<&Operation>>())?;
+        let mut rng = seed::LazyRng::new(self.seed);
+
+        let mut bit_outputs: HashMap<String, BitOutputRegister> = HashMap::new();
+        let mut float_outputs: HashMap<String, FloatOutputRegister> = HashMap::new();
+        let mut complex_outputs: HashMap<String, ComplexOutputRegister> = HashMap::new();
+        for operation in operations.iter() {
+            match operation {
+                Operation::DefinitionBit(def) if *def.is_output() => {
+                    bit_outputs.insert(def.name().clone(), Vec::new());
+                }
+                Operation::DefinitionFloat(def) if *def.is_output() => {
+                    float_outputs.insert(def.name().clone(), Vec::new());
+                }
+                Operation::DefinitionComplex(def) if *def.is_output() => {
+                    complex_outputs.insert(def.name().clone(), Vec::new());
+                }
+                _ => (),
+            }
+        }
+
+        let mut report = TruncationReport {
+            discarded_weight: 0.0,
+            largest_bond_dimension: 1,
+            truncated_gates: 0,
+        };
+        for _ in 0..repetitions {
+            let mut simulation = Simulation::new(self, &mut rng);
+            for operation in operations.iter() {
+                simulation.apply(operation, &mut bit_outputs)?;
+            }
+            if let Some(sampling) = &final_sampling {
+                simulation.sample_final_measurements(sampling, &mut bit_outputs)?;
+            }
+            for (name, register) in bit_outputs.iter_mut() {
+                if !simulation.sampled_readouts.contains(name) {
+                    if let Some(bits) = simulation.bit_registers.remove(name) {
+                        register.push(bits);
+                    }
+                }
+            }
+            for (name, register) in float_outputs.iter_mut() {
+                if let Some(values) = simulation.float_registers.remove(name) {
+                    register.push(values);
+                }
+            }
+            for (name, register) in complex_outputs.iter_mut() {
+                if let Some(values) = simulation.complex_registers.remove(name) {
+                    register.push(values);
+                }
+            }
+            if simulation.state.report.discarded_weight >= report.discarded_weight {
+                report = simulation.state.report;
+            }
+        }
+        Ok((bit_outputs, float_outputs, complex_outputs, report))
+    }
+}
+
+impl EvaluatingBackend for MpsBackend {
+    fn run_circuit_iterator<'a>(
+        &self,
+        circuit: impl Iterator<Item = &'a Operation>,
+    ) -> RegisterResult {
+        let (bit_outputs, float_outputs, complex_outputs, _) = self.run(circuit)?;
+        Ok((bit_outputs, float_outputs, complex_outputs))
+    }
+}
+
+/// A matrix product state in mixed canonical form.
+///
+/// `sites[k][s]` is the matrix of qubit `k` in the basis state `s`. All sites left of `center` are left-canonical and
+/// all sites right of it are right-canonical, so the norm of the state is the norm of the center site.
+#[derive(Debug, Clone)]
+struct MatrixProductState {
+    sites: Vec<[DMatrix<Complex64>; 2]>,
+    center: usize,
+    max_bond_dimension: usize,
+    report: TruncationReport,
+}
+
+impl MatrixProductState {
+    /// Creates the product state |0...0> with bond dimension 1.
+    fn new(number_qubits: usize, max_bond_dimension: usize) -> Self {
+        let zero = DMatrix::from_element(1, 1, Complex64::new(1.0, 0.0));
+        let one = DMatrix::zeros(1, 1);
+        MatrixProductState {
+            sites: vec![[zero, one]; number_qubits],
+            center: 0,
+            max_bond_dimension,
+            report: TruncationReport {
+                discarded_weight: 0.0,
+                largest_bond_dimension: 1,
+                truncated_gates: 0,
+            },
+        }
+    }
+
+    fn number_qubits(&self) -> usize {
+        self.sites.len()
+    }
+
+    fn check_qubit(&self, operation: &Operation, qubit: usize) -> Result<(), RoqoqoBackendError> {
+        if qubit >= self.number_qubits() {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "{} acts on qubit {} but the backend only has {} qubits",
+                    operation.hqslang(),
+                    qubit,
+                    self.number_qubits()
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Moves the orthogonality center to `site`, discarding only singular values below [SINGULAR_VALUE_CUTOFF].
+    fn move_center(&mut self, site: usize) {
+        while self.center < site {
+            let center = self.center;
+            let (left, right) = (self.sites[center][0].nrows(), self.sites[center][0].ncols());
+            // Rows are (basis state, left bond), columns the right bond
+            let matrix = DMatrix::from_fn(2 * left, right, |row, column| {
+                self.sites[center][row / left][(row % left, column)]
+            });
+            let (u, singular_values, v_t) = singular_value_decomposition(&matrix);
+            let rank = rank(&singular_values);
+            let singular_values = DMatrix::from_diagonal(&DVector::from_iterator(
+                rank,
+                singular_values.into_iter().take(rank).map(Complex64::from),
+            ));
+            for state in 0..2 {
+                self.sites[center][state] =
+                    u.rows(state * left, left).columns(0, rank).into_owned();
+                self.sites[center + 1][state] =
+                    &singular_values * v_t.rows(0, rank) * &self.sites[center + 1][state];
+            }
+            self.center += 1;
+        }
+        while self.center > site {
+            let center = self.center;
+            let (left, right) = (self.sites[center][0].nrows(), self.sites[center][0].ncols());
+            // Rows are the left bond, columns (basis state, right bond)
+            let matrix = DMatrix::from_fn(left, 2 * right, |row, column| {
+                self.sites[center][column / right][(row, column % right)]
+            });
+            let (u, singular_values, v_t) = singular_value_decomposition(&matrix);
+            let rank = rank(&singular_values);
+            let singular_values = DMatrix::from_diagonal(&DVector::from_iterator(
+                rank,
+                singular_values.into_iter().take(rank).map(Complex64::from),
+            ));
+            for state in 0..2 {
+                self.sites[center][state] =
+                    v_t.columns(state * right, right).rows(0, rank).into_owned();
+                self.sites[center - 1][state] =
+                    &self.sites[center - 1][state] * u.columns(0, rank) * &singular_values;
+            }
+            self.center -= 1;
+        }
+    }
+
+    /// Applies the 2x2 matrix of a single-qubit gate to a site.
+    fn apply_single_qubit(&mut self, qubit: usize, matrix: &ndarray::Array2<Complex64>) {
+        let [zero, one] = &self.sites[qubit];
+        let new_zero = zero * matrix[[0, 0]] + one * matrix[[0, 1]];
+        let new_one = zero * matrix[[1, 0]] + one * matrix[[1, 1]];
+        self.sites[qubit] = [new_zero, new_one];
+    }
+
+    /// Applies the 4x4 matrix of a two-qubit gate to the neighbouring sites `site` and `site + 1`.
+    ///
+    /// `site_is_first` tells if `site` is the most significant qubit of the gate matrix.
+    fn apply_neighbouring(
+        &mut self,
+        site: usize,
+        matrix: &ndarray::Array2<Complex64>,
+        site_is_first: bool,
+    ) {
+        self.move_center(site);
+        let left = self.sites[site][0].nrows();
+        let right = self.sites[site + 1][0].ncols();
+        let pair: Vec<Vec<DMatrix<Complex64>>> = (0..2)
+            .map(|first| {
+                (0..2)
+                    .map(|second| &self.sites[site][first] * &self.sites[site + 1][second])
+                    .collect()
+            })
+            .collect();
+        let gate_index = |first: usize, second: usize| {
+            if site_is_first {
+                2 * first + second
+            } else {
+                2 * second + first
+            }
+        };
+        // Rows are (basis state of site, left bond), columns (basis state of site + 1, right bond)
+        let mut theta = DMatrix::zeros(2 * left, 2 * right);
+        for first in 0..2 {
+            for second in 0..2 {
+                let mut block = DMatrix::zeros(left, right);
+                for old_first in 0..2 {
+                    for old_second in 0..2 {
+                        let element =
+                            matrix[[gate_index(first, second), gate_index(old_first, old_second)]];
+                        if element != Complex64::new(0.0, 0.0) {
+                            block += &pair[old_first][old_second] * element;
+                        }
+                    }
+                }
+                theta
+                    .view_mut((first * left, second * right), (left, right))
+                    .copy_from(&block);
+            }
+        }
+        let (u, singular_values, v_t) = singular_value_decomposition(&theta);
+        let total_weight: f64 = singular_values.iter().map(|value| value * value).sum();
+        let kept = rank(&singular_values).min(self.max_bond_dimension);
+        let kept_weight: f64 = singular_values
+            .iter()
+            .take(kept)
+            .map(|value| value * value)
+            .sum();
+        let discarded_weight = (total_weight - kept_weight) / total_weight;
+        if singular_values.len() > kept && singular_values[kept] > SINGULAR_VALUE_CUTOFF {
+            self.report.discarded_weight += discarded_weight;
+            self.report.truncated_gates += 1;
+        }
+        self.report.largest_bond_dimension = self.report.largest_bond_dimension.max(kept);
+        // The kept singular values are normalised to keep the norm of the state
+        let normalisation = (total_weight / kept_weight).sqrt();
+        for state in 0..2 {
+            self.sites[site][state] = u.view((state * left, 0), (left, kept)).into_owned();
+            let mut next = v_t.view((0, state * right), (kept, right)).into_owned();
+            for (row, value) in singular_values.iter().take(kept).enumerate() {
+                next.row_mut(row).scale_mut(*value * normalisation);
+            }
+            self.sites[site + 1][state] = next;
+        }
+        self.center = site + 1;
+    }
+
+    /// Applies a gate with one or two qubits.
+    fn apply_gate(&mut self, operation: &Operation) -> Result<(), RoqoqoBackendError> {
+        let gate = GateOperation::try_from(operation).map_err(|_| {
+            RoqoqoBackendError::OperationNotInBackend {
+                backend: BACKEND_NAME,
+                hqslang: operation.hqslang(),
+            }
+        })?;
+        let matrix = gate.unitary_matrix()?;
+        if let Ok(op) = SingleQubitGateOperation::try_from(operation) {
+            self.check_qubit(operation, *op.qubit())?;
+            self.apply_single_qubit(*op.qubit(), &matrix);
+            return Ok(());
+        }
+        let Ok(op) = TwoQubitGateOperation::try_from(operation) else {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "{} acts on more than two qubits, the MpsBackend only supports one- and two-qubit gates",
+                    operation.hqslang()
+                ),
+            });
+        };
+        let (control, target) = (*op.control(), *op.target());
+        self.check_qubit(operation, control)?;
+        self.check_qubit(operation, target)?;
+        let (low, high) = (control.min(target), control.max(target));
+        let swap: ndarray::Array2<Complex64> = SWAP::new(0, 1).unitary_matrix()?;
+        // The qubit `high` travels to the site `low + 1`, the qubits in between move up by one site
+        for site in (low + 1..high).rev() {
+            self.apply_neighbouring(site, &swap, true);
+        }
+        self.apply_neighbouring(low, &matrix, control < target);
+        for site in low + 1..high {
+            self.apply_neighbouring(site, &swap, true);
+        }
+        Ok(())
+    }
+
+    /// Returns the probability to measure `qubit` in the state `|1>`.
+    fn probability_of_one(&mut self, qubit: usize) -> f64 {
+        self.move_center(qubit);
+        let [zero, one] = &self.sites[qubit];
+        let (zero, one) = (zero.norm_squared(), one.norm_squared());
+        one / (zero + one)
+    }
+
+    /// Projects a qubit onto an outcome and normalises the state.
+    fn project(&mut self, qubit: usize, outcome: bool) {
+        self.move_center(qubit);
+        let kept = usize::from(outcome);
+        self.sites[qubit][1 - kept].fill(Complex64::new(0.0, 0.0));
+        let norm = self.sites[qubit][kept].norm();
+        self.sites[qubit][kept].unscale_mut(norm);
+    }
+
+    /// Samples the basis states of all qubits without changing the state.
+    ///
+    /// The qubits are sampled site by site from the left, which needs the center at site 0 so that all other sites are
+    /// right-canonical.
+    fn sample<R: Rng>(&self, rng: &mut R) -> Vec<bool> {
+        debug_assert_eq!(self.center, 0);
+        let mut left = DMatrix::from_element(1, 1, Complex64::new(1.0, 0.0));
+        let mut outcomes = Vec::with_capacity(self.number_qubits());
+        for site in self.sites.iter() {
+            let zero = &left * &site[0];
+            let one = &left * &site[1];
+            let (zero_weight, one_weight) = (zero.norm_squared(), one.norm_squared());
+            let outcome = rng.gen::<f64>() * (zero_weight + one_weight) < one_weight;
+            left = if outcome { one } else { zero };
+            let norm = left.norm();
+            left.unscale_mut(norm);
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+
+    /// Returns the overlap `<self|other>` of two matrix product states.
+    fn overlap(&self, other: &MatrixProductState) -> Complex64 {
+        let mut environment = DMatrix::from_element(1, 1, Complex64::new(1.0, 0.0));
+        for (site, other_site) in self.sites.iter().zip(other.sites.iter()) {
+            environment = (0..2)
+                .map(|state| site[state].adjoint() * &environment * &other_site[state])
+                .fold(
+                    DMatrix::zeros(site[0].ncols(), other_site[0].ncols()),
+                    |sum, term| sum + term,
+                );
+        }
+        environment[(0, 0)]
+    }
+
+    /// Contracts the matrix product state into a state vector.
+    fn state_vector(&self) -> DVector<Complex64> {
+        DVector::from_fn(1 << self.number_qubits(), |index, _| {
+            self.sites.iter().enumerate().fold(
+                DMatrix::from_element(1, 1, Complex64::new(1.0, 0.0)),
+                |product, (qubit, site)| product * &site[index >> qubit & 1],
+            )[(0, 0)]
+        })
+    }
+}
+
+/// The largest number of sweeps of the Jacobi singular value decomposition.
+const MAX_JACOBI_SWEEPS: usize = 100;
+
+/// Returns the singular value decomposition `(u, singular_values, v_t)` of a matrix with the singular values in
+/// descending order.
+///
+/// The decomposition uses one-sided Jacobi rotations, which orthogonalise the columns of the matrix until every pair of
+/// columns is orthogonal to machine precision. The singular value decomposition of nalgebra loses up to seven digits for
+/// some of the complex matrices appearing in the simulation of a circuit, which breaks the canonical form of the matrix
+/// product state. The columns of `u` belonging to vanishing singular values are zero.
+fn singular_value_decomposition(
+    matrix: &DMatrix<Complex64>,
+) -> (DMatrix<Complex64>, Vec<f64>, DMatrix<Complex64>) {
+    if matrix.nrows() < matrix.ncols() {
+        let (u, singular_values, v_t) = singular_value_decomposition(&matrix.adjoint());
+        return (v_t.adjoint(), singular_values, u.adjoint());
+    }
+    let columns = matrix.ncols();
+    let mut a = matrix.clone();
+    let mut v = DMatrix::<Complex64>::identity(columns, columns);
+    for _ in 0..MAX_JACOBI_SWEEPS {
+        let mut rotated = false;
+        for p in 0..columns {
+            for q in p + 1..columns {
+                let alpha = a.column(p).norm_squared();
+                let beta = a.column(q).norm_squared();
+                let gamma = a.column(p).dotc(&a.column(q));
+                if gamma.norm() <= f64::EPSILON * (alpha * beta).sqrt() || gamma.norm() == 0.0 {
+                    continue;
+                }
+                rotated = true;
+                // After multiplying column q with the conjugate phase of gamma the rotation is real
+                let phase = (gamma / gamma.norm()).conj();
+                let zeta = (beta - alpha) / (2.0 * gamma.norm());
+                let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+                let c = Complex64::from(1.0 / (1.0 + t * t).sqrt());
+                let s = c * t;
+                for matrix in [&mut a, &mut v] {
+                    let column_p = matrix.column(p).into_owned();
+                    let column_q = matrix.column(q) * phase;
+                    matrix.set_column(p, &(&column_p * c - &column_q * s));
+                    matrix.set_column(q, &(column_p * s + column_q * c));
+                }
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+    let mut order: Vec<(f64, usize)> = (0..columns).map(|j| (a.column(j).norm(), j)).collect();
+    order.sort_by(|first, second| second.0.total_cmp(&first.0));
+    let mut u = DMatrix::zeros(a.nrows(), columns);
+    let mut v_t = DMatrix::zeros(columns, columns);
+    for (index, (singular_value, column)) in order.iter().enumerate() {
+        if *singular_value > 0.0 {
+            u.set_column(index, &a.column(*column).map(|x| x / *singular_value));
+        }
+        v_t.set_row(index, &v.column(*column).adjoint());
+    }
+    (
+        u,
+        order
+            .into_iter()
+            .map(|(singular_value, _)| singular_value)
+            .collect(),
+        v_t,
+    )
+}
+
+/// Returns the number of singular values above [SINGULAR_VALUE_CUTOFF], at least one.
+fn rank(singular_values: &[f64]) -> usize {
+    singular_values
+        .iter()
+        .filter(|value| **value > SINGULAR_VALUE_CUTOFF)
+        .count()
+        .max(1)
+}
+
+/// The matrix product state and the classical registers of a single run of a circuit.
+struct Simulation<'a, R: Rng> {
+    state: MatrixProductState,
+    bit_registers: HashMap<String, BitRegister>,
+    float_registers: HashMap<String, FloatRegister>,
+    complex_registers: HashMap<String, ComplexRegister>,
+    /// Readouts whose shots have been written to the output registers directly.
+    sampled_readouts: HashSet<String>,
+    rng: &'a mut R,
+}
+
+impl<'a, R: Rng> Simulation<'a, R> {
+    fn new(backend: &MpsBackend, rng: &'a mut R) -> Self {
+        Simulation {
+            state: MatrixProductState::new(backend.number_qubits, backend.max_bond_dimension),
+            bit_registers: HashMap::new(),
+            float_registers: HashMap::new(),
+            complex_registers: HashMap::new(),
+            sampled_readouts: HashSet::new(),
+            rng,
+        }
+    }
+
+    fn apply(
+        &mut self,
+        operation: &Operation,
+        bit_outputs: &mut HashMap<String, BitOutputRegister>,
+    ) -> Result<(), RoqoqoBackendError> {
+        match operation {
+            Operation::DefinitionBit(def) => {
+                self.bit_registers
+                    .insert(def.name().clone(), vec![false; *def.length()]);
+            }
+            Operation::DefinitionFloat(def) => {
+                self.float_registers
+                    .insert(def.name().clone(), vec![0.0; *def.length()]);
+            }
+            Operation::DefinitionComplex(def) => {
+                self.complex_registers.insert(
+                    def.name().clone(),
+                    vec![Complex64::new(0.0, 0.0); *def.length()],
+                );
+            }
+            Operation::InputBit(op) => *self.bit(op.name(), *op.index())? = *op.value(),
+            Operation::MeasureQubit(op) => {
+                self.state.check_qubit(operation, *op.qubit())?;
+                let outcome = self.rng.gen::<f64>() < self.state.probability_of_one(*op.qubit());
+                self.state.project(*op.qubit(), outcome);
+                *self.bit(op.readout(), *op.readout_index())? = outcome;
+            }
+            Operation::PragmaActiveReset(op) => {
+                self.state.check_qubit(operation, *op.qubit())?;
+                let outcome = self.rng.gen::<f64>() < self.state.probability_of_one(*op.qubit());
+                self.state.project(*op.qubit(), outcome);
+                if outcome {
+                    self.state.apply_gate(&PauliX::new(*op.qubit()).into())?;
+                }
+            }
+            Operation::PragmaConditional(op) => {
+                if *self.bit(op.condition_register(), *op.condition_index())? {
+                    for operation in op.circuit().iter() {
+                        self.apply(operation, bit_outputs)?;
+                    }
+                }
+            }
+            Operation::PragmaLoop(op) => {
+                for _ in 0..*op.repetitions().float()? as usize {
+                    for operation in op.circuit().iter() {
+                        self.apply(operation, bit_outputs)?;
+                    }
+                }
+            }
+            Operation::PragmaRepeatedMeasurement(op) => {
+                let length = self.bit_registers.get(op.readout()).map_or(0, Vec::len);
+                let measured_qubits: Vec<(usize, usize)> = (0..self.state.number_qubits())
+                    .filter_map(|qubit| match op.qubit_mapping() {
+                        Some(mapping) => mapping.get(&qubit).map(|index| (qubit, *index)),
+                        None => Some((qubit, qubit)),
+                    })
+                    .filter(|(_, index)| *index < length)
+                    .collect();
+                let sampling = FinalSampling {
+                    readout: op.readout().clone(),
+                    number_measurements: *op.number_measurements(),
+                    measured_qubits,
+                };
+                self.sample_final_measurements(&sampling, bit_outputs)?;
+            }
+            Operation::PragmaGetStateVector(op) => {
+                if self.state.number_qubits() > MAX_STATE_VECTOR_QUBITS {
+                    return Err(RoqoqoBackendError::GenericError {
+                        msg: format!(
+                            "The state vector of {} qubits is too large to be read out, the MpsBackend reads out at most {} qubits",
+                            self.state.number_qubits(),
+                            MAX_STATE_VECTOR_QUBITS
+                        ),
+                    });
+                }
+                let state = self.rotated_state(op.circuit().as_ref())?;
+                self.complex_registers.insert(
+                    op.readout().clone(),
+                    state.state_vector().iter().copied().collect(),
+                );
+            }
+            Operation::PragmaGetPauliProduct(op) => {
+                let state = self.rotated_state(Some(op.circuit()))?;
+                let mut product = state.clone();
+                for (qubit, pauli) in op.qubit_paulis() {
+                    let pauli: Operation = match pauli {
+                        0 => continue,
+                        1 => PauliX::new(*qubit).into(),
+                        2 => PauliY::new(*qubit).into(),
+                        3 => PauliZ::new(*qubit).into(),
+                        _ => {
+                            return Err(RoqoqoBackendError::GenericError {
+                                msg: format!("Unknown Pauli operator {} in PauliProduct", pauli),
+                            })
+                        }
+                    };
+                    product.apply_gate(&pauli)?;
+                }
+                self.float_registers
+                    .insert(op.readout().clone(), vec![state.overlap(&product).re]);
+            }
+            // Operations without an effect on the state, the global phase is dropped like in QuEST
+            Operation::DefinitionUsize(_)
+            | Operation::InputSymbolic(_)
+            | Operation::PragmaSetNumberOfMeasurements(_)
+            | Operation::PragmaGlobalPhase(_)
+            | Operation::PragmaStopParallelBlock(_)
+            | Operation::PragmaStartDecompositionBlock(_)
+            | Operation::PragmaStopDecompositionBlock(_)
+            | Operation::PragmaSleep(_) => (),
+            _ if unitary::is_gate(operation) => self.state.apply_gate(operation)?,
+            _ => {
+                return Err(RoqoqoBackendError::OperationNotInBackend {
+                    backend: BACKEND_NAME,
+                    hqslang: operation.hqslang(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn bit(&mut self, name: &str, index: usize) -> Result<&mut bool, RoqoqoBackendError> {
+        self.bit_registers
+            .get_mut(name)
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Bit register {} is not defined", name),
+            })?
+            .get_mut(index)
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Index {} is outside of bit register {}", index, name),
+            })
+    }
+
+    /// Samples shots of the measured qubits from the state without changing it.
+    ///
+    /// Every shot starts from the current content of the readout register and is appended to its output register.
+    fn sample_final_measurements(
+        &mut self,
+        sampling: &FinalSampling,
+        bit_outputs: &mut HashMap<String, BitOutputRegister>,
+    ) -> Result<(), RoqoqoBackendError> {
+        let register = self
+            .bit_registers
+            .get(&sampling.readout)
+            .cloned()
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Bit register {} is not defined", sampling.readout),
+            })?;
+        if let Some((qubit, index)) = sampling
+            .measured_qubits
+            .iter()
+            .find(|(qubit, index)| *qubit >= self.state.number_qubits() || *index >= register.len())
+        {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "Qubit {} can not be measured into bit {} of register {}",
+                    qubit, index, sampling.readout
+                ),
+            });
+        }
+        self.state.move_center(0);
+        let shots: BitOutputRegister = (0..sampling.number_measurements)
+            .map(|_| {
+                let outcomes = self.state.sample(self.rng);
+                let mut shot = register.clone();
+                for (qubit, index) in sampling.measured_qubits.iter() {
+                    shot[*index] = outcomes[*qubit];
+                }
+                shot
+            })
+            .collect();
+        if let Some(output) = bit_outputs.get_mut(&sampling.readout) {
+            output.extend(shots);
+            self.sampled_readouts.insert(sampling.readout.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns a copy of the state after applying the gates of an optional basis rotation circuit.
+    fn rotated_state(
+        &self,
+        circuit: Option<&Circuit>,
+    ) -> Result<MatrixProductState, RoqoqoBackendError> {
+        let mut state = self.state.clone();
+        for operation in circuit.into_iter().flat_map(|circuit| circuit.iter()) {
+            state.apply_gate(operation)?;
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_vector::StateVectorBackend;
+    use std::f64::consts::PI;
+
+    /// Returns a random circuit of rotations and two-qubit gates, also on qubits that are not neighbours.
+    fn random_circuit(rng: &mut StdRng, number_qubits: usize) -> Circuit {
+        let mut circuit = Circuit::new();
+        for _ in 0..40 {
+            let qubit = rng.gen_range(0..number_qubits);
+            let other = (qubit + rng.gen_range(1..number_qubits)) % number_qubits;
+            let angle = rng.gen_range(-PI..PI);
+            circuit += match rng.gen_range(0..6) {
+                0 => Operation::from(RotateX::new(qubit, angle.into())),
+                1 => RotateY::new(qubit, angle.into()).into(),
+                2 => Hadamard::new(qubit).into(),
+                3 => CNOT::new(qubit, other).into(),
+                4 => ControlledPhaseShift::new(qubit, other, angle.into()).into(),
+                _ => ISwap::new(qubit, other).into(),
+            };
+        }
+        circuit += DefinitionComplex::new("state".to_string(), 1 << number_qubits, true);
+        circuit += PragmaGetStateVector::new("state".to_string(), None);
+        circuit
+    }
+
+    #[test]
+    fn agrees_with_state_vector_without_truncation() {
+        let mut rng = StdRng::seed_from_u64(13);
+        for number_qubits in 6..=8 {
+            for _ in 0..3 {
+                let circuit = random_circuit(&mut rng, number_qubits);
+                let (_, _, mps_state, report) = MpsBackend::new(number_qubits, 64)
+                    .run_circuit_with_report(&circuit)
+                    .unwrap();
+                let (_, _, exact_state) = StateVectorBackend::new(number_qubits)
+                    .run_circuit(&circuit)
+                    .unwrap();
+                let (mps_state, exact_state) = (&mps_state["state"][0], &exact_state["state"][0]);
+                let overlap: Complex64 = mps_state
+                    .iter()
+                    .zip(exact_state.iter())
+                    .map(|(mps, exact)| mps.conj() * exact)
+                    .sum();
+                assert!((overlap.norm() - 1.0).abs() < 1e-10, "overlap {overlap}");
+                assert_eq!(report.truncated_gates, 0);
+                assert!(report.discarded_weight < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn capped_bond_dimension_is_reported() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let circuit = random_circuit(&mut rng, 8);
+        let (_, _, _, report) = MpsBackend::new(8, 2)
+            .run_circuit_with_report(&circuit)
+            .unwrap();
+        assert!(report.truncated_gates > 0);
+        assert!(report.discarded_weight > 1e-6);
+        assert!(report.largest_bond_dimension <= 2);
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Insertion of the noise described by roqoqo noise models into ideal circuits.
+//!
+//! [add_noise] adds noise pragmas after every gate of a circuit. The noise acts for the gate time the device returns
+//! for the gate:
+//!
+//! * `ContinuousDecoherenceModel` - The rates of the model on the qubits of the gate.
+//! * `DecoherenceOnIdleModel` - The rates of the model on all other qubits of the circuit, which wait for the gate.
+//! * `DecoherenceOnGateModel` - The extra noise the model defines for the gate on its qubits.
+//!
+//! The rates of all models for one qubit are added up. Pure damping becomes a `PragmaDamping`, every other
+//! combination a `PragmaGeneralNoise`. QuEST simulates circuits containing these pragmas with a density matrix.
+//! Measurements and other pragmas do not get noise, the device does not define how long they take.
+
+use crate::diagram::number_qubits;
+use crate::schedule::gate_time;
+use crate::unitary;
+use ndarray::Array2;
+use roqoqo::devices::Device;
+use roqoqo::noise_models::NoiseModel;
+use roqoqo::operations::*;
+use roqoqo::{Circuit, RoqoqoError};
+use std::collections::{BTreeMap, HashSet};
+use struqture::prelude::*;
+use struqture::spins::{PlusMinusLindbladNoiseOperator, SinglePlusMinusOperator};
+
+/// Rates below this value are treated as zero.
+const RATE_TOLERANCE: f64 = 1e-15;
+
+/// Adds the noise of the noise models after every gate of a circuit.
+///
+/// Circuits nested in `PragmaConditional` and `PragmaLoop` get noise as well.
+///
+/// # Arguments
+///
+/// * `circuit` - The ideal circuit. All gates need to be available on the device.
+/// * `device` - The device providing the gate times.
+/// * `noise_models` - The continuous, idle and gate noise models.
+///
+/// # Returns
+///
+/// * `Ok(Circuit)` - The circuit with noise pragmas after every gate.
+/// * `Err(RoqoqoError)` - A gate is not available on the device, a noise model is not supported or it correlates
+///   several qubits.
+///
+pub fn add_noise<T: Device>(
+    circuit: &Circuit,
+    device: &T,
+    noise_models: &[NoiseModel],
+) -> Result<Circuit, RoqoqoError> {
+    for noise_model in noise_models {
+        if !matches!(
+            noise_model,
+            NoiseModel::ContinuousDecoherenceModel(_)
+                | NoiseModel::DecoherenceOnIdleModel(_)
+                | NoiseModel::DecoherenceOnGateModel(_)
+        ) {
+            return Err(RoqoqoError::GenericError {
+                msg: format!(
+                    "Noise model {:?} does not describe decoherence during gates",
+                    noise_model
+                ),
+            });
+        }
+    }
+    insert_noise(circuit, device, noise_models, number_qubits(circuit))
+}
+
+fn insert_noise<T: Device>(
+    circuit: &Circuit,
+    device: &T,
+    noise_models: &[NoiseModel],
+    number_qubits: usize,
+) -> Result<Circuit, RoqoqoError> {
+    let mut noisy = Circuit::new();
+    for operation in circuit.iter() {
+        match operation {
+            Operation::PragmaConditional(op) => {
+                noisy += PragmaConditional::new(
+                    op.condition_register().clone(),
+                    *op.condition_index(),
+                    insert_noise(op.circuit(), device, noise_models, number_qubits)?,
+                );
+            }
+            Operation::PragmaLoop(op) => {
+                noisy += PragmaLoop::new(
+                    op.repetitions().clone(),
+                    insert_noise(op.circuit(), device, noise_models, number_qubits)?,
+                );
+            }
+            _ if unitary::is_gate(operation) => {
+                noisy += operation.clone();
+                let time = gate_time(device, operation)?;
+                for (qubit, rates) in gate_noise_rates(operation, noise_models, number_qubits)? {
+                    noisy += noise_pragma(qubit, time, rates);
+                }
+            }
+            _ => noisy += operation.clone(),
+        }
+    }
+    Ok(noisy)
+}
+
+/// Collects the rates of all noise models acting on each qubit while a gate is executed.
+fn gate_noise_rates(
+    operation: &Operation,
+    noise_models: &[NoiseModel],
+    number_qubits: usize,
+) -> Result<BTreeMap<usize, Array2<f64>>, RoqoqoError> {
+    let gate_qubits: HashSet<usize> = match operation.involved_qubits() {
+        InvolvedQubits::Set(qubits) => qubits,
+        _ => (0..number_qubits).collect(),
+    };
+    let mut rates: BTreeMap<usize, Array2<f64>> = BTreeMap::new();
+    let mut add = |noise: &PlusMinusLindbladNoiseOperator, qubits: &dyn Fn(usize) -> bool| {
+        for (qubit, qubit_rates) in single_qubit_rates(noise)? {
+            if qubits(qubit) {
+                *rates.entry(qubit).or_insert_with(|| Array2::zeros((3, 3))) += &qubit_rates;
+            }
+        }
+        Ok::<(), RoqoqoError>(())
+    };
+    for noise_model in noise_models {
+        match noise_model {
+            NoiseModel::ContinuousDecoherenceModel(model) => {
+                add(&model.lindblad_noise, &|qubit| gate_qubits.contains(&qubit))?;
+            }
+            NoiseModel::DecoherenceOnIdleModel(model) => {
+                add(&model.lindblad_noise, &|qubit| {
+                    qubit < number_qubits && !gate_qubits.contains(&qubit)
+                })?;
+            }
+            NoiseModel::DecoherenceOnGateModel(model) => {
+                let name = operation.hqslang();
+                let noise = if let Ok(gate) = SingleQubitGateOperation::try_from(operation) {
+                    model.get_single_qubit_gate_error(name, *gate.qubit())
+                } else if let Ok(gate) = TwoQubitGateOperation::try_from(operation) {
+                    model.get_two_qubit_gate_error(name, *gate.control(), *gate.target())
+                } else if let Ok(gate) = ThreeQubitGateOperation::try_from(operation) {
+                    model.get_three_qubit_gate_error(
+                        name,
+                        *gate.control_0(),
+                        *gate.control_1(),
+                        *gate.target(),
+                    )
+                } else if let Ok(gate) = MultiQubitGateOperation::try_from(operation) {
+                    model.get_multi_qubit_gate_error(name, gate.qubits().clone())
+                } else {
+                    None
+                };
+                if let Some(noise) = noise {
+                    add(noise, &|_| true)?;
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(rates)
+}
+
+/// Splits a Lindblad noise operator into the 3x3 rate matrices of the single qubits.
+///
+/// The rows and columns of the matrices belong to the operators `sigma^+`, `sigma^-` and `sigma^z`, as in
+/// `PragmaGeneralNoise`.
+fn single_qubit_rates(
+    noise: &PlusMinusLindbladNoiseOperator,
+) -> Result<BTreeMap<usize, Array2<f64>>, RoqoqoError> {
+    let index = |operator: &SinglePlusMinusOperator| match operator {
+        SinglePlusMinusOperator::Plus => Some(0),
+        SinglePlusMinusOperator::Minus => Some(1),
+        SinglePlusMinusOperator::Z => Some(2),
+        _ => None,
+    };
+    let mut rates: BTreeMap<usize, Array2<f64>> = BTreeMap::new();
+    for ((left, right), value) in noise.iter() {
+        let left: Vec<&(usize, SinglePlusMinusOperator)> = left.iter().collect();
+        let right: Vec<&(usize, SinglePlusMinusOperator)> = right.iter().collect();
+        let term = match (left.as_slice(), right.as_slice()) {
+            ([(qubit, left)], [(right_qubit, right)]) if qubit == right_qubit => index(left)
+                .zip(index(right))
+                .map(|indices| (*qubit, indices)),
+            _ => None,
+        };
+        let (qubit, (row, column)) = term.ok_or_else(|| RoqoqoError::GenericError {
+            msg: format!(
+                "Noise term {:?} {:?} does not act on a single qubit",
+                left, right
+            ),
+        })?;
+        if value.im.float()?.abs() > RATE_TOLERANCE {
+            return Err(RoqoqoError::GenericError {
+                msg: format!("Noise rate {} is not real", value),
+            });
+        }
+        rates.entry(qubit).or_insert_with(|| Array2::zeros((3, 3)))[[row, column]] +=
+            *value.re.float()?;
+    }
+    Ok(rates)
+}
+
+/// Returns the noise pragma for the rates of one qubit acting for `time`.
+fn noise_pragma(qubit: usize, time: f64, rates: Array2<f64>) -> Operation {
+    let is_damping = rates
+        .indexed_iter()
+        .all(|(indices, rate)| indices == (0, 0) || rate.abs() < RATE_TOLERANCE);
+    if is_damping {
+        PragmaDamping::new(qubit, time.into(), rates[[0, 0]].into()).into()
+    } else {
+        PragmaGeneralNoise::new(qubit, time.into(), rates).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qoqo_calculator::CalculatorFloat;
+    use roqoqo::devices::AllToAllDevice;
+    use roqoqo::noise_models::{ContinuousDecoherenceModel, DecoherenceOnIdleModel};
+    use struqture::spins::PlusMinusProduct;
+
+    fn device() -> AllToAllDevice {
+        AllToAllDevice::new(3, &["RotateX".to_string()], &["CNOT".to_string()], 0.5)
+    }
+
+    #[test]
+    fn damping_after_the_gate() {
+        let circuit = Circuit::new() + RotateX::new(1, 0.3.into());
+        let model = ContinuousDecoherenceModel::new().add_damping_rate(&[0, 1, 2], 0.02);
+        let noisy = add_noise(&circuit, &device(), &[model.into()]).unwrap();
+        let mut expected = circuit.clone();
+        expected += PragmaDamping::new(1, 0.5.into(), 0.02.into());
+        assert_eq!(noisy, expected);
+    }
+
+    #[test]
+    fn dephasing_as_general_noise() {
+        let circuit = Circuit::new() + RotateX::new(0, 0.3.into());
+        let model = ContinuousDecoherenceModel::new().add_dephasing_rate(&[0], 0.05);
+        let noisy = add_noise(&circuit, &device(), &[model.into()]).unwrap();
+        assert_eq!(noisy.len(), 2);
+        match &noisy[1] {
+            Operation::PragmaGeneralNoise(op) => {
+                assert_eq!(op.qubit(), &0);
+                assert_eq!(op.gate_time(), &CalculatorFloat::from(0.5));
+                // roqoqo stores half the dephasing rate as the coefficient of the sigma^z term
+                let mut rates = Array2::zeros((3, 3));
+                rates[[2, 2]] = 0.025;
+                assert_eq!(op.rates(), &rates);
+            }
+            operation => panic!("Expected a PragmaGeneralNoise, found {operation:?}"),
+        }
+    }
+
+    #[test]
+    fn idle_noise_on_waiting_qubits() {
+        let mut circuit = Circuit::new();
+        circuit += CNOT::new(0, 2);
+        circuit += RotateX::new(1, 0.3.into());
+        let model = DecoherenceOnIdleModel::new().add_damping_rate(&[0, 1, 2], 0.01);
+        let noisy = add_noise(&circuit, &device(), &[model.into()]).unwrap();
+        let mut expected = Circuit::new();
+        expected += CNOT::new(0, 2);
+        expected += PragmaDamping::new(1, 0.5.into(), 0.01.into());
+        expected += RotateX::new(1, 0.3.into());
+        expected += PragmaDamping::new(0, 0.5.into(), 0.01.into());
+        expected += PragmaDamping::new(2, 0.5.into(), 0.01.into());
+        assert_eq!(noisy, expected);
+    }
+
+    #[test]
+    fn correlated_noise_is_rejected() {
+        let mut lindblad_noise = PlusMinusLindbladNoiseOperator::new();
+        let correlated = PlusMinusProduct::new().plus(0).plus(1);
+        lindblad_noise
+            .add_operator_product((correlated.clone(), correlated), 0.1.into())
+            .unwrap();
+        let model = ContinuousDecoherenceModel::from(lindblad_noise);
+        let circuit = Circuit::new() + CNOT::new(0, 1);
+        let error = add_noise(&circuit, &device(), &[model.into()]).unwrap_err();
+        assert!(error.to_string().contains("does not act on a single qubit"));
+    }
+}
@@ -0,0 +1,931 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Classical optimizers for variational algorithms.
+//!
+//! An [Objective] wraps a closure returning the value to minimize for a list of parameters, typically an expectation
+//! value returned by `QuantumProgram::run`, and counts how often it is evaluated. Every [Optimizer] minimizes an
+//! objective from a starting point and records the best energy, the parameters and the number of evaluations after
+//! every iteration in a [History], which can be written to json for plotting.
+//!
+//! * [Cobyla] and [NelderMead] only evaluate the objective. [Cobyla] follows linear models interpolating a simplex of
+//!   points within a trust region, like the COBYLA method of `scipy.optimize.minimize` used by the VHA notebook.
+//!   [NelderMead] moves a simplex by reflections, expansions and contractions.
+//! * [Spsa] estimates the gradient from two evaluations at randomly perturbed parameters and tolerates shot noise.
+//! * [Adam] and [Lbfgs] follow the gradient of the objective, set with [Objective::set_gradient], e.g. from the
+//!   parameter-shift rule of [crate::gradient], or from central finite differences otherwise.
+
+use nalgebra::{DMatrix, DVector};
+use rand::Rng;
+use roqoqo::RoqoqoBackendError;
+
+/// The step of the central finite differences of objectives without a gradient.
+const FINITE_DIFFERENCE_STEP: f64 = 1e-5;
+
+/// A closure returning the value of an objective or its gradient for a list of parameters.
+type Function<'a, T> = Box<dyn FnMut(&[f64]) -> Result<T, RoqoqoBackendError> + 'a>;
+
+/// The function minimized by an [Optimizer], with an optional gradient.
+pub struct Objective<'a> {
+    function: Function<'a, f64>,
+    gradient: Option<Function<'a, Vec<f64>>>,
+    number_evaluations: usize,
+    number_gradient_evaluations: usize,
+}
+
+impl<'a> Objective<'a> {
+    /// Creates a new objective.
+    ///
+    /// # Arguments
+    ///
+    /// * `function` - Returns the value to minimize, e.g. an expectation value of `QuantumProgram::run`.
+    pub fn new<F>(function: F) -> Self
+    where
+        F: FnMut(&[f64]) -> Result<f64, RoqoqoBackendError> + 'a,
+    {
+        Self {
+            function: Box::new(function),
+            gradient: None,
+            number_evaluations: 0,
+            number_gradient_evaluations: 0,
+        }
+    }
+
+    /// Sets the gradient of the objective, which is computed from central finite differences otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `gradient` - Returns the derivatives of the objective by the parameters.
+    pub fn set_gradient<G>(mut self, gradient: G) -> Self
+    where
+        G: FnMut(&[f64]) -> Result<Vec<f64>, RoqoqoBackendError> + 'a,
+    {
+        self.gradient = Some(Box::new(gradient));
+        self
+    }
+
+    /// Evaluates the objective.
+    pub fn value(&mut self, parameters: &[f64]) -> Result<f64, RoqoqoBackendError> {
+        self.number_evaluations += 1;
+        (self.function)(parameters)
+    }
+
+    /// Evaluates the gradient of the objective.
+    ///
+    /// Without a gradient set with [Objective::set_gradient] the central finite differences cost two evaluations of
+    /// the objective per parameter.
+    pub fn gradient(&mut self, parameters: &[f64]) -> Result<Vec<f64>, RoqoqoBackendError> {
+        if let Some(gradient) = self.gradient.as_mut() {
+            self.number_gradient_evaluations += 1;
+            return gradient(parameters);
+        }
+        let mut shifted = parameters.to_vec();
+        let mut derivatives = Vec::with_capacity(parameters.len());
+        for index in 0..parameters.len() {
+            shifted[index] = parameters[index] + FINITE_DIFFERENCE_STEP;
+            let plus = self.value(&shifted)?;
+            shifted[index] = parameters[index] - FINITE_DIFFERENCE_STEP;
+            let minus = self.value(&shifted)?;
+            shifted[index] = parameters[index];
+            derivatives.push((plus - minus) / (2.0 * FINITE_DIFFERENCE_STEP));
+        }
+        Ok(derivatives)
+    }
+
+    /// Returns the number of evaluations of the objective so far.
+    pub fn number_evaluations(&self) -> usize {
+        self.number_evaluations
+    }
+
+    /// Returns the number of evaluations of the gradient set with [Objective::set_gradient] so far.
+    pub fn number_gradient_evaluations(&self) -> usize {
+        self.number_gradient_evaluations
+    }
+}
+
+/// The state of an optimization after one iteration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Iteration {
+    /// The number of the iteration, zero for the starting point.
+    pub iteration: usize,
+    /// The value of the objective, for [Spsa] the mean of the two perturbed evaluations.
+    pub energy: f64,
+    /// The parameters after the iteration.
+    pub parameters: Vec<f64>,
+    /// The number of evaluations of the objective up to this iteration.
+    pub number_evaluations: usize,
+    /// The number of evaluations of the gradient up to this iteration.
+    pub number_gradient_evaluations: usize,
+}
+
+/// The iterations of an optimization.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    /// The name of the optimizer.
+    pub optimizer: String,
+    /// The state after every iteration.
+    pub iterations: Vec<Iteration>,
+}
+
+impl History {
+    /// Creates an empty history of an optimizer.
+    pub fn new(optimizer: &str) -> Self {
+        Self {
+            optimizer: optimizer.to_string(),
+            iterations: Vec::new(),
+        }
+    }
+
+    /// Appends the state after the next iteration.
+    pub fn record(&mut self, energy: f64, parameters: &[f64], objective: &Objective) {
+        self.iterations.push(Iteration {
+            iteration: self.iterations.len(),
+            energy,
+            parameters: parameters.to_vec(),
+            number_evaluations: objective.number_evaluations(),
+            number_gradient_evaluations: objective.number_gradient_evaluations(),
+        });
+    }
+
+    /// Formats the history as json, an object with the name of the optimizer and the list of iterations.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "optimizer": self.optimizer,
+            "iterations": self
+                .iterations
+                .iter()
+                .map(|iteration| {
+                    serde_json::json!({
+                        "iteration": iteration.iteration,
+                        "energy": iteration.energy,
+                        "parameters": iteration.parameters,
+                        "number_evaluations": iteration.number_evaluations,
+                        "number_gradient_evaluations": iteration.number_gradient_evaluations,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        })
+        .to_string()
+    }
+}
+
+/// The result of an optimization.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizationResult {
+    /// The parameters minimizing the objective.
+    pub parameters: Vec<f64>,
+    /// The value of the objective at the parameters.
+    pub energy: f64,
+    /// The number of evaluations of the objective.
+    pub number_evaluations: usize,
+    /// The state after every iteration.
+    pub history: History,
+}
+
+impl OptimizationResult {
+    /// Creates the result of an optimization from the final parameters and energy.
+    fn new(parameters: Vec<f64>, energy: f64, objective: &Objective, history: History) -> Self {
+        Self {
+            parameters,
+            energy,
+            number_evaluations: objective.number_evaluations(),
+            history,
+        }
+    }
+}
+
+/// Minimizes objectives.
+pub trait Optimizer {
+    /// Returns the name of the optimizer.
+    fn name(&self) -> &'static str;
+
+    /// Minimizes an objective.
+    ///
+    /// # Arguments
+    ///
+    /// * `objective` - The objective to minimize.
+    /// * `start` - The parameters the optimization starts from.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(OptimizationResult)` - The best parameters found and the history of the optimization.
+    /// * `Err(RoqoqoBackendError)` - Evaluating the objective or its gradient failed.
+    fn minimize(
+        &self,
+        objective: &mut Objective,
+        start: &[f64],
+    ) -> Result<OptimizationResult, RoqoqoBackendError>;
+}
+
+/// The COBYLA method of Powell for objectives without constraints, the method of `scipy.optimize.minimize` used by the
+/// VHA notebook.
+///
+/// The objective is evaluated on a simplex of `n + 1` points and approximated by the linear function interpolating
+/// them. Every iteration steps from the best point downhill to the edge of the trust region of radius `delta` and
+/// compares the actual decrease of the objective with the decrease predicted by the linear model. The ratio of the two
+/// grows the trust region after good predictions and shrinks it after bad ones, but never below the resolution `rho`.
+/// The new point replaces the point of the simplex farthest away from the best one. When a step of length `rho` fails,
+/// the simplex is rebuilt around the best point and `rho` is halved.
+///
+/// The optimization stops when `rho` would fall below `rho_end`, when the values on the simplex differ by less than
+/// `tolerance` before `rho` is halved, or after `max_iterations` iterations. The tolerance keeps the last steps from
+/// depending on rounding differences of the objective.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cobyla {
+    /// The initial radius of the trust region.
+    pub rho_begin: f64,
+    /// The final radius of the trust region.
+    pub rho_end: f64,
+    /// The optimization stops when the values on the simplex differ by less than the tolerance before `rho` is halved.
+    pub tolerance: f64,
+    /// The largest number of iterations.
+    pub max_iterations: usize,
+}
+
+impl Default for Cobyla {
+    fn default() -> Self {
+        Self {
+            rho_begin: 0.5,
+            rho_end: 1e-4,
+            tolerance: 1e-8,
+            max_iterations: 1000,
+        }
+    }
+}
+
+impl Optimizer for Cobyla {
+    fn name(&self) -> &'static str {
+        "COBYLA"
+    }
+
+    fn minimize(
+        &self,
+        objective: &mut Objective,
+        start: &[f64],
+    ) -> Result<OptimizationResult, RoqoqoBackendError> {
+        let mut history = History::new(self.name());
+        let mut rho = self.rho_begin;
+        let mut delta = rho;
+        let start_value = objective.value(start)?;
+        history.record(start_value, start, objective);
+        let mut simplex = coordinate_simplex(objective, &(start.to_vec(), start_value), rho)?;
+        for _ in 0..self.max_iterations {
+            sort_simplex(&mut simplex);
+            let (best, best_value) = simplex[0].clone();
+            let step = linear_model_gradient(&simplex).and_then(|gradient| {
+                let gradient_norm = norm(&gradient);
+                (gradient_norm > 0.0).then(|| {
+                    let trial: Vec<f64> = best
+                        .iter()
+                        .zip(gradient.iter())
+                        .map(|(x, g)| x - delta * g / gradient_norm)
+                        .collect();
+                    (trial, delta * gradient_norm)
+                })
+            });
+            let mut failed = true;
+            if let Some((trial, predicted)) = step {
+                let value = objective.value(&trial)?;
+                let ratio = (best_value - value) / predicted;
+                failed = ratio <= 0.1;
+                let step_length = delta;
+                delta = if ratio <= 0.1 {
+                    0.5 * delta
+                } else if ratio <= 0.7 {
+                    delta
+                } else {
+                    2.0 * delta
+                };
+                if delta <= 1.5 * rho {
+                    delta = rho;
+                }
+                // The new point replaces the point farthest away from the best point after the step, failed steps
+                // only replace points farther away than the step
+                let center = if value < best_value { &trial } else { &best };
+                let (farthest, farthest_distance) = simplex
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (point, _))| (index, distance(point, center)))
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .unwrap_or((0, 0.0));
+                if value < best_value || farthest_distance > step_length {
+                    simplex[farthest] = (trial, value);
+                }
+                failed = failed && step_length <= rho;
+            }
+            if failed {
+                sort_simplex(&mut simplex);
+                let far = simplex
+                    .iter()
+                    .any(|(point, _)| distance(point, &simplex[0].0) > 2.0 * rho);
+                if !far {
+                    let spread = simplex[simplex.len() - 1].1 - simplex[0].1;
+                    if spread < self.tolerance || rho / 2.0 < self.rho_end {
+                        history.record(simplex[0].1, &simplex[0].0, objective);
+                        break;
+                    }
+                    rho /= 2.0;
+                    delta = rho;
+                }
+                simplex = coordinate_simplex(objective, &simplex[0], rho)?;
+            }
+            sort_simplex(&mut simplex);
+            history.record(simplex[0].1, &simplex[0].0, objective);
+        }
+        sort_simplex(&mut simplex);
+        let (parameters, energy) = simplex.swap_remove(0);
+        Ok(OptimizationResult::new(
+            parameters, energy, objective, history,
+        ))
+    }
+}
+
+/// The downhill simplex method of Nelder and Mead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NelderMead {
+    /// The distance of the other points of the initial simplex from the starting point.
+    pub initial_step: f64,
+    /// The optimization stops when the values on the simplex differ by less than the tolerance.
+    pub tolerance: f64,
+    /// The largest number of iterations.
+    pub max_iterations: usize,
+}
+
+impl Default for NelderMead {
+    fn default() -> Self {
+        Self {
+            initial_step: 0.5,
+            tolerance: 1e-8,
+            max_iterations: 1000,
+        }
+    }
+}
+
+impl Optimizer for NelderMead {
+    fn name(&self) -> &'static str {
+        "Nelder-Mead"
+    }
+
+    fn minimize(
+        &self,
+        objective: &mut Objective,
+        start: &[f64],
+    ) -> Result<OptimizationResult, RoqoqoBackendError> {
+        let mut history = History::new(self.name());
+        let start_value = objective.value(start)?;
+        history.record(start_value, start, objective);
+        let mut simplex =
+            coordinate_simplex(objective, &(start.to_vec(), start_value), self.initial_step)?;
+        for _ in 0..self.max_iterations {
+            sort_simplex(&mut simplex);
+            let worst = simplex.len() - 1;
+            if simplex[worst].1 - simplex[0].1 < self.tolerance {
+                break;
+            }
+            let centroid: Vec<f64> = (0..start.len())
+                .map(|i| simplex[..worst].iter().map(|(x, _)| x[i]).sum::<f64>() / worst as f64)
+                .collect();
+            // Points on the line from the centroid through the worst point
+            let along = |factor: f64| -> Vec<f64> {
+                centroid
+                    .iter()
+                    .zip(simplex[worst].0.iter())
+                    .map(|(c, x)| c + factor * (c - x))
+                    .collect()
+            };
+            let reflected = along(1.0);
+            let reflected_value = objective.value(&reflected)?;
+            if reflected_value < simplex[0].1 {
+                let expanded = along(2.0);
+                let expanded_value = objective.value(&expanded)?;
+                simplex[worst] = if expanded_value < reflected_value {
+                    (expanded, expanded_value)
+                } else {
+                    (reflected, reflected_value)
+                };
+            } else if reflected_value < simplex[worst - 1].1 {
+                simplex[worst] = (reflected, reflected_value);
+            } else {
+                let contracted = if reflected_value < simplex[worst].1 {
+                    along(0.5)
+                } else {
+                    along(-0.5)
+                };
+                let contracted_value = objective.value(&contracted)?;
+                if contracted_value < reflected_value.min(simplex[worst].1) {
+                    simplex[worst] = (contracted, contracted_value);
+                } else {
+                    // Shrinking the simplex towards the best point
+                    let best = simplex[0].0.clone();
+                    for (point, value) in simplex.iter_mut().skip(1) {
+                        *point = best
+                            .iter()
+                            .zip(point.iter())
+                            .map(|(b, x)| b + 0.5 * (x - b))
+                            .collect();
+                        *value = objective.value(point)?;
+                    }
+                }
+            }
+            sort_simplex(&mut simplex);
+            history.record(simplex[0].1, &simplex[0].0, objective);
+        }
+        sort_simplex(&mut simplex);
+        let (parameters, energy) = simplex.swap_remove(0);
+        Ok(OptimizationResult::new(
+            parameters, energy, objective, history,
+        ))
+    }
+}
+
+/// Simultaneous perturbation stochastic approximation.
+///
+/// Every iteration evaluates the objective at the parameters perturbed by `±c_k` in a random direction of `±1`
+/// entries and steps by `a_k` times the resulting estimate of the gradient, with the gains
+/// `a_k = a / (k + 1 + A)^0.602` and `c_k = c / (k + 1)^0.101` and `A` a tenth of the iterations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spsa {
+    /// The scale `a` of the step sizes.
+    pub learning_rate: f64,
+    /// The scale `c` of the perturbations.
+    pub perturbation: f64,
+    /// The number of iterations.
+    pub max_iterations: usize,
+    /// The seed of the random perturbations, `None` uses [crate::seed::rng].
+    pub seed: Option<u64>,
+}
+
+impl Default for Spsa {
+    fn default() -> Self {
+        Self {
+            learning_rate: 0.05,
+            perturbation: 0.1,
+            max_iterations: 300,
+            seed: None,
+        }
+    }
+}
+
+impl Spsa {
+    /// Sets the seed of the random perturbations.
+    ///
+    /// # Arguments
+    ///
+    /// `seed` - The seed of the random number generator.
+    pub fn set_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl Optimizer for Spsa {
+    fn name(&self) -> &'static str {
+        "SPSA"
+    }
+
+    fn minimize(
+        &self,
+        objective: &mut Objective,
+        start: &[f64],
+    ) -> Result<OptimizationResult, RoqoqoBackendError> {
+        let mut history = History::new(self.name());
+        let mut rng = crate::seed::backend_rng(self.seed);
+        let mut parameters = start.to_vec();
+        let start_value = objective.value(start)?;
+        history.record(start_value, start, objective);
+        let stability = 0.1 * self.max_iterations as f64;
+        for k in 0..self.max_iterations {
+            let step = self.learning_rate / (k as f64 + 1.0 + stability).powf(0.602);
+            let perturbation = self.perturbation / (k as f64 + 1.0).powf(0.101);
+            let direction: Vec<f64> = (0..parameters.len())
+                .map(|_| if rng.gen_bool(0.5) { 1.0 } else { -1.0 })
+                .collect();
+            let perturbed = |sign: f64| -> Vec<f64> {
+                parameters
+                    .iter()
+                    .zip(direction.iter())
+                    .map(|(x, d)| x + sign * perturbation * d)
+                    .collect()
+            };
+            let plus = objective.value(&perturbed(1.0))?;
+            let minus = objective.value(&perturbed(-1.0))?;
+            for (x, d) in parameters.iter_mut().zip(direction.iter()) {
+                *x -= step * (plus - minus) / (2.0 * perturbation * d);
+            }
+            history.record((plus + minus) / 2.0, &parameters, objective);
+        }
+        let energy = objective.value(&parameters)?;
+        Ok(OptimizationResult::new(
+            parameters, energy, objective, history,
+        ))
+    }
+}
+
+/// Gradient descent with the adaptive moment estimates of Adam.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adam {
+    /// The step size.
+    pub learning_rate: f64,
+    /// The decay rate of the mean of the gradients.
+    pub beta1: f64,
+    /// The decay rate of the mean of the squared gradients.
+    pub beta2: f64,
+    /// The optimization stops when the norm of the gradient falls below the tolerance.
+    pub tolerance: f64,
+    /// The largest number of iterations.
+    pub max_iterations: usize,
+}
+
+impl Default for Adam {
+    fn default() -> Self {
+        Self {
+            learning_rate: 0.05,
+            beta1: 0.9,
+            beta2: 0.999,
+            tolerance: 1e-6,
+            max_iterations: 500,
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn name(&self) -> &'static str {
+        "Adam"
+    }
+
+    fn minimize(
+        &self,
+        objective: &mut Objective,
+        start: &[f64],
+    ) -> Result<OptimizationResult, RoqoqoBackendError> {
+        let mut history = History::new(self.name());
+        let mut parameters = start.to_vec();
+        let mut energy = objective.value(start)?;
+        history.record(energy, start, objective);
+        let mut mean = vec![0.0; start.len()];
+        let mut squared_mean = vec![0.0; start.len()];
+        for k in 1..=self.max_iterations {
+            let gradient = objective.gradient(&parameters)?;
+            if norm(&gradient) < self.tolerance {
+                break;
+            }
+            for i in 0..parameters.len() {
+                mean[i] = self.beta1 * mean[i] + (1.0 - self.beta1) * gradient[i];
+                squared_mean[i] =
+                    self.beta2 * squared_mean[i] + (1.0 - self.beta2) * gradient[i] * gradient[i];
+                let corrected_mean = mean[i] / (1.0 - self.beta1.powi(k as i32));
+                let corrected_squared_mean = squared_mean[i] / (1.0 - self.beta2.powi(k as i32));
+                parameters[i] -=
+                    self.learning_rate * corrected_mean / (corrected_squared_mean.sqrt() + 1e-8);
+            }
+            energy = objective.value(&parameters)?;
+            history.record(energy, &parameters, objective);
+        }
+        Ok(OptimizationResult::new(
+            parameters, energy, objective, history,
+        ))
+    }
+}
+
+/// The limited-memory Broyden–Fletcher–Goldfarb–Shanno quasi-Newton method with a backtracking line search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lbfgs {
+    /// The number of previous steps approximating the inverse Hessian.
+    pub memory: usize,
+    /// The optimization stops when the norm of the gradient falls below the tolerance.
+    pub tolerance: f64,
+    /// The largest number of iterations.
+    pub max_iterations: usize,
+}
+
+impl Default for Lbfgs {
+    fn default() -> Self {
+        Self {
+            memory: 10,
+            tolerance: 1e-6,
+            max_iterations: 200,
+        }
+    }
+}
+
+impl Optimizer for Lbfgs {
+    fn name(&self) -> &'static str {
+        "L-BFGS"
+    }
+
+    fn minimize(
+        &self,
+        objective: &mut Objective,
+        start: &[f64],
+    ) -> Result<OptimizationResult, RoqoqoBackendError> {
+        let mut history = History::new(self.name());
+        let mut parameters = start.to_vec();
+        let mut energy = objective.value(start)?;
+        history.record(energy, start, objective);
+        let mut gradient = objective.gradient(&parameters)?;
+        // The last steps s and changes of the gradient y
+        let mut steps: Vec<(Vec<f64>, Vec<f64>)> = Vec::new();
+        for _ in 0..self.max_iterations {
+            if norm(&gradient) < self.tolerance {
+                break;
+            }
+            // Two-loop recursion for the direction -H g
+            let mut direction = gradient.clone();
+            let mut alphas = Vec::with_capacity(steps.len());
+            for (s, y) in steps.iter().rev() {
+                let alpha = dot(s, &direction) / dot(y, s);
+                for (d, y) in direction.iter_mut().zip(y.iter()) {
+                    *d -= alpha * y;
+                }
+                alphas.push(alpha);
+            }
+            let scale = match steps.last() {
+                Some((s, y)) => dot(s, y) / dot(y, y),
+                None => 1.0 / norm(&gradient),
+            };
+            direction.iter_mut().for_each(|d| *d *= scale);
+            for ((s, y), alpha) in steps.iter().zip(alphas.iter().rev()) {
+                let beta = dot(y, &direction) / dot(y, s);
+                for (d, s) in direction.iter_mut().zip(s.iter()) {
+                    *d += (alpha - beta) * s;
+                }
+            }
+            direction.iter_mut().for_each(|d| *d = -*d);
+            let mut slope = dot(&gradient, &direction);
+            if slope >= 0.0 {
+                // Not a descent direction, restarting with steepest descent
+                steps.clear();
+                direction = gradient.iter().map(|g| -g / norm(&gradient)).collect();
+                slope = dot(&gradient, &direction);
+            }
+
+            // Backtracking until the Armijo condition holds
+            let mut length = 1.0;
+            let mut trial: Vec<f64>;
+            let mut trial_energy;
+            let mut halvings = 0;
+            loop {
+                trial = parameters
+                    .iter()
+                    .zip(direction.iter())
+                    .map(|(x, d)| x + length * d)
+                    .collect();
+                trial_energy = objective.value(&trial)?;
+                if trial_energy <= energy + 1e-4 * length * slope || halvings == 30 {
+                    break;
+                }
+                length /= 2.0;
+                halvings += 1;
+            }
+            if trial_energy >= energy {
+                break;
+            }
+            let trial_gradient = objective.gradient(&trial)?;
+            let s: Vec<f64> = trial
+                .iter()
+                .zip(parameters.iter())
+                .map(|(a, b)| a - b)
+                .collect();
+            let y: Vec<f64> = trial_gradient
+                .iter()
+                .zip(gradient.iter())
+                .map(|(a, b)| a - b)
+                .collect();
+            if dot(&s, &y) > 1e-12 {
+                steps.push((s, y));
+                if steps.len() > self.memory {
+                    steps.remove(0);
+                }
+            }
+            parameters = trial;
+            energy = trial_energy;
+            gradient = trial_gradient;
+            history.record(energy, &parameters, objective);
+        }
+        Ok(OptimizationResult::new(
+            parameters, energy, objective, history,
+        ))
+    }
+}
+
+/// Returns the simplex of a point and the points shifted by `step` along every coordinate.
+fn coordinate_simplex(
+    objective: &mut Objective,
+    (point, value): &(Vec<f64>, f64),
+    step: f64,
+) -> Result<Vec<(Vec<f64>, f64)>, RoqoqoBackendError> {
+    let mut simplex = vec![(point.clone(), *value)];
+    for index in 0..point.len() {
+        let mut shifted = point.clone();
+        shifted[index] += step;
+        let shifted_value = objective.value(&shifted)?;
+        simplex.push((shifted, shifted_value));
+    }
+    Ok(simplex)
+}
+
+/// Sorts the points of a simplex by their values, best first.
+fn sort_simplex(simplex: &mut [(Vec<f64>, f64)]) {
+    simplex.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+}
+
+/// Returns the gradient of the linear function interpolating the values on a simplex, `None` for a degenerate simplex.
+fn linear_model_gradient(simplex: &[(Vec<f64>, f64)]) -> Option<Vec<f64>> {
+    let (best, best_value) = &simplex[0];
+    let dimension = best.len();
+    let differences = DMatrix::from_fn(dimension, dimension, |row, column| {
+        simplex[row + 1].0[column] - best[column]
+    });
+    let values = DVector::from_fn(dimension, |row, _| simplex[row + 1].1 - best_value);
+    differences
+        .lu()
+        .solve(&values)
+        .map(|gradient| gradient.iter().copied().collect())
+}
+
+/// Returns the scalar product of two vectors.
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+}
+
+/// Returns the Euclidean norm of a vector.
+fn norm(a: &[f64]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+/// Returns the Euclidean distance of two points.
+fn distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f64>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A quadratic with its minimum 1 at (1, -2).
+    fn quadratic(x: &[f64]) -> Result<f64, RoqoqoBackendError> {
+        Ok((x[0] - 1.0).powi(2) + 4.0 * (x[1] + 2.0).powi(2) + x[0] * (x[1] + 2.0) - x[0] + 2.0)
+    }
+
+    /// The Rosenbrock function with its minimum 0 at (1, 1).
+    fn rosenbrock(x: &[f64]) -> Result<f64, RoqoqoBackendError> {
+        Ok((1.0 - x[0]).powi(2) + 100.0 * (x[1] - x[0] * x[0]).powi(2))
+    }
+
+    /// The optimizers with enough iterations for the Rosenbrock function.
+    fn optimizers() -> Vec<Box<dyn Optimizer>> {
+        vec![
+            Box::new(Cobyla {
+                max_iterations: 10000,
+                rho_end: 1e-8,
+                tolerance: 0.0,
+                ..Cobyla::default()
+            }),
+            Box::new(NelderMead::default()),
+            Box::new(
+                Spsa {
+                    learning_rate: 0.1,
+                    perturbation: 0.01,
+                    max_iterations: 20000,
+                    ..Spsa::default()
+                }
+                .set_seed(3),
+            ),
+            Box::new(Adam {
+                max_iterations: 10000,
+                ..Adam::default()
+            }),
+            Box::new(Lbfgs::default()),
+        ]
+    }
+
+    #[test]
+    fn minimize_quadratic() {
+        // Minimum of the quadratic from the zero of its gradient
+        let minimum = [1.6, -2.2];
+        for optimizer in optimizers() {
+            let mut objective = Objective::new(quadratic);
+            let result = optimizer.minimize(&mut objective, &[3.0, 1.0]).unwrap();
+            assert!(
+                distance(&result.parameters, &minimum) < 1e-2,
+                "{} found {:?}",
+                optimizer.name(),
+                result.parameters
+            );
+            assert_eq!(result.history.optimizer, optimizer.name());
+            assert_eq!(result.number_evaluations, objective.number_evaluations());
+        }
+    }
+
+    #[test]
+    fn minimize_rosenbrock() {
+        for optimizer in optimizers() {
+            let mut objective = Objective::new(rosenbrock);
+            let result = optimizer.minimize(&mut objective, &[-1.2, 1.0]).unwrap();
+            assert!(
+                distance(&result.parameters, &[1.0, 1.0]) < 5e-2,
+                "{} found {:?}",
+                optimizer.name(),
+                result.parameters
+            );
+        }
+    }
+
+    #[test]
+    fn cobyla_stops_at_tolerance() {
+        let optimizer = Cobyla::default();
+        let mut objective = Objective::new(quadratic);
+        let result = optimizer.minimize(&mut objective, &[3.0, 1.0]).unwrap();
+        assert!(distance(&result.parameters, &[1.6, -2.2]) < 1e-3);
+        assert!(result.history.iterations.len() < optimizer.max_iterations);
+        let rounded = optimizer
+            .minimize(
+                &mut Objective::new(|x: &[f64]| Ok(quadratic(x)? * (1.0 + 1e-15))),
+                &[3.0, 1.0],
+            )
+            .unwrap();
+        assert_eq!(
+            rounded.history.iterations.len(),
+            result.history.iterations.len()
+        );
+    }
+
+    #[test]
+    fn analytic_gradient_is_used() {
+        let mut objective = Objective::new(rosenbrock).set_gradient(|x: &[f64]| {
+            Ok(vec![
+                -2.0 * (1.0 - x[0]) - 400.0 * x[0] * (x[1] - x[0] * x[0]),
+                200.0 * (x[1] - x[0] * x[0]),
+            ])
+        });
+        let result = Lbfgs::default()
+            .minimize(&mut objective, &[-1.2, 1.0])
+            .unwrap();
+        assert!(distance(&result.parameters, &[1.0, 1.0]) < 1e-4);
+        assert!(objective.number_gradient_evaluations() > 0);
+    }
+
+    #[test]
+    fn spsa_repeats_with_seed() {
+        let optimizer = Spsa::default().set_seed(5);
+        let first = optimizer
+            .minimize(&mut Objective::new(rosenbrock), &[-1.2, 1.0])
+            .unwrap();
+        let second = optimizer
+            .minimize(&mut Objective::new(rosenbrock), &[-1.2, 1.0])
+            .unwrap();
+        assert_eq!(first, second);
+        let other = Spsa::default()
+            .set_seed(6)
+            .minimize(&mut Objective::new(rosenbrock), &[-1.2, 1.0])
+            .unwrap();
+        assert_ne!(first.parameters, other.parameters);
+    }
+
+    #[test]
+    fn history_to_json() {
+        let mut objective = Objective::new(quadratic);
+        let result = NelderMead {
+            max_iterations: 3,
+            ..NelderMead::default()
+        }
+        .minimize(&mut objective, &[0.0, 0.0])
+        .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&result.history.to_json()).unwrap();
+        assert_eq!(json["optimizer"], "Nelder-Mead");
+        let iterations = json["iterations"].as_array().unwrap();
+        assert_eq!(iterations.len(), 4);
+        for (index, iteration) in iterations.iter().enumerate() {
+            let mut fields: Vec<&String> = iteration.as_object().unwrap().keys().collect();
+            fields.sort();
+            assert_eq!(
+                fields,
+                [
+                    "energy",
+                    "iteration",
+                    "number_evaluations",
+                    "number_gradient_evaluations",
+                    "parameters"
+                ]
+            );
+            assert_eq!(iteration["iteration"], index);
+            assert_eq!(
+                iteration["energy"].as_f64().unwrap(),
+                result.history.iterations[index].energy
+            );
+        }
+    }
+}
@@ -0,0 +1,574 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenQASM 2.0 and 3.0 export and import of roqoqo circuits.
+//!
+//! The export uses the [roqoqo_qasm] interface. The import covers the part of OpenQASM that the
+//! interface produces for the example circuits, including classically controlled `if` statements
+//! which are translated back to `PragmaConditional` operations.
+
+use qoqo_calculator::{Calculator, CalculatorFloat};
+use roqoqo::operations::*;
+use roqoqo::{Circuit, RoqoqoBackendError};
+
+/// Operations that can only be executed on simulators and have no OpenQASM counterpart.
+///
+/// roqoqo_qasm silently drops most of them, the export lists them as comments instead.
+const UNREPRESENTABLE_OPERATIONS: &[&str] = &[
+    "DefinitionComplex",
+    "DefinitionFloat",
+    "PragmaGetStateVector",
+    "PragmaGetDensityMatrix",
+    "PragmaGetOccupationProbability",
+    "PragmaGetPauliProduct",
+    "PragmaSetNumberOfMeasurements",
+];
+
+/// Returns the part of the circuit that can be represented in OpenQASM.
+///
+/// This is the circuit that is recovered when an exported QASM string is parsed back.
+///
+pub fn qasm_representable(circuit: &Circuit) -> Circuit {
+    let mut representable = Circuit::new();
+    for operation in circuit.iter() {
+        if !UNREPRESENTABLE_OPERATIONS.contains(&operation.hqslang()) {
+            representable.add_operation(operation.clone());
+        }
+    }
+    representable
+}
+
+/// Exports a circuit to OpenQASM.
+///
+/// Operations without an OpenQASM representation are omitted and listed as comments after the version header.
+///
+/// # Arguments
+///
+/// * `circuit` - The circuit to export.
+/// * `qasm_version` - The OpenQASM version, `"2.0"` or `"3.0"`.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The OpenQASM program.
+/// * `Err(RoqoqoBackendError)` - The version is unknown or the circuit contains an operation not supported by roqoqo_qasm.
+///
+pub fn circuit_to_qasm(
+    circuit: &Circuit,
+    qasm_version: &str,
+) -> Result<String, RoqoqoBackendError> {
+    let backend = roqoqo_qasm::Backend::new(None, Some(qasm_version.to_string()))?;
+    let qasm = backend.circuit_to_qasm_str(&qasm_representable(circuit))?;
+    let mut comments = String::new();
+    for operation in circuit.iter() {
+        if UNREPRESENTABLE_OPERATIONS.contains(&operation.hqslang()) {
+            comments.push_str(&format!(
+                "// {} is not representable in OpenQASM and was omitted\n",
+                operation.hqslang()
+            ));
+        } else if let Operation::PragmaRepeatedMeasurement(op) = operation {
+            comments.push_str(&format!(
+                "// PragmaRepeatedMeasurement: the {} repetitions of the measurement of {} are not representable in OpenQASM\n",
+                op.number_measurements(),
+                op.readout()
+            ));
+        }
+    }
+    match qasm.split_once('\n') {
+        Some((header, body)) => Ok(format!("{}\n{}{}", header, comments, body)),
+        None => Ok(qasm),
+    }
+}
+
+/// Parses an OpenQASM 2.0 or 3.0 program into a roqoqo circuit.
+///
+/// Gate definitions are skipped, gate calls are mapped to the roqoqo gates with the same name in the
+/// OpenQASM standard library. Parameters that can not be evaluated to a number are kept as symbolic parameters.
+///
+/// # Returns
+///
+/// * `Ok(Circuit)` - The parsed circuit.
+/// * `Err(RoqoqoBackendError::GenericError)` - The program contains a statement that can not be translated.
+///
+pub fn qasm_to_circuit(qasm: &str) -> Result<Circuit, RoqoqoBackendError> {
+    let statements = split_statements(qasm)?;
+    let mut parser = QasmParser::default();
+    parser.parse_statements(&statements)
+}
+
+/// A single OpenQASM statement, either terminated by `;` or a block in braces.
+enum Statement {
+    Simple(String),
+    Block(String, Vec<Statement>),
+}
+
+/// Removes comments and splits the program into (possibly nested) statements.
+fn split_statements(qasm: &str) -> Result<Vec<Statement>, RoqoqoBackendError> {
+    let text: String = qasm
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or_default())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let mut stack: Vec<(String, Vec<Statement>)> = vec![(String::new(), Vec::new())];
+    let mut current = String::new();
+    for character in text.chars() {
+        match character {
+            ';' => {
+                if !current.trim().is_empty() {
+                    stack
+                        .last_mut()
+                        .expect("Stack always contains the top level")
+                        .1
+                        .push(Statement::Simple(current.trim().to_string()));
+                }
+                current.clear();
+            }
+            '{' => {
+                stack.push((current.trim().to_string(), Vec::new()));
+                current.clear();
+            }
+            '}' => {
+                if !current.trim().is_empty() {
+                    return Err(generic_error(format!(
+                        "Missing ';' after statement '{}'",
+                        current.trim()
+                    )));
+                }
+                let (head, body) = stack.pop().expect("Stack always contains the top level");
+                match stack.last_mut() {
+                    Some((_, statements)) => statements.push(Statement::Block(head, body)),
+                    None => return Err(generic_error("Unmatched '}'".to_string())),
+                }
+            }
+            _ => current.push(character),
+        }
+    }
+    if stack.len() != 1 {
+        return Err(generic_error("Unclosed '{'".to_string()));
+    }
+    if !current.trim().is_empty() {
+        return Err(generic_error(format!(
+            "Missing ';' after statement '{}'",
+            current.trim()
+        )));
+    }
+    Ok(stack.pop().expect("Stack contains the top level").1)
+}
+
+/// Translates statements to operations and keeps track of the declared qubit register.
+#[derive(Default)]
+struct QasmParser {
+    /// Name and length of the quantum register.
+    qubit_register: Option<(String, usize)>,
+}
+
+impl QasmParser {
+    /// Translates a list of statements into a circuit.
+    fn parse_statements(
+        &mut self,
+        statements: &[Statement],
+    ) -> Result<Circuit, RoqoqoBackendError> {
+        let mut circuit = Circuit::new();
+        for statement in statements {
+            let operations = match statement {
+                Statement::Simple(statement) => self.parse_simple(statement)?,
+                Statement::Block(head, body) => {
+                    if head.starts_with("gate ") {
+                        // Gate definitions only define standard gates in terms of U and CX
+                        Vec::new()
+                    } else if head.starts_with("if") {
+                        let (register, index, rest) = parse_condition(head)?;
+                        if !rest.is_empty() {
+                            return Err(generic_error(format!(
+                                "Unexpected '{}' before block",
+                                rest
+                            )));
+                        }
+                        let conditional_circuit = self.parse_statements(body)?;
+                        vec![Operation::from(PragmaConditional::new(
+                            register,
+                            index,
+                            conditional_circuit,
+                        ))]
+                    } else {
+                        return Err(generic_error(format!("Unsupported block '{}'", head)));
+                    }
+                }
+            };
+            for operation in operations {
+                add_merging_conditionals(&mut circuit, operation);
+            }
+        }
+        Ok(circuit)
+    }
+
+    /// Translates a single statement into zero or more operations.
+    fn parse_simple(&mut self, statement: &str) -> Result<Vec<Operation>, RoqoqoBackendError> {
+        let words: Vec<&str> = statement.split_whitespace().collect();
+        match words.as_slice() {
+            ["OPENQASM", ..] | ["include", ..] | ["barrier", ..] => Ok(Vec::new()),
+            // Symbolic parameters are recovered from the gate arguments
+            ["input", ..] => Ok(Vec::new()),
+            ["qreg", register] => {
+                self.qubit_register = Some(parse_argument(register)?);
+                Ok(Vec::new())
+            }
+            [qubit_type, name] if qubit_type.starts_with("qubit") => {
+                let length = parse_bracket_length(qubit_type)?;
+                self.qubit_register = Some((name.to_string(), length));
+                Ok(Vec::new())
+            }
+            ["creg", register] => {
+                let (name, length) = parse_argument(register)?;
+                Ok(vec![Operation::from(DefinitionBit::new(
+                    name, length, true,
+                ))])
+            }
+            [bit_type, name] | ["output", bit_type, name] if bit_type.starts_with("bit") => {
+                let length = parse_bracket_length(bit_type)?;
+                Ok(vec![Operation::from(DefinitionBit::new(
+                    name.to_string(),
+                    length,
+                    true,
+                ))])
+            }
+            // Float outputs belong to simulator readouts that are not representable
+            ["output", ..] => Ok(Vec::new()),
+            ["measure", qubit, "->", bit] => self.parse_measurement(qubit, bit),
+            [bit, "=", "measure", qubit] => self.parse_measurement(qubit, bit),
+            ["reset", qubit] => Ok(vec![Operation::from(PragmaActiveReset::new(
+                self.qubit_index(qubit)?,
+            ))]),
+            _ if statement.starts_with("if") => {
+                let (register, index, rest) = parse_condition(statement)?;
+                let mut conditional_circuit = Circuit::new();
+                for operation in self.parse_simple(&rest)? {
+                    conditional_circuit.add_operation(operation);
+                }
+                Ok(vec![Operation::from(PragmaConditional::new(
+                    register,
+                    index,
+                    conditional_circuit,
+                ))])
+            }
+            _ => Ok(vec![self.parse_gate(statement)?]),
+        }
+    }
+
+    /// Translates `measure q[i] -> c[j]` or `measure q -> c` into measurement operations.
+    fn parse_measurement(
+        &self,
+        qubit: &str,
+        bit: &str,
+    ) -> Result<Vec<Operation>, RoqoqoBackendError> {
+        if qubit.contains('[') {
+            let (readout, readout_index) = parse_argument(bit)?;
+            Ok(vec![Operation::from(MeasureQubit::new(
+                self.qubit_index(qubit)?,
+                readout,
+                readout_index,
+            ))])
+        } else {
+            // Measuring the whole register, OpenQASM does not specify a number of repetitions
+            Ok(vec![Operation::from(PragmaRepeatedMeasurement::new(
+                bit.to_string(),
+                1,
+                None,
+            ))])
+        }
+    }
+
+    /// Returns the index of a qubit argument `q[i]` in the declared qubit register.
+    fn qubit_index(&self, argument: &str) -> Result<usize, RoqoqoBackendError> {
+        let (name, index) = parse_argument(argument)?;
+        match &self.qubit_register {
+            Some((register, length)) if *register == name && index < *length => Ok(index),
+            Some((register, length)) => Err(generic_error(format!(
+                "Qubit {} is not part of the register {}[{}]",
+                argument, register, length
+            ))),
+            None => Err(generic_error(format!(
+                "Qubit {} used before a qubit register was declared",
+                argument
+            ))),
+        }
+    }
+
+    /// Translates a gate call `name(parameters) q[a],q[b];` into a roqoqo gate.
+    fn parse_gate(&self, statement: &str) -> Result<Operation, RoqoqoBackendError> {
+        let name_end = statement
+            .find(|c: char| c == '(' || c.is_whitespace())
+            .ok_or_else(|| generic_error(format!("Unsupported statement '{}'", statement)))?;
+        let name = &statement[..name_end];
+        let mut rest = statement[name_end..].trim_start();
+        let mut parameters: Vec<CalculatorFloat> = Vec::new();
+        if rest.starts_with('(') {
+            let close = matching_parenthesis(rest)?;
+            parameters = split_top_level(&rest[1..close])
+                .iter()
+                .map(|parameter| parse_parameter(parameter))
+                .collect();
+            rest = rest[close + 1..].trim_start();
+        }
+        let qubits = rest
+            .split(',')
+            .map(|argument| self.qubit_index(argument.trim()))
+            .collect::<Result<Vec<usize>, RoqoqoBackendError>>()?;
+
+        let operation: Option<Operation> = match (name, parameters.as_slice(), qubits.as_slice()) {
+            ("id", [], [q]) => Some(Identity::new(*q).into()),
+            ("h", [], [q]) => Some(Hadamard::new(*q).into()),
+            ("x", [], [q]) => Some(PauliX::new(*q).into()),
+            ("y", [], [q]) => Some(PauliY::new(*q).into()),
+            ("z", [], [q]) => Some(PauliZ::new(*q).into()),
+            ("s", [], [q]) => Some(SGate::new(*q).into()),
+            ("t", [], [q]) => Some(TGate::new(*q).into()),
+            ("sx", [], [q]) => Some(SqrtPauliX::new(*q).into()),
+            ("sxdg", [], [q]) => Some(InvSqrtPauliX::new(*q).into()),
+            ("rx", [theta], [q]) => Some(RotateX::new(*q, theta.clone()).into()),
+            ("ry", [theta], [q]) => Some(RotateY::new(*q, theta.clone()).into()),
+            ("rz", [theta], [q]) => Some(RotateZ::new(*q, theta.clone()).into()),
+            ("p", [theta], [q]) | ("u1", [theta], [q]) => {
+                Some(PhaseShiftState1::new(*q, theta.clone()).into())
+            }
+            ("cx", [], [c, t]) | ("CX", [], [c, t]) => Some(CNOT::new(*c, *t).into()),
+            ("cy", [], [c, t]) => Some(ControlledPauliY::new(*c, *t).into()),
+            ("cz", [], [c, t]) => Some(ControlledPauliZ::new(*c, *t).into()),
+            ("cp", [theta], [c, t]) => {
+                Some(ControlledPhaseShift::new(*c, *t, theta.clone()).into())
+            }
+            ("swap", [], [c, t]) => Some(SWAP::new(*c, *t).into()),
+            ("ccx", [], [c0, c1, t]) => Some(Toffoli::new(*c0, *c1, *t).into()),
+            _ => None,
+        };
+        operation.ok_or_else(|| {
+            generic_error(format!(
+                "Gate '{}' with {} parameters on {} qubits can not be translated to roqoqo",
+                name,
+                parameters.len(),
+                qubits.len()
+            ))
+        })
+    }
+}
+
+/// Adds an operation to the circuit and merges consecutive `PragmaConditional`s with the same condition.
+///
+/// OpenQASM 2.0 writes one `if` statement per conditional gate, this restores the original conditional circuit.
+///
+fn add_merging_conditionals(circuit: &mut Circuit, operation: Operation) {
+    if let (Operation::PragmaConditional(new), Some(Operation::PragmaConditional(last))) =
+        (&operation, circuit.iter().last())
+    {
+        if new.condition_register() == last.condition_register()
+            && new.condition_index() == last.condition_index()
+        {
+            let merged = PragmaConditional::new(
+                last.condition_register().clone(),
+                *last.condition_index(),
+                last.circuit().clone() + new.circuit().clone(),
+            );
+            let mut rebuilt = Circuit::new();
+            for previous in circuit.iter().take(circuit.len() - 1) {
+                rebuilt.add_operation(previous.clone());
+            }
+            rebuilt.add_operation(merged);
+            *circuit = rebuilt;
+            return;
+        }
+    }
+    circuit.add_operation(operation);
+}
+
+/// Parses `if(c[i]==1) rest` into the condition register, index and the remaining statement.
+fn parse_condition(statement: &str) -> Result<(String, usize, String), RoqoqoBackendError> {
+    let rest = statement
+        .strip_prefix("if")
+        .map(str::trim_start)
+        .filter(|rest| rest.starts_with('('))
+        .ok_or_else(|| generic_error(format!("Malformed condition '{}'", statement)))?;
+    let close = matching_parenthesis(rest)?;
+    let condition: String = rest[1..close]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let (bit, value) = condition
+        .split_once("==")
+        .ok_or_else(|| generic_error(format!("Unsupported condition '{}'", condition)))?;
+    if value != "1" && value != "true" {
+        return Err(generic_error(format!(
+            "Only conditions on a single bit being 1 can be translated, found '{}'",
+            condition
+        )));
+    }
+    let (register, index) = parse_argument(bit)?;
+    Ok((register, index, rest[close + 1..].trim().to_string()))
+}
+
+/// Parses an indexed argument `name[index]`.
+fn parse_argument(argument: &str) -> Result<(String, usize), RoqoqoBackendError> {
+    let (name, index) = argument
+        .trim()
+        .strip_suffix(']')
+        .and_then(|argument| argument.split_once('['))
+        .ok_or_else(|| generic_error(format!("Expected 'name[index]', found '{}'", argument)))?;
+    let index = index
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| generic_error(format!("Invalid index in '{}'", argument)))?;
+    Ok((name.trim().to_string(), index))
+}
+
+/// Parses the length of an OpenQASM 3.0 type `qubit[n]` or `bit[n]`.
+fn parse_bracket_length(declaration: &str) -> Result<usize, RoqoqoBackendError> {
+    declaration
+        .split_once('[')
+        .and_then(|(_, length)| length.strip_suffix(']'))
+        .and_then(|length| length.parse::<usize>().ok())
+        .ok_or_else(|| generic_error(format!("Invalid declaration '{}'", declaration)))
+}
+
+/// Returns the position of the parenthesis closing the one at the start of `text`.
+fn matching_parenthesis(text: &str) -> Result<usize, RoqoqoBackendError> {
+    let mut depth = 0;
+    for (position, character) in text.char_indices() {
+        match character {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(position);
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(generic_error(format!(
+        "Unbalanced parentheses in '{}'",
+        text
+    )))
+}
+
+/// Splits a parameter list at the commas that are not inside parentheses.
+fn split_top_level(text: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut depth = 0;
+    for character in text.chars() {
+        match character {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        parts
+            .last_mut()
+            .expect("parts is never empty")
+            .push(character);
+    }
+    parts.iter().map(|part| part.trim().to_string()).collect()
+}
+
+/// Parses a gate parameter, keeping it symbolic when it contains free variables.
+fn parse_parameter(parameter: &str) -> CalculatorFloat {
+    // Replace the constant pi, but not variables containing "pi" in their name
+    let mut expression = String::new();
+    let mut identifier = String::new();
+    for character in parameter.chars().chain(std::iter::once(' ')) {
+        if character.is_alphanumeric() || character == '_' {
+            identifier.push(character);
+        } else {
+            if identifier == "pi" {
+                expression.push_str(&format!("{:e}", std::f64::consts::PI));
+            } else {
+                expression.push_str(&identifier);
+            }
+            identifier.clear();
+            expression.push(character);
+        }
+    }
+    let expression = expression.trim().to_string();
+    match Calculator::new().parse_str(&expression) {
+        Ok(value) => CalculatorFloat::Float(value),
+        Err(_) => CalculatorFloat::Str(parameter.to_string()),
+    }
+}
+
+fn generic_error(msg: String) -> RoqoqoBackendError {
+    RoqoqoBackendError::GenericError { msg }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bell_circuit() -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 2, true);
+        circuit += Hadamard::new(0);
+        circuit += CNOT::new(0, 1);
+        circuit += RotateZ::new(1, 0.25.into());
+        circuit += MeasureQubit::new(0, "ro".to_string(), 0);
+        circuit += MeasureQubit::new(1, "ro".to_string(), 1);
+        circuit
+    }
+
+    #[test]
+    fn round_trip() {
+        for version in ["2.0", "3.0"] {
+            let qasm = circuit_to_qasm(&bell_circuit(), version).unwrap();
+            assert_eq!(qasm_to_circuit(&qasm).unwrap(), bell_circuit());
+        }
+    }
+
+    #[test]
+    fn round_trip_conditional() {
+        let mut correction = Circuit::new();
+        correction += PauliX::new(1);
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 2, true);
+        circuit += Hadamard::new(0);
+        circuit += MeasureQubit::new(0, "ro".to_string(), 0);
+        circuit += PragmaConditional::new("ro".to_string(), 0, correction);
+        circuit += MeasureQubit::new(1, "ro".to_string(), 1);
+        for version in ["2.0", "3.0"] {
+            let qasm = circuit_to_qasm(&circuit, version).unwrap();
+            assert_eq!(qasm_to_circuit(&qasm).unwrap(), circuit);
+        }
+    }
+
+    #[test]
+    fn unrepresentable_operations_are_commented() {
+        let mut circuit = bell_circuit();
+        circuit += DefinitionComplex::new("state".to_string(), 4, true);
+        circuit += PragmaGetStateVector::new("state".to_string(), None);
+        let qasm = circuit_to_qasm(&circuit, "2.0").unwrap();
+        assert!(qasm.contains("// PragmaGetStateVector is not representable in OpenQASM"));
+        assert_eq!(
+            qasm_to_circuit(&qasm).unwrap(),
+            qasm_representable(&circuit)
+        );
+        assert_eq!(qasm_representable(&circuit), bell_circuit());
+    }
+
+    #[test]
+    fn unknown_gate() {
+        let qasm = "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[1];\nunknown_gate q[0];\n";
+        assert!(qasm_to_circuit(qasm).is_err());
+    }
+
+    #[test]
+    fn unknown_version() {
+        assert!(circuit_to_qasm(&bell_circuit(), "4.0").is_err());
+    }
+}
@@ -0,0 +1,364 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simulation and mitigation of readout errors.
+//!
+//! Simulators measure perfectly. [apply_readout_errors] flips the measured bits of a `BitOutputRegister` with the
+//! probabilities of an `ImperfectReadoutModel`, like the readout of a real device would.
+//!
+//! The readout errors are mitigated with a [ReadoutCalibration]. Its matrix `A[i, j]` is the probability to read the
+//! outcome `i` when the qubits are prepared in the basis state `j`, measured with the [calibration_circuits]. The
+//! measured probabilities are `A p` for the true probabilities `p`, which are recovered by inverting the matrix or by
+//! iterative Bayesian unfolding.
+//!
+//! Outcomes are numbered by their bits, bit `k` of the register is bit `k` of the outcome.
+
+use nalgebra::{DMatrix, DVector};
+use rand::Rng;
+use roqoqo::noise_models::ImperfectReadoutModel;
+use roqoqo::operations::*;
+use roqoqo::registers::BitOutputRegister;
+use roqoqo::{Circuit, RoqoqoError};
+
+/// Flips the bits of measured shots with the error probabilities of a readout model.
+///
+/// # Arguments
+///
+/// * `register` - The measured shots.
+/// * `qubits` - The qubit measured into each bit of the register.
+/// * `model` - The readout model with the error probabilities of the qubits.
+/// * `rng` - The random number generator drawing the errors.
+///
+/// # Returns
+///
+/// * `BitOutputRegister` - The shots with readout errors.
+///
+pub fn apply_readout_errors<R: Rng>(
+    register: &BitOutputRegister,
+    qubits: &[usize],
+    model: &ImperfectReadoutModel,
+    rng: &mut R,
+) -> BitOutputRegister {
+    register
+        .iter()
+        .map(|shot| {
+            shot.iter()
+                .zip(qubits.iter())
+                .map(|(bit, qubit)| {
+                    let flip_probability = if *bit {
+                        model.prob_detect_1_as_0(qubit)
+                    } else {
+                        model.prob_detect_0_as_1(qubit)
+                    };
+                    *bit ^ rng.gen_bool(flip_probability)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the relative frequency of every outcome in the shots of a register.
+pub fn outcome_probabilities(register: &BitOutputRegister, number_bits: usize) -> Vec<f64> {
+    let mut probabilities = vec![0.0; 1 << number_bits];
+    for shot in register.iter() {
+        let outcome: usize = shot
+            .iter()
+            .enumerate()
+            .filter(|(_, bit)| **bit)
+            .map(|(index, _)| 1 << index)
+            .sum();
+        probabilities[outcome] += 1.0 / register.len() as f64;
+    }
+    probabilities
+}
+
+/// Returns the total variation distance between two probability distributions.
+pub fn total_variation_distance(first: &[f64], second: &[f64]) -> f64 {
+    first
+        .iter()
+        .zip(second.iter())
+        .map(|(first, second)| (first - second).abs())
+        .sum::<f64>()
+        / 2.0
+}
+
+/// Returns the circuits preparing and measuring every basis state of the qubits.
+///
+/// Circuit `j` prepares the basis state `j`, where qubit `qubits[k]` is flipped if bit `k` of `j` is set, and measures
+/// qubit `qubits[k]` into bit `k` of the register `readout`.
+///
+pub fn calibration_circuits(
+    qubits: &[usize],
+    readout: &str,
+    number_measurements: usize,
+) -> Vec<Circuit> {
+    (0..1 << qubits.len())
+        .map(|state: usize| {
+            let mut circuit = Circuit::new();
+            circuit += DefinitionBit::new(readout.to_string(), qubits.len(), true);
+            for (index, qubit) in qubits.iter().enumerate() {
+                if state >> index & 1 == 1 {
+                    circuit += PauliX::new(*qubit);
+                }
+            }
+            for (index, qubit) in qubits.iter().enumerate() {
+                circuit += MeasureQubit::new(*qubit, readout.to_string(), index);
+            }
+            circuit += PragmaSetNumberOfMeasurements::new(number_measurements, readout.to_string());
+            circuit
+        })
+        .collect()
+}
+
+/// Matrix of the probabilities to read each outcome for each prepared basis state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadoutCalibration {
+    /// The probability `matrix[(i, j)]` to read outcome `i` when basis state `j` is prepared.
+    pub matrix: DMatrix<f64>,
+}
+
+impl ReadoutCalibration {
+    /// Estimates the calibration from the shots of the calibration circuits.
+    ///
+    /// # Arguments
+    ///
+    /// * `registers` - The shots of the [calibration_circuits], in the order of the circuits.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ReadoutCalibration)` - The estimated calibration.
+    /// * `Err(RoqoqoError)` - There is not one register with shots for every basis state.
+    ///
+    pub fn from_registers(registers: &[BitOutputRegister]) -> Result<Self, RoqoqoError> {
+        let number_bits = registers.len().trailing_zeros() as usize;
+        if !registers.len().is_power_of_two()
+            || registers
+                .iter()
+                .flatten()
+                .any(|shot| shot.len() != number_bits)
+        {
+            return Err(RoqoqoError::GenericError {
+                msg: format!(
+                    "Calibration needs one register for every basis state, got {} registers",
+                    registers.len()
+                ),
+            });
+        }
+        if let Some(state) = registers.iter().position(|register| register.is_empty()) {
+            return Err(RoqoqoError::GenericError {
+                msg: format!("Calibration register of basis state {} has no shots", state),
+            });
+        }
+        let columns: Vec<DVector<f64>> = registers
+            .iter()
+            .map(|register| DVector::from_vec(outcome_probabilities(register, number_bits)))
+            .collect();
+        Ok(ReadoutCalibration {
+            matrix: DMatrix::from_columns(&columns),
+        })
+    }
+
+    /// Returns the exact calibration of a readout model for the measured qubits.
+    pub fn from_model(model: &ImperfectReadoutModel, qubits: &[usize]) -> Self {
+        let dimension = 1 << qubits.len();
+        let matrix = DMatrix::from_fn(dimension, dimension, |outcome, state| {
+            qubits
+                .iter()
+                .enumerate()
+                .map(
+                    |(index, qubit)| match (state >> index & 1 == 1, outcome >> index & 1 == 1) {
+                        (false, false) => 1.0 - model.prob_detect_0_as_1(qubit),
+                        (false, true) => model.prob_detect_0_as_1(qubit),
+                        (true, false) => model.prob_detect_1_as_0(qubit),
+                        (true, true) => 1.0 - model.prob_detect_1_as_0(qubit),
+                    },
+                )
+                .product()
+        });
+        ReadoutCalibration { matrix }
+    }
+
+    /// Mitigates measured probabilities with the inverse of the calibration matrix.
+    ///
+    /// Statistical noise can make some mitigated probabilities negative. They are set to zero and the distribution is
+    /// normalised again.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<f64>)` - The mitigated probabilities.
+    /// * `Err(RoqoqoError)` - The number of probabilities does not match the calibration, the calibration matrix can not
+    ///   be inverted or no mitigated probability is positive.
+    ///
+    pub fn mitigate_inverse(&self, measured: &[f64]) -> Result<Vec<f64>, RoqoqoError> {
+        self.check_dimension(measured)?;
+        let solution = self
+            .matrix
+            .clone()
+            .lu()
+            .solve(&DVector::from_column_slice(measured))
+            .ok_or_else(|| RoqoqoError::GenericError {
+                msg: "Readout calibration matrix is singular".to_string(),
+            })?;
+        let clipped: Vec<f64> = solution
+            .iter()
+            .map(|probability| probability.max(0.0))
+            .collect();
+        let norm: f64 = clipped.iter().sum();
+        if norm <= 0.0 {
+            return Err(RoqoqoError::GenericError {
+                msg: "No mitigated probability is positive".to_string(),
+            });
+        }
+        Ok(clipped
+            .iter()
+            .map(|probability| probability / norm)
+            .collect())
+    }
+
+    /// Mitigates measured probabilities with iterative Bayesian unfolding.
+    ///
+    /// Starting from a uniform distribution `p`, every iteration updates
+    /// `p[j] <- sum_i measured[i] A[i, j] p[j] / sum_k A[i, k] p[k]`, which keeps the probabilities positive and
+    /// normalised.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<f64>)` - The mitigated probabilities.
+    /// * `Err(RoqoqoError)` - The number of probabilities does not match the calibration.
+    ///
+    pub fn mitigate_bayesian(
+        &self,
+        measured: &[f64],
+        iterations: usize,
+    ) -> Result<Vec<f64>, RoqoqoError> {
+        self.check_dimension(measured)?;
+        let dimension = measured.len();
+        let mut probabilities = vec![1.0 / dimension as f64; dimension];
+        for _ in 0..iterations {
+            let expected: Vec<f64> = (0..dimension)
+                .map(|outcome| {
+                    (0..dimension)
+                        .map(|state| self.matrix[(outcome, state)] * probabilities[state])
+                        .sum()
+                })
+                .collect();
+            probabilities = (0..dimension)
+                .map(|state| {
+                    (0..dimension)
+                        .filter(|outcome| expected[*outcome] > 0.0)
+                        .map(|outcome| {
+                            measured[outcome] * self.matrix[(outcome, state)] * probabilities[state]
+                                / expected[outcome]
+                        })
+                        .sum()
+                })
+                .collect();
+        }
+        Ok(probabilities)
+    }
+
+    /// Returns an error if the number of measured probabilities does not match the square calibration matrix.
+    fn check_dimension(&self, measured: &[f64]) -> Result<(), RoqoqoError> {
+        if self.matrix.nrows() != self.matrix.ncols() || measured.len() != self.matrix.nrows() {
+            return Err(RoqoqoError::GenericError {
+                msg: format!(
+                    "{} measured probabilities do not match the {}x{} calibration matrix",
+                    measured.len(),
+                    self.matrix.nrows(),
+                    self.matrix.ncols()
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Formats probability distributions as a table with one row per outcome.
+///
+/// The outcomes are written as bit strings with bit 0 on the right.
+///
+pub fn format_histograms(columns: &[(&str, &[f64])], number_bits: usize) -> String {
+    let mut table = format!("{:>width$}", "outcome", width = number_bits.max(7));
+    for (name, _) in columns {
+        table += &format!(" | {:>9}", name);
+    }
+    for outcome in 0..1 << number_bits {
+        table += &format!(
+            "\n{:>width$}",
+            format!("{:0bits$b}", outcome, bits = number_bits),
+            width = number_bits.max(7)
+        );
+        for (_, probabilities) in columns {
+            table += &format!(" | {:>9.4}", probabilities[outcome]);
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_from_registers() {
+        let registers: Vec<BitOutputRegister> = vec![
+            vec![vec![false], vec![false], vec![false], vec![true]],
+            vec![vec![true], vec![true]],
+        ];
+        let calibration = ReadoutCalibration::from_registers(&registers).unwrap();
+        assert_eq!(
+            calibration.matrix,
+            DMatrix::from_row_slice(2, 2, &[0.75, 0.0, 0.25, 1.0])
+        );
+    }
+
+    #[test]
+    fn invalid_calibration_registers() {
+        assert!(ReadoutCalibration::from_registers(&[]).is_err());
+        let three = vec![vec![vec![false, false]]; 3];
+        assert!(ReadoutCalibration::from_registers(&three).is_err());
+        let no_shots = vec![vec![vec![false]], vec![]];
+        assert!(ReadoutCalibration::from_registers(&no_shots).is_err());
+    }
+
+    #[test]
+    fn mitigation_recovers_the_true_probabilities() {
+        let model = ImperfectReadoutModel::new_with_uniform_error(2, 0.03, 0.08).unwrap();
+        let calibration = ReadoutCalibration::from_model(&model, &[0, 1]);
+        let exact = [0.25, 0.5, 0.25, 0.0];
+        let measured: Vec<f64> = (&calibration.matrix * DVector::from_column_slice(&exact))
+            .iter()
+            .copied()
+            .collect();
+        let inverse = calibration.mitigate_inverse(&measured).unwrap();
+        assert!(total_variation_distance(&inverse, &exact) < 1e-10);
+        let bayesian = calibration.mitigate_bayesian(&measured, 1000).unwrap();
+        assert!(total_variation_distance(&bayesian, &exact) < 1e-2);
+    }
+
+    #[test]
+    fn invalid_mitigation_inputs() {
+        let calibration = ReadoutCalibration {
+            matrix: DMatrix::identity(2, 2),
+        };
+        assert!(calibration.mitigate_inverse(&[1.0]).is_err());
+        assert!(calibration
+            .mitigate_bayesian(&[0.5, 0.25, 0.25], 10)
+            .is_err());
+        assert!(calibration.mitigate_inverse(&[0.0, 0.0]).is_err());
+        let rectangular = ReadoutCalibration {
+            matrix: DMatrix::from_element(2, 3, 0.5),
+        };
+        assert!(rectangular.mitigate_inverse(&[0.5, 0.5]).is_err());
+        assert!(rectangular.mitigate_bayesian(&[0.5, 0.5], 10).is_err());
+    }
+}
@@ -0,0 +1,850 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Submitting programs to a job queue and fetching their results asynchronously.
+//!
+//! A remote quantum device does not run a program while the caller waits for it. The program is submitted as a job and
+//! gets a job ID, the caller polls the status of the job and fetches the result once the job has completed.
+//! [JobBackend] is this submit/poll/fetch interface, [run_job] submits a [JobRequest], polls until the job has
+//! completed and fetches the result, with a timeout and retries of failed requests. [wait_for_job] does the same for a
+//! job that has already been submitted.
+//!
+//! [LocalJobQueue] implements [JobBackend] for any `EvaluatingBackend` by running the jobs on the blocking threads of
+//! tokio. [serve] offers a [LocalJobQueue] as a mock remote service over HTTP, which is what the `qoqo-mock-server`
+//! binary runs, and [HttpJobClient] is the [JobBackend] talking to it. The service accepts the json of a `Circuit` or
+//! `QuantumProgram` as written by `serde_json` and answers with json:
+//!
+//! * `POST /jobs` with `{"program": <program>, "parameters": {<name>: <value>}, "number_qubits": <n or null>}` submits
+//!   a job and answers `202` with `{"job_id": <id>}`.
+//! * `GET /jobs/<id>` answers with `{"status": "queued" | "running" | "completed" | "failed", "error": <message>}`.
+//! * `GET /jobs/<id>/result` answers with the result of a completed job as written by [RunResult::to_json].
+//!
+//! Requests the service rejects with a `5xx` status and requests that do not reach it are `NetworkError`s, which
+//! [run_job] retries. To test the retries and timeouts of clients, the service can fail a fraction of the requests with
+//! `503` and the queue can hold every job for a while before running it. The rejected requests are drawn with
+//! [seed::rng_or] and every job samples from a stream of random numbers given by its job number, so with a global seed
+//! the result of a job only depends on the order the jobs were submitted in.
+
+use crate::runner::{RunResult, SerializedProgram};
+use crate::seed;
+use async_trait::async_trait;
+use rand::Rng;
+use roqoqo::backends::EvaluatingBackend;
+use roqoqo::RoqoqoBackendError;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The largest size of the head of an HTTP message.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// The first stream of random numbers of the jobs, which keeps them apart from the streams of the examples.
+const JOB_STREAMS: u64 = 1 << 63;
+
+/// The seed of the random numbers choosing the requests the mock remote service rejects, see [seed::rng_or].
+const FAILURE_SEED: u64 = 503;
+
+/// A program with the values of its parameters, submitted as one job.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobRequest {
+    /// The circuit or quantum program to run.
+    pub program: SerializedProgram,
+    /// Values of the free parameters by name, see [SerializedProgram::parameter_names].
+    pub parameters: HashMap<String, f64>,
+    /// The number of simulated qubits, `None` uses [SerializedProgram::number_qubits].
+    pub number_qubits: Option<usize>,
+}
+
+impl JobRequest {
+    /// Creates a new job request simulating the qubits used by the program.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The circuit or quantum program to run.
+    /// * `parameters` - Values of the free parameters by name.
+    pub fn new(program: SerializedProgram, parameters: HashMap<String, f64>) -> Self {
+        Self {
+            program,
+            parameters,
+            number_qubits: None,
+        }
+    }
+
+    /// Serializes the request to the json body of `POST /jobs`.
+    pub fn to_json(&self) -> Result<String, RoqoqoBackendError> {
+        let program: serde_json::Value =
+            serde_json::from_str(&self.program.to_json()?).map_err(|err| {
+                RoqoqoBackendError::GenericError {
+                    msg: err.to_string(),
+                }
+            })?;
+        Ok(serde_json::json!({
+            "program": program,
+            "parameters": self.parameters,
+            "number_qubits": self.number_qubits,
+        })
+        .to_string())
+    }
+
+    /// Reads a request from the json body of `POST /jobs`.
+    pub fn from_json(json: &str) -> Result<Self, RoqoqoBackendError> {
+        let error = |msg: String| RoqoqoBackendError::GenericError {
+            msg: format!("Invalid job request: {}", msg),
+        };
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|err| error(err.to_string()))?;
+        let program = value
+            .get("program")
+            .ok_or_else(|| error("missing program".to_string()))?;
+        let parameters = match value.get("parameters") {
+            Some(parameters) if !parameters.is_null() => {
+                serde_json::from_value(parameters.clone()).map_err(|err| error(err.to_string()))?
+            }
+            _ => HashMap::new(),
+        };
+        Ok(Self {
+            program: SerializedProgram::from_json(&program.to_string())?,
+            parameters,
+            number_qubits: value
+                .get("number_qubits")
+                .and_then(serde_json::Value::as_u64)
+                .map(|number_qubits| number_qubits as usize),
+        })
+    }
+}
+
+/// The status of a submitted job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    /// The job waits to be run.
+    Queued,
+    /// The job is running.
+    Running,
+    /// The job has completed, its result can be fetched.
+    Completed,
+    /// The job has failed with an error message.
+    Failed(String),
+}
+
+impl JobStatus {
+    /// Returns the name of the status in the json of the HTTP service.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed(_) => "failed",
+        }
+    }
+}
+
+/// Submits jobs, polls their status and fetches their results.
+#[async_trait]
+pub trait JobBackend: Send + Sync {
+    /// Submits a job and returns its job ID.
+    async fn submit(&self, request: &JobRequest) -> Result<String, RoqoqoBackendError>;
+
+    /// Returns the status of a job.
+    async fn status(&self, job_id: &str) -> Result<JobStatus, RoqoqoBackendError>;
+
+    /// Returns the result of a completed job.
+    async fn fetch(&self, job_id: &str) -> Result<RunResult, RoqoqoBackendError>;
+}
+
+/// How [run_job] waits for a job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOptions {
+    /// The longest time to wait for the job to complete after it has been submitted.
+    pub timeout: Duration,
+    /// The time between two status requests.
+    pub poll_interval: Duration,
+    /// The number of times a request failing with a `NetworkError` is repeated.
+    pub retries: usize,
+    /// The time before the first repetition of a failed request, doubled for every further repetition.
+    pub retry_delay: Duration,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            poll_interval: Duration::from_millis(20),
+            retries: 3,
+            retry_delay: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Submits a job, waits until it has completed and fetches its result.
+///
+/// # Arguments
+///
+/// * `backend` - The job backend the job is submitted to.
+/// * `request` - The program and parameters of the job.
+/// * `options` - The timeout, the poll interval and the retries of failed requests.
+///
+/// # Returns
+///
+/// * `Ok(RunResult)` - The result of the job.
+/// * `Err(RoqoqoBackendError::Timeout)` - The job has not completed within the timeout.
+/// * `Err(RoqoqoBackendError::NetworkError)` - A request has still failed after all retries.
+/// * `Err(RoqoqoBackendError)` - The job has failed.
+pub async fn run_job<J>(
+    backend: &J,
+    request: &JobRequest,
+    options: &RunOptions,
+) -> Result<RunResult, RoqoqoBackendError>
+where
+    J: JobBackend + ?Sized,
+{
+    let job_id = with_retries(options, || backend.submit(request)).await?;
+    wait_for_job(backend, &job_id, options).await
+}
+
+/// Waits until a submitted job has completed and fetches its result.
+///
+/// # Arguments
+///
+/// * `backend` - The job backend the job was submitted to.
+/// * `job_id` - The ID returned when the job was submitted.
+/// * `options` - The timeout, the poll interval and the retries of failed requests.
+///
+/// # Returns
+///
+/// * `Ok(RunResult)` - The result of the job.
+/// * `Err(RoqoqoBackendError::Timeout)` - The job has not completed within the timeout.
+/// * `Err(RoqoqoBackendError::NetworkError)` - A request has still failed after all retries.
+/// * `Err(RoqoqoBackendError)` - The job has failed.
+pub async fn wait_for_job<J>(
+    backend: &J,
+    job_id: &str,
+    options: &RunOptions,
+) -> Result<RunResult, RoqoqoBackendError>
+where
+    J: JobBackend + ?Sized,
+{
+    let completion = async {
+        loop {
+            match with_retries(options, || backend.status(job_id)).await? {
+                JobStatus::Completed => return Ok(()),
+                JobStatus::Failed(msg) => {
+                    return Err(RoqoqoBackendError::GenericError {
+                        msg: format!("Job {} failed: {}", job_id, msg),
+                    })
+                }
+                JobStatus::Queued | JobStatus::Running => {
+                    tokio::time::sleep(options.poll_interval).await
+                }
+            }
+        }
+    };
+    tokio::time::timeout(options.timeout, completion)
+        .await
+        .map_err(|_| RoqoqoBackendError::Timeout {
+            msg: format!(
+                "Job {} has not completed within {:.3} s",
+                job_id,
+                options.timeout.as_secs_f64()
+            ),
+        })??;
+    with_retries(options, || backend.fetch(job_id)).await
+}
+
+/// Repeats a request failing with a `NetworkError` up to `options.retries` times.
+///
+/// The delay before each repetition starts at `options.retry_delay` and doubles every time.
+///
+/// # Arguments
+///
+/// * `options` - The number of retries and the delay before the first one.
+/// * `request` - Sends the request, called once for every try.
+pub async fn with_retries<T, F, R>(
+    options: &RunOptions,
+    request: R,
+) -> Result<T, RoqoqoBackendError>
+where
+    F: Future<Output = Result<T, RoqoqoBackendError>>,
+    R: Fn() -> F,
+{
+    let mut delay = options.retry_delay;
+    for _ in 0..options.retries {
+        match request().await {
+            Err(RoqoqoBackendError::NetworkError { .. }) => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    request().await
+}
+
+/// A job and its result in a [LocalJobQueue].
+#[derive(Debug, Clone)]
+struct Job {
+    status: JobStatus,
+    result: Option<RunResult>,
+}
+
+/// Runs the jobs on a local backend, each job on a blocking thread of tokio.
+pub struct LocalJobQueue<B> {
+    new_backend: Arc<dyn Fn(usize) -> B + Send + Sync>,
+    delay: Duration,
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+    number_jobs: AtomicU64,
+}
+
+impl<B> LocalJobQueue<B>
+where
+    B: EvaluatingBackend + Send + 'static,
+{
+    /// Creates a new job queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_backend` - Creates the backend of a job for a number of qubits, e.g. `Backend::new`.
+    pub fn new<F>(new_backend: F) -> Self
+    where
+        F: Fn(usize) -> B + Send + Sync + 'static,
+    {
+        Self {
+            new_backend: Arc::new(new_backend),
+            delay: Duration::ZERO,
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            number_jobs: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the time every job stays queued before it is run, like on a busy remote device.
+    ///
+    /// # Arguments
+    ///
+    /// `delay` - The time a job stays queued.
+    pub fn set_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    fn job<T>(
+        &self,
+        job_id: &str,
+        f: impl FnOnce(&Job) -> Result<T, RoqoqoBackendError>,
+    ) -> Result<T, RoqoqoBackendError> {
+        let jobs = self
+            .jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match jobs.get(job_id) {
+            Some(job) => f(job),
+            None => Err(RoqoqoBackendError::GenericError {
+                msg: format!("Unknown job {}", job_id),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl<B> JobBackend for LocalJobQueue<B>
+where
+    B: EvaluatingBackend + Send + 'static,
+{
+    async fn submit(&self, request: &JobRequest) -> Result<String, RoqoqoBackendError> {
+        let number = self.number_jobs.fetch_add(1, Ordering::Relaxed) + 1;
+        let job_id = format!("job-{}", number);
+        let set = {
+            let jobs = self.jobs.clone();
+            let job_id = job_id.clone();
+            move |status: JobStatus, result: Option<RunResult>| {
+                jobs.lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .insert(job_id.clone(), Job { status, result });
+            }
+        };
+        set(JobStatus::Queued, None);
+        let backend = (self.new_backend)(
+            request
+                .number_qubits
+                .unwrap_or_else(|| request.program.number_qubits()),
+        );
+        let request = request.clone();
+        let delay = self.delay;
+        // The job number alone gives every job its own stream of random numbers, see seed::with_stream, so the result
+        // of a job does not depend on the jobs running at the same time
+        let stream = JOB_STREAMS | number;
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            set(JobStatus::Running, None);
+            let run = tokio::task::spawn_blocking(move || {
+                seed::with_stream(stream, || request.program.run(backend, &request.parameters))
+            })
+            .await;
+            match run {
+                Ok(Ok(result)) => set(JobStatus::Completed, Some(result)),
+                Ok(Err(err)) => set(JobStatus::Failed(err.to_string()), None),
+                Err(err) => set(JobStatus::Failed(err.to_string()), None),
+            }
+        });
+        Ok(job_id)
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, RoqoqoBackendError> {
+        self.job(job_id, |job| Ok(job.status.clone()))
+    }
+
+    async fn fetch(&self, job_id: &str) -> Result<RunResult, RoqoqoBackendError> {
+        self.job(job_id, |job| {
+            job.result
+                .clone()
+                .ok_or_else(|| RoqoqoBackendError::GenericError {
+                    msg: format!(
+                        "Job {} has not completed, its status is {}",
+                        job_id,
+                        job.status.name()
+                    ),
+                })
+        })
+    }
+}
+
+/// Serves a job queue as the mock remote service over HTTP.
+///
+/// Runs until the task is dropped, every connection is handled in its own task.
+///
+/// # Arguments
+///
+/// * `listener` - The bound TCP listener, e.g. on `127.0.0.1:0` for a free port.
+/// * `queue` - The job queue running the submitted jobs.
+/// * `failure_rate` - The fraction of requests rejected with `503 Service Unavailable`.
+pub async fn serve<B>(listener: TcpListener, queue: Arc<LocalJobQueue<B>>, failure_rate: f64)
+where
+    B: EvaluatingBackend + Send + 'static,
+{
+    // The rejected requests are drawn in the order the connections are accepted
+    let mut rng = seed::rng_or(FAILURE_SEED);
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let queue = queue.clone();
+        let reject = rng.gen_bool(failure_rate);
+        tokio::spawn(async move {
+            let _ = handle_connection(stream, queue.as_ref(), reject).await;
+        });
+    }
+}
+
+/// Answers one HTTP request of the mock remote service.
+async fn handle_connection<B>(
+    mut stream: TcpStream,
+    queue: &LocalJobQueue<B>,
+    reject: bool,
+) -> std::io::Result<()>
+where
+    B: EvaluatingBackend + Send + 'static,
+{
+    let (head, body) = read_message(&mut stream).await?;
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut request = httparse::Request::new(&mut headers);
+    let (status, response) = match request.parse(&head) {
+        Ok(httparse::Status::Complete(_)) if reject => (
+            503,
+            serde_json::json!({"error": "The service is temporarily unavailable"}),
+        ),
+        Ok(httparse::Status::Complete(_)) => {
+            let method = request.method.unwrap_or_default();
+            let path = request.path.unwrap_or_default();
+            route(queue, method, path, &String::from_utf8_lossy(&body)).await
+        }
+        _ => (400, serde_json::json!({"error": "Invalid HTTP request"})),
+    };
+    let body = response.to_string();
+    let reason = match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        _ => "Service Unavailable",
+    };
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                reason,
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .await?;
+    stream.shutdown().await
+}
+
+/// Returns the status code and json body answering a request of the mock remote service.
+async fn route<B>(
+    queue: &LocalJobQueue<B>,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> (u16, serde_json::Value)
+where
+    B: EvaluatingBackend + Send + 'static,
+{
+    let error = |status: u16, err: RoqoqoBackendError| {
+        (status, serde_json::json!({"error": err.to_string()}))
+    };
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("POST", ["jobs"]) => match JobRequest::from_json(body) {
+            Ok(request) => match queue.submit(&request).await {
+                Ok(job_id) => (202, serde_json::json!({ "job_id": job_id })),
+                Err(err) => error(400, err),
+            },
+            Err(err) => error(400, err),
+        },
+        ("GET", ["jobs", job_id]) => match queue.status(job_id).await {
+            Ok(JobStatus::Failed(msg)) => {
+                (200, serde_json::json!({"status": "failed", "error": msg}))
+            }
+            Ok(status) => (200, serde_json::json!({ "status": status.name() })),
+            Err(err) => error(404, err),
+        },
+        ("GET", ["jobs", job_id, "result"]) => match queue.status(job_id).await {
+            Ok(JobStatus::Completed) => match queue.fetch(job_id).await {
+                Ok(result) => (
+                    200,
+                    serde_json::from_str(&result.to_json()).unwrap_or_default(),
+                ),
+                Err(err) => error(409, err),
+            },
+            Ok(status) => (
+                409,
+                serde_json::json!({
+                    "error": format!("Job {} has not completed, its status is {}", job_id, status.name())
+                }),
+            ),
+            Err(err) => error(404, err),
+        },
+        _ => (
+            404,
+            serde_json::json!({"error": format!("No route for {} {}", method, path)}),
+        ),
+    }
+}
+
+/// Reads the head and the body of an HTTP message, the length of the body is given by its `Content-Length` header.
+async fn read_message<S>(stream: &mut S) -> std::io::Result<(Vec<u8>, Vec<u8>)>
+where
+    S: AsyncRead + Unpin,
+{
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+    let mut buffer = Vec::new();
+    let head_length = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+        if buffer.len() > MAX_HEAD_SIZE {
+            return Err(invalid("HTTP head too large"));
+        }
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(invalid("Connection closed before the end of the HTTP head"));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+    let head = String::from_utf8_lossy(&buffer[..head_length]).to_string();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map(|(_, value)| value.trim().parse::<usize>())
+        .transpose()
+        .map_err(|_| invalid("Invalid Content-Length"))?
+        .unwrap_or(0);
+    let mut body = buffer.split_off(head_length);
+    while body.len() < content_length {
+        let mut chunk = vec![0u8; content_length - body.len()];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(invalid("Connection closed before the end of the HTTP body"));
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+    Ok((buffer, body))
+}
+
+/// The [JobBackend] of the mock remote service served by [serve].
+#[derive(Debug)]
+pub struct HttpJobClient {
+    address: String,
+    failed_requests: AtomicUsize,
+}
+
+impl HttpJobClient {
+    /// Creates a client of the service at an address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The host and port of the service, e.g. `127.0.0.1:8080`.
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address
+                .trim_start_matches("http://")
+                .trim_end_matches('/')
+                .to_string(),
+            failed_requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of requests that failed with a `NetworkError` so far.
+    pub fn failed_requests(&self) -> usize {
+        self.failed_requests.load(Ordering::Relaxed)
+    }
+
+    /// Sends a request and returns the json body of a successful answer.
+    async fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<String>,
+    ) -> Result<serde_json::Value, RoqoqoBackendError> {
+        let result = self.send(method, path, body).await;
+        if let Err(RoqoqoBackendError::NetworkError { .. }) = result {
+            self.failed_requests.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    async fn send(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<String>,
+    ) -> Result<serde_json::Value, RoqoqoBackendError> {
+        let network_error = |msg: String| RoqoqoBackendError::NetworkError {
+            msg: format!("{} {} at {}: {}", method, path, self.address, msg),
+        };
+        let mut stream = TcpStream::connect(&self.address)
+            .await
+            .map_err(|err| network_error(err.to_string()))?;
+        let body = body.unwrap_or_default();
+        stream
+            .write_all(
+                format!(
+                    "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    method,
+                    path,
+                    self.address,
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .await
+            .map_err(|err| network_error(err.to_string()))?;
+        let (head, body) = read_message(&mut stream)
+            .await
+            .map_err(|err| network_error(err.to_string()))?;
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut response = httparse::Response::new(&mut headers);
+        response
+            .parse(&head)
+            .map_err(|err| network_error(err.to_string()))?;
+        let status = response.code.unwrap_or_default();
+        let value: serde_json::Value = serde_json::from_slice(&body)
+            .map_err(|err| network_error(format!("invalid json answer: {}", err)))?;
+        let message = || {
+            value
+                .get("error")
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        match status {
+            200..=299 => Ok(value),
+            500..=599 => Err(network_error(format!("status {}, {}", status, message()))),
+            _ => Err(RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "{} {} failed with status {}: {}",
+                    method,
+                    path,
+                    status,
+                    message()
+                ),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl JobBackend for HttpJobClient {
+    async fn submit(&self, request: &JobRequest) -> Result<String, RoqoqoBackendError> {
+        let answer = self
+            .request("POST", "/jobs", Some(request.to_json()?))
+            .await?;
+        answer
+            .get("job_id")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: "The answer to the submission contains no job_id".to_string(),
+            })
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, RoqoqoBackendError> {
+        let answer = self
+            .request("GET", &format!("/jobs/{}", job_id), None)
+            .await?;
+        match answer.get("status").and_then(serde_json::Value::as_str) {
+            Some("queued") => Ok(JobStatus::Queued),
+            Some("running") => Ok(JobStatus::Running),
+            Some("completed") => Ok(JobStatus::Completed),
+            Some("failed") => Ok(JobStatus::Failed(
+                answer
+                    .get("error")
+                    .and_then(serde_json::Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+            )),
+            status => Err(RoqoqoBackendError::GenericError {
+                msg: format!("Unknown status {:?} of job {}", status, job_id),
+            }),
+        }
+    }
+
+    async fn fetch(&self, job_id: &str) -> Result<RunResult, RoqoqoBackendError> {
+        let answer = self
+            .request("GET", &format!("/jobs/{}/result", job_id), None)
+            .await?;
+        RunResult::from_json(&answer.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_vector::StateVectorBackend;
+    use roqoqo::operations::*;
+    use roqoqo::Circuit;
+
+    /// Serves a job queue on a free local port and returns a client of it.
+    async fn bind(queue: LocalJobQueue<StateVectorBackend>, failure_rate: f64) -> HttpJobClient {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(listener, Arc::new(queue), failure_rate));
+        HttpJobClient::new(&address)
+    }
+
+    fn bell_request() -> JobRequest {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 2, true);
+        circuit += Hadamard::new(0);
+        circuit += CNOT::new(0, 1);
+        circuit += PragmaRepeatedMeasurement::new("ro".to_string(), 10, None);
+        JobRequest::new(SerializedProgram::Circuit(circuit), HashMap::new())
+    }
+
+    fn options(retries: usize) -> RunOptions {
+        RunOptions {
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_millis(5),
+            retries,
+            retry_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_rejected_requests() {
+        let client = bind(LocalJobQueue::new(StateVectorBackend::new), 1.0).await;
+        let request = bell_request();
+        let result = with_retries(&options(3), || client.submit(&request)).await;
+        assert!(matches!(
+            result,
+            Err(RoqoqoBackendError::NetworkError { .. })
+        ));
+        assert_eq!(client.failed_requests(), 4);
+
+        // Fails twice and succeeds on the third try
+        let tries = AtomicUsize::new(0);
+        let flaky = || async {
+            match tries.fetch_add(1, Ordering::Relaxed) {
+                0 | 1 => Err(RoqoqoBackendError::NetworkError {
+                    msg: "rejected".to_string(),
+                }),
+                try_number => Ok(try_number),
+            }
+        };
+        assert_eq!(with_retries(&options(2), flaky).await.unwrap(), 2);
+        tries.store(0, Ordering::Relaxed);
+        assert!(with_retries(&options(1), flaky).await.is_err());
+        assert_eq!(tries.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn completed_job() {
+        let client = bind(LocalJobQueue::new(StateVectorBackend::new), 0.0).await;
+        match run_job(&client, &bell_request(), &options(0))
+            .await
+            .unwrap()
+        {
+            RunResult::Registers { bit_registers, .. } => {
+                assert_eq!(bit_registers["ro"].len(), 10);
+                assert!(bit_registers["ro"].iter().all(|shot| shot[0] == shot[1]));
+            }
+            result => panic!("Expected registers, found {result:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn delayed_job_times_out() {
+        let queue = LocalJobQueue::new(StateVectorBackend::new).set_delay(Duration::from_secs(5));
+        let client = bind(queue, 0.0).await;
+        let options = RunOptions {
+            timeout: Duration::from_millis(50),
+            ..options(0)
+        };
+        let result = run_job(&client, &bell_request(), &options).await;
+        assert!(matches!(result, Err(RoqoqoBackendError::Timeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn failing_program() {
+        let client = bind(LocalJobQueue::new(StateVectorBackend::new), 0.0).await;
+        // The CNOT acts on a qubit outside of the simulated state
+        let mut request = bell_request();
+        request.number_qubits = Some(1);
+        let job_id = client.submit(&request).await.unwrap();
+        let status = loop {
+            match client.status(&job_id).await.unwrap() {
+                JobStatus::Queued | JobStatus::Running => {
+                    tokio::time::sleep(Duration::from_millis(5)).await
+                }
+                status => break status,
+            }
+        };
+        assert!(matches!(status, JobStatus::Failed(_)), "{status:?}");
+        let error = wait_for_job(&client, &job_id, &options(0))
+            .await
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains(&format!("Job {} failed", job_id)));
+        assert!(client.fetch(&job_id).await.is_err());
+    }
+}
@@ -0,0 +1,373 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Routing of circuits onto the qubit connectivity of a device.
+//!
+//! Two-qubit gates can only be executed on qubits that are connected on the device, the edges returned by
+//! `Device::two_qubit_edges`. [route_circuit] starts with qubit `k` of the circuit on qubit `k` of the device and,
+//! whenever a two-qubit gate acts on unconnected qubits, inserts `SWAP` gates along a shortest path of the device
+//! until the two qubits are neighbours. All following operations are moved along with the qubits, so measurements
+//! still write the same qubit of the circuit into the same readout.
+//!
+//! The inserted `SWAP` gates are not native gates of most devices, they still have to be decomposed.
+
+use crate::diagram::number_qubits;
+use crate::unitary::{self, UnitaryComparison};
+use roqoqo::devices::Device;
+use roqoqo::operations::*;
+use roqoqo::{Circuit, RoqoqoError};
+use std::collections::{HashMap, VecDeque};
+
+/// A circuit routed onto a device with [route_circuit].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutedCircuit {
+    /// The routed circuit acting on the qubits of the device.
+    pub circuit: Circuit,
+    /// The device qubit that holds each qubit at the end of the circuit, for all qubits of the device.
+    pub final_layout: Vec<usize>,
+    /// The number of inserted SWAP gates.
+    pub number_swaps: usize,
+    /// The number of two-qubit gates in the original circuit.
+    pub number_two_qubit_gates: usize,
+}
+
+impl RoutedCircuit {
+    /// Returns the number of inserted SWAP gates per two-qubit gate of the original circuit.
+    pub fn swap_overhead(&self) -> f64 {
+        if self.number_two_qubit_gates == 0 {
+            0.0
+        } else {
+            self.number_swaps as f64 / self.number_two_qubit_gates as f64
+        }
+    }
+}
+
+impl std::fmt::Display for RoutedCircuit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} SWAP gates inserted for {} two-qubit gates (overhead {:.2}), final layout {:?}",
+            self.number_swaps,
+            self.number_two_qubit_gates,
+            self.swap_overhead(),
+            self.final_layout
+        )
+    }
+}
+
+/// Assignment of the qubits of the circuit to the qubits of the device.
+struct Layout {
+    /// The device qubit of every circuit qubit.
+    physical: Vec<usize>,
+    /// The circuit qubit on every device qubit.
+    logical: Vec<usize>,
+}
+
+impl Layout {
+    fn swap(&mut self, first: usize, second: usize) {
+        self.logical.swap(first, second);
+        self.physical[self.logical[first]] = first;
+        self.physical[self.logical[second]] = second;
+    }
+
+    fn mapping(&self) -> HashMap<usize, usize> {
+        self.physical.iter().copied().enumerate().collect()
+    }
+}
+
+/// State shared while routing a circuit and the circuits nested in it.
+struct Router {
+    neighbours: Vec<Vec<usize>>,
+    layout: Layout,
+    number_logical_qubits: usize,
+    number_swaps: usize,
+    number_two_qubit_gates: usize,
+}
+
+/// Routes a circuit onto the connectivity of a device by inserting SWAP gates.
+///
+/// Circuits nested in `PragmaConditional` and `PragmaLoop` are routed as well. The SWAP gates inserted inside them are
+/// undone at the end of the nested circuit, so the layout after the pragma does not depend on measurement results.
+/// Operations acting on all qubits, like `PragmaGetStateVector`, are not changed and see the qubits in the current
+/// layout, with the exception of `PragmaRepeatedMeasurement`, which gets a qubit mapping that writes every qubit into
+/// its original readout index.
+///
+/// # Arguments
+///
+/// * `circuit` - The circuit. Gates acting on more than two qubits need to be decomposed first.
+/// * `device` - The device whose two-qubit edges define which qubits are connected.
+///
+/// # Returns
+///
+/// * `Ok(RoutedCircuit)` - The routed circuit with the final layout and the number of inserted SWAP gates.
+/// * `Err(RoqoqoError)` - The circuit does not fit on the device or contains gates on more than two qubits.
+///
+pub fn route_circuit<T: Device>(
+    circuit: &Circuit,
+    device: &T,
+) -> Result<RoutedCircuit, RoqoqoError> {
+    let number_device_qubits = device.number_qubits();
+    let number_logical_qubits = number_qubits(circuit);
+    if number_logical_qubits > number_device_qubits {
+        return Err(RoqoqoError::GenericError {
+            msg: format!(
+                "Circuit uses {} qubits but the device only has {}",
+                number_logical_qubits, number_device_qubits
+            ),
+        });
+    }
+    let mut neighbours = vec![Vec::new(); number_device_qubits];
+    for (first, second) in device.two_qubit_edges() {
+        neighbours[first].push(second);
+        neighbours[second].push(first);
+    }
+    let mut router = Router {
+        neighbours,
+        layout: Layout {
+            physical: (0..number_device_qubits).collect(),
+            logical: (0..number_device_qubits).collect(),
+        },
+        number_logical_qubits,
+        number_swaps: 0,
+        number_two_qubit_gates: 0,
+    };
+    let routed = router.route(circuit, false)?;
+    Ok(RoutedCircuit {
+        circuit: routed,
+        final_layout: router.layout.physical,
+        number_swaps: router.number_swaps,
+        number_two_qubit_gates: router.number_two_qubit_gates,
+    })
+}
+
+impl Router {
+    fn route(&mut self, circuit: &Circuit, restore_layout: bool) -> Result<Circuit, RoqoqoError> {
+        let mut routed = Circuit::new();
+        let mut swaps: Vec<(usize, usize)> = Vec::new();
+        for operation in circuit.iter() {
+            match operation {
+                Operation::PragmaConditional(op) => {
+                    routed += PragmaConditional::new(
+                        op.condition_register().clone(),
+                        *op.condition_index(),
+                        self.route(op.circuit(), true)?,
+                    );
+                }
+                Operation::PragmaLoop(op) => {
+                    routed +=
+                        PragmaLoop::new(op.repetitions().clone(), self.route(op.circuit(), true)?);
+                }
+                Operation::PragmaRepeatedMeasurement(op) => {
+                    let readout_index = |qubit: usize| match op.qubit_mapping() {
+                        Some(mapping) => mapping.get(&qubit).copied(),
+                        None => Some(qubit),
+                    };
+                    let mapping: HashMap<usize, usize> = (0..self.number_logical_qubits)
+                        .filter_map(|qubit| {
+                            readout_index(qubit).map(|index| (self.layout.physical[qubit], index))
+                        })
+                        .collect();
+                    routed += PragmaRepeatedMeasurement::new(
+                        op.readout().clone(),
+                        *op.number_measurements(),
+                        Some(mapping),
+                    );
+                }
+                _ => match operation.involved_qubits() {
+                    InvolvedQubits::Set(qubits) if qubits.len() > 2 => {
+                        return Err(RoqoqoError::GenericError {
+                            msg: format!(
+                                "{} acts on {} qubits, only gates on up to two qubits can be routed",
+                                operation.hqslang(),
+                                qubits.len()
+                            ),
+                        });
+                    }
+                    InvolvedQubits::Set(qubits) if qubits.len() == 2 => {
+                        // Sorted so the routing does not depend on the order of the set, the larger qubit is moved
+                        let mut qubits: Vec<usize> = qubits.into_iter().collect();
+                        qubits.sort_unstable_by(|a, b| b.cmp(a));
+                        let (first, second) = (qubits[0], qubits[1]);
+                        let path = self.shortest_path(
+                            self.layout.physical[first],
+                            self.layout.physical[second],
+                        )?;
+                        // Moves the first qubit along the path until it is next to the second one
+                        for step in path.windows(2).take(path.len().saturating_sub(2)) {
+                            routed += SWAP::new(step[0], step[1]);
+                            self.layout.swap(step[0], step[1]);
+                            swaps.push((step[0], step[1]));
+                            self.number_swaps += 1;
+                        }
+                        self.number_two_qubit_gates += 1;
+                        routed += operation.remap_qubits(&self.layout.mapping())?;
+                    }
+                    InvolvedQubits::Set(_) => {
+                        routed += operation.remap_qubits(&self.layout.mapping())?;
+                    }
+                    _ => routed += operation.clone(),
+                },
+            }
+        }
+        if restore_layout {
+            for (first, second) in swaps.into_iter().rev() {
+                routed += SWAP::new(first, second);
+                self.layout.swap(first, second);
+                self.number_swaps += 1;
+            }
+        }
+        Ok(routed)
+    }
+
+    /// Finds a shortest path between two device qubits with a breadth-first search.
+    fn shortest_path(&self, start: usize, end: usize) -> Result<Vec<usize>, RoqoqoError> {
+        let mut previous: Vec<Option<usize>> = vec![None; self.neighbours.len()];
+        let mut queue = VecDeque::from([start]);
+        previous[start] = Some(start);
+        while let Some(qubit) = queue.pop_front() {
+            if qubit == end {
+                let mut path = vec![end];
+                while let Some(&last) = path.last() {
+                    if last == start {
+                        break;
+                    }
+                    path.push(previous[last].expect("Visited qubits have a predecessor"));
+                }
+                path.reverse();
+                return Ok(path);
+            }
+            for &neighbour in self.neighbours[qubit].iter() {
+                if previous[neighbour].is_none() {
+                    previous[neighbour] = Some(qubit);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        Err(RoqoqoError::GenericError {
+            msg: format!(
+                "Device qubits {} and {} are not connected by two-qubit edges",
+                start, end
+            ),
+        })
+    }
+}
+
+/// Compares the gates of a routed circuit with the gates of the original circuit.
+///
+/// The routed circuit has to act like the original circuit followed by moving every qubit to its device qubit in the
+/// final layout. Only the gates of both circuits are compared, so measurements are expected at the end of the circuit.
+///
+/// # Returns
+///
+/// * `Ok(UnitaryComparison)` - The comparison of the two unitaries on all qubits of the device.
+/// * `Err(RoqoqoError)` - The unitary of one of the circuits could not be computed.
+///
+pub fn compare_routed_circuit(
+    original: &Circuit,
+    routed: &RoutedCircuit,
+) -> Result<UnitaryComparison, RoqoqoError> {
+    let gates = |circuit: &Circuit| -> Circuit {
+        circuit
+            .iter()
+            .filter(|operation| unitary::is_gate(operation))
+            .cloned()
+            .collect()
+    };
+    let number_qubits = routed.final_layout.len();
+    let layout = unitary::permutation_unitary(number_qubits, |index| {
+        routed
+            .final_layout
+            .iter()
+            .enumerate()
+            .filter(|(qubit, _)| index >> qubit & 1 == 1)
+            .map(|(_, physical)| 1 << physical)
+            .sum()
+    });
+    unitary::compare_unitaries(
+        &unitary::circuit_unitary(&gates(&routed.circuit), number_qubits)?,
+        &(layout * unitary::circuit_unitary(&gates(original), number_qubits)?),
+        unitary::TOLERANCE,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_vector::StateVectorBackend;
+    use roqoqo::backends::EvaluatingBackend;
+    use roqoqo::devices::SquareLatticeDevice;
+
+    fn line() -> SquareLatticeDevice {
+        SquareLatticeDevice::new(1, 4, &["RotateZ".to_string()], &["CNOT".to_string()], 1.0)
+    }
+
+    #[test]
+    fn cnot_across_a_line() {
+        let circuit = Circuit::new() + CNOT::new(0, 3);
+        let routed = route_circuit(&circuit, &line()).unwrap();
+        let mut expected = Circuit::new();
+        expected += SWAP::new(3, 2);
+        expected += SWAP::new(2, 1);
+        expected += CNOT::new(0, 1);
+        assert_eq!(routed.circuit, expected);
+        assert_eq!(routed.number_swaps, 2);
+        assert_eq!(routed.number_two_qubit_gates, 1);
+        assert_eq!(routed.final_layout, vec![0, 2, 3, 1]);
+        assert!(compare_routed_circuit(&circuit, &routed)
+            .unwrap()
+            .is_equivalent());
+    }
+
+    #[test]
+    fn conditional_swaps_are_undone() {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("condition".to_string(), 1, false);
+        circuit +=
+            PragmaConditional::new("condition".to_string(), 0, Circuit::new() + CNOT::new(0, 3));
+        let routed = route_circuit(&circuit, &line()).unwrap();
+        let mut expected = Circuit::new();
+        expected += SWAP::new(3, 2);
+        expected += SWAP::new(2, 1);
+        expected += CNOT::new(0, 1);
+        expected += SWAP::new(2, 1);
+        expected += SWAP::new(3, 2);
+        match &routed.circuit[1] {
+            Operation::PragmaConditional(op) => assert_eq!(op.circuit(), &expected),
+            operation => panic!("Expected a PragmaConditional, found {operation:?}"),
+        }
+        assert_eq!(routed.number_swaps, 4);
+        assert_eq!(routed.final_layout, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn repeated_measurement_keeps_readout_indices() {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 4, true);
+        circuit += PauliX::new(0);
+        circuit += PauliX::new(2);
+        circuit += CNOT::new(0, 3);
+        circuit += PragmaRepeatedMeasurement::new("ro".to_string(), 10, None);
+        let routed = route_circuit(&circuit, &line()).unwrap();
+        match &routed.circuit[routed.circuit.len() - 1] {
+            Operation::PragmaRepeatedMeasurement(op) => assert_eq!(
+                op.qubit_mapping(),
+                &Some(HashMap::from([(0, 0), (2, 1), (3, 2), (1, 3)]))
+            ),
+            operation => panic!("Expected a PragmaRepeatedMeasurement, found {operation:?}"),
+        }
+        let backend = StateVectorBackend::new(4);
+        let (original_bits, _, _) = backend.run_circuit(&circuit).unwrap();
+        let (routed_bits, _, _) = backend.run_circuit(&routed.circuit).unwrap();
+        assert_eq!(original_bits["ro"][0], vec![true, false, true, true]);
+        assert_eq!(routed_bits["ro"], original_bits["ro"]);
+    }
+}
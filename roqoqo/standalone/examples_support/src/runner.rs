@@ -0,0 +1,507 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Running serialized circuits and quantum programs.
+//!
+//! A [SerializedProgram] is either a `Circuit` or a `QuantumProgram` read from json, as written by
+//! `serde_json::to_string` in the serialization example or by `to_json` in qoqo.
+
+use num_complex::Complex64;
+use qoqo_calculator::Calculator;
+use roqoqo::backends::EvaluatingBackend;
+use roqoqo::operations::*;
+use roqoqo::registers::{BitOutputRegister, ComplexOutputRegister, FloatOutputRegister};
+use roqoqo::{Circuit, QuantumProgram, RoqoqoBackendError};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// A circuit or quantum program that has been deserialized from json.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SerializedProgram {
+    /// A single circuit, symbolic parameters are replaced by name.
+    Circuit(Circuit),
+    /// A quantum program with its list of input parameter names.
+    QuantumProgram(QuantumProgram),
+}
+
+/// The result of running a [SerializedProgram].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunResult {
+    /// The classical output registers of a circuit or a `ClassicalRegister` measurement.
+    Registers {
+        /// Bit registers, one entry per measurement.
+        bit_registers: HashMap<String, BitOutputRegister>,
+        /// Float registers.
+        float_registers: HashMap<String, FloatOutputRegister>,
+        /// Complex registers, e.g. state vectors.
+        complex_registers: HashMap<String, ComplexOutputRegister>,
+    },
+    /// The expectation values of an expectation value measurement.
+    ExpectationValues(HashMap<String, f64>),
+}
+
+impl SerializedProgram {
+    /// Deserializes a `QuantumProgram` or, if that fails, a `Circuit` from json.
+    pub fn from_json(json: &str) -> Result<Self, RoqoqoBackendError> {
+        let program_error = match serde_json::from_str::<QuantumProgram>(json) {
+            Ok(program) => return Ok(SerializedProgram::QuantumProgram(program)),
+            Err(err) => err,
+        };
+        serde_json::from_str::<Circuit>(json)
+            .map(SerializedProgram::Circuit)
+            .map_err(|err| RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "Input is neither a QuantumProgram ({}) nor a Circuit ({})",
+                    program_error, err
+                ),
+            })
+    }
+
+    /// Serializes the circuit or quantum program to json, the inverse of [SerializedProgram::from_json].
+    pub fn to_json(&self) -> Result<String, RoqoqoBackendError> {
+        match self {
+            SerializedProgram::Circuit(circuit) => serde_json::to_string(circuit),
+            SerializedProgram::QuantumProgram(program) => serde_json::to_string(program),
+        }
+        .map_err(|err| RoqoqoBackendError::GenericError {
+            msg: format!("Could not serialize the program: {}", err),
+        })
+    }
+
+    /// Returns the names of the input parameters of a quantum program in positional order.
+    ///
+    /// Symbolic parameters of a plain circuit are not listed, they are replaced by name when running.
+    ///
+    pub fn parameter_names(&self) -> Vec<String> {
+        match self {
+            SerializedProgram::Circuit(_) => Vec::new(),
+            SerializedProgram::QuantumProgram(program) => match program {
+                QuantumProgram::PauliZProduct {
+                    input_parameter_names,
+                    ..
+                }
+                | QuantumProgram::CheatedPauliZProduct {
+                    input_parameter_names,
+                    ..
+                }
+                | QuantumProgram::Cheated {
+                    input_parameter_names,
+                    ..
+                }
+                | QuantumProgram::ClassicalRegister {
+                    input_parameter_names,
+                    ..
+                } => input_parameter_names.clone(),
+                _ => Vec::new(),
+            },
+        }
+    }
+
+    /// Returns all circuits that are executed when running.
+    pub fn circuits(&self) -> Vec<&Circuit> {
+        match self {
+            SerializedProgram::Circuit(circuit) => vec![circuit],
+            SerializedProgram::QuantumProgram(program) => program_circuits(program),
+        }
+    }
+
+    /// Returns the number of qubits needed to run all circuits.
+    ///
+    /// Operations acting on all qubits, like `PragmaRepeatedMeasurement`, do not contribute.
+    ///
+    pub fn number_qubits(&self) -> usize {
+        self.circuits()
+            .iter()
+            .flat_map(|circuit| circuit.iter())
+            .filter_map(|operation| match operation.involved_qubits() {
+                InvolvedQubits::Set(qubits) => qubits.into_iter().max(),
+                _ => None,
+            })
+            .max()
+            .map_or(0, |qubit| qubit + 1)
+    }
+
+    /// Returns a copy where every readout is measured `number_measurements` times.
+    ///
+    /// See [set_number_measurements] for how the circuits are changed.
+    ///
+    pub fn with_number_measurements(&self, number_measurements: usize) -> Self {
+        match self {
+            SerializedProgram::Circuit(circuit) => {
+                SerializedProgram::Circuit(set_number_measurements(circuit, number_measurements))
+            }
+            SerializedProgram::QuantumProgram(program) => {
+                let mut program = program.clone();
+                for circuit in program_circuits_mut(&mut program) {
+                    *circuit = set_number_measurements(circuit, number_measurements);
+                }
+                SerializedProgram::QuantumProgram(program)
+            }
+        }
+    }
+
+    /// Runs the circuit or quantum program on a backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend the circuits are executed on.
+    /// * `parameters` - Values of the free parameters by name, see [SerializedProgram::parameter_names].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(RunResult)` - The output registers or the expectation values.
+    /// * `Err(RoqoqoBackendError)` - A parameter is missing or unknown, or running failed.
+    ///
+    pub fn run<T>(
+        &self,
+        backend: T,
+        parameters: &HashMap<String, f64>,
+    ) -> Result<RunResult, RoqoqoBackendError>
+    where
+        T: EvaluatingBackend,
+    {
+        let names = self.parameter_names();
+        let missing: Vec<&String> = names
+            .iter()
+            .filter(|name| !parameters.contains_key(*name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!("Missing values for parameters {:?}", missing),
+            });
+        }
+        let mut unknown: Vec<&String> = parameters
+            .keys()
+            .filter(|name| match self {
+                // A name is unknown if the circuit can be substituted without it
+                SerializedProgram::Circuit(circuit) => {
+                    let mut calculator = Calculator::new();
+                    for (other, value) in parameters.iter().filter(|(other, _)| other != name) {
+                        calculator.set_variable(other, *value);
+                    }
+                    circuit.substitute_parameters(&calculator).is_ok()
+                }
+                SerializedProgram::QuantumProgram(_) => !names.contains(name),
+            })
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort();
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!("Unknown parameters {:?}", unknown),
+            });
+        }
+        match self {
+            SerializedProgram::Circuit(circuit) => {
+                let mut calculator = Calculator::new();
+                for (name, value) in parameters {
+                    calculator.set_variable(name, *value);
+                }
+                let circuit = circuit.substitute_parameters(&calculator)?;
+                let (bit_registers, float_registers, complex_registers) =
+                    backend.run_circuit(&circuit)?;
+                Ok(RunResult::Registers {
+                    bit_registers,
+                    float_registers,
+                    complex_registers,
+                })
+            }
+            SerializedProgram::QuantumProgram(program) => {
+                let values: Vec<f64> = names.iter().map(|name| parameters[name]).collect();
+                if let QuantumProgram::ClassicalRegister { .. } = program {
+                    let (bit_registers, float_registers, complex_registers) =
+                        program.run_registers(backend, &values)?;
+                    Ok(RunResult::Registers {
+                        bit_registers,
+                        float_registers,
+                        complex_registers,
+                    })
+                } else {
+                    Ok(RunResult::ExpectationValues(
+                        program.run(backend, &values)?.unwrap_or_default(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl RunResult {
+    /// Reads a result written by [RunResult::to_json].
+    ///
+    /// An object of numbers is read as expectation values, an object with the three kinds of registers as registers.
+    ///
+    pub fn from_json(json: &str) -> Result<Self, RoqoqoBackendError> {
+        let error = |msg: String| RoqoqoBackendError::GenericError {
+            msg: format!("Invalid result json: {}", msg),
+        };
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|err| error(err.to_string()))?;
+        let object = value
+            .as_object()
+            .ok_or_else(|| error("expected an object".to_string()))?;
+        if object.values().all(serde_json::Value::is_number) {
+            return Ok(RunResult::ExpectationValues(
+                object
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.as_f64().unwrap_or_default()))
+                    .collect(),
+            ));
+        }
+        let register = |name: &str| {
+            object
+                .get(name)
+                .cloned()
+                .ok_or_else(|| error(format!("missing {}", name)))
+        };
+        let bit_registers = serde_json::from_value(register("bit_registers")?)
+            .map_err(|err| error(err.to_string()))?;
+        let float_registers = serde_json::from_value(register("float_registers")?)
+            .map_err(|err| error(err.to_string()))?;
+        let complex_registers: HashMap<String, Vec<Vec<[f64; 2]>>> =
+            serde_json::from_value(register("complex_registers")?)
+                .map_err(|err| error(err.to_string()))?;
+        Ok(RunResult::Registers {
+            bit_registers,
+            float_registers,
+            complex_registers: complex_registers
+                .into_iter()
+                .map(|(name, register)| {
+                    (
+                        name,
+                        register
+                            .into_iter()
+                            .map(|values| {
+                                values
+                                    .into_iter()
+                                    .map(|[re, im]| Complex64::new(re, im))
+                                    .collect()
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// Formats the result as json with sorted keys.
+    ///
+    /// Complex numbers are written as `[real, imaginary]` pairs.
+    ///
+    pub fn to_json(&self) -> String {
+        match self {
+            RunResult::Registers {
+                bit_registers,
+                float_registers,
+                complex_registers,
+            } => {
+                let complex_registers: BTreeMap<&String, Vec<Vec<[f64; 2]>>> = complex_registers
+                    .iter()
+                    .map(|(name, register)| {
+                        (
+                            name,
+                            register
+                                .iter()
+                                .map(|values| values.iter().map(|c| [c.re, c.im]).collect())
+                                .collect(),
+                        )
+                    })
+                    .collect();
+                serde_json::json!({
+                    "bit_registers": bit_registers.iter().collect::<BTreeMap<_, _>>(),
+                    "float_registers": float_registers.iter().collect::<BTreeMap<_, _>>(),
+                    "complex_registers": complex_registers,
+                })
+                .to_string()
+            }
+            RunResult::ExpectationValues(values) => {
+                serde_json::json!(values.iter().collect::<BTreeMap<_, _>>()).to_string()
+            }
+        }
+    }
+
+    /// Formats the result as csv.
+    ///
+    /// Registers are written as one row per register and measurement: `register,measurement,value_0,value_1,...`
+    /// with bits as `0`/`1` and complex numbers as `re+imi`. Expectation values are written as `name,value`.
+    ///
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        match self {
+            RunResult::Registers {
+                bit_registers,
+                float_registers,
+                complex_registers,
+            } => {
+                csv.push_str("register,measurement,values\n");
+                for (name, register) in bit_registers.iter().collect::<BTreeMap<_, _>>() {
+                    for (index, values) in register.iter().enumerate() {
+                        let values: Vec<&str> = values
+                            .iter()
+                            .map(|&bit| if bit { "1" } else { "0" })
+                            .collect();
+                        csv.push_str(&format!("{},{},{}\n", name, index, values.join(",")));
+                    }
+                }
+                for (name, register) in float_registers.iter().collect::<BTreeMap<_, _>>() {
+                    for (index, values) in register.iter().enumerate() {
+                        let values: Vec<String> = values.iter().map(f64::to_string).collect();
+                        csv.push_str(&format!("{},{},{}\n", name, index, values.join(",")));
+                    }
+                }
+                for (name, register) in complex_registers.iter().collect::<BTreeMap<_, _>>() {
+                    for (index, values) in register.iter().enumerate() {
+                        let values: Vec<String> = values.iter().map(|c| c.to_string()).collect();
+                        csv.push_str(&format!("{},{},{}\n", name, index, values.join(",")));
+                    }
+                }
+            }
+            RunResult::ExpectationValues(values) => {
+                csv.push_str("name,value\n");
+                for (name, value) in values.iter().collect::<BTreeMap<_, _>>() {
+                    csv.push_str(&format!("{},{}\n", name, value));
+                }
+            }
+        }
+        csv
+    }
+}
+
+/// Returns a copy of the circuit where every readout is measured `number_measurements` times.
+///
+/// The counts of `PragmaRepeatedMeasurement` and `PragmaSetNumberOfMeasurements` are replaced.
+/// Readouts that are only written by `MeasureQubit` get an additional `PragmaSetNumberOfMeasurements`.
+///
+pub fn set_number_measurements(circuit: &Circuit, number_measurements: usize) -> Circuit {
+    let mut new_circuit = Circuit::new();
+    let mut counted_readouts: HashSet<String> = HashSet::new();
+    let mut measured_readouts: Vec<String> = Vec::new();
+    for operation in circuit.iter() {
+        match operation {
+            Operation::PragmaRepeatedMeasurement(op) => {
+                counted_readouts.insert(op.readout().clone());
+                new_circuit += PragmaRepeatedMeasurement::new(
+                    op.readout().clone(),
+                    number_measurements,
+                    op.qubit_mapping().clone(),
+                );
+            }
+            Operation::PragmaSetNumberOfMeasurements(op) => {
+                counted_readouts.insert(op.readout().clone());
+                new_circuit +=
+                    PragmaSetNumberOfMeasurements::new(number_measurements, op.readout().clone());
+            }
+            Operation::MeasureQubit(op) => {
+                if !measured_readouts.contains(op.readout()) {
+                    measured_readouts.push(op.readout().clone());
+                }
+                new_circuit.add_operation(operation.clone());
+            }
+            _ => new_circuit.add_operation(operation.clone()),
+        }
+    }
+    for readout in measured_readouts {
+        if !counted_readouts.contains(&readout) {
+            new_circuit += PragmaSetNumberOfMeasurements::new(number_measurements, readout);
+        }
+    }
+    new_circuit
+}
+
+/// Returns the measurement circuits and the constant circuit of a quantum program.
+pub fn program_circuits(program: &QuantumProgram) -> Vec<&Circuit> {
+    let (constant_circuit, circuits) = match program {
+        QuantumProgram::PauliZProduct { measurement, .. } => {
+            (&measurement.constant_circuit, &measurement.circuits)
+        }
+        QuantumProgram::CheatedPauliZProduct { measurement, .. } => {
+            (&measurement.constant_circuit, &measurement.circuits)
+        }
+        QuantumProgram::Cheated { measurement, .. } => {
+            (&measurement.constant_circuit, &measurement.circuits)
+        }
+        QuantumProgram::ClassicalRegister { measurement, .. } => {
+            (&measurement.constant_circuit, &measurement.circuits)
+        }
+        _ => return Vec::new(),
+    };
+    constant_circuit.iter().chain(circuits.iter()).collect()
+}
+
+/// Returns mutable references to the measurement circuits and the constant circuit of a quantum program.
+pub fn program_circuits_mut(program: &mut QuantumProgram) -> Vec<&mut Circuit> {
+    let (constant_circuit, circuits) = match program {
+        QuantumProgram::PauliZProduct { measurement, .. } => {
+            (&mut measurement.constant_circuit, &mut measurement.circuits)
+        }
+        QuantumProgram::CheatedPauliZProduct { measurement, .. } => {
+            (&mut measurement.constant_circuit, &mut measurement.circuits)
+        }
+        QuantumProgram::Cheated { measurement, .. } => {
+            (&mut measurement.constant_circuit, &mut measurement.circuits)
+        }
+        QuantumProgram::ClassicalRegister { measurement, .. } => {
+            (&mut measurement.constant_circuit, &mut measurement.circuits)
+        }
+        _ => return Vec::new(),
+    };
+    constant_circuit
+        .iter_mut()
+        .chain(circuits.iter_mut())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_vector::StateVectorBackend;
+
+    fn rotation() -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 1, true);
+        circuit += RotateX::new(0, "theta".into());
+        circuit += MeasureQubit::new(0, "ro".to_string(), 0);
+        circuit += PragmaSetNumberOfMeasurements::new(10, "ro".to_string());
+        circuit
+    }
+
+    #[test]
+    fn invalid_json_reports_both_errors() {
+        let msg = SerializedProgram::from_json("{}").unwrap_err().to_string();
+        assert!(msg.contains("QuantumProgram ("));
+        assert!(msg.contains("Circuit ("));
+    }
+
+    #[test]
+    fn unknown_parameters_are_rejected() {
+        let theta = HashMap::from([("theta".to_string(), 0.5)]);
+        let mut phi = theta.clone();
+        phi.insert("phi".to_string(), 1.0);
+        let circuit = SerializedProgram::Circuit(rotation());
+        assert!(circuit.run(StateVectorBackend::new(1), &theta).is_ok());
+        let msg = circuit
+            .run(StateVectorBackend::new(1), &phi)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("Unknown parameters [\"phi\"]"));
+        let program = SerializedProgram::QuantumProgram(QuantumProgram::ClassicalRegister {
+            measurement: roqoqo::measurements::ClassicalRegister {
+                constant_circuit: None,
+                circuits: vec![rotation()],
+            },
+            input_parameter_names: vec!["theta".to_string()],
+        });
+        assert!(program.run(StateVectorBackend::new(1), &theta).is_ok());
+        assert!(program.run(StateVectorBackend::new(1), &phi).is_err());
+        assert!(program
+            .run(StateVectorBackend::new(1), &HashMap::new())
+            .is_err());
+    }
+}
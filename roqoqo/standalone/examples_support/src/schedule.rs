@@ -0,0 +1,404 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scheduling of circuits with the gate times of a device and estimates of the accumulated decoherence.
+//!
+//! [schedule_circuit] starts every operation as soon as all its qubits are free. Gates take the gate time the device
+//! returns for the gate on its qubits, measurements and resets take a fixed measurement time and all other pragmas
+//! except `PragmaSleep` take no time. Operations in a `PragmaConditional` wait for the measurement that writes the
+//! condition bit and are scheduled as if they were always executed, so the duration is the worst case.
+//!
+//! The decoherence rates of a device are the matrix of the Lindblad equation in the basis of the operators
+//! `sigma^+`, which brings `|1>` to `|0>`, `sigma^-` and `sigma^z`. A qubit relaxes with the rate `1/T1 = M[0,0] + M[1,1]` and loses its coherence
+//! with the rate `1/T2 = 1/(2 T1) + 2 M[2,2]` from the moment the circuit starts until its last operation.
+
+use crate::diagram::number_qubits;
+use crate::unitary;
+use roqoqo::devices::Device;
+use roqoqo::operations::*;
+use roqoqo::{Circuit, RoqoqoError};
+use std::collections::HashMap;
+
+/// An operation with its start time in a [Schedule].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledOperation {
+    /// The operation.
+    pub operation: Operation,
+    /// The time the operation starts.
+    pub start: f64,
+    /// The time the operation takes.
+    pub duration: f64,
+}
+
+impl ScheduledOperation {
+    /// Returns the time the operation ends.
+    pub fn end(&self) -> f64 {
+        self.start + self.duration
+    }
+}
+
+/// Timing and decoherence estimate of one qubit in a [Schedule].
+#[derive(Debug, Clone, PartialEq)]
+pub struct QubitTiming {
+    /// The qubit.
+    pub qubit: usize,
+    /// The time the qubit spends in gates and measurements.
+    pub busy_time: f64,
+    /// The end of the last operation on the qubit, the qubit decoheres from the start of the circuit until then.
+    pub lifetime: f64,
+    /// The relaxation rate 1/T1 of the qubit.
+    pub relaxation_rate: f64,
+    /// The dephasing rate 1/T2 of the qubit.
+    pub dephasing_rate: f64,
+}
+
+impl QubitTiming {
+    /// Returns the time the qubit waits for other qubits.
+    pub fn idle_time(&self) -> f64 {
+        self.lifetime - self.busy_time
+    }
+
+    /// Returns the probability that an excited qubit relaxes during its lifetime.
+    pub fn relaxation_probability(&self) -> f64 {
+        1.0 - (-self.relaxation_rate * self.lifetime).exp()
+    }
+
+    /// Returns the fraction of the coherence of the qubit that is lost during its lifetime.
+    pub fn coherence_loss(&self) -> f64 {
+        1.0 - (-self.dephasing_rate * self.lifetime).exp()
+    }
+}
+
+/// A circuit scheduled on a device with [schedule_circuit].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    /// The operations of the circuit with their start times, nested circuits are unrolled.
+    pub operations: Vec<ScheduledOperation>,
+    /// The indices of the operations on the longest chain of operations waiting for each other.
+    pub critical_path: Vec<usize>,
+    /// The timing of every qubit used in the circuit.
+    pub qubits: Vec<QubitTiming>,
+}
+
+impl Schedule {
+    /// Returns the duration of the circuit, the end of the critical path.
+    pub fn duration(&self) -> f64 {
+        self.critical_path
+            .last()
+            .map(|index| self.operations[*index].end())
+            .unwrap_or(0.0)
+    }
+
+    /// Returns the estimated fraction of the coherence that survives on all qubits.
+    pub fn estimated_coherence(&self) -> f64 {
+        self.qubits
+            .iter()
+            .map(|timing| 1.0 - timing.coherence_loss())
+            .product()
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path: Vec<String> = self
+            .critical_path
+            .iter()
+            .map(|index| describe(&self.operations[*index].operation))
+            .collect();
+        writeln!(
+            f,
+            "duration {:.3}, critical path: {}",
+            self.duration(),
+            path.join(" -> ")
+        )?;
+        writeln!(
+            f,
+            "{:>6} | {:>8} | {:>8} | {:>8} | {:>8} | {:>8} | {:>10} | {:>10}",
+            "qubit", "busy", "idle", "lifetime", "T1", "T2", "relaxation", "coh. loss"
+        )?;
+        for timing in self.qubits.iter() {
+            writeln!(
+                f,
+                "{:>6} | {:>8.3} | {:>8.3} | {:>8.3} | {:>8.1} | {:>8.1} | {:>10.2e} | {:>10.2e}",
+                timing.qubit,
+                timing.busy_time,
+                timing.idle_time(),
+                timing.lifetime,
+                1.0 / timing.relaxation_rate,
+                1.0 / timing.dephasing_rate,
+                timing.relaxation_probability(),
+                timing.coherence_loss()
+            )?;
+        }
+        write!(
+            f,
+            "estimated coherence of all qubits {:.4}",
+            self.estimated_coherence()
+        )
+    }
+}
+
+/// The time from which on a qubit or bit is available and the operation it waits for.
+#[derive(Debug, Clone, Copy, Default)]
+struct Ready {
+    time: f64,
+    operation: Option<usize>,
+}
+
+impl Ready {
+    fn later(self, other: Ready) -> Ready {
+        if other.time > self.time {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+/// State shared while scheduling a circuit and the circuits nested in it.
+struct Scheduler<'a, T: Device> {
+    device: &'a T,
+    measurement_time: f64,
+    number_qubits: usize,
+    operations: Vec<ScheduledOperation>,
+    predecessors: Vec<Option<usize>>,
+    qubits: Vec<Ready>,
+    bits: HashMap<(String, usize), Ready>,
+    busy_time: Vec<f64>,
+}
+
+/// Schedules a circuit on a device and estimates the decoherence of its qubits.
+///
+/// # Arguments
+///
+/// * `circuit` - The circuit. All gates need to be available on the device, decompose the circuit first.
+/// * `device` - The device providing the gate times and decoherence rates.
+/// * `measurement_time` - The time of `MeasureQubit`, `PragmaRepeatedMeasurement` and `PragmaActiveReset`.
+///
+/// # Returns
+///
+/// * `Ok(Schedule)` - The scheduled operations with the critical path and the timing of every qubit.
+/// * `Err(RoqoqoError)` - A gate is not available on its qubits of the device.
+///
+pub fn schedule_circuit<T: Device>(
+    circuit: &Circuit,
+    device: &T,
+    measurement_time: f64,
+) -> Result<Schedule, RoqoqoError> {
+    let number_qubits = number_qubits(circuit);
+    if number_qubits > device.number_qubits() {
+        return Err(RoqoqoError::GenericError {
+            msg: format!(
+                "Circuit uses {} qubits but the device only has {}",
+                number_qubits,
+                device.number_qubits()
+            ),
+        });
+    }
+    let mut scheduler = Scheduler {
+        device,
+        measurement_time,
+        number_qubits,
+        operations: Vec::new(),
+        predecessors: Vec::new(),
+        qubits: vec![Ready::default(); number_qubits],
+        bits: HashMap::new(),
+        busy_time: vec![0.0; number_qubits],
+    };
+    scheduler.schedule(circuit, Ready::default())?;
+
+    let mut critical_path = Vec::new();
+    let mut last = (0..scheduler.operations.len()).max_by(|first, second| {
+        scheduler.operations[*first]
+            .end()
+            .total_cmp(&scheduler.operations[*second].end())
+    });
+    while let Some(index) = last {
+        critical_path.push(index);
+        last = scheduler.predecessors[index];
+    }
+    critical_path.reverse();
+
+    let qubits = (0..number_qubits)
+        .map(|qubit| {
+            let rates = device.qubit_decoherence_rates(&qubit);
+            let rate = |row: usize, column: usize| {
+                rates.as_ref().map_or(0.0, |rates| rates[[row, column]])
+            };
+            let relaxation_rate = rate(0, 0) + rate(1, 1);
+            QubitTiming {
+                qubit,
+                busy_time: scheduler.busy_time[qubit],
+                lifetime: scheduler.qubits[qubit].time,
+                relaxation_rate,
+                dephasing_rate: relaxation_rate / 2.0 + 2.0 * rate(2, 2),
+            }
+        })
+        .collect();
+    Ok(Schedule {
+        operations: scheduler.operations,
+        critical_path,
+        qubits,
+    })
+}
+
+impl<T: Device> Scheduler<'_, T> {
+    fn schedule(&mut self, circuit: &Circuit, condition: Ready) -> Result<(), RoqoqoError> {
+        for operation in circuit.iter() {
+            match operation {
+                Operation::PragmaConditional(op) => {
+                    let bit = (op.condition_register().clone(), *op.condition_index());
+                    let ready = self.bits.get(&bit).copied().unwrap_or_default();
+                    self.schedule(op.circuit(), condition.later(ready))?;
+                }
+                Operation::PragmaLoop(op) => {
+                    for _ in 0..(*op.repetitions().float()? as usize) {
+                        self.schedule(op.circuit(), condition)?;
+                    }
+                }
+                _ => {
+                    let qubits: Vec<usize> = match operation.involved_qubits() {
+                        InvolvedQubits::Set(qubits) => qubits.into_iter().collect(),
+                        InvolvedQubits::All => (0..self.number_qubits).collect(),
+                        InvolvedQubits::None => continue,
+                    };
+                    let ready = qubits
+                        .iter()
+                        .fold(condition, |ready, qubit| ready.later(self.qubits[*qubit]));
+                    let duration = self.duration(operation)?;
+                    let index = self.operations.len();
+                    let done = Ready {
+                        time: ready.time + duration,
+                        operation: Some(index),
+                    };
+                    for qubit in qubits {
+                        self.qubits[qubit] = done;
+                        self.busy_time[qubit] += duration;
+                    }
+                    if let Operation::MeasureQubit(op) = operation {
+                        self.bits
+                            .insert((op.readout().clone(), *op.readout_index()), done);
+                    }
+                    self.operations.push(ScheduledOperation {
+                        operation: operation.clone(),
+                        start: ready.time,
+                        duration,
+                    });
+                    self.predecessors.push(ready.operation);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn duration(&self, operation: &Operation) -> Result<f64, RoqoqoError> {
+        match operation {
+            Operation::MeasureQubit(_)
+            | Operation::PragmaRepeatedMeasurement(_)
+            | Operation::PragmaActiveReset(_) => Ok(self.measurement_time),
+            Operation::PragmaSleep(op) => Ok(*op.sleep_time().float()?),
+            _ if !unitary::is_gate(operation) => Ok(0.0),
+            _ => gate_time(self.device, operation),
+        }
+    }
+}
+
+/// Returns the time a gate takes on the device.
+///
+/// # Returns
+///
+/// * `Ok(f64)` - The gate time.
+/// * `Err(RoqoqoError)` - The gate is not available on its qubits of the device.
+///
+pub(crate) fn gate_time<T: Device>(device: &T, operation: &Operation) -> Result<f64, RoqoqoError> {
+    let gate_time = if let Ok(gate) = SingleQubitGateOperation::try_from(operation) {
+        device.single_qubit_gate_time(gate.hqslang(), gate.qubit())
+    } else if let Ok(gate) = TwoQubitGateOperation::try_from(operation) {
+        device.two_qubit_gate_time(gate.hqslang(), gate.control(), gate.target())
+    } else if let Ok(gate) = ThreeQubitGateOperation::try_from(operation) {
+        device.three_qubit_gate_time(
+            gate.hqslang(),
+            gate.control_0(),
+            gate.control_1(),
+            gate.target(),
+        )
+    } else if let Ok(gate) = MultiQubitGateOperation::try_from(operation) {
+        device.multi_qubit_gate_time(gate.hqslang(), gate.qubits())
+    } else {
+        None
+    };
+    gate_time.ok_or_else(|| RoqoqoError::GenericError {
+        msg: format!("{} is not available on the device", describe(operation)),
+    })
+}
+
+/// Short description of an operation with the qubits it acts on.
+pub(crate) fn describe(operation: &Operation) -> String {
+    match operation.involved_qubits() {
+        InvolvedQubits::Set(qubits) => {
+            let mut qubits: Vec<usize> = qubits.into_iter().collect();
+            qubits.sort_unstable();
+            let qubits: Vec<String> = qubits.iter().map(|qubit| qubit.to_string()).collect();
+            format!("{}({})", operation.hqslang(), qubits.join(","))
+        }
+        _ => operation.hqslang().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roqoqo::devices::AllToAllDevice;
+
+    #[test]
+    fn two_gates_on_all_to_all_device() {
+        let (damping, dephasing) = (0.02, 0.05);
+        let device = AllToAllDevice::new(2, &["RotateX".to_string()], &["CNOT".to_string()], 1.0)
+            .set_all_single_qubit_gate_times("RotateX", 0.1)
+            .set_all_two_qubit_gate_times("CNOT", 0.5)
+            .add_damping_all(damping)
+            .add_dephasing_all(dephasing);
+        let mut circuit = Circuit::new();
+        circuit += RotateX::new(0, 1.0.into());
+        circuit += CNOT::new(0, 1);
+        let schedule = schedule_circuit(&circuit, &device, 1.0).unwrap();
+
+        assert_eq!(schedule.critical_path, vec![0, 1]);
+        assert_eq!(schedule.operations[1].start, 0.1);
+        assert!((schedule.duration() - 0.6).abs() < 1e-12);
+        let (first, second) = (&schedule.qubits[0], &schedule.qubits[1]);
+        assert!(first.idle_time().abs() < 1e-12);
+        assert!((second.idle_time() - 0.1).abs() < 1e-12);
+        for timing in [first, second] {
+            assert!((timing.lifetime - 0.6).abs() < 1e-12);
+            assert!(
+                (timing.relaxation_probability() - (1.0 - (-damping * 0.6_f64).exp())).abs()
+                    < 1e-12
+            );
+            // add_dephasing stores half the dephasing rate at [2, 2]
+            let dephasing_rate = damping / 2.0 + dephasing;
+            assert!(
+                (timing.coherence_loss() - (1.0 - (-dephasing_rate * 0.6_f64).exp())).abs() < 1e-12
+            );
+        }
+        assert!(schedule.to_string().contains("coh. loss"));
+    }
+
+    #[test]
+    fn unavailable_gate() {
+        let device = AllToAllDevice::new(2, &["RotateX".to_string()], &["CNOT".to_string()], 1.0);
+        let mut circuit = Circuit::new();
+        circuit += Hadamard::new(0);
+        let error = schedule_circuit(&circuit, &device, 1.0).unwrap_err();
+        assert!(error.to_string().contains("Hadamard(0)"));
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The global seed that makes the sampled results of the examples reproducible.
+//!
+//! The seed is passed to an example with the command line flag `--seed <seed>` (or `--seed=<seed>`) or with the
+//! environment variable `ROQOQO_EXAMPLES_SEED`, the flag takes precedence:
+//!
+//! ```bash
+//! cargo run --release -- --seed 7
+//! ROQOQO_EXAMPLES_SEED=7 cargo run --release
+//! ```
+//!
+//! The examples and binaries read the seed with [parse_seed] and pass it to [set_global_seed] before they run
+//! anything, the library itself never looks at the command line.
+//!
+//! Without a global seed every run of the simulators draws fresh random numbers. With a global seed the simulators of
+//! examples_support take the random number generator of each run from [rng], which derives it from the global seed
+//! and the number of generators handed out before. A run only takes a generator when it samples, so deterministic runs
+//! like the ones of `PragmaGetStateVector` or `PragmaGetPauliProduct` leave the following shots unchanged. Sections of
+//! an example, jobs and batches run inside their own [with_stream], so rerunning an example with the same seed gives
+//! bit-identical `BitOutputRegister`s. Sampling done in Rust by the examples themselves, like drawing readout errors,
+//! uses [rng_or] with the fixed seed it used before. QuEST can not be seeded from roqoqo-quest, so
+//! [Backend](crate::backend::Backend) runs the simulators written in Rust instead of QuEST while a global seed is set.
+//! Runs that compare QuEST itself with the simulators of examples_support, like the cross-checks of the intro example,
+//! stay random.
+
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+/// The command line flag setting the global seed.
+pub const SEED_FLAG: &str = "--seed";
+
+/// The environment variable setting the global seed.
+pub const SEED_VARIABLE: &str = "ROQOQO_EXAMPLES_SEED";
+
+/// The global seed set with [set_global_seed].
+static GLOBAL_SEED: RwLock<Option<u64>> = RwLock::new(None);
+
+/// The number of random number generators handed out by [rng].
+static NUMBER_RNGS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The stream set by [with_stream] on this thread and the number of generators handed out from it.
+    static STREAM: Cell<Option<(u64, u64)>> = const { Cell::new(None) };
+}
+
+/// Returns the global seed of the example, `None` while no seed is set.
+pub fn global_seed() -> Option<u64> {
+    *GLOBAL_SEED
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Sets the global seed of the example, `None` removes it.
+///
+/// # Arguments
+///
+/// * `seed` - The new global seed.
+pub fn set_global_seed(seed: Option<u64>) {
+    *GLOBAL_SEED
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = seed;
+}
+
+/// Parses the global seed from the command line arguments and the value of the environment variable.
+///
+/// # Arguments
+///
+/// * `arguments` - The command line arguments without the name of the program.
+/// * `variable` - The value of [SEED_VARIABLE], `None` if it is not set.
+///
+/// # Returns
+///
+/// * `Ok(Option<u64>)` - The seed of the flag, otherwise the one of the variable, `None` if neither is set.
+/// * `Err(String)` - The seed is not an unsigned 64-bit integer.
+pub fn parse_seed(arguments: &[String], variable: Option<String>) -> Result<Option<u64>, String> {
+    let value = match seed_argument(arguments)? {
+        Some(value) => value.to_string(),
+        None => match variable {
+            Some(value) => value,
+            None => return Ok(None),
+        },
+    };
+    value.trim().parse().map(Some).map_err(|_| {
+        format!(
+            "The seed {:?} set with {} or {} is not an unsigned 64-bit integer",
+            value, SEED_FLAG, SEED_VARIABLE
+        )
+    })
+}
+
+/// Returns the value of the seed flag in a list of command line arguments.
+///
+/// # Arguments
+///
+/// * `arguments` - The command line arguments without the name of the program.
+fn seed_argument(arguments: &[String]) -> Result<Option<&str>, String> {
+    for (index, argument) in arguments.iter().enumerate() {
+        if argument == SEED_FLAG {
+            return arguments
+                .get(index + 1)
+                .map(|value| Some(value.as_str()))
+                .ok_or_else(|| format!("Missing value for {}", SEED_FLAG));
+        }
+        if let Some(value) = argument
+            .strip_prefix(SEED_FLAG)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the random number generator for the next run of a simulator.
+///
+/// With a global seed the generators are derived from the seed and the number of generators handed out before,
+/// otherwise they are seeded from the operating system. Inside [with_stream] only the generators handed out by the
+/// stream on the current thread are counted.
+pub fn rng() -> StdRng {
+    match global_seed() {
+        Some(seed) => match STREAM.get() {
+            Some((stream, index)) => {
+                STREAM.set(Some((stream, index + 1)));
+                StdRng::seed_from_u64(mix(mix(seed, stream), index))
+            }
+            None => {
+                let index = NUMBER_RNGS.fetch_add(1, Ordering::Relaxed);
+                StdRng::seed_from_u64(mix(seed, index))
+            }
+        },
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Runs a closure with its own stream of random number generators on the current thread.
+///
+/// The generators that [rng] hands out inside the closure depend only on the global seed, `stream` and their order
+/// inside the closure. Runs on several threads stay reproducible when each of them has its own stream, no matter in
+/// which order the threads run.
+///
+/// # Arguments
+///
+/// * `stream` - The number of the stream.
+/// * `f` - The closure running the simulators.
+pub fn with_stream<T>(stream: u64, f: impl FnOnce() -> T) -> T {
+    let previous = STREAM.replace(Some((stream, 0)));
+    let result = f();
+    STREAM.set(previous);
+    result
+}
+
+/// Returns a random number generator for sampling with a fixed seed.
+///
+/// Without a global seed the generator is seeded with `default_seed`. With a global seed it is seeded with a
+/// combination of both, so changing the global seed changes the samples while every site sampling with its own
+/// default seed still draws independent numbers.
+///
+/// # Arguments
+///
+/// * `default_seed` - The seed used when no global seed is set.
+pub fn rng_or(default_seed: u64) -> StdRng {
+    match global_seed() {
+        Some(seed) => StdRng::seed_from_u64(mix(seed, default_seed)),
+        None => StdRng::seed_from_u64(default_seed),
+    }
+}
+
+/// Returns the random number generator of a simulator run with an optional seed of the backend.
+///
+/// A backend with its own seed draws the same numbers in every run, a backend without one uses [rng].
+pub(crate) fn backend_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => rng(),
+    }
+}
+
+/// The random number generator of a simulator run, taken from [backend_rng] when the run first samples.
+///
+/// Runs that never sample do not take a generator, so they do not change the generators of the following runs.
+pub(crate) struct LazyRng {
+    seed: Option<u64>,
+    rng: Option<StdRng>,
+}
+
+impl LazyRng {
+    /// Creates the generator of a run with an optional seed of the backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed of the backend, `None` uses [rng].
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        Self { seed, rng: None }
+    }
+
+    /// Returns the generator, taking it on the first call.
+    fn get(&mut self) -> &mut StdRng {
+        let seed = self.seed;
+        self.rng.get_or_insert_with(|| backend_rng(seed))
+    }
+}
+
+impl RngCore for LazyRng {
+    fn next_u32(&mut self) -> u32 {
+        self.get().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.get().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.get().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.get().try_fill_bytes(dest)
+    }
+}
+
+/// Serializes the tests that set the global seed or sample without a seed.
+#[cfg(test)]
+static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Sets the global seed in a test until the guard is dropped, which restores the previous seed.
+///
+/// The guard holds a lock shared by all tests that set the global seed or sample without a seed, so tests running in
+/// parallel never see the seed of another test.
+#[cfg(test)]
+pub(crate) struct GlobalSeedGuard {
+    previous: Option<u64>,
+    _lock: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl GlobalSeedGuard {
+    /// Locks the global seed and sets it.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The global seed while the guard lives.
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        let lock = TEST_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let previous = global_seed();
+        set_global_seed(seed);
+        Self {
+            previous,
+            _lock: lock,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for GlobalSeedGuard {
+    fn drop(&mut self) {
+        set_global_seed(self.previous);
+    }
+}
+
+/// Combines two numbers into one seed with the finaliser of the SplitMix64 generator.
+fn mix(seed: u64, stream: u64) -> u64 {
+    let mut z = seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Backend;
+    use roqoqo::backends::EvaluatingBackend;
+    use roqoqo::measurements::ClassicalRegister;
+    use roqoqo::operations as ops;
+    use roqoqo::{Circuit, QuantumProgram};
+
+    #[test]
+    fn seeded_program_runs_are_bit_identical() {
+        let _seed = GlobalSeedGuard::new(Some(7));
+        let mut circuit = Circuit::new();
+        circuit += ops::DefinitionBit::new("ro".to_string(), 3, true);
+        circuit += ops::Hadamard::new(0);
+        circuit += ops::CNOT::new(0, 1);
+        circuit += ops::RotateX::new(2, 1.0.into());
+        circuit += ops::PragmaRepeatedMeasurement::new("ro".to_string(), 100, None);
+        let program = QuantumProgram::ClassicalRegister {
+            measurement: ClassicalRegister {
+                constant_circuit: None,
+                circuits: vec![circuit],
+            },
+            input_parameter_names: vec![],
+        };
+        let mut state_circuit = Circuit::new();
+        state_circuit += ops::DefinitionComplex::new("state".to_string(), 8, true);
+        state_circuit += ops::Hadamard::new(0);
+        state_circuit += ops::PragmaGetStateVector::new("state".to_string(), None);
+
+        let run = |deterministic_runs: usize| {
+            with_stream(1, || {
+                for _ in 0..deterministic_runs {
+                    Backend::new(3).run_circuit(&state_circuit).unwrap();
+                }
+                program.run_registers(Backend::new(3), &[]).unwrap().0
+            })
+        };
+        let first = run(0);
+        assert_eq!(first["ro"].len(), 100);
+        assert_eq!(first, run(0));
+        // Runs that sample nothing do not take a generator from the stream
+        assert_eq!(first, run(2));
+    }
+}
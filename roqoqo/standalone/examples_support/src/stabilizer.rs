@@ -0,0 +1,771 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A stabilizer simulator for Clifford circuits, implementing the roqoqo `EvaluatingBackend`.
+//!
+//! [StabilizerBackend] stores the stabilizer tableau of Aaronson and Gottesman (CHP) instead of the state vector. A
+//! gate costs `O(n)` and a measurement `O(n^2)` operations for `n` qubits, so Clifford circuits with hundreds of
+//! qubits can be simulated. Every gate is reduced by [clifford_decomposition] to `Hadamard`, `SGate`, `InvSGate`, the
+//! Pauli gates and `CNOT`. Rotations are Clifford gates when their angle is a multiple of `pi/2`, other angles and
+//! gates like `TGate` or `Toffoli` are rejected with an error naming the gate.
+//!
+//! Besides the measurements and the classical control flow of the
+//! [StateVectorBackend](crate::state_vector::StateVectorBackend), `PragmaGetPauliProduct` is supported. The
+//! expectation value of a Pauli product in a stabilizer state is `+1`, `-1` or `0`. Readouts of the full state, like
+//! `PragmaGetStateVector`, are not available.
+
+use crate::seed;
+use crate::state_vector::{plan_measurements, FinalSampling};
+use crate::unitary;
+use core::f64::consts::FRAC_PI_2;
+use rand::prelude::*;
+use roqoqo::backends::{EvaluatingBackend, RegisterResult};
+use roqoqo::operations::*;
+use roqoqo::registers::{
+    BitOutputRegister, BitRegister, ComplexOutputRegister, FloatOutputRegister, FloatRegister,
+};
+use roqoqo::{Circuit, RoqoqoBackendError};
+use std::collections::{HashMap, HashSet};
+
+/// The name of the backend in `OperationNotInBackend` errors.
+const BACKEND_NAME: &str = "StabilizerBackend";
+
+/// Rotation angles closer than this to a multiple of `pi/2` are Clifford gates.
+const ANGLE_TOLERANCE: f64 = 1e-10;
+
+/// Simulates Clifford circuits with a stabilizer tableau of `number_qubits` qubits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StabilizerBackend {
+    /// Number of qubits supported by the backend.
+    pub number_qubits: usize,
+    /// The seed of the random number generator of every run, `None` uses [crate::seed::rng].
+    pub seed: Option<u64>,
+}
+
+impl StabilizerBackend {
+    /// Creates a new stabilizer backend.
+    ///
+    /// # Arguments
+    ///
+    /// `number_qubits` - The number of qubits supported by the backend.
+    pub fn new(number_qubits: usize) -> Self {
+        Self {
+            number_qubits,
+            seed: None,
+        }
+    }
+
+    /// Sets the seed of the random number generator.
+    ///
+    /// Every run of the backend with a seed draws the same random numbers, so running a circuit twice gives the same
+    /// measurement results. Without a seed the runs draw from [crate::seed::rng], which follows the global seed.
+    ///
+    /// # Arguments
+    ///
+    /// `seed` - The seed of the random number generator.
+    pub fn set_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl EvaluatingBackend for StabilizerBackend {
+    fn run_circuit_iterator<'a>(
+        &self,
+        circuit: impl Iterator<Item = &'a Operation>,
+    ) -> RegisterResult {
+        let (operations, final_sampling, repetitions) =
+            plan_measurements(circuit.collect::<Vec<&Operation>>())?;
+        let mut rng = seed::LazyRng::new(self.seed);
+
+        let mut bit_outputs: HashMap<String, BitOutputRegister> = HashMap::new();
+        let mut float_outputs: HashMap<String, FloatOutputRegister> = HashMap::new();
+        for operation in operations.iter() {
+            match operation {
+                Operation::DefinitionBit(def) if *def.is_output() => {
+                    bit_outputs.insert(def.name().clone(), Vec::new());
+                }
+                Operation::DefinitionFloat(def) if *def.is_output() => {
+                    float_outputs.insert(def.name().clone(), Vec::new());
+                }
+                _ => (),
+            }
+        }
+
+        for _ in 0..repetitions {
+            let mut simulation = Simulation::new(self.number_qubits, &mut rng);
+            for operation in operations.iter() {
+                simulation.apply(operation, &mut bit_outputs)?;
+            }
+            if let Some(sampling) = &final_sampling {
+                simulation.sample_final_measurements(sampling, &mut bit_outputs)?;
+            }
+            for (name, register) in bit_outputs.iter_mut() {
+                if !simulation.sampled_readouts.contains(name) {
+                    if let Some(bits) = simulation.bit_registers.remove(name) {
+                        register.push(bits);
+                    }
+                }
+            }
+            for (name, register) in float_outputs.iter_mut() {
+                if let Some(values) = simulation.float_registers.remove(name) {
+                    register.push(values);
+                }
+            }
+        }
+        Ok((
+            bit_outputs,
+            float_outputs,
+            HashMap::<String, ComplexOutputRegister>::new(),
+        ))
+    }
+}
+
+/// Decomposes a Clifford gate into `Hadamard`, `SGate`, `InvSGate`, `PauliX`, `PauliY`, `PauliZ` and `CNOT` gates.
+///
+/// The decomposition is equal to the gate up to a global phase.
+///
+/// # Returns
+///
+/// * `Ok(Circuit)` - The decomposed gate.
+/// * `Err(RoqoqoBackendError)` - The operation is not a Clifford gate or has a symbolic parameter.
+///
+pub fn clifford_decomposition(operation: &Operation) -> Result<Circuit, RoqoqoBackendError> {
+    let mut circuit = Circuit::new();
+    match operation {
+        Operation::Identity(_) => (),
+        Operation::Hadamard(_)
+        | Operation::SGate(_)
+        | Operation::InvSGate(_)
+        | Operation::PauliX(_)
+        | Operation::PauliY(_)
+        | Operation::PauliZ(_)
+        | Operation::CNOT(_) => circuit.add_operation(operation.clone()),
+        Operation::SqrtPauliX(op) => circuit += rotate_x(*op.qubit(), 1),
+        Operation::SXGate(op) => circuit += rotate_x(*op.qubit(), 1),
+        Operation::InvSqrtPauliX(op) => circuit += rotate_x(*op.qubit(), 3),
+        Operation::InvSXGate(op) => circuit += rotate_x(*op.qubit(), 3),
+        Operation::SqrtPauliY(op) => circuit += rotate_y(*op.qubit(), 1),
+        Operation::InvSqrtPauliY(op) => circuit += rotate_y(*op.qubit(), 3),
+        Operation::RotateX(op) => {
+            circuit += rotate_x(*op.qubit(), quarter_turns(operation, op.theta())?)
+        }
+        Operation::RotateY(op) => {
+            circuit += rotate_y(*op.qubit(), quarter_turns(operation, op.theta())?)
+        }
+        Operation::RotateZ(op) => {
+            circuit += rotate_z(*op.qubit(), quarter_turns(operation, op.theta())?)
+        }
+        Operation::PhaseShiftState1(op) => {
+            circuit += rotate_z(*op.qubit(), quarter_turns(operation, op.theta())?)
+        }
+        Operation::PhaseShiftState0(op) => {
+            circuit += rotate_z(*op.qubit(), 4 - quarter_turns(operation, op.theta())?)
+        }
+        Operation::ControlledPauliZ(op) => {
+            circuit += Hadamard::new(*op.target());
+            circuit += CNOT::new(*op.control(), *op.target());
+            circuit += Hadamard::new(*op.target());
+        }
+        Operation::ControlledPauliY(op) => {
+            circuit += InvSGate::new(*op.target());
+            circuit += CNOT::new(*op.control(), *op.target());
+            circuit += SGate::new(*op.target());
+        }
+        Operation::SWAP(op) => {
+            circuit += CNOT::new(*op.control(), *op.target());
+            circuit += CNOT::new(*op.target(), *op.control());
+            circuit += CNOT::new(*op.control(), *op.target());
+        }
+        // A controlled Z, a SWAP and a phase of i on both qubits
+        Operation::ISwap(op) => {
+            circuit += SGate::new(*op.control());
+            circuit += SGate::new(*op.target());
+            circuit += Hadamard::new(*op.target());
+            circuit += CNOT::new(*op.control(), *op.target());
+            circuit += Hadamard::new(*op.target());
+            circuit += CNOT::new(*op.control(), *op.target());
+            circuit += CNOT::new(*op.target(), *op.control());
+            circuit += CNOT::new(*op.control(), *op.target());
+        }
+        _ if unitary::is_gate(operation) => {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "{} is not a Clifford gate and can not be simulated by the stabilizer backend",
+                    operation.hqslang()
+                ),
+            })
+        }
+        _ => {
+            return Err(RoqoqoBackendError::OperationNotInBackend {
+                backend: BACKEND_NAME,
+                hqslang: operation.hqslang(),
+            })
+        }
+    }
+    Ok(circuit)
+}
+
+/// Returns the number of quarter turns `k` in `0..4` of a rotation angle `k pi/2`.
+fn quarter_turns(
+    operation: &Operation,
+    theta: &qoqo_calculator::CalculatorFloat,
+) -> Result<usize, RoqoqoBackendError> {
+    let theta = *theta.float()?;
+    let turns = (theta / FRAC_PI_2).round();
+    if (theta - turns * FRAC_PI_2).abs() > ANGLE_TOLERANCE {
+        return Err(RoqoqoBackendError::GenericError {
+            msg: format!(
+                "{} with angle {} is not a Clifford gate and can not be simulated by the stabilizer backend, \
+                 the angle has to be a multiple of pi/2",
+                operation.hqslang(),
+                theta
+            ),
+        });
+    }
+    Ok((turns as i64).rem_euclid(4) as usize)
+}
+
+fn rotate_z(qubit: usize, quarter_turns: usize) -> Circuit {
+    let mut circuit = Circuit::new();
+    match quarter_turns % 4 {
+        1 => circuit += SGate::new(qubit),
+        2 => circuit += PauliZ::new(qubit),
+        3 => circuit += InvSGate::new(qubit),
+        _ => (),
+    }
+    circuit
+}
+
+fn rotate_x(qubit: usize, quarter_turns: usize) -> Circuit {
+    match quarter_turns % 4 {
+        0 => Circuit::new(),
+        2 => Circuit::new() + PauliX::new(qubit),
+        turns => {
+            Circuit::new() + Hadamard::new(qubit) + rotate_z(qubit, turns) + Hadamard::new(qubit)
+        }
+    }
+}
+
+fn rotate_y(qubit: usize, quarter_turns: usize) -> Circuit {
+    match quarter_turns % 4 {
+        1 => Circuit::new() + Hadamard::new(qubit) + PauliX::new(qubit),
+        2 => Circuit::new() + PauliY::new(qubit),
+        3 => Circuit::new() + PauliX::new(qubit) + Hadamard::new(qubit),
+        _ => Circuit::new(),
+    }
+}
+
+/// The stabilizer tableau of Aaronson and Gottesman.
+///
+/// Rows `0..n` are the destabilizers, rows `n..2n` the stabilizers and row `2n` is scratch space. The x and z bits of
+/// every row are packed into 64-bit words.
+#[derive(Debug, Clone)]
+struct Tableau {
+    number_qubits: usize,
+    words: usize,
+    x: Vec<u64>,
+    z: Vec<u64>,
+    phase: Vec<bool>,
+}
+
+impl Tableau {
+    /// Creates the tableau of the state |0...0>.
+    fn new(number_qubits: usize) -> Self {
+        let words = number_qubits.div_ceil(64).max(1);
+        let rows = 2 * number_qubits + 1;
+        let mut tableau = Tableau {
+            number_qubits,
+            words,
+            x: vec![0; rows * words],
+            z: vec![0; rows * words],
+            phase: vec![false; rows],
+        };
+        for qubit in 0..number_qubits {
+            tableau.x[qubit * words + qubit / 64] |= 1 << (qubit % 64);
+            tableau.z[(qubit + number_qubits) * words + qubit / 64] |= 1 << (qubit % 64);
+        }
+        tableau
+    }
+
+    fn x(&self, row: usize, qubit: usize) -> bool {
+        self.x[row * self.words + qubit / 64] >> (qubit % 64) & 1 == 1
+    }
+
+    fn z(&self, row: usize, qubit: usize) -> bool {
+        self.z[row * self.words + qubit / 64] >> (qubit % 64) & 1 == 1
+    }
+
+    fn set(&mut self, row: usize, qubit: usize, x: bool, z: bool) {
+        let (index, mask) = (row * self.words + qubit / 64, 1 << (qubit % 64));
+        self.x[index] = if x {
+            self.x[index] | mask
+        } else {
+            self.x[index] & !mask
+        };
+        self.z[index] = if z {
+            self.z[index] | mask
+        } else {
+            self.z[index] & !mask
+        };
+    }
+
+    fn check_qubit(&self, operation: &Operation, qubit: usize) -> Result<(), RoqoqoBackendError> {
+        if qubit >= self.number_qubits {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "{} acts on qubit {} but the backend only has {} qubits",
+                    operation.hqslang(),
+                    qubit,
+                    self.number_qubits
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies a Clifford gate to the tableau.
+    fn apply_gate(&mut self, operation: &Operation) -> Result<(), RoqoqoBackendError> {
+        for gate in clifford_decomposition(operation)?.iter() {
+            if let InvolvedQubits::Set(qubits) = gate.involved_qubits() {
+                for qubit in qubits {
+                    self.check_qubit(operation, qubit)?;
+                }
+            }
+            match gate {
+                Operation::Hadamard(op) => self.hadamard(*op.qubit()),
+                Operation::SGate(op) => self.phase_gate(*op.qubit()),
+                Operation::InvSGate(op) => {
+                    self.phase_gate(*op.qubit());
+                    self.pauli(*op.qubit(), false, true);
+                }
+                Operation::PauliX(op) => self.pauli(*op.qubit(), true, false),
+                Operation::PauliY(op) => self.pauli(*op.qubit(), true, true),
+                Operation::PauliZ(op) => self.pauli(*op.qubit(), false, true),
+                Operation::CNOT(op) => self.cnot(*op.control(), *op.target()),
+                _ => unreachable!("Clifford decompositions only contain the primitive gates"),
+            }
+        }
+        Ok(())
+    }
+
+    fn hadamard(&mut self, qubit: usize) {
+        for row in 0..2 * self.number_qubits {
+            let (x, z) = (self.x(row, qubit), self.z(row, qubit));
+            self.phase[row] ^= x && z;
+            self.set(row, qubit, z, x);
+        }
+    }
+
+    fn phase_gate(&mut self, qubit: usize) {
+        for row in 0..2 * self.number_qubits {
+            let (x, z) = (self.x(row, qubit), self.z(row, qubit));
+            self.phase[row] ^= x && z;
+            self.set(row, qubit, x, z ^ x);
+        }
+    }
+
+    /// Applies the Pauli gate with the given x and z bits, which flips the sign of all anticommuting rows.
+    fn pauli(&mut self, qubit: usize, pauli_x: bool, pauli_z: bool) {
+        for row in 0..2 * self.number_qubits {
+            self.phase[row] ^= (pauli_x && self.z(row, qubit)) ^ (pauli_z && self.x(row, qubit));
+        }
+    }
+
+    fn cnot(&mut self, control: usize, target: usize) {
+        for row in 0..2 * self.number_qubits {
+            let (x_control, z_control) = (self.x(row, control), self.z(row, control));
+            let (x_target, z_target) = (self.x(row, target), self.z(row, target));
+            self.phase[row] ^= x_control && z_target && !(x_target ^ z_control);
+            self.set(row, control, x_control, z_control ^ z_target);
+            self.set(row, target, x_target ^ x_control, z_target);
+        }
+    }
+
+    /// Multiplies row `source` into row `target`, keeping track of the sign.
+    fn row_sum(&mut self, target: usize, source: usize) {
+        // Twice the exponent of i of the product, modulo 4
+        let mut exponent: i64 = 2 * (self.phase[target] as i64 + self.phase[source] as i64);
+        for word in 0..self.words {
+            let (x1, z1) = (
+                self.x[source * self.words + word],
+                self.z[source * self.words + word],
+            );
+            let (x2, z2) = (
+                self.x[target * self.words + word],
+                self.z[target * self.words + word],
+            );
+            let plus = (x1 & z1 & z2 & !x2) | (x1 & !z1 & z2 & x2) | (!x1 & z1 & x2 & !z2);
+            let minus = (x1 & z1 & x2 & !z2) | (x1 & !z1 & z2 & !x2) | (!x1 & z1 & x2 & z2);
+            exponent += plus.count_ones() as i64 - minus.count_ones() as i64;
+            self.x[target * self.words + word] ^= x1;
+            self.z[target * self.words + word] ^= z1;
+        }
+        self.phase[target] = exponent.rem_euclid(4) == 2;
+    }
+
+    fn clear_row(&mut self, row: usize) {
+        for word in 0..self.words {
+            self.x[row * self.words + word] = 0;
+            self.z[row * self.words + word] = 0;
+        }
+        self.phase[row] = false;
+    }
+
+    fn copy_row(&mut self, target: usize, source: usize) {
+        for word in 0..self.words {
+            self.x[target * self.words + word] = self.x[source * self.words + word];
+            self.z[target * self.words + word] = self.z[source * self.words + word];
+        }
+        self.phase[target] = self.phase[source];
+    }
+
+    /// Measures a qubit in the Z basis and projects the state onto the outcome.
+    fn measure<R: Rng>(&mut self, qubit: usize, rng: &mut R) -> bool {
+        let n = self.number_qubits;
+        let random_row = (n..2 * n).find(|row| self.x(*row, qubit));
+        match random_row {
+            Some(pivot) => {
+                for row in (0..2 * n).filter(|row| *row != pivot) {
+                    if self.x(row, qubit) {
+                        self.row_sum(row, pivot);
+                    }
+                }
+                self.copy_row(pivot - n, pivot);
+                self.clear_row(pivot);
+                let outcome = rng.gen::<bool>();
+                self.set(pivot, qubit, false, true);
+                self.phase[pivot] = outcome;
+                outcome
+            }
+            None => {
+                self.clear_row(2 * n);
+                for row in 0..n {
+                    if self.x(row, qubit) {
+                        self.row_sum(2 * n, row + n);
+                    }
+                }
+                self.phase[2 * n]
+            }
+        }
+    }
+
+    /// Returns the expectation value of a Pauli product, given as pairs of a qubit and its x and z bits.
+    fn pauli_expectation(&mut self, paulis: &[(usize, bool, bool)]) -> f64 {
+        let n = self.number_qubits;
+        let anticommutes = |tableau: &Tableau, row: usize| {
+            paulis
+                .iter()
+                .filter(|(qubit, x, z)| {
+                    (*x && tableau.z(row, *qubit)) ^ (*z && tableau.x(row, *qubit))
+                })
+                .count()
+                % 2
+                == 1
+        };
+        if (n..2 * n).any(|row| anticommutes(self, row)) {
+            return 0.0;
+        }
+        // The product is in the stabilizer group, it is the product of the stabilizers whose destabilizers anticommute
+        self.clear_row(2 * n);
+        for row in 0..n {
+            if anticommutes(self, row) {
+                self.row_sum(2 * n, row + n);
+            }
+        }
+        if self.phase[2 * n] {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+}
+
+/// The stabilizer tableau and the classical registers of a single run of a circuit.
+struct Simulation<'a, R: Rng> {
+    tableau: Tableau,
+    bit_registers: HashMap<String, BitRegister>,
+    float_registers: HashMap<String, FloatRegister>,
+    /// Readouts whose shots have been written to the output registers directly.
+    sampled_readouts: HashSet<String>,
+    rng: &'a mut R,
+}
+
+impl<'a, R: Rng> Simulation<'a, R> {
+    fn new(number_qubits: usize, rng: &'a mut R) -> Self {
+        Simulation {
+            tableau: Tableau::new(number_qubits),
+            bit_registers: HashMap::new(),
+            float_registers: HashMap::new(),
+            sampled_readouts: HashSet::new(),
+            rng,
+        }
+    }
+
+    fn apply(
+        &mut self,
+        operation: &Operation,
+        bit_outputs: &mut HashMap<String, BitOutputRegister>,
+    ) -> Result<(), RoqoqoBackendError> {
+        match operation {
+            Operation::DefinitionBit(def) => {
+                self.bit_registers
+                    .insert(def.name().clone(), vec![false; *def.length()]);
+            }
+            Operation::DefinitionFloat(def) => {
+                self.float_registers
+                    .insert(def.name().clone(), vec![0.0; *def.length()]);
+            }
+            Operation::InputBit(op) => *self.bit(op.name(), *op.index())? = *op.value(),
+            Operation::MeasureQubit(op) => {
+                self.tableau.check_qubit(operation, *op.qubit())?;
+                let outcome = self.tableau.measure(*op.qubit(), self.rng);
+                *self.bit(op.readout(), *op.readout_index())? = outcome;
+            }
+            Operation::PragmaActiveReset(op) => {
+                self.tableau.check_qubit(operation, *op.qubit())?;
+                if self.tableau.measure(*op.qubit(), self.rng) {
+                    self.tableau.pauli(*op.qubit(), true, false);
+                }
+            }
+            Operation::PragmaConditional(op) => {
+                if *self.bit(op.condition_register(), *op.condition_index())? {
+                    for operation in op.circuit().iter() {
+                        self.apply(operation, bit_outputs)?;
+                    }
+                }
+            }
+            Operation::PragmaLoop(op) => {
+                for _ in 0..*op.repetitions().float()? as usize {
+                    for operation in op.circuit().iter() {
+                        self.apply(operation, bit_outputs)?;
+                    }
+                }
+            }
+            Operation::PragmaRepeatedMeasurement(op) => {
+                let length = self.bit_registers.get(op.readout()).map_or(0, Vec::len);
+                let measured_qubits: Vec<(usize, usize)> = (0..self.tableau.number_qubits)
+                    .filter_map(|qubit| match op.qubit_mapping() {
+                        Some(mapping) => mapping.get(&qubit).map(|index| (qubit, *index)),
+                        None => Some((qubit, qubit)),
+                    })
+                    .filter(|(_, index)| *index < length)
+                    .collect();
+                let sampling = FinalSampling {
+                    readout: op.readout().clone(),
+                    number_measurements: *op.number_measurements(),
+                    measured_qubits,
+                };
+                self.sample_final_measurements(&sampling, bit_outputs)?;
+            }
+            Operation::PragmaGetPauliProduct(op) => {
+                let mut tableau = self.tableau.clone();
+                for operation in op.circuit().iter() {
+                    tableau.apply_gate(operation)?;
+                }
+                let mut paulis = Vec::with_capacity(op.qubit_paulis().len());
+                for (qubit, pauli) in op.qubit_paulis() {
+                    tableau.check_qubit(operation, *qubit)?;
+                    match pauli {
+                        0 => (),
+                        1 => paulis.push((*qubit, true, false)),
+                        2 => paulis.push((*qubit, true, true)),
+                        3 => paulis.push((*qubit, false, true)),
+                        _ => {
+                            return Err(RoqoqoBackendError::GenericError {
+                                msg: format!("Unknown Pauli operator {} in PauliProduct", pauli),
+                            })
+                        }
+                    }
+                }
+                self.float_registers.insert(
+                    op.readout().clone(),
+                    vec![tableau.pauli_expectation(&paulis)],
+                );
+            }
+            // Operations without an effect on the state, the global phase is dropped like in QuEST
+            Operation::DefinitionUsize(_)
+            | Operation::InputSymbolic(_)
+            | Operation::PragmaSetNumberOfMeasurements(_)
+            | Operation::PragmaGlobalPhase(_)
+            | Operation::PragmaStopParallelBlock(_)
+            | Operation::PragmaStartDecompositionBlock(_)
+            | Operation::PragmaStopDecompositionBlock(_)
+            | Operation::PragmaSleep(_) => (),
+            _ => self.tableau.apply_gate(operation)?,
+        }
+        Ok(())
+    }
+
+    fn bit(&mut self, name: &str, index: usize) -> Result<&mut bool, RoqoqoBackendError> {
+        self.bit_registers
+            .get_mut(name)
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Bit register {} is not defined", name),
+            })?
+            .get_mut(index)
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Index {} is outside of bit register {}", index, name),
+            })
+    }
+
+    /// Samples shots of the measured qubits without changing the state.
+    ///
+    /// Every shot measures a copy of the tableau, starting from the current content of the readout register, and is
+    /// appended to its output register.
+    fn sample_final_measurements(
+        &mut self,
+        sampling: &FinalSampling,
+        bit_outputs: &mut HashMap<String, BitOutputRegister>,
+    ) -> Result<(), RoqoqoBackendError> {
+        let register = self
+            .bit_registers
+            .get(&sampling.readout)
+            .cloned()
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Bit register {} is not defined", sampling.readout),
+            })?;
+        if let Some((qubit, index)) = sampling
+            .measured_qubits
+            .iter()
+            .find(|(qubit, index)| *qubit >= self.tableau.number_qubits || *index >= register.len())
+        {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "Qubit {} can not be measured into bit {} of register {}",
+                    qubit, index, sampling.readout
+                ),
+            });
+        }
+        let shots: BitOutputRegister = (0..sampling.number_measurements)
+            .map(|_| {
+                let mut tableau = self.tableau.clone();
+                let mut shot = register.clone();
+                for (qubit, index) in sampling.measured_qubits.iter() {
+                    shot[*index] = tableau.measure(*qubit, self.rng);
+                }
+                shot
+            })
+            .collect();
+        if let Some(output) = bit_outputs.get_mut(&sampling.readout) {
+            output.extend(shots);
+            self.sampled_readouts.insert(sampling.readout.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_vector::StateVectorBackend;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn large_ghz_state() {
+        let number_qubits = 300;
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), number_qubits, true);
+        circuit += Hadamard::new(0);
+        for qubit in 1..number_qubits {
+            circuit += CNOT::new(qubit - 1, qubit);
+        }
+        circuit += PragmaRepeatedMeasurement::new("ro".to_string(), 40, None);
+        let (bits, _, _) = StabilizerBackend::new(number_qubits)
+            .set_seed(3)
+            .run_circuit(&circuit)
+            .unwrap();
+        let shots = &bits["ro"];
+        assert_eq!(shots.len(), 40);
+        for shot in shots {
+            assert_eq!(shot.len(), number_qubits);
+            assert!(shot.iter().all(|bit| *bit == shot[0]));
+        }
+        assert!(shots.iter().any(|shot| shot[0]) && shots.iter().any(|shot| !shot[0]));
+    }
+
+    /// Returns a random gate of the Clifford gates the backend decomposes.
+    fn random_clifford(rng: &mut StdRng, number_qubits: usize) -> Operation {
+        let qubit = rng.gen_range(0..number_qubits);
+        let other = (qubit + rng.gen_range(1..number_qubits)) % number_qubits;
+        let angle = FRAC_PI_2 * rng.gen_range(-4..5) as f64;
+        match rng.gen_range(0..19) {
+            0 => Hadamard::new(qubit).into(),
+            1 => SGate::new(qubit).into(),
+            2 => InvSGate::new(qubit).into(),
+            3 => PauliX::new(qubit).into(),
+            4 => PauliY::new(qubit).into(),
+            5 => PauliZ::new(qubit).into(),
+            6 => SqrtPauliX::new(qubit).into(),
+            7 => InvSqrtPauliX::new(qubit).into(),
+            8 => SqrtPauliY::new(qubit).into(),
+            9 => InvSqrtPauliY::new(qubit).into(),
+            10 => RotateX::new(qubit, angle.into()).into(),
+            11 => RotateY::new(qubit, angle.into()).into(),
+            12 => RotateZ::new(qubit, angle.into()).into(),
+            13 => PhaseShiftState0::new(qubit, angle.into()).into(),
+            14 => PhaseShiftState1::new(qubit, angle.into()).into(),
+            15 => CNOT::new(qubit, other).into(),
+            16 => ControlledPauliZ::new(qubit, other).into(),
+            17 => ControlledPauliY::new(qubit, other).into(),
+            _ => ISwap::new(qubit, other).into(),
+        }
+    }
+
+    #[test]
+    fn pauli_products_agree_with_state_vector() {
+        let number_qubits = 3;
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..20 {
+            let mut circuit = Circuit::new();
+            for _ in 0..12 {
+                circuit += random_clifford(&mut rng, number_qubits);
+            }
+            circuit += SWAP::new(0, 2);
+            // All 4^3 Pauli products, the digits of the index in base four are the Paulis of the qubits
+            for index in 0..4usize.pow(number_qubits as u32) {
+                let qubit_paulis: HashMap<usize, usize> = (0..number_qubits)
+                    .map(|qubit| (qubit, index / 4usize.pow(qubit as u32) % 4))
+                    .collect();
+                let readout = format!("pauli_{}", index);
+                circuit += DefinitionFloat::new(readout.clone(), 1, true);
+                circuit += PragmaGetPauliProduct::new(qubit_paulis, readout, Circuit::new());
+            }
+            let (_, stabilizer, _) = StabilizerBackend::new(number_qubits)
+                .run_circuit(&circuit)
+                .unwrap();
+            let (_, state_vector, _) = StateVectorBackend::new(number_qubits)
+                .run_circuit(&circuit)
+                .unwrap();
+            assert_eq!(stabilizer.len(), 64);
+            for (readout, values) in stabilizer.iter() {
+                let expected = state_vector[readout][0][0];
+                assert!(
+                    (values[0][0] - expected).abs() < 1e-10,
+                    "{readout}: {} != {expected} for {circuit:?}",
+                    values[0][0]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn non_clifford_rotation() {
+        let circuit = Circuit::new() + RotateY::new(0, (PI / 4.0).into());
+        let error = StabilizerBackend::new(1).run_circuit(&circuit).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("RotateY with angle 0.785"));
+        assert!(message.contains("the angle has to be a multiple of pi/2"));
+        let error = clifford_decomposition(&TGate::new(0).into()).unwrap_err();
+        assert!(error.to_string().contains("TGate is not a Clifford gate"));
+    }
+}
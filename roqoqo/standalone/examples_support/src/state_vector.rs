@@ -0,0 +1,614 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A state-vector simulator written in Rust, implementing the roqoqo `EvaluatingBackend`.
+//!
+//! [StateVectorBackend] runs the circuits of the examples without the QuEST C library. It supports
+//!
+//! * all gates with a unitary matrix, including `Hadamard`, `CNOT` and the rotations,
+//! * `MeasureQubit`, `PragmaActiveReset`, `InputBit`, `PragmaConditional` and `PragmaLoop`,
+//! * `PragmaSetStateVector` and the readouts `PragmaGetStateVector`, `PragmaGetOccupationProbability` and
+//!   `PragmaGetPauliProduct`,
+//! * `PragmaRepeatedMeasurement` and `PragmaSetNumberOfMeasurements`.
+//!
+//! Noise pragmas need a density matrix and are rejected, the
+//! [DensityMatrixBackend](crate::density_matrix::DensityMatrixBackend) simulates them. Repeated measurements behave
+//! like in QuEST: when the `MeasureQubit` operations of the readout of a `PragmaSetNumberOfMeasurements` are the last
+//! operations of the circuit, the circuit runs once and all shots are sampled from the final state. Otherwise the
+//! circuit runs once per shot.
+
+use crate::seed;
+use crate::unitary;
+use nalgebra::DVector;
+use num_complex::Complex64;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use roqoqo::backends::{EvaluatingBackend, RegisterResult};
+use roqoqo::operations::*;
+use roqoqo::registers::{
+    BitOutputRegister, BitRegister, ComplexOutputRegister, ComplexRegister, FloatOutputRegister,
+    FloatRegister,
+};
+use roqoqo::RoqoqoBackendError;
+use std::collections::{HashMap, HashSet};
+
+/// The name of the backend in `OperationNotInBackend` errors.
+const BACKEND_NAME: &str = "StateVectorBackend";
+
+/// Simulates circuits with a state vector of `number_qubits` qubits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateVectorBackend {
+    /// Number of qubits supported by the backend.
+    pub number_qubits: usize,
+    /// The seed of the random number generator of every run, `None` uses [crate::seed::rng].
+    pub seed: Option<u64>,
+}
+
+impl StateVectorBackend {
+    /// Creates a new state-vector backend.
+    ///
+    /// # Arguments
+    ///
+    /// `number_qubits` - The number of qubits supported by the backend.
+    pub fn new(number_qubits: usize) -> Self {
+        Self {
+            number_qubits,
+            seed: None,
+        }
+    }
+
+    /// Sets the seed of the random number generator.
+    ///
+    /// Every run of the backend with a seed draws the same random numbers, so running a circuit twice gives the same
+    /// measurement results. Without a seed the runs draw from [crate::seed::rng], which follows the global seed.
+    ///
+    /// # Arguments
+    ///
+    /// `seed` - The seed of the random number generator.
+    pub fn set_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl EvaluatingBackend for StateVectorBackend {
+    fn run_circuit_iterator<'a>(
+        &self,
+        circuit: impl Iterator<Item = &'a Operation>,
+    ) -> RegisterResult {
+        let (operations, final_sampling, repetitions) =
+            plan_measurements(circuit.collect::<Vec<&Operation>>())?;
+        let mut rng = seed::LazyRng::new(self.seed);
+
+        let mut bit_outputs: HashMap<String, BitOutputRegister> = HashMap::new();
+        let mut float_outputs: HashMap<String, FloatOutputRegister> = HashMap::new();
+        let mut complex_outputs: HashMap<String, ComplexOutputRegister> = HashMap::new();
+        for operation in operations.iter() {
+            match operation {
+                Operation::DefinitionBit(def) if *def.is_output() => {
+                    bit_outputs.insert(def.name().clone(), Vec::new());
+                }
+                Operation::DefinitionFloat(def) if *def.is_output() => {
+                    float_outputs.insert(def.name().clone(), Vec::new());
+                }
+                Operation::DefinitionComplex(def) if *def.is_output() => {
+                    complex_outputs.insert(def.name().clone(), Vec::new());
+                }
+                _ => (),
+            }
+        }
+
+        for _ in 0..repetitions {
+            let mut simulation = Simulation::new(self.number_qubits, &mut rng);
+            for operation in operations.iter() {
+                simulation.apply(operation, &mut bit_outputs)?;
+            }
+            if let Some(sampling) = &final_sampling {
+                simulation.sample_final_measurements(sampling, &mut bit_outputs)?;
+            }
+            for (name, register) in bit_outputs.iter_mut() {
+                if !simulation.sampled_readouts.contains(name) {
+                    if let Some(bits) = simulation.bit_registers.remove(name) {
+                        register.push(bits);
+                    }
+                }
+            }
+            for (name, register) in float_outputs.iter_mut() {
+                if let Some(values) = simulation.float_registers.remove(name) {
+                    register.push(values);
+                }
+            }
+            for (name, register) in complex_outputs.iter_mut() {
+                if let Some(values) = simulation.complex_registers.remove(name) {
+                    register.push(values);
+                }
+            }
+        }
+        Ok((bit_outputs, float_outputs, complex_outputs))
+    }
+}
+
+/// The `MeasureQubit` operations at the end of a circuit that are sampled from the final state.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FinalSampling {
+    /// The readout register of the measurements.
+    pub(crate) readout: String,
+    /// The number of shots.
+    pub(crate) number_measurements: usize,
+    /// Pairs of a measured qubit and the index of its bit in the readout register.
+    pub(crate) measured_qubits: Vec<(usize, usize)>,
+}
+
+/// Decides how the measurements of a circuit are repeated.
+///
+/// Returns the operations to run, the measurements that are sampled from the final state instead of being run, and
+/// the number of times the operations are run.
+pub(crate) fn plan_measurements(
+    operations: Vec<&Operation>,
+) -> Result<(Vec<&Operation>, Option<FinalSampling>, usize), RoqoqoBackendError> {
+    let repeated_readouts: Vec<(&String, usize)> = operations
+        .iter()
+        .filter_map(|operation| match operation {
+            Operation::PragmaRepeatedMeasurement(op) => {
+                Some((op.readout(), *op.number_measurements()))
+            }
+            Operation::PragmaSetNumberOfMeasurements(op) => {
+                Some((op.readout(), *op.number_measurements()))
+            }
+            _ => None,
+        })
+        .collect();
+    if repeated_readouts.len() > 1 {
+        return Err(RoqoqoBackendError::GenericError {
+            msg: format!(
+                "Only one repeated measurement allowed, found repeated measurements of {:?}",
+                repeated_readouts
+            ),
+        });
+    }
+    let pragma = operations.iter().find_map(|operation| match operation {
+        Operation::PragmaSetNumberOfMeasurements(op) => Some(op),
+        _ => None,
+    });
+    let Some(pragma) = pragma else {
+        return Ok((operations, None, 1));
+    };
+    let measures_readout = |operation: &&Operation| match operation {
+        Operation::MeasureQubit(op) => op.readout() == pragma.readout(),
+        _ => false,
+    };
+    let Some(first_measurement) = operations.iter().position(measures_readout) else {
+        return Ok((operations, None, 1));
+    };
+    let mut measured_qubits: Vec<(usize, usize)> = Vec::new();
+    for operation in operations[first_measurement..].iter() {
+        match operation {
+            Operation::MeasureQubit(op)
+                if op.readout() == pragma.readout()
+                    && measured_qubits.iter().all(|(qubit, _)| qubit != op.qubit()) =>
+            {
+                measured_qubits.push((*op.qubit(), *op.readout_index()));
+            }
+            Operation::PragmaSetNumberOfMeasurements(_) => (),
+            // Operations after the measurements can depend on their outcome, every shot needs its own run
+            _ => return Ok((operations, None, *pragma.number_measurements())),
+        }
+    }
+    let sampling = FinalSampling {
+        readout: pragma.readout().clone(),
+        number_measurements: *pragma.number_measurements(),
+        measured_qubits,
+    };
+    Ok((operations[..first_measurement].to_vec(), Some(sampling), 1))
+}
+
+/// The quantum state and the classical registers of a single run of a circuit.
+struct Simulation<'a, R: Rng> {
+    state_vector: DVector<Complex64>,
+    bit_registers: HashMap<String, BitRegister>,
+    float_registers: HashMap<String, FloatRegister>,
+    complex_registers: HashMap<String, ComplexRegister>,
+    /// Readouts whose shots have been written to the output registers directly.
+    sampled_readouts: HashSet<String>,
+    rng: &'a mut R,
+}
+
+impl<'a, R: Rng> Simulation<'a, R> {
+    fn new(number_qubits: usize, rng: &'a mut R) -> Self {
+        let mut state_vector = DVector::zeros(1 << number_qubits);
+        state_vector[0] = Complex64::new(1.0, 0.0);
+        Simulation {
+            state_vector,
+            bit_registers: HashMap::new(),
+            float_registers: HashMap::new(),
+            complex_registers: HashMap::new(),
+            sampled_readouts: HashSet::new(),
+            rng,
+        }
+    }
+
+    fn apply(
+        &mut self,
+        operation: &Operation,
+        bit_outputs: &mut HashMap<String, BitOutputRegister>,
+    ) -> Result<(), RoqoqoBackendError> {
+        match operation {
+            Operation::DefinitionBit(def) => {
+                self.bit_registers
+                    .insert(def.name().clone(), vec![false; *def.length()]);
+            }
+            Operation::DefinitionFloat(def) => {
+                self.float_registers
+                    .insert(def.name().clone(), vec![0.0; *def.length()]);
+            }
+            Operation::DefinitionComplex(def) => {
+                self.complex_registers.insert(
+                    def.name().clone(),
+                    vec![Complex64::new(0.0, 0.0); *def.length()],
+                );
+            }
+            Operation::InputBit(op) => *self.bit(op.name(), *op.index())? = *op.value(),
+            Operation::MeasureQubit(op) => {
+                let outcome = self.measure(*op.qubit())?;
+                *self.bit(op.readout(), *op.readout_index())? = outcome;
+            }
+            Operation::PragmaActiveReset(op) => {
+                if self.measure(*op.qubit())? {
+                    unitary::apply_gate(&mut self.state_vector, &PauliX::new(*op.qubit()).into())?;
+                }
+            }
+            Operation::PragmaConditional(op) => {
+                if *self.bit(op.condition_register(), *op.condition_index())? {
+                    for operation in op.circuit().iter() {
+                        self.apply(operation, bit_outputs)?;
+                    }
+                }
+            }
+            Operation::PragmaLoop(op) => {
+                for _ in 0..*op.repetitions().float()? as usize {
+                    for operation in op.circuit().iter() {
+                        self.apply(operation, bit_outputs)?;
+                    }
+                }
+            }
+            Operation::PragmaRepeatedMeasurement(op) => {
+                let length = self.bit_registers.get(op.readout()).map_or(0, Vec::len);
+                let measured_qubits: Vec<(usize, usize)> = (0..self.number_qubits())
+                    .filter_map(|qubit| match op.qubit_mapping() {
+                        Some(mapping) => mapping.get(&qubit).map(|index| (qubit, *index)),
+                        None => Some((qubit, qubit)),
+                    })
+                    .filter(|(_, index)| *index < length)
+                    .collect();
+                let sampling = FinalSampling {
+                    readout: op.readout().clone(),
+                    number_measurements: *op.number_measurements(),
+                    measured_qubits,
+                };
+                self.sample_final_measurements(&sampling, bit_outputs)?;
+            }
+            Operation::PragmaSetStateVector(op) => {
+                if op.statevector().len() != self.state_vector.len() {
+                    return Err(RoqoqoBackendError::GenericError {
+                        msg: format!(
+                            "State vector with {} entries does not fit {} qubits",
+                            op.statevector().len(),
+                            self.number_qubits()
+                        ),
+                    });
+                }
+                self.state_vector = DVector::from_iterator(
+                    op.statevector().len(),
+                    op.statevector().iter().copied(),
+                );
+            }
+            Operation::PragmaGetStateVector(op) => {
+                let state_vector = self.rotated_state(op.circuit().as_ref())?;
+                self.complex_registers
+                    .insert(op.readout().clone(), state_vector.iter().copied().collect());
+            }
+            Operation::PragmaGetOccupationProbability(op) => {
+                let state_vector = self.rotated_state(op.circuit().as_ref())?;
+                let probabilities = state_vector
+                    .iter()
+                    .map(|amplitude| amplitude.norm_sqr())
+                    .collect();
+                self.float_registers
+                    .insert(op.readout().clone(), probabilities);
+            }
+            Operation::PragmaGetPauliProduct(op) => {
+                let state_vector = self.rotated_state(Some(op.circuit()))?;
+                let mut product = state_vector.clone();
+                for (qubit, pauli) in op.qubit_paulis() {
+                    let pauli: Operation = match pauli {
+                        0 => continue,
+                        1 => PauliX::new(*qubit).into(),
+                        2 => PauliY::new(*qubit).into(),
+                        3 => PauliZ::new(*qubit).into(),
+                        _ => {
+                            return Err(RoqoqoBackendError::GenericError {
+                                msg: format!("Unknown Pauli operator {} in PauliProduct", pauli),
+                            })
+                        }
+                    };
+                    unitary::apply_gate(&mut product, &pauli)?;
+                }
+                self.float_registers
+                    .insert(op.readout().clone(), vec![state_vector.dotc(&product).re]);
+            }
+            // Operations without an effect on the state, the global phase is dropped like in QuEST
+            Operation::DefinitionUsize(_)
+            | Operation::InputSymbolic(_)
+            | Operation::PragmaSetNumberOfMeasurements(_)
+            | Operation::PragmaGlobalPhase(_)
+            | Operation::PragmaStopParallelBlock(_)
+            | Operation::PragmaStartDecompositionBlock(_)
+            | Operation::PragmaStopDecompositionBlock(_)
+            | Operation::PragmaSleep(_) => (),
+            _ if unitary::is_gate(operation) => {
+                unitary::apply_gate(&mut self.state_vector, operation)?;
+            }
+            _ => {
+                return Err(RoqoqoBackendError::OperationNotInBackend {
+                    backend: BACKEND_NAME,
+                    hqslang: operation.hqslang(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn number_qubits(&self) -> usize {
+        self.state_vector.len().trailing_zeros() as usize
+    }
+
+    fn bit(&mut self, name: &str, index: usize) -> Result<&mut bool, RoqoqoBackendError> {
+        self.bit_registers
+            .get_mut(name)
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Bit register {} is not defined", name),
+            })?
+            .get_mut(index)
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Index {} is outside of bit register {}", index, name),
+            })
+    }
+
+    /// Measures a qubit and projects the state onto the outcome.
+    fn measure(&mut self, qubit: usize) -> Result<bool, RoqoqoBackendError> {
+        if qubit >= self.number_qubits() {
+            return Err(RoqoqoBackendError::GenericError {
+                msg: format!(
+                    "Qubit {} is measured but the backend only has {} qubits",
+                    qubit,
+                    self.number_qubits()
+                ),
+            });
+        }
+        let probability = probability_of_one(&self.state_vector, qubit);
+        let outcome = self.rng.gen::<f64>() < probability;
+        for (index, amplitude) in self.state_vector.iter_mut().enumerate() {
+            if (index >> qubit & 1 == 1) != outcome {
+                *amplitude = Complex64::new(0.0, 0.0);
+            }
+        }
+        let norm = self.state_vector.norm();
+        self.state_vector.unscale_mut(norm);
+        Ok(outcome)
+    }
+
+    /// Samples shots of the measured qubits from the state without changing it.
+    ///
+    /// Every shot starts from the current content of the readout register and is appended to its output register.
+    fn sample_final_measurements(
+        &mut self,
+        sampling: &FinalSampling,
+        bit_outputs: &mut HashMap<String, BitOutputRegister>,
+    ) -> Result<(), RoqoqoBackendError> {
+        let register = self
+            .bit_registers
+            .get(&sampling.readout)
+            .cloned()
+            .ok_or_else(|| RoqoqoBackendError::GenericError {
+                msg: format!("Bit register {} is not defined", sampling.readout),
+            })?;
+        let shots = sample_shots(
+            self.state_vector
+                .iter()
+                .map(|amplitude| amplitude.norm_sqr()),
+            sampling,
+            &register,
+            self.number_qubits(),
+            self.rng,
+        )?;
+        if let Some(output) = bit_outputs.get_mut(&sampling.readout) {
+            output.extend(shots);
+            self.sampled_readouts.insert(sampling.readout.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns a copy of the state after applying the gates of an optional basis rotation circuit.
+    fn rotated_state(
+        &self,
+        circuit: Option<&roqoqo::Circuit>,
+    ) -> Result<DVector<Complex64>, RoqoqoBackendError> {
+        let mut state_vector = self.state_vector.clone();
+        for operation in circuit.into_iter().flat_map(|circuit| circuit.iter()) {
+            unitary::apply_gate(&mut state_vector, operation)?;
+        }
+        Ok(state_vector)
+    }
+}
+
+/// Samples shots of the measured qubits from the probabilities of the basis states.
+///
+/// Every shot starts from `register`, the current content of the readout register.
+pub(crate) fn sample_shots<R: Rng>(
+    probabilities: impl IntoIterator<Item = f64>,
+    sampling: &FinalSampling,
+    register: &BitRegister,
+    number_qubits: usize,
+    rng: &mut R,
+) -> Result<BitOutputRegister, RoqoqoBackendError> {
+    if let Some((qubit, index)) = sampling
+        .measured_qubits
+        .iter()
+        .find(|(qubit, index)| *qubit >= number_qubits || *index >= register.len())
+    {
+        return Err(RoqoqoBackendError::GenericError {
+            msg: format!(
+                "Qubit {} can not be measured into bit {} of register {}",
+                qubit, index, sampling.readout
+            ),
+        });
+    }
+    let distribution = WeightedIndex::new(
+        probabilities
+            .into_iter()
+            .map(|probability| probability.max(0.0)),
+    )
+    .map_err(|err| RoqoqoBackendError::GenericError {
+        msg: format!("Quantum state can not be sampled: {}", err),
+    })?;
+    Ok((0..sampling.number_measurements)
+        .map(|_| {
+            let basis_state = distribution.sample(rng);
+            let mut shot = register.clone();
+            for (qubit, index) in sampling.measured_qubits.iter() {
+                shot[*index] = basis_state >> qubit & 1 == 1;
+            }
+            shot
+        })
+        .collect())
+}
+
+/// Returns the probability to measure `qubit` in the state `|1>`.
+fn probability_of_one(state_vector: &DVector<Complex64>, qubit: usize) -> f64 {
+    state_vector
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| index >> qubit & 1 == 1)
+        .map(|(_, amplitude)| amplitude.norm_sqr())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seed::GlobalSeedGuard;
+    use roqoqo::Circuit;
+
+    /// A circuit with entangling gates and rotations followed by the readouts of the state.
+    #[cfg(feature = "quest")]
+    fn readout_circuit() -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionComplex::new("state".to_string(), 8, true);
+        circuit += DefinitionFloat::new("occupation".to_string(), 3, true);
+        circuit += DefinitionFloat::new("z0z1".to_string(), 1, true);
+        circuit += Hadamard::new(0);
+        circuit += CNOT::new(0, 1);
+        circuit += RotateY::new(2, 0.7.into());
+        circuit += ControlledPhaseShift::new(1, 2, 0.3.into());
+        circuit += RotateX::new(0, (-1.1).into());
+        circuit += PragmaGetStateVector::new("state".to_string(), None);
+        circuit += PragmaGetOccupationProbability::new("occupation".to_string(), None);
+        circuit += PragmaGetPauliProduct::new(
+            HashMap::from([(0, 3), (1, 3)]),
+            "z0z1".to_string(),
+            Circuit::new(),
+        );
+        circuit
+    }
+
+    /// Prepares a Bell pair and measures it repeatedly.
+    fn bell_circuit(number_measurements: usize) -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 2, true);
+        circuit += Hadamard::new(0);
+        circuit += CNOT::new(0, 1);
+        circuit += PragmaRepeatedMeasurement::new("ro".to_string(), number_measurements, None);
+        circuit
+    }
+
+    #[cfg(feature = "quest")]
+    #[test]
+    fn readouts_agree_with_quest() {
+        let circuit = readout_circuit();
+        let (_, floats, complexes) = StateVectorBackend::new(3).run_circuit(&circuit).unwrap();
+        let (_, quest_floats, quest_complexes) =
+            roqoqo_quest::Backend::new(3).run_circuit(&circuit).unwrap();
+        assert!((floats["z0z1"][0][0] - quest_floats["z0z1"][0][0]).abs() < 1e-10);
+        let state = &complexes["state"][0];
+        assert_eq!(state.len(), 8);
+        for (amplitude, quest_amplitude) in state.iter().zip(quest_complexes["state"][0].iter()) {
+            assert!((amplitude - quest_amplitude).norm() < 1e-10);
+        }
+        // The occupation probabilities of roqoqo-quest 0.10 do not sum to one for this circuit, they are checked
+        // against the state vector instead
+        for (probability, amplitude) in floats["occupation"][0].iter().zip(state.iter()) {
+            assert!((probability - amplitude.norm_sqr()).abs() < 1e-10);
+        }
+    }
+
+    #[cfg(feature = "quest")]
+    #[test]
+    fn bell_statistics_agree_with_quest() {
+        let _seed = GlobalSeedGuard::new(None);
+        let number_measurements = 2000;
+        let circuit = bell_circuit(number_measurements);
+        let (bits, _, _) = StateVectorBackend::new(2).run_circuit(&circuit).unwrap();
+        let (quest_bits, _, _) = roqoqo_quest::Backend::new(2).run_circuit(&circuit).unwrap();
+        // Five standard deviations of the fraction of ones in 2000 shots
+        let tolerance = 5.0 * 0.5 / (number_measurements as f64).sqrt();
+        for shots in [&bits["ro"], &quest_bits["ro"]] {
+            assert_eq!(shots.len(), number_measurements);
+            assert!(shots.iter().all(|shot| shot[0] == shot[1]));
+            let ones = shots.iter().filter(|shot| shot[0]).count() as f64;
+            assert!((ones / number_measurements as f64 - 0.5).abs() < tolerance);
+        }
+    }
+
+    #[test]
+    fn conditional_operations_per_shot() {
+        let _seed = GlobalSeedGuard::new(None);
+        let mut correction = Circuit::new();
+        correction += PauliX::new(1);
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 2, true);
+        circuit += Hadamard::new(0);
+        circuit += MeasureQubit::new(0, "ro".to_string(), 0);
+        circuit += PragmaConditional::new("ro".to_string(), 0, correction);
+        circuit += MeasureQubit::new(1, "ro".to_string(), 1);
+        circuit += PragmaSetNumberOfMeasurements::new(50, "ro".to_string());
+        let (bits, _, _) = StateVectorBackend::new(2).run_circuit(&circuit).unwrap();
+        assert_eq!(bits["ro"].len(), 50);
+        assert!(bits["ro"].iter().all(|shot| shot[0] == shot[1]));
+    }
+
+    #[test]
+    fn seeded_runs_are_identical() {
+        let backend = StateVectorBackend::new(2).set_seed(3);
+        let circuit = bell_circuit(100);
+        assert_eq!(
+            backend.run_circuit(&circuit).unwrap().0,
+            backend.run_circuit(&circuit).unwrap().0
+        );
+    }
+
+    #[test]
+    fn noise_is_rejected() {
+        let mut circuit = Circuit::new();
+        circuit += PragmaDamping::new(0, 1.0.into(), 0.1.into());
+        assert!(StateVectorBackend::new(1).run_circuit(&circuit).is_err());
+    }
+}
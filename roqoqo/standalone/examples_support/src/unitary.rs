@@ -0,0 +1,523 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unitary matrices of gate-only roqoqo circuits and equivalence checks between circuits.
+
+use nalgebra::{DMatrix, DVector};
+use num_complex::Complex64;
+use roqoqo::operations::*;
+use roqoqo::{Circuit, RoqoqoError};
+
+/// Default tolerance used when comparing matrix elements.
+pub const TOLERANCE: f64 = 1e-10;
+
+/// Returns the qubits a gate acts on, ordered from the most to the least significant index of its `unitary_matrix()`.
+///
+/// roqoqo orders the basis of two-qubit gates as |control, target>, of three-qubit gates as |control_0, control_1, target>
+/// and so on. Multi-qubit gates use the order of `qubits()`.
+///
+fn ordered_qubits(operation: &Operation) -> Result<Vec<usize>, RoqoqoError> {
+    if let Ok(op) = SingleQubitGateOperation::try_from(operation) {
+        Ok(vec![*op.qubit()])
+    } else if let Ok(op) = TwoQubitGateOperation::try_from(operation) {
+        Ok(vec![*op.control(), *op.target()])
+    } else if let Ok(op) = ThreeQubitGateOperation::try_from(operation) {
+        Ok(vec![*op.control_0(), *op.control_1(), *op.target()])
+    } else if let Ok(op) = FourQubitGateOperation::try_from(operation) {
+        Ok(vec![
+            *op.control_0(),
+            *op.control_1(),
+            *op.control_2(),
+            *op.target(),
+        ])
+    } else if let Ok(op) = MultiQubitGateOperation::try_from(operation) {
+        Ok(op.qubits().clone())
+    } else {
+        Err(RoqoqoError::GenericError {
+            msg: format!(
+                "Operation {} is not a gate and has no unitary matrix",
+                operation.hqslang()
+            ),
+        })
+    }
+}
+
+/// Returns true if the operation is a gate with a unitary matrix.
+pub fn is_gate(operation: &Operation) -> bool {
+    GateOperation::try_from(operation).is_ok()
+}
+
+/// Returns the number of qubits needed to hold all gates of the circuit.
+pub fn number_gate_qubits(circuit: &Circuit) -> usize {
+    circuit
+        .iter()
+        .filter_map(|operation| ordered_qubits(operation).ok())
+        .flatten()
+        .max()
+        .map_or(0, |qubit| qubit + 1)
+}
+
+/// Computes the full unitary matrix of a gate-only circuit on `number_qubits` qubits.
+///
+/// The matrix uses the roqoqo state vector convention: qubit `k` is bit `k` of the basis state index.
+///
+/// # Returns
+///
+/// * `Ok(DMatrix<Complex64>)` - The 2^n x 2^n unitary matrix of the circuit.
+/// * `Err(RoqoqoError)` - The circuit contains an operation that is not a gate or a gate with a symbolic parameter.
+///
+pub fn circuit_unitary(
+    circuit: &Circuit,
+    number_qubits: usize,
+) -> Result<DMatrix<Complex64>, RoqoqoError> {
+    let dimension = 1 << number_qubits;
+    let mut unitary = DMatrix::<Complex64>::identity(dimension, dimension);
+    for operation in circuit.iter() {
+        let gate = GateOperation::try_from(operation).map_err(|_| RoqoqoError::GenericError {
+            msg: format!(
+                "Circuit is not gate-only, it contains {}",
+                operation.hqslang()
+            ),
+        })?;
+        let qubits = ordered_qubits(operation)?;
+        if let Some(qubit) = qubits.iter().find(|&&qubit| qubit >= number_qubits) {
+            return Err(RoqoqoError::GenericError {
+                msg: format!(
+                    "{} acts on qubit {} but the unitary only has {} qubits",
+                    operation.hqslang(),
+                    qubit,
+                    number_qubits
+                ),
+            });
+        }
+        let gate_matrix = gate.unitary_matrix()?;
+        let mask: usize = qubits.iter().map(|qubit| 1 << qubit).sum();
+        let spread_indices = spread_indices(&qubits);
+        let mut new_unitary = DMatrix::<Complex64>::zeros(dimension, dimension);
+        for row in 0..dimension {
+            let local_row = spread_indices
+                .iter()
+                .position(|&index| index == row & mask)
+                .expect("Every masked index corresponds to a local basis state");
+            let rest = row & !mask;
+            for (local_column, spread_column) in spread_indices.iter().enumerate() {
+                let element = gate_matrix[[local_row, local_column]];
+                if element == Complex64::new(0.0, 0.0) {
+                    continue;
+                }
+                let source_row = rest | spread_column;
+                for column in 0..dimension {
+                    new_unitary[(row, column)] += element * unitary[(source_row, column)];
+                }
+            }
+        }
+        unitary = new_unitary;
+    }
+    Ok(unitary)
+}
+
+/// Applies a gate to a state vector, using the roqoqo convention that qubit `k` is bit `k` of the basis state index.
+///
+/// Only the amplitudes the gate mixes are combined, so this is much cheaper than multiplying with the full unitary.
+///
+/// # Returns
+///
+/// * `Err(RoqoqoError)` - The operation is not a gate, has a symbolic parameter or acts outside of the state vector.
+///
+pub fn apply_gate(
+    state_vector: &mut DVector<Complex64>,
+    operation: &Operation,
+) -> Result<(), RoqoqoError> {
+    let gate = GateOperation::try_from(operation).map_err(|_| RoqoqoError::GenericError {
+        msg: format!(
+            "Operation {} is not a gate and has no unitary matrix",
+            operation.hqslang()
+        ),
+    })?;
+    let qubits = ordered_qubits(operation)?;
+    if let Some(qubit) = qubits
+        .iter()
+        .find(|&&qubit| 1 << qubit >= state_vector.len())
+    {
+        return Err(RoqoqoError::GenericError {
+            msg: format!(
+                "{} acts on qubit {} outside of a state vector with {} entries",
+                operation.hqslang(),
+                qubit,
+                state_vector.len()
+            ),
+        });
+    }
+    let gate_matrix = gate.unitary_matrix()?;
+    let mask: usize = qubits.iter().map(|qubit| 1 << qubit).sum();
+    let spread_indices = spread_indices(&qubits);
+    for rest in (0..state_vector.len()).filter(|index| index & mask == 0) {
+        let amplitudes: Vec<Complex64> = spread_indices
+            .iter()
+            .map(|spread| state_vector[rest | spread])
+            .collect();
+        for (local_row, spread_row) in spread_indices.iter().enumerate() {
+            state_vector[rest | spread_row] = amplitudes
+                .iter()
+                .enumerate()
+                .map(|(local_column, amplitude)| gate_matrix[[local_row, local_column]] * amplitude)
+                .sum();
+        }
+    }
+    Ok(())
+}
+
+/// Returns the basis state index of every basis state of a gate matrix acting on `qubits`.
+///
+/// The first qubit is the most significant bit of the gate matrix index.
+fn spread_indices(qubits: &[usize]) -> Vec<usize> {
+    (0..1usize << qubits.len())
+        .map(|local| {
+            qubits
+                .iter()
+                .enumerate()
+                .filter(|(position, _)| local & (1 << (qubits.len() - 1 - position)) != 0)
+                .map(|(_, qubit)| 1 << qubit)
+                .sum()
+        })
+        .collect()
+}
+
+/// Computes the unitary of a reversible classical function that maps every basis state to another basis state.
+///
+/// # Arguments
+///
+/// * `number_qubits` - The number of qubits, basis state indices use bit `k` for qubit `k`.
+/// * `map` - The index of the output basis state for every input basis state, has to be a permutation.
+///
+pub fn permutation_unitary(
+    number_qubits: usize,
+    map: impl Fn(usize) -> usize,
+) -> DMatrix<Complex64> {
+    let dimension = 1 << number_qubits;
+    let mut unitary = DMatrix::<Complex64>::zeros(dimension, dimension);
+    for input in 0..dimension {
+        unitary[(map(input), input)] = Complex64::new(1.0, 0.0);
+    }
+    unitary
+}
+
+/// Matrix element in which two unitaries differ after removing the global phase.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementDifference {
+    /// Row of the element, the output basis state.
+    pub row: usize,
+    /// Column of the element, the input basis state.
+    pub column: usize,
+    /// Element of the first unitary.
+    pub first: Complex64,
+    /// Element of the second unitary multiplied with the global phase.
+    pub second: Complex64,
+}
+
+impl ElementDifference {
+    /// Returns true if the two elements have the same magnitude and only differ in their phase.
+    pub fn is_phase_only(&self, tolerance: f64) -> bool {
+        (self.first.norm() - self.second.norm()).abs() < tolerance
+    }
+}
+
+/// Result of comparing two unitaries up to a global phase.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitaryComparison {
+    /// Number of qubits the unitaries act on.
+    pub number_qubits: usize,
+    /// Global phase the second unitary is multiplied with before comparing.
+    pub global_phase: Complex64,
+    /// All elements that still differ after removing the global phase.
+    pub differences: Vec<ElementDifference>,
+}
+
+impl UnitaryComparison {
+    /// Returns true if the unitaries are equal up to the global phase.
+    pub fn is_equivalent(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl std::fmt::Display for UnitaryComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bits = |index: usize| format!("{:0width$b}", index, width = self.number_qubits.max(1));
+        if self.is_equivalent() {
+            return write!(
+                f,
+                "Unitaries are equal up to the global phase exp(i {:.4})",
+                self.global_phase.arg()
+            );
+        }
+        writeln!(
+            f,
+            "{} matrix elements differ after removing the global phase exp(i {:.4}):",
+            self.differences.len(),
+            self.global_phase.arg()
+        )?;
+        for difference in self.differences.iter().take(MAXIMUM_REPORTED_DIFFERENCES) {
+            writeln!(
+                f,
+                "  |{}><{}|: {:.4} vs {:.4}",
+                bits(difference.row),
+                bits(difference.column),
+                difference.first,
+                difference.second
+            )?;
+        }
+        if self.differences.len() > MAXIMUM_REPORTED_DIFFERENCES {
+            writeln!(
+                f,
+                "  ... and {} more",
+                self.differences.len() - MAXIMUM_REPORTED_DIFFERENCES
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of differing elements that are listed when printing a [UnitaryComparison].
+const MAXIMUM_REPORTED_DIFFERENCES: usize = 16;
+
+/// Compares two unitaries up to a global phase.
+///
+/// The global phase is the phase of the overlap `Tr(second^† first)`, which aligns the two unitaries best.
+///
+/// # Returns
+///
+/// * `Ok(UnitaryComparison)` - The global phase and all elements that differ by more than `tolerance`.
+/// * `Err(RoqoqoError)` - The matrices do not have the same square power-of-two shape.
+///
+pub fn compare_unitaries(
+    first: &DMatrix<Complex64>,
+    second: &DMatrix<Complex64>,
+    tolerance: f64,
+) -> Result<UnitaryComparison, RoqoqoError> {
+    let dimension = first.nrows();
+    if first.shape() != second.shape() || first.ncols() != dimension || !dimension.is_power_of_two()
+    {
+        return Err(RoqoqoError::GenericError {
+            msg: format!(
+                "Unitaries of shape {:?} and {:?} can not be compared",
+                first.shape(),
+                second.shape()
+            ),
+        });
+    }
+    let overlap: Complex64 = first
+        .iter()
+        .zip(second.iter())
+        .map(|(a, b)| a * b.conj())
+        .sum();
+    let global_phase = if overlap.norm() < tolerance {
+        Complex64::new(1.0, 0.0)
+    } else {
+        overlap / overlap.norm()
+    };
+    let mut differences = Vec::new();
+    // nalgebra stores matrices column by column
+    for column in 0..dimension {
+        for row in 0..dimension {
+            let second_element = global_phase * second[(row, column)];
+            if (first[(row, column)] - second_element).norm() >= tolerance {
+                differences.push(ElementDifference {
+                    row,
+                    column,
+                    first: first[(row, column)],
+                    second: second_element,
+                });
+            }
+        }
+    }
+    Ok(UnitaryComparison {
+        number_qubits: dimension.trailing_zeros() as usize,
+        global_phase,
+        differences,
+    })
+}
+
+/// Compares the unitaries of two gate-only circuits up to a global phase.
+///
+/// Both unitaries are computed on the qubits used by either circuit.
+///
+pub fn compare_circuit_unitaries(
+    first: &Circuit,
+    second: &Circuit,
+) -> Result<UnitaryComparison, RoqoqoError> {
+    let number_qubits = number_gate_qubits(first).max(number_gate_qubits(second));
+    compare_unitaries(
+        &circuit_unitary(first, number_qubits)?,
+        &circuit_unitary(second, number_qubits)?,
+        TOLERANCE,
+    )
+}
+
+/// Returns true if the two matrices are equal up to a global phase within `tolerance`.
+pub fn equal_up_to_global_phase(
+    first: &DMatrix<Complex64>,
+    second: &DMatrix<Complex64>,
+    tolerance: f64,
+) -> bool {
+    compare_unitaries(first, second, tolerance).is_ok_and(|comparison| comparison.is_equivalent())
+}
+
+/// Checks if two circuits are equivalent.
+///
+/// Consecutive gates are collected into blocks that are compared by their unitary up to a global phase.
+/// All other operations (definitions, measurements, pragmas) have to agree one by one,
+/// the circuits of `PragmaConditional` operations are compared recursively.
+///
+pub fn circuits_equivalent(first: &Circuit, second: &Circuit) -> Result<bool, RoqoqoError> {
+    let first_blocks = split_gate_blocks(first);
+    let second_blocks = split_gate_blocks(second);
+    if first_blocks.len() != second_blocks.len() {
+        return Ok(false);
+    }
+    for (first_block, second_block) in first_blocks.iter().zip(second_blocks.iter()) {
+        let equivalent = match (first_block, second_block) {
+            (Block::Gates(first_gates), Block::Gates(second_gates)) => {
+                let number_qubits =
+                    number_gate_qubits(first_gates).max(number_gate_qubits(second_gates));
+                equal_up_to_global_phase(
+                    &circuit_unitary(first_gates, number_qubits)?,
+                    &circuit_unitary(second_gates, number_qubits)?,
+                    TOLERANCE,
+                )
+            }
+            (
+                Block::Other(Operation::PragmaConditional(first_conditional)),
+                Block::Other(Operation::PragmaConditional(second_conditional)),
+            ) => {
+                first_conditional.condition_register() == second_conditional.condition_register()
+                    && first_conditional.condition_index() == second_conditional.condition_index()
+                    && circuits_equivalent(
+                        first_conditional.circuit(),
+                        second_conditional.circuit(),
+                    )?
+            }
+            (Block::Other(first_operation), Block::Other(second_operation)) => {
+                first_operation == second_operation
+            }
+            _ => false,
+        };
+        if !equivalent {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Part of a circuit that is either a run of consecutive gates or a single other operation.
+enum Block {
+    Gates(Circuit),
+    Other(Operation),
+}
+
+/// Splits a circuit into runs of consecutive gates and the other operations between them.
+fn split_gate_blocks(circuit: &Circuit) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    for operation in circuit.iter() {
+        if is_gate(operation) {
+            if let Some(Block::Gates(gates)) = blocks.last_mut() {
+                gates.add_operation(operation.clone());
+                continue;
+            }
+            let mut gates = Circuit::new();
+            gates.add_operation(operation.clone());
+            blocks.push(Block::Gates(gates));
+        } else {
+            blocks.push(Block::Other(operation.clone()));
+        }
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn cnot_is_a_permutation() {
+        let mut circuit = Circuit::new();
+        circuit += CNOT::new(0, 1);
+        let cnot = permutation_unitary(2, |input| input ^ ((input & 1) << 1));
+        assert!(equal_up_to_global_phase(
+            &circuit_unitary(&circuit, 2).unwrap(),
+            &cnot,
+            TOLERANCE
+        ));
+    }
+
+    #[test]
+    fn equivalent_up_to_global_phase() {
+        let mut first = Circuit::new();
+        first += Hadamard::new(0);
+        first += PauliZ::new(0);
+        first += Hadamard::new(0);
+        let mut second = Circuit::new();
+        second += RotateX::new(0, PI.into());
+        let comparison = compare_circuit_unitaries(&first, &second).unwrap();
+        assert!(comparison.is_equivalent());
+        assert!((comparison.global_phase - Complex64::new(0.0, 1.0)).norm() < TOLERANCE);
+    }
+
+    #[test]
+    fn reports_differing_elements() {
+        let mut first = Circuit::new();
+        first += ControlledPauliZ::new(0, 1);
+        let second = Circuit::new();
+        let comparison = compare_circuit_unitaries(&first, &second).unwrap();
+        assert!(!comparison.is_equivalent());
+        // Only the sign of |11> differs, like the extra phase of the half adder
+        assert_eq!(comparison.differences.len(), 1);
+        let difference = &comparison.differences[0];
+        assert_eq!((difference.row, difference.column), (3, 3));
+        assert!(difference.is_phase_only(TOLERANCE));
+        assert!(comparison.to_string().contains("|11><11|"));
+    }
+
+    #[test]
+    fn mismatched_shapes() {
+        let first = DMatrix::<Complex64>::identity(2, 2);
+        let second = DMatrix::<Complex64>::identity(4, 4);
+        assert!(compare_unitaries(&first, &second, TOLERANCE).is_err());
+    }
+
+    #[test]
+    fn non_gate_operations_have_no_unitary() {
+        let mut circuit = Circuit::new();
+        circuit += DefinitionBit::new("ro".to_string(), 1, true);
+        circuit += MeasureQubit::new(0, "ro".to_string(), 0);
+        assert!(circuit_unitary(&circuit, 1).is_err());
+    }
+
+    #[test]
+    fn circuits_with_measurements() {
+        let mut first = Circuit::new();
+        first += DefinitionBit::new("ro".to_string(), 1, true);
+        first += PauliX::new(0);
+        first += MeasureQubit::new(0, "ro".to_string(), 0);
+        let mut second = Circuit::new();
+        second += DefinitionBit::new("ro".to_string(), 1, true);
+        second += RotateX::new(0, PI.into());
+        second += MeasureQubit::new(0, "ro".to_string(), 0);
+        assert!(circuits_equivalent(&first, &second).unwrap());
+
+        let mut reordered = Circuit::new();
+        reordered += DefinitionBit::new("ro".to_string(), 1, true);
+        reordered += MeasureQubit::new(0, "ro".to_string(), 0);
+        reordered += PauliX::new(0);
+        assert!(!circuits_equivalent(&first, &reordered).unwrap());
+    }
+}
@@ -0,0 +1,467 @@
+// Copyright © 2023 HQS Quantum Simulations GmbH. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use this file except
+// in compliance with the License. You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software distributed under the
+// License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either
+// express or implied. See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Zero-noise extrapolation of the expectation values of quantum programs.
+//!
+//! The noise of a device can not be switched off, but it can be amplified. [run_scaled] runs a `QuantumProgram` with
+//! the noise scaled by several factors `lambda >= 1` and [ScaledExpectationValues::extrapolate] fits the expectation
+//! values as a function of `lambda` and evaluates the fit at `lambda = 0`.
+//!
+//! The noise is scaled in one of two ways:
+//!
+//! * [fold_gates] - Every gate `G` is replaced by `G (G^-1 G)^k` before the noise is added. The circuit does the same
+//!   but every gate is executed `2k + 1` times and collects its noise as often.
+//! * [scale_noise] - The gate times of the noise pragmas of a noisy circuit are multiplied by `lambda`, so the qubits
+//!   decohere for longer.
+
+use crate::runner::program_circuits_mut;
+use crate::unitary;
+use qoqo_calculator::CalculatorFloat;
+use roqoqo::backends::EvaluatingBackend;
+use roqoqo::operations::*;
+use roqoqo::{Circuit, QuantumProgram, RoqoqoBackendError, RoqoqoError};
+use std::collections::HashMap;
+
+/// The fit used to extrapolate expectation values to zero noise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extrapolation {
+    /// Least-squares fit of a straight line.
+    Linear,
+    /// The polynomial through all points, of degree one less than the number of scale factors.
+    Richardson,
+    /// Least-squares fit of `asymptote + b exp(-c lambda)`.
+    ///
+    /// The asymptote is the expectation value of the fully decohered state, for example zero for an operator without
+    /// an identity term when the noise drives the qubits into the maximally mixed state.
+    Exponential {
+        /// The expectation value for infinite noise.
+        asymptote: f64,
+    },
+}
+
+impl Extrapolation {
+    /// Returns the value of the fit through `(scale_factors[i], values[i])` at zero noise.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(f64)` - The extrapolated value.
+    /// * `Err(RoqoqoError)` - There are fewer than two distinct scale factors, or for the exponential fit the values
+    ///   are not all on the same side of the asymptote.
+    ///
+    pub fn extrapolate(&self, scale_factors: &[f64], values: &[f64]) -> Result<f64, RoqoqoError> {
+        let distinct = scale_factors
+            .iter()
+            .any(|factor| (factor - scale_factors[0]).abs() > f64::EPSILON);
+        if scale_factors.len() != values.len() || !distinct {
+            return Err(RoqoqoError::GenericError {
+                msg: format!(
+                    "Extrapolation needs values for at least two different scale factors, got {:?}",
+                    scale_factors
+                ),
+            });
+        }
+        match self {
+            Extrapolation::Linear => Ok(linear_fit(scale_factors, values).0),
+            Extrapolation::Richardson => Ok(values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    value
+                        * scale_factors
+                            .iter()
+                            .enumerate()
+                            .filter(|(j, _)| *j != i)
+                            .map(|(_, factor)| factor / (factor - scale_factors[i]))
+                            .product::<f64>()
+                })
+                .sum()),
+            Extrapolation::Exponential { asymptote } => {
+                let sign = (values[0] - asymptote).signum();
+                if values.iter().any(|value| (value - asymptote) * sign <= 0.0) {
+                    return Err(RoqoqoError::GenericError {
+                        msg: format!(
+                            "Values {:?} are not all on the same side of the asymptote {}",
+                            values, asymptote
+                        ),
+                    });
+                }
+                let logarithms: Vec<f64> = values
+                    .iter()
+                    .map(|value| ((value - asymptote) * sign).ln())
+                    .collect();
+                Ok(asymptote + sign * linear_fit(scale_factors, &logarithms).0.exp())
+            }
+        }
+    }
+}
+
+/// Returns intercept and slope of the least-squares line through the points.
+fn linear_fit(x: &[f64], y: &[f64]) -> (f64, f64) {
+    let n = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / n;
+    let mean_y = y.iter().sum::<f64>() / n;
+    let covariance: f64 = x
+        .iter()
+        .zip(y.iter())
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = x.iter().map(|x| (x - mean_x).powi(2)).sum();
+    let slope = covariance / variance;
+    (mean_y - slope * mean_x, slope)
+}
+
+/// The expectation values of a quantum program for several noise scale factors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScaledExpectationValues {
+    /// The factors the noise was scaled with.
+    pub scale_factors: Vec<f64>,
+    /// The expectation values for each scale factor.
+    pub values: Vec<HashMap<String, f64>>,
+}
+
+impl ScaledExpectationValues {
+    /// Returns the values of one expectation value in the order of the scale factors.
+    pub fn values_of(&self, name: &str) -> Vec<f64> {
+        self.values
+            .iter()
+            .map(|values| values.get(name).copied().unwrap_or(f64::NAN))
+            .collect()
+    }
+
+    /// Extrapolates every expectation value to zero noise.
+    pub fn extrapolate(
+        &self,
+        extrapolation: Extrapolation,
+    ) -> Result<HashMap<String, f64>, RoqoqoError> {
+        self.values
+            .first()
+            .into_iter()
+            .flat_map(|values| values.keys())
+            .map(|name| {
+                extrapolation
+                    .extrapolate(&self.scale_factors, &self.values_of(name))
+                    .map(|value| (name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+/// Runs a quantum program with the noise scaled by each of the scale factors.
+///
+/// # Arguments
+///
+/// * `program` - The ideal quantum program returning expectation values.
+/// * `backend` - The backend running the noisy programs.
+/// * `parameters` - The input parameters of the program.
+/// * `scale_factors` - The factors the noise is scaled with, `1.0` is the unscaled noise.
+/// * `scale_circuit` - Returns the noisy version of a circuit of the program for a scale factor.
+///
+/// # Returns
+///
+/// * `Ok(ScaledExpectationValues)` - The expectation values for every scale factor.
+/// * `Err(RoqoqoBackendError)` - A circuit can not be scaled, the program can not be run or returns no expectation
+///   values.
+///
+pub fn run_scaled<T, F>(
+    program: &QuantumProgram,
+    backend: &T,
+    parameters: &[f64],
+    scale_factors: &[f64],
+    scale_circuit: F,
+) -> Result<ScaledExpectationValues, RoqoqoBackendError>
+where
+    T: EvaluatingBackend + Clone,
+    F: Fn(&Circuit, f64) -> Result<Circuit, RoqoqoError>,
+{
+    let mut values = Vec::new();
+    for scale_factor in scale_factors {
+        let mut scaled_program = program.clone();
+        for circuit in program_circuits_mut(&mut scaled_program) {
+            *circuit = scale_circuit(circuit, *scale_factor)?;
+        }
+        values.push(
+            scaled_program
+                .run(backend.clone(), parameters)?
+                .ok_or_else(|| RoqoqoBackendError::GenericError {
+                    msg: "Quantum program returned no expectation values".to_string(),
+                })?,
+        );
+    }
+    Ok(ScaledExpectationValues {
+        scale_factors: scale_factors.to_vec(),
+        values,
+    })
+}
+
+/// Folds the gates of a circuit to scale the number of executed gates by `scale_factor`.
+///
+/// A folded gate `G (G^-1 G)^k` acts like `G`. Every gate is folded `k` times so that the number of gates grows by the
+/// factor `2k + 1` closest to `scale_factor`. For scale factors between odd integers the first gates of the circuit
+/// are folded once more than the others. Circuits nested in `PragmaConditional` and `PragmaLoop` are folded as well.
+///
+/// # Returns
+///
+/// * `Ok(Circuit)` - The folded circuit.
+/// * `Err(RoqoqoError)` - The scale factor is smaller than one or a gate has no known inverse.
+///
+pub fn fold_gates(circuit: &Circuit, scale_factor: f64) -> Result<Circuit, RoqoqoError> {
+    if scale_factor < 1.0 {
+        return Err(RoqoqoError::GenericError {
+            msg: format!("Noise scale factor {} is smaller than one", scale_factor),
+        });
+    }
+    let number_gates = circuit
+        .iter()
+        .filter(|operation| unitary::is_gate(operation))
+        .count();
+    let number_folds = ((scale_factor - 1.0) * number_gates as f64 / 2.0).round() as usize;
+    let mut folded = Circuit::new();
+    let mut gate_index = 0;
+    for operation in circuit.iter() {
+        match operation {
+            Operation::PragmaConditional(op) => {
+                folded += PragmaConditional::new(
+                    op.condition_register().clone(),
+                    *op.condition_index(),
+                    fold_gates(op.circuit(), scale_factor)?,
+                );
+            }
+            Operation::PragmaLoop(op) => {
+                folded += PragmaLoop::new(
+                    op.repetitions().clone(),
+                    fold_gates(op.circuit(), scale_factor)?,
+                );
+            }
+            _ if unitary::is_gate(operation) => {
+                folded += operation.clone();
+                let repetitions = number_folds / number_gates
+                    + usize::from(gate_index < number_folds % number_gates);
+                if repetitions > 0 {
+                    let inverse = inverse_gate(operation)?;
+                    for _ in 0..repetitions {
+                        folded += inverse.clone();
+                        folded += operation.clone();
+                    }
+                }
+                gate_index += 1;
+            }
+            _ => folded += operation.clone(),
+        }
+    }
+    Ok(folded)
+}
+
+/// Returns the inverse of a gate.
+fn inverse_gate(operation: &Operation) -> Result<Operation, RoqoqoError> {
+    let inverse: Operation = match operation {
+        Operation::Hadamard(_)
+        | Operation::PauliX(_)
+        | Operation::PauliY(_)
+        | Operation::PauliZ(_)
+        | Operation::CNOT(_)
+        | Operation::SWAP(_)
+        | Operation::ControlledPauliY(_)
+        | Operation::ControlledPauliZ(_)
+        | Operation::Toffoli(_)
+        | Operation::ControlledControlledPauliZ(_) => operation.clone(),
+        Operation::RotateX(op) => RotateX::new(*op.qubit(), -op.theta().clone()).into(),
+        Operation::RotateY(op) => RotateY::new(*op.qubit(), -op.theta().clone()).into(),
+        Operation::RotateZ(op) => RotateZ::new(*op.qubit(), -op.theta().clone()).into(),
+        Operation::PhaseShiftState0(op) => {
+            PhaseShiftState0::new(*op.qubit(), -op.theta().clone()).into()
+        }
+        Operation::PhaseShiftState1(op) => {
+            PhaseShiftState1::new(*op.qubit(), -op.theta().clone()).into()
+        }
+        Operation::SGate(op) => InvSGate::new(*op.qubit()).into(),
+        Operation::InvSGate(op) => SGate::new(*op.qubit()).into(),
+        Operation::TGate(op) => InvTGate::new(*op.qubit()).into(),
+        Operation::InvTGate(op) => TGate::new(*op.qubit()).into(),
+        Operation::SqrtPauliX(op) => InvSqrtPauliX::new(*op.qubit()).into(),
+        Operation::InvSqrtPauliX(op) => SqrtPauliX::new(*op.qubit()).into(),
+        Operation::ControlledPhaseShift(op) => {
+            ControlledPhaseShift::new(*op.control(), *op.target(), -op.theta().clone()).into()
+        }
+        Operation::ControlledRotateX(op) => {
+            ControlledRotateX::new(*op.control(), *op.target(), -op.theta().clone()).into()
+        }
+        _ => {
+            return Err(RoqoqoError::GenericError {
+                msg: format!("Gate {} can not be folded", operation.hqslang()),
+            })
+        }
+    };
+    Ok(inverse)
+}
+
+/// Multiplies the gate times of the noise pragmas of a circuit by `scale_factor`.
+///
+/// Circuits nested in `PragmaConditional` and `PragmaLoop` are scaled as well.
+///
+pub fn scale_noise(circuit: &Circuit, scale_factor: f64) -> Circuit {
+    let scale = |gate_time: &CalculatorFloat| gate_time * scale_factor;
+    let mut scaled = Circuit::new();
+    for operation in circuit.iter() {
+        scaled += match operation {
+            Operation::PragmaDamping(op) => {
+                PragmaDamping::new(*op.qubit(), scale(op.gate_time()), op.rate().clone()).into()
+            }
+            Operation::PragmaDephasing(op) => {
+                PragmaDephasing::new(*op.qubit(), scale(op.gate_time()), op.rate().clone()).into()
+            }
+            Operation::PragmaDepolarising(op) => {
+                PragmaDepolarising::new(*op.qubit(), scale(op.gate_time()), op.rate().clone())
+                    .into()
+            }
+            Operation::PragmaRandomNoise(op) => PragmaRandomNoise::new(
+                *op.qubit(),
+                scale(op.gate_time()),
+                op.depolarising_rate().clone(),
+                op.dephasing_rate().clone(),
+            )
+            .into(),
+            Operation::PragmaGeneralNoise(op) => {
+                PragmaGeneralNoise::new(*op.qubit(), scale(op.gate_time()), op.rates().clone())
+                    .into()
+            }
+            Operation::PragmaConditional(op) => PragmaConditional::new(
+                op.condition_register().clone(),
+                *op.condition_index(),
+                scale_noise(op.circuit(), scale_factor),
+            )
+            .into(),
+            Operation::PragmaLoop(op) => PragmaLoop::new(
+                op.repetitions().clone(),
+                scale_noise(op.circuit(), scale_factor),
+            )
+            .into(),
+            _ => operation.clone(),
+        };
+    }
+    scaled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::DMatrix;
+    use ndarray::Array2;
+    use num_complex::Complex64;
+
+    const SCALE_FACTORS: [f64; 3] = [1.0, 2.0, 3.0];
+
+    fn extrapolate(extrapolation: Extrapolation, value: impl Fn(f64) -> f64) -> f64 {
+        let values: Vec<f64> = SCALE_FACTORS.iter().map(|&factor| value(factor)).collect();
+        extrapolation.extrapolate(&SCALE_FACTORS, &values).unwrap()
+    }
+
+    #[test]
+    fn extrapolations_are_exact_for_their_model() {
+        let linear = extrapolate(Extrapolation::Linear, |lambda| 0.8 - 0.1 * lambda);
+        assert!((linear - 0.8).abs() < 1e-12);
+        let richardson = extrapolate(Extrapolation::Richardson, |lambda| {
+            0.8 - 0.1 * lambda + 0.02 * lambda * lambda
+        });
+        assert!((richardson - 0.8).abs() < 1e-12);
+        let decaying = extrapolate(Extrapolation::Exponential { asymptote: 0.1 }, |lambda| {
+            0.1 + 0.7 * (-0.3 * lambda).exp()
+        });
+        assert!((decaying - 0.8).abs() < 1e-12);
+        let rising = extrapolate(Extrapolation::Exponential { asymptote: 0.0 }, |lambda| {
+            -0.5 * (-0.2 * lambda).exp()
+        });
+        assert!((rising + 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn extrapolation_needs_distinct_scale_factors() {
+        assert!(Extrapolation::Linear
+            .extrapolate(&[1.0, 1.0], &[0.5, 0.4])
+            .is_err());
+        assert!(Extrapolation::Exponential { asymptote: 0.0 }
+            .extrapolate(&[1.0, 2.0], &[0.5, -0.4])
+            .is_err());
+    }
+
+    #[test]
+    fn folding_triples_the_gates() {
+        let mut circuit = Circuit::new();
+        circuit += Hadamard::new(0);
+        circuit += CNOT::new(0, 1);
+        circuit += RotateZ::new(1, 0.4.into());
+        circuit += ControlledPhaseShift::new(1, 0, 1.1.into());
+        circuit += SqrtPauliX::new(0);
+        let folded = fold_gates(&circuit, 3.0).unwrap();
+        assert_eq!(folded.len(), 3 * circuit.len());
+        assert!(unitary::circuits_equivalent(&circuit, &folded).unwrap());
+        assert!(fold_gates(&circuit, 0.5).is_err());
+    }
+
+    #[test]
+    fn inverse_gates() {
+        let gates: Vec<Operation> = vec![
+            Hadamard::new(0).into(),
+            PauliX::new(0).into(),
+            PauliY::new(0).into(),
+            PauliZ::new(0).into(),
+            CNOT::new(0, 1).into(),
+            SWAP::new(0, 1).into(),
+            ControlledPauliY::new(1, 0).into(),
+            ControlledPauliZ::new(0, 1).into(),
+            Toffoli::new(0, 1, 2).into(),
+            ControlledControlledPauliZ::new(2, 0, 1).into(),
+            RotateX::new(0, 0.3.into()).into(),
+            RotateY::new(0, 0.3.into()).into(),
+            RotateZ::new(0, 0.3.into()).into(),
+            PhaseShiftState0::new(0, 0.3.into()).into(),
+            PhaseShiftState1::new(0, 0.3.into()).into(),
+            SGate::new(0).into(),
+            InvSGate::new(0).into(),
+            TGate::new(0).into(),
+            InvTGate::new(0).into(),
+            SqrtPauliX::new(0).into(),
+            InvSqrtPauliX::new(0).into(),
+            ControlledPhaseShift::new(0, 1, 0.3.into()).into(),
+            ControlledRotateX::new(1, 0, 0.3.into()).into(),
+        ];
+        let identity = DMatrix::<Complex64>::identity(8, 8);
+        for gate in gates {
+            let mut product = Circuit::new();
+            product += gate.clone();
+            product += inverse_gate(&gate).unwrap();
+            let unitary = unitary::circuit_unitary(&product, 3).unwrap();
+            assert!((unitary - &identity).norm() < 1e-12, "{gate:?}");
+        }
+        assert!(inverse_gate(&Operation::from(RotateXY::new(0, 0.3.into(), 0.1.into()))).is_err());
+    }
+
+    #[test]
+    fn scaling_changes_only_gate_times() {
+        let noise = |gate_time: f64| {
+            let mut circuit = Circuit::new();
+            circuit += RotateX::new(0, 0.3.into());
+            circuit += PragmaDamping::new(0, gate_time.into(), 0.02.into());
+            circuit += PragmaDephasing::new(0, gate_time.into(), 0.03.into());
+            circuit += PragmaDepolarising::new(0, gate_time.into(), 0.04.into());
+            circuit += PragmaRandomNoise::new(0, gate_time.into(), 0.05.into(), 0.06.into());
+            circuit += PragmaGeneralNoise::new(0, gate_time.into(), Array2::eye(3) * 0.07);
+            circuit
+        };
+        let mut circuit = noise(0.5);
+        circuit += DefinitionBit::new("ro".to_string(), 1, true);
+        circuit += PragmaConditional::new("ro".to_string(), 0, noise(0.5));
+        let mut expected = noise(1.5);
+        expected += DefinitionBit::new("ro".to_string(), 1, true);
+        expected += PragmaConditional::new("ro".to_string(), 0, noise(1.5));
+        assert_eq!(scale_noise(&circuit, 3.0), expected);
+    }
+}